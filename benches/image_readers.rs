@@ -0,0 +1,108 @@
+//! Benchmarks for image verification against fake flash.
+//!
+//! Run with `LOADSTONE_CONFIG='' cargo bench`. The byte by byte benchmark reproduces
+//! the old, unbuffered scan as a baseline for the chunked image readers.
+#![feature(test)]
+extern crate test;
+
+use blue_hal::{
+    hal::{doubles::flash::Address, flash::ReadWrite},
+    utilities::iterator::UntilSequence,
+};
+use crc::{crc32, Hasher32};
+use loadstone_image::Trailer;
+use loadstone_lib::{
+    devices::image::{magic_string_inverted, Bank},
+    error::{Convertible, Error},
+};
+use test::{black_box, Bencher};
+
+const IMAGE_SIZE: usize = 64 * 1024;
+const BANK: Bank<Address> = Bank {
+    index: 1,
+    size: IMAGE_SIZE + 1024,
+    location: Address(0),
+    bootable: false,
+    is_golden: false,
+};
+
+/// Flash held in memory. Unlike the fake flash in `blue_hal`, its errors convert to
+/// Loadstone errors outside of unit tests, so images can be verified against it.
+struct BenchFlash(Vec<u8>);
+
+#[derive(Copy, Clone, Debug)]
+struct BenchFlashError;
+
+impl Convertible for BenchFlashError {
+    fn into(self) -> Error { Error::DeviceError("Read out of bounds") }
+}
+
+impl ReadWrite for BenchFlash {
+    type Error = BenchFlashError;
+    type Address = Address;
+
+    fn label() -> &'static str { "Bench Flash" }
+
+    fn read(&mut self, address: Address, bytes: &mut [u8]) -> nb::Result<(), BenchFlashError> {
+        let start = address.0 as usize;
+        let data =
+            self.0.get(start..start + bytes.len()).ok_or(nb::Error::Other(BenchFlashError))?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write(&mut self, _: Address, _: &[u8]) -> nb::Result<(), BenchFlashError> {
+        unimplemented!()
+    }
+
+    fn range(&self) -> (Address, Address) { (Address(0), Address(self.0.len() as u32)) }
+
+    fn erase(&mut self) -> nb::Result<(), BenchFlashError> { unimplemented!() }
+
+    fn write_from_blocks<I: Iterator<Item = [u8; N]>, const N: usize>(
+        &mut self,
+        _: Address,
+        _: I,
+    ) -> Result<(), BenchFlashError> {
+        unimplemented!()
+    }
+}
+
+/// Flash containing a single CRC image with a body of `IMAGE_SIZE` bytes.
+fn flash_with_crc_image() -> BenchFlash {
+    let mut image: Vec<u8> = (0..IMAGE_SIZE).map(|i| (i % 251) as u8).collect();
+    Trailer::default().encode(|bytes| image.extend_from_slice(bytes)).unwrap();
+    image.extend_from_slice(&magic_string_inverted());
+    let mut digest = crc32::Digest::new(crc32::IEEE);
+    digest.write(&image);
+    image.extend_from_slice(&digest.sum32().to_le_bytes());
+    image.resize(BANK.size, 0xFF);
+    BenchFlash(image)
+}
+
+#[bench]
+fn byte_by_byte_crc_scan(bencher: &mut Bencher) {
+    let mut flash = flash_with_crc_image();
+    bencher.iter(|| {
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        let size = flash
+            .bytes(BANK.location)
+            .take(BANK.size)
+            .until_sequence(&magic_string_inverted())
+            .fold(0usize, |byte_count, byte| {
+                digest.write(&[byte]);
+                byte_count + 1
+            });
+        digest.write(&magic_string_inverted());
+        black_box((size, digest.sum32()))
+    });
+}
+
+#[cfg(not(any(feature = "ecdsa-verify", feature = "p384-verify", feature = "ed25519-verify")))]
+#[bench]
+fn chunked_crc_image_reader(bencher: &mut Bencher) {
+    use loadstone_lib::devices::image::{CrcImageReader, Reader};
+
+    let mut flash = flash_with_crc_image();
+    bencher.iter(|| black_box(CrcImageReader::image_at(&mut flash, BANK).unwrap()));
+}
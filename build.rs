@@ -15,6 +15,7 @@ fn main() -> Result<()> { process_configuration_file() }
 
 fn process_configuration_file() -> Result<()> {
     println!("cargo:rerun-if-env-changed=LOADSTONE_CONFIG");
+    println!("cargo:rerun-if-env-changed=LOADSTONE_RELOCATION_BANK");
 
     let configuration: Configuration = if let Ok(config) = std::env::var("LOADSTONE_CONFIG") {
         if config.is_empty() {
@@ -54,11 +55,26 @@ fn validate_feature_flags_against_configuration(configuration: &Configuration) {
         .filter(|f| !&supplied_flags.contains(&(*f).to_owned()))
         .collect();
 
-    if configuration.security_configuration.security_mode != SecurityMode::P256ECDSA
-        && supplied_flags.contains(&"ecdsa_verify".to_owned())
+    let security_mode = configuration.security_configuration.security_mode;
+    for flag in SecurityMode::all_feature_flags() {
+        if security_mode.feature_flag() != Some(flag)
+            && supplied_flags.contains(&flag.replace("-", "_"))
+        {
+            panic!(
+                "Configuration mismatch. Configuration file specifies {} security mode, \
+                but the `{}` flag was supplied. Try again without `{}`.",
+                security_mode.name(),
+                flag,
+                flag
+            );
+        }
+    }
+
+    if !configuration.security_configuration.encryption_enabled()
+        && supplied_flags.contains(&"encryption".to_owned())
     {
-        panic!("Configuration mismatch. Configuration file does not specify ECDSA security mode, \
-                but the `ecdsa-verify` flag was supplied. Try again without `ecdsa-verify` for CRC mode.");
+        panic!("Configuration mismatch. Configuration file does not supply an image encryption \
+                key, but the `encryption` flag was supplied. Try again without `encryption`.");
     }
 
     if !missing_flags.is_empty() {
@@ -1,6 +1,6 @@
 use std::{fs::OpenOptions, io::Write};
 
-use crate::{port::LinkerScriptConstants, Configuration};
+use crate::{memory::UPDATE_SIGNAL_RAM_SIZE, port::LinkerScriptConstants, Configuration};
 use anyhow::{anyhow, Result};
 
 /// Generates the linker script `memory.x`, which describes the amount and location
@@ -18,30 +18,54 @@ pub fn generate_linker_script(configuration: &Configuration) -> Result<()> {
         relocate_to_bootable_bank(&mut constants, configuration)?;
     }
 
+    // The update signal's RAM is left out, so neither Loadstone nor the application
+    // initialise or use it.
+    if configuration.update_signal_ram_address().is_some() {
+        constants.ram.origin += UPDATE_SIGNAL_RAM_SIZE;
+        constants.ram.size -= UPDATE_SIGNAL_RAM_SIZE as usize;
+    }
+
     write!(
         file,
         "MEMORY\n\
          {{\n\
              FLASH : ORIGIN = 0x{:08X}, LENGTH = {}K\n\
-             RAM : ORIGIN = 0x{:08X}, LENGTH = {}K\n\
+             RAM : ORIGIN = 0x{:08X}, LENGTH = {}\n\
          }}\n",
         constants.flash.origin,
         constants.flash.size / 1024,
         constants.ram.origin,
-        constants.ram.size / 1024,
+        constants.ram.size,
     )?;
 
     Ok(())
 }
 
+/// Relocates the image to the start of a bootable bank: the main one, or the one numbered
+/// by the `LOADSTONE_RELOCATION_BANK` environment variable (starting from 1, as shown by
+/// the configuration app), so an application can be built for each bootable bank.
 #[allow(unused)]
 fn relocate_to_bootable_bank(
     constants: &mut LinkerScriptConstants,
     configuration: &Configuration,
 ) -> Result<()> {
-    let bootable_address = configuration.memory_configuration.bootable_address().ok_or(anyhow!(
-        "Impossible to relocate: bootable bank is undefined in configuration file."
-    ))?;
+    let memory_configuration = &configuration.memory_configuration;
+    let bootable_address = match std::env::var("LOADSTONE_RELOCATION_BANK") {
+        Ok(bank) => {
+            let index = bank
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1))
+                .ok_or(anyhow!("Invalid relocation bank: {}", bank))?;
+            memory_configuration.bootable_address_of(index).ok_or(anyhow!(
+                "Impossible to relocate: bank {} is not bootable in configuration file.",
+                bank
+            ))?
+        }
+        Err(_) => memory_configuration.bootable_address().ok_or(anyhow!(
+            "Impossible to relocate: bootable bank is undefined in configuration file."
+        ))?,
+    };
     let offset = bootable_address - constants.flash.origin;
     constants.flash.size = constants.flash.size.saturating_sub(offset as usize);
     constants.flash.origin = bootable_address;
@@ -3,7 +3,7 @@ use quote::{format_ident, quote};
 use std::{fs::OpenOptions, io::Write, path::Path};
 
 use crate::{
-    memory::{ExternalMemoryMap, InternalMemoryMap, MemoryConfiguration},
+    memory::{self, Bank, ExternalMemoryMap, InternalMemoryMap, MemoryConfiguration},
     port::{Port, Subfamily},
 };
 
@@ -11,10 +11,15 @@ use super::prettify_file;
 
 /// Generates the `memory_map.rs` module, containing a description of the MCU
 /// flash banks and, if applicable, external flash banks for a particular
-/// Loadstone instance.
+/// Loadstone instance, as well as the persistent storage region, the scratch
+/// region for swapping images and the location of the update signal, if required.
 pub fn generate<P: AsRef<Path>>(
     autogenerated_folder_path: P,
     memory_configuration: &MemoryConfiguration,
+    storage_region: Option<&Bank>,
+    scratch_region: Option<&Bank>,
+    update_signal_region: Option<&Bank>,
+    update_signal_ram_address: Option<u32>,
     port: &Port,
 ) -> Result<()> {
     let filename = autogenerated_folder_path.as_ref().join("memory_map.rs");
@@ -31,10 +36,25 @@ pub fn generate<P: AsRef<Path>>(
         &memory_configuration.external_memory_map,
         memory_configuration.golden_index,
     )?;
+    check_reserved_regions(
+        &memory_configuration.internal_memory_map,
+        &[
+            ("storage", storage_region),
+            ("scratch", scratch_region),
+            ("update signal", update_signal_region),
+        ],
+        port,
+    );
+    let storage = generate_storage(storage_region, port)?;
+    let scratch = generate_scratch(scratch_region)?;
+    let update_signal = generate_update_signal(update_signal_region, update_signal_ram_address)?;
 
     file.write_all(imports.as_bytes())?;
     file.write_all(mcu_banks.as_bytes())?;
     file.write_all(external_banks.as_bytes())?;
+    file.write_all(storage.as_bytes())?;
+    file.write_all(scratch.as_bytes())?;
+    file.write_all(update_signal.as_bytes())?;
     prettify_file(filename).ok();
     Ok(())
 }
@@ -70,6 +90,7 @@ fn generate_imports(memory_configuration: &MemoryConfiguration, port: &Port) ->
         //! in the next project build. Generation logic for this module is defined in
         //! `loadstone_config/src/codegen/memory_map.rs`
         use crate::devices::image as image;
+        use crate::devices::storage as storage;
         #[allow(unused_imports)]
         use super::pin_configuration::ExternalFlash;
         use #(#mcu_address)::* as McuAddress;
@@ -115,8 +136,20 @@ fn generate_mcu_banks(
     let number_of_mcu_banks = map.banks.len();
     let index: Vec<u8> =
         map.banks.iter().enumerate().map(|(i, _)| (i + base_index) as u8).collect();
+    if let Some(main) = map.bootable_index {
+        for &index in &map.additional_bootable_indices {
+            if index <= main || index >= number_of_mcu_banks || Some(index) == golden_index {
+                panic!(
+                    "Additional bootable bank {} must follow the main bootable bank ({}), \
+                    exist, and not be golden.",
+                    index + 1,
+                    main + 1
+                );
+            }
+        }
+    }
     let bootable: Vec<bool> =
-        (0..number_of_mcu_banks).map(|i| Some(i) == map.bootable_index).collect();
+        (0..number_of_mcu_banks).map(|i| map.bootable_indices().any(|b| b == i)).collect();
     let location: Vec<u32> = map.banks.iter().map(|b| b.start_address).collect();
     let size: Vec<usize> = map.banks.iter().map(|b| (b.size_kb * 1024) as usize).collect();
     let golden: Vec<bool> = (0..number_of_mcu_banks).map(|i| Some(i) == golden_index).collect();
@@ -135,3 +168,111 @@ fn generate_mcu_banks(
     };
     Ok(format!("{}", code))
 }
+
+/// Ensures every reserved region of MCU flash is made of whole erase sectors, and
+/// overlaps neither the bootloader, any bank, nor any other reserved region.
+fn check_reserved_regions(map: &InternalMemoryMap, regions: &[(&str, Option<&Bank>)], port: &Port) {
+    let overlap =
+        |a: &Bank, b: &Bank| a.start_address < b.end_address() && b.start_address < a.end_address();
+    let sectors = memory::internal_sectors(port);
+    let bootloader =
+        Bank { start_address: map.bootloader_location, size_kb: map.bootloader_length_kb };
+    let regions: Vec<_> =
+        regions.iter().filter_map(|(name, region)| region.map(|r| (*name, r))).collect();
+    for (i, (name, region)) in regions.iter().enumerate() {
+        if !sectors.iter().any(|s| s.start_address == region.start_address)
+            || !sectors.iter().any(|s| s.end_address() == region.end_address())
+        {
+            panic!(
+                "The {} region at 0x{:x} is not made of whole sectors",
+                name, region.start_address
+            );
+        }
+        if overlap(region, &bootloader) {
+            panic!("The bootloader overlaps the {} region at 0x{:x}", name, region.start_address);
+        }
+        if let Some(bank) = map.banks.iter().find(|b| overlap(region, b)) {
+            panic!(
+                "MCU bank at 0x{:x} overlaps the {} region at 0x{:x}",
+                bank.start_address, name, region.start_address
+            );
+        }
+        if let Some((other, _)) = regions[i + 1..].iter().find(|(_, r)| overlap(region, r)) {
+            panic!("The {} region overlaps the {} region", name, other);
+        }
+    }
+}
+
+fn generate_storage(storage_region: Option<&Bank>, port: &Port) -> Result<String> {
+    let code = match storage_region {
+        Some(_) => {
+            let (clearable, permanent) = memory::storage_sectors(port);
+            let permanent = permanent.start_address;
+            let clearable = clearable.start_address;
+            quote! {
+                pub static STORAGE: Option<storage::Storage<McuAddress>> =
+                    Some(storage::Storage {
+                        permanent: McuAddress(#permanent),
+                        clearable: McuAddress(#clearable),
+                    });
+            }
+        }
+        None => quote! {
+            pub static STORAGE: Option<storage::Storage<McuAddress>> = None;
+        },
+    };
+    Ok(format!("{}", code))
+}
+
+/// Generates the scratch bank used to swap images. It isn't part of the sequence of
+/// image banks, so it takes index 0, which is never assigned to them.
+fn generate_scratch(scratch_region: Option<&Bank>) -> Result<String> {
+    let code = match scratch_region {
+        Some(region) => {
+            let location = region.start_address;
+            let size = (region.size_kb * 1024) as usize;
+            quote! {
+                pub static SCRATCH_BANK: Option<image::Bank<McuAddress>> = Some(image::Bank {
+                    index: 0,
+                    bootable: false,
+                    location: McuAddress(#location),
+                    size: #size,
+                    is_golden: false,
+                });
+            }
+        }
+        None => quote! {
+            pub static SCRATCH_BANK: Option<image::Bank<McuAddress>> = None;
+        },
+    };
+    Ok(format!("{}", code))
+}
+
+/// Generates the location of the update signal: a sector of MCU flash or a word of RAM,
+/// depending on the backend it's kept in. Neither is generated for other backends.
+fn generate_update_signal(
+    update_signal_region: Option<&Bank>,
+    update_signal_ram_address: Option<u32>,
+) -> Result<String> {
+    let sector = match update_signal_region {
+        Some(region) => {
+            let location = region.start_address;
+            quote! { Some(McuAddress(#location)) }
+        }
+        None => quote! { None },
+    };
+    let ram_address = match update_signal_ram_address {
+        Some(address) => {
+            let address = address as usize;
+            quote! { Some(#address) }
+        }
+        None => quote! { None },
+    };
+    let code = quote! {
+        #[allow(unused)]
+        pub static UPDATE_SIGNAL_SECTOR: Option<McuAddress> = #sector;
+        #[allow(unused)]
+        pub static UPDATE_SIGNAL_RAM_ADDRESS: Option<usize> = #ram_address;
+    };
+    Ok(format!("{}", code))
+}
@@ -1,8 +1,6 @@
 //! Generates code from parsed .ron configuration. This is where
 //! concrete Loadstone modules are constructed from user configuration
 //! gathered from the web app GUI.
-use p256::ecdsa::VerifyingKey;
-use std::str::FromStr;
 use quote::{__private::Span, quote};
 use std::{
     fs::{self, OpenOptions},
@@ -12,7 +10,15 @@ use std::{
 };
 use syn::LitStr;
 
-use crate::{Configuration, features::{BootMetrics, Greetings, Serial, UpdateSignal}, security::SecurityMode};
+use crate::{
+    Configuration,
+    features::{
+        BootMetrics, DefaultUpdatePlan, Greetings, RecoveryTimeout, RecoveryTimeoutAction, Serial,
+        UpdateSignal, Watchdog, MAX_BOOT_ATTEMPTS, MAX_RECOVERY_TIMEOUTS, MAX_RECOVERY_WINDOW_S,
+        MAX_TRIAL_BOOTS, MAX_WATCHDOG_TIMEOUT_MS, MIN_RECOVERY_WINDOW_S, MIN_WATCHDOG_TIMEOUT_MS,
+    },
+    security::SecurityMode,
+};
 use anyhow::Result;
 
 use self::linker_script::generate_linker_script;
@@ -35,12 +41,20 @@ pub fn generate_modules<P: AsRef<Path>>(
     generate_linker_script(&configuration)?;
     generate_top_level_module(&autogenerated_folder_path, configuration)?;
 
-    if std::env::var("CARGO_FEATURE_ECDSA_VERIFY").is_ok() {
-        generate_key(loadstone_path, configuration)?;
+    let signature_flag = configuration.security_configuration.security_mode.feature_flag();
+    if signature_flag.map_or(false, feature_enabled) {
+        generate_key(&loadstone_path, configuration)?;
+    }
+    if feature_enabled("encryption") {
+        generate_encryption_key(&loadstone_path, configuration)?;
     }
     memory_map::generate(
         &autogenerated_folder_path,
         &configuration.memory_configuration,
+        configuration.storage_region().as_ref(),
+        configuration.scratch_region().as_ref(),
+        configuration.update_signal_region().as_ref(),
+        configuration.update_signal_ram_address(),
         &configuration.port,
     )?;
     pins::generate(&autogenerated_folder_path, &configuration)?;
@@ -48,21 +62,51 @@ pub fn generate_modules<P: AsRef<Path>>(
     Ok(())
 }
 
-/// Generates a public key file under the `src/devices/assets/` folder.
+/// Whether a Loadstone feature flag was supplied to the build.
+fn feature_enabled(flag: &str) -> bool {
+    std::env::var(format!("CARGO_FEATURE_{}", flag.to_uppercase().replace("-", "_"))).is_ok()
+}
+
+/// Generates a key ring file under the `src/devices/assets/` folder, holding
+/// all verifying keys in the raw format expected by the image reader for the
+/// configured security mode.
 fn generate_key<P: AsRef<Path>>(loadstone_path: P, configuration: &Configuration) -> Result<()> {
-    assert!(configuration.security_configuration.security_mode == SecurityMode::P256ECDSA,
-        "Configuration mismatch: Config file requires ECDSA verification, but feature is disabled");
+    let key_file = match configuration.security_configuration.security_mode {
+        SecurityMode::Crc => panic!(
+            "Configuration mismatch: Signature verification feature is enabled in CRC mode"
+        ),
+        SecurityMode::P256ECDSA => "key_ring.p256",
+        SecurityMode::P384ECDSA => "key_ring.p384",
+        SecurityMode::Ed25519 => "key_ring.ed25519",
+    };
 
     fs::create_dir(loadstone_path.as_ref().join("src/devices/assets/")).ok();
-    let key_path = loadstone_path.as_ref().join(
-        "src/devices/assets/key.sec1"
-    );
+    let key_path = loadstone_path.as_ref().join("src/devices/assets/").join(key_file);
 
-    let key = VerifyingKey::from_str(&configuration.security_configuration.verifying_key_raw)
-        .expect("Supplied public key is not valid");
+    let key_ring = configuration
+        .security_configuration
+        .key_ring()
+        .expect("Supplied public keys are not valid, or key IDs are not unique");
+
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&key_path)?;
+    file.write_all(&key_ring)?;
+    Ok(())
+}
+
+/// Generates the image encryption key file under the `src/devices/assets/` folder.
+fn generate_encryption_key<P: AsRef<Path>>(
+    loadstone_path: P,
+    configuration: &Configuration,
+) -> Result<()> {
+    let key = configuration
+        .security_configuration
+        .encryption_key()
+        .expect("Configuration mismatch: Encryption feature is enabled, but no valid key was supplied");
 
+    fs::create_dir(loadstone_path.as_ref().join("src/devices/assets/")).ok();
+    let key_path = loadstone_path.as_ref().join("src/devices/assets/image_key.aes");
     let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&key_path)?;
-    file.write_all(key.to_encoded_point(false).as_bytes())?;
+    file.write_all(&key)?;
     Ok(())
 }
 
@@ -75,19 +119,34 @@ fn generate_top_level_module<P: AsRef<Path>>(
     let filename = autogenerated_folder_path.as_ref().join("mod.rs");
     let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&filename)?;
 
-    let (serial_enabled, recovery_enabled) = if let Serial::Enabled { recovery_enabled, .. } =
-        configuration.feature_configuration.serial
-    {
-        if !Serial::supported(&configuration.port) {
-            panic!(
-                "Serial features enabled for a port that doesn't support them: {:?}",
-                configuration.port
-            );
-        }
-        (true, recovery_enabled)
-    } else {
-        (false, false)
-    };
+    let (serial_enabled, recovery_enabled, in_place_recovery) =
+        if let Serial::Enabled { recovery_enabled, in_place_recovery, .. } =
+            configuration.feature_configuration.serial
+        {
+            if !Serial::supported(&configuration.port) {
+                panic!(
+                    "Serial features enabled for a port that doesn't support them: {:?}",
+                    configuration.port
+                );
+            }
+            (true, recovery_enabled, recovery_enabled && in_place_recovery)
+        } else {
+            (false, false, false)
+        };
+
+    // Recovered images are staged in the scratch bank or in a spare MCU bank (neither
+    // bootable nor golden) before replacing their target bank's image.
+    let map = &configuration.memory_configuration.internal_memory_map;
+    let golden_index = configuration.memory_configuration.golden_index;
+    let spare_mcu_bank_exists = (0..map.banks.len())
+        .any(|i| !map.bootable_indices().any(|b| b == i) && Some(i) != golden_index);
+    let staging_possible = configuration.scratch_region().is_some() || spare_mcu_bank_exists;
+    if recovery_enabled && !in_place_recovery && !staging_possible {
+        panic!(
+            "Serial recovery enabled without in-place recovery, and there's no scratch bank \
+            or spare MCU bank to stage recovered images in"
+        );
+    }
 
     let boot_time_metrics_enabled = if let BootMetrics::Enabled { timing: true } =
         &configuration.feature_configuration.boot_metrics
@@ -114,6 +173,95 @@ fn generate_top_level_module<P: AsRef<Path>>(
 
     let update_signal = configuration.feature_configuration.update_signal;
     let update_signal_enabled = matches!(update_signal, UpdateSignal::Enabled);
+    let update_signal_backend = configuration.feature_configuration.update_signal_backend;
+    if update_signal_enabled && !update_signal_backend.supported(&configuration.port) {
+        panic!(
+            "Update signal backend {:?} is not supported by port {:?}",
+            update_signal_backend, configuration.port
+        );
+    }
+    let update_signal_one_shot =
+        update_signal_enabled && configuration.feature_configuration.update_signal_mode.one_shot();
+    let default_update_plan = match configuration.feature_configuration.default_update_plan {
+        DefaultUpdatePlan::Any => quote! { crate::devices::update_signal::UpdatePlan::Any },
+        DefaultUpdatePlan::None => quote! { crate::devices::update_signal::UpdatePlan::None },
+    };
+    let anti_rollback_enabled = configuration.feature_configuration.anti_rollback.enabled();
+    let key_revocation_enabled = configuration.security_configuration.key_revocation;
+    let swap_updates_enabled = configuration.feature_configuration.update_mode.swaps();
+    let copy_journal_enabled = configuration.feature_configuration.copy_journal.enabled();
+    let trial_boots = match configuration.feature_configuration.trial_boot.boots() {
+        Some(boots) if boots == 0 || boots > MAX_TRIAL_BOOTS => {
+            panic!("Trial boots must be between 1 and {}, got {}", MAX_TRIAL_BOOTS, boots)
+        }
+        Some(boots) => quote! { Some(#boots) },
+        None => quote! { None },
+    };
+    let boot_attempts = match configuration.feature_configuration.boot_attempts.attempts() {
+        Some(attempts) if attempts == 0 || attempts > MAX_BOOT_ATTEMPTS => {
+            panic!("Boot attempts must be between 1 and {}, got {}", MAX_BOOT_ATTEMPTS, attempts)
+        }
+        Some(attempts) => quote! { Some(#attempts) },
+        None => quote! { None },
+    };
+    let watchdog_timeouts_ms = MIN_WATCHDOG_TIMEOUT_MS..=MAX_WATCHDOG_TIMEOUT_MS;
+    let watchdog_timeout_ms = match configuration.feature_configuration.watchdog.timeout_ms() {
+        Some(_) if !Watchdog::supported(&configuration.port) => panic!(
+            "Watchdog enabled for a port that doesn't support it: {:?}",
+            configuration.port
+        ),
+        Some(timeout) if !watchdog_timeouts_ms.contains(&timeout) => {
+            panic!(
+                "Watchdog timeout must be between {} and {} ms, got {}",
+                MIN_WATCHDOG_TIMEOUT_MS, MAX_WATCHDOG_TIMEOUT_MS, timeout
+            )
+        }
+        Some(timeout) => quote! { Some(#timeout) },
+        None => quote! { None },
+    };
+    let (recovery_window_ms, recovery_timeout_restores, recovery_timeouts) =
+        match configuration.feature_configuration.recovery_timeout {
+            RecoveryTimeout::Enabled { .. } if !recovery_enabled => {
+                panic!("Recovery timeout enabled without serial recovery")
+            }
+            RecoveryTimeout::Enabled { .. }
+                if !RecoveryTimeout::supported(&configuration.port) =>
+            {
+                panic!(
+                    "Recovery timeout enabled for a port that doesn't support it: {:?}",
+                    configuration.port
+                )
+            }
+            RecoveryTimeout::Enabled { window_s, .. }
+                if !(MIN_RECOVERY_WINDOW_S..=MAX_RECOVERY_WINDOW_S).contains(&window_s) =>
+            {
+                panic!(
+                    "Recovery window must be between {} and {} s, got {}",
+                    MIN_RECOVERY_WINDOW_S, MAX_RECOVERY_WINDOW_S, window_s
+                )
+            }
+            RecoveryTimeout::Enabled { allowed_timeouts, .. }
+                if allowed_timeouts == 0 || allowed_timeouts > MAX_RECOVERY_TIMEOUTS =>
+            {
+                panic!(
+                    "Recovery timeouts must be between 1 and {}, got {}",
+                    MAX_RECOVERY_TIMEOUTS, allowed_timeouts
+                )
+            }
+            RecoveryTimeout::Enabled { window_s, action, allowed_timeouts } => {
+                let window_ms = window_s * 1000;
+                (
+                    quote! { Some(#window_ms) },
+                    action == RecoveryTimeoutAction::RetryRestore,
+                    quote! { Some(#allowed_timeouts) },
+                )
+            }
+            RecoveryTimeout::Disabled => (quote! { None }, false, quote! { None }),
+        };
+    let hardware_id = match configuration.hardware_id {
+        Some(id) => quote! { Some(#id) },
+        None => quote! { None },
+    };
 
     let code = quote! {
         //! This entire module is autogenerated. Don't modify it manually!
@@ -134,6 +282,35 @@ fn generate_top_level_module<P: AsRef<Path>>(
         pub const DEMO_APP_GREETING: &str = #demo_app_greeting;
         #[allow(unused)]
         pub const UPDATE_SIGNAL_ENABLED: bool = #update_signal_enabled;
+        #[allow(unused)]
+        pub const UPDATE_SIGNAL_ONE_SHOT: bool = #update_signal_one_shot;
+        #[allow(unused)]
+        pub const UPDATE_SIGNAL_DEFAULT_PLAN: crate::devices::update_signal::UpdatePlan =
+            #default_update_plan;
+        #[allow(unused)]
+        pub const ANTI_ROLLBACK_ENABLED: bool = #anti_rollback_enabled;
+        #[allow(unused)]
+        pub const KEY_REVOCATION_ENABLED: bool = #key_revocation_enabled;
+        #[allow(unused)]
+        pub const HARDWARE_ID: Option<u32> = #hardware_id;
+        #[allow(unused)]
+        pub const SWAP_UPDATES_ENABLED: bool = #swap_updates_enabled;
+        #[allow(unused)]
+        pub const TRIAL_BOOTS: Option<u32> = #trial_boots;
+        #[allow(unused)]
+        pub const COPY_JOURNAL_ENABLED: bool = #copy_journal_enabled;
+        #[allow(unused)]
+        pub const WATCHDOG_TIMEOUT_MS: Option<u32> = #watchdog_timeout_ms;
+        #[allow(unused)]
+        pub const BOOT_ATTEMPTS: Option<u32> = #boot_attempts;
+        #[allow(unused)]
+        pub const RECOVERY_WINDOW_MS: Option<u32> = #recovery_window_ms;
+        #[allow(unused)]
+        pub const RECOVERY_TIMEOUT_RESTORES: bool = #recovery_timeout_restores;
+        #[allow(unused)]
+        pub const RECOVERY_TIMEOUTS: Option<u32> = #recovery_timeouts;
+        #[allow(unused)]
+        pub const RECOVERY_IN_PLACE: bool = #in_place_recovery;
     };
 
     file.write_all(format!("{}", code).as_bytes())?;
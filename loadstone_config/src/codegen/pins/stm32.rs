@@ -4,7 +4,10 @@ use quote::{format_ident, quote, TokenStreamExt};
 use std::{array::IntoIter, fs::File, io::Write};
 use syn::{Ident, Index};
 
-use crate::{features::Serial, Configuration};
+use crate::{
+    features::{RecoveryButton, Serial},
+    Configuration,
+};
 
 struct InputPinTokens {
     bank: char,
@@ -34,6 +37,7 @@ pub fn generate_stm32f4_pins(configuration: &Configuration, file: &mut File) ->
     generate_imports_and_types(configuration, &mut code);
     generate_gpio_macros(configuration, &mut code);
     generate_pin_constructor(configuration, &mut code);
+    generate_recovery_button(configuration, &mut code);
 
     file.write_all(format!("{}", code).as_bytes())?;
     Ok(())
@@ -98,20 +102,61 @@ fn generate_pin_constructor(
             Box::new(None.into_iter())
         };
 
+    let recovery_button_pin = if let RecoveryButton::Enabled { pin, .. } =
+        &configuration.feature_configuration.recovery_button
+    {
+        let structure = format_ident!("gpio{}", pin.bank);
+        let field = format_ident!("p{}{}", pin.bank, pin.index);
+        quote! { #structure.#field }
+    } else {
+        quote! { () }
+    };
+
     code.append_all(quote! {
         #[allow(unused)]
-        pub fn pins(#(#gpio_fields: stm32pac::#pac_gpio_fields),*, rcc: &mut stm32pac::RCC) -> (UsartPins, QspiPins) {
+        pub fn pins(#(#gpio_fields: stm32pac::#pac_gpio_fields),*, rcc: &mut stm32pac::RCC) -> (UsartPins, QspiPins, RecoveryButtonPin) {
 
             #(let #gpio_fields = #gpio_fields.split(rcc);)*
             (
                 (#(#serial_pin_structs.#serial_pin_fields),*),
-                (#(#qspi_pin_structs.#qspi_pin_fields),*)
+                (#(#qspi_pin_structs.#qspi_pin_fields),*),
+                #recovery_button_pin
             )
 
         }
     });
 }
 
+fn generate_recovery_button(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) {
+    if let RecoveryButton::Enabled { pin, active_low } =
+        &configuration.feature_configuration.recovery_button
+    {
+        let pin_type = format_ident!("P{}{}", pin.bank, pin.index);
+        let (mode, held) = if *active_low {
+            (format_ident!("PullUp"), quote! { pin.is_low() })
+        } else {
+            (format_ident!("PullDown"), quote! { pin.is_high() })
+        };
+        code.append_all(quote! {
+            pub type RecoveryButtonPin = #pin_type<Input<#mode>>;
+            /// Whether the recovery button is currently held down.
+            pub fn recovery_button_held(pin: &RecoveryButtonPin) -> bool {
+                use blue_hal::hal::gpio::InputPin;
+                #held
+            }
+        });
+    } else {
+        code.append_all(quote! {
+            pub type RecoveryButtonPin = ();
+            /// Whether the recovery button is currently held down.
+            pub fn recovery_button_held(_: &RecoveryButtonPin) -> bool { false }
+        });
+    }
+}
+
 fn generate_imports_and_types(
     configuration: &Configuration,
     code: &mut quote::__private::TokenStream,
@@ -198,11 +243,39 @@ fn generate_gpio_macros(configuration: &Configuration, code: &mut quote::__priva
     }
 }
 
-fn input_tokens(_configuration: &Configuration) -> Box<dyn Iterator<Item = InputPinTokens>> {
-    Box::new(IntoIter::new([
+fn input_tokens(configuration: &Configuration) -> Box<dyn Iterator<Item = InputPinTokens>> {
+    let defaults = IntoIter::new([
         InputPinTokens { bank: 'a', index: 0.into(), mode: format_ident!("Floating") },
         InputPinTokens { bank: 'a', index: 1.into(), mode: format_ident!("Floating") },
-    ]))
+    ]);
+
+    if let RecoveryButton::Enabled { pin, active_low } =
+        &configuration.feature_configuration.recovery_button
+    {
+        if let Serial::Enabled { tx_pin, rx_pin, .. } = &configuration.feature_configuration.serial
+        {
+            if pin.conflicts_with(tx_pin) || pin.conflicts_with(rx_pin) {
+                panic!("Recovery button pin {} is already in use by the serial peripheral", pin);
+            }
+        }
+        let bank = pin.bank.chars().nth(0).unwrap();
+        if qspi_flash_pin_tokens(configuration)
+            .any(|t| t.bank == bank && t.index.index == pin.index)
+        {
+            panic!("Recovery button pin {} is already in use by the external flash", pin);
+        }
+
+        let button = InputPinTokens {
+            bank,
+            index: (pin.index as usize).into(),
+            mode: format_ident!("{}", if *active_low { "PullUp" } else { "PullDown" }),
+        };
+        let index = pin.index;
+        let defaults = defaults.filter(move |t| !(t.bank == bank && t.index.index == index));
+        Box::new(defaults.chain(Some(button)))
+    } else {
+        Box::new(defaults)
+    }
 }
 
 fn serial_tokens(configuration: &Configuration) -> Box<dyn Iterator<Item = SerialPinTokens>> {
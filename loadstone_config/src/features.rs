@@ -2,7 +2,10 @@ use std::borrow::Cow;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{pins::PeripheralPin, port::Port};
+use crate::{
+    pins::{InputPin, PeripheralPin},
+    port::Port,
+};
 
 /// Collection of Loadstone features that are optional or
 /// somehow configurable.
@@ -12,6 +15,28 @@ pub struct FeatureConfiguration {
     pub boot_metrics: BootMetrics,
     pub update_signal: UpdateSignal,
     pub greetings: Greetings,
+    #[serde(default)]
+    pub anti_rollback: AntiRollback,
+    #[serde(default)]
+    pub update_mode: UpdateMode,
+    #[serde(default)]
+    pub trial_boot: TrialBoot,
+    #[serde(default)]
+    pub copy_journal: CopyJournal,
+    #[serde(default)]
+    pub watchdog: Watchdog,
+    #[serde(default)]
+    pub boot_attempts: BootAttempts,
+    #[serde(default)]
+    pub update_signal_mode: UpdateSignalMode,
+    #[serde(default)]
+    pub update_signal_backend: UpdateSignalBackend,
+    #[serde(default)]
+    pub default_update_plan: DefaultUpdatePlan,
+    #[serde(default)]
+    pub recovery_button: RecoveryButton,
+    #[serde(default)]
+    pub recovery_timeout: RecoveryTimeout,
 }
 
 /// Feature that governs whether loadstone will relay boot information
@@ -60,6 +85,11 @@ pub enum Serial {
         /// If enabled, loadstone will offer the option to recover a device
         /// with no bootable image via serial.
         recovery_enabled: bool,
+        /// If enabled, recovery writes received images straight into their target
+        /// bank when there's no scratch bank or spare MCU bank to stage and verify
+        /// them in first, so a failed transfer destroys the image in the target bank.
+        #[serde(default)]
+        in_place_recovery: bool,
         /// Hardware pin for serial transmission (from loadstone's perspective).
         tx_pin: PeripheralPin,
         /// Hardware pin for serial reception (from loadstone's perspective).
@@ -84,6 +114,37 @@ impl Serial {
     pub fn enabled(&self) -> bool { matches!(self, Serial::Enabled { .. }) }
 }
 
+/// Recovery button feature. If enabled, holding the button at reset makes Loadstone go
+/// straight to serial recovery mode, even if the current image is valid. Requires serial
+/// recovery to be enabled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecoveryButton {
+    Disabled,
+    Enabled {
+        /// Input pin the button is connected to.
+        pin: InputPin,
+        /// Whether the button pulls the pin low when held (the pin is pulled up
+        /// otherwise). If false, the button pulls the pin high (and it's pulled down).
+        active_low: bool,
+    },
+}
+
+impl Default for RecoveryButton {
+    fn default() -> Self { RecoveryButton::Disabled }
+}
+
+impl RecoveryButton {
+    /// Whether a port is capable of reading a recovery button.
+    pub fn supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F412 => true,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { matches!(self, RecoveryButton::Enabled { .. }) }
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum UpdateSignal {
     Disabled,
@@ -93,3 +154,279 @@ pub enum UpdateSignal {
 impl Default for UpdateSignal {
     fn default() -> Self { UpdateSignal::Disabled }
 }
+
+/// How long an update plan set through the update signal lasts. Persistent plans steer
+/// every boot until the application changes them. One-shot plans are cleared once
+/// Loadstone acts on them, so later boots allow updates from any bank.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum UpdateSignalMode {
+    Persistent,
+    OneShot,
+}
+
+impl Default for UpdateSignalMode {
+    fn default() -> Self { UpdateSignalMode::Persistent }
+}
+
+impl UpdateSignalMode {
+    pub fn one_shot(&self) -> bool { matches!(self, UpdateSignalMode::OneShot) }
+}
+
+/// Where the update signal is kept between the application setting a plan and
+/// Loadstone reading it on the next boot.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum UpdateSignalBackend {
+    /// Registers in the backup domain, which survive resets while it's powered.
+    BackupRegisters,
+    /// A word of RAM reserved in the linker script and never initialised, protected
+    /// by a magic value and checksum. It survives resets, but not power loss.
+    NoInitRam,
+    /// A dedicated sector of MCU flash, which survives power loss too.
+    FlashSector,
+}
+
+impl Default for UpdateSignalBackend {
+    fn default() -> Self { UpdateSignalBackend::BackupRegisters }
+}
+
+impl UpdateSignalBackend {
+    /// Whether a port is capable of keeping the update signal in this backend.
+    pub fn supported(&self, port: &Port) -> bool {
+        match self {
+            UpdateSignalBackend::BackupRegisters => matches!(port, Port::Stm32F412),
+            UpdateSignalBackend::NoInitRam | UpdateSignalBackend::FlashSector => true,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpdateSignalBackend::BackupRegisters => "Backup registers",
+            UpdateSignalBackend::NoInitRam => "No-init RAM",
+            UpdateSignalBackend::FlashSector => "Flash sector",
+        }
+    }
+}
+
+/// Plan Loadstone follows when the update signal holds no valid plan, as after a cold
+/// start, once a one-shot plan is cleared, or if the signal is corrupted.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DefaultUpdatePlan {
+    /// Allow updates from any bank.
+    Any,
+    /// Refuse updates.
+    None,
+}
+
+impl Default for DefaultUpdatePlan {
+    fn default() -> Self { DefaultUpdatePlan::Any }
+}
+
+impl DefaultUpdatePlan {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DefaultUpdatePlan::Any => "Allow updates",
+            DefaultUpdatePlan::None => "Refuse updates",
+        }
+    }
+}
+
+/// Anti-rollback protection feature. If enabled, Loadstone keeps a monotonic
+/// security counter in a reserved region of MCU flash, and refuses to boot
+/// non-golden images with a security epoch lower than it.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum AntiRollback {
+    Disabled,
+    Enabled,
+}
+
+impl Default for AntiRollback {
+    fn default() -> Self { AntiRollback::Disabled }
+}
+
+impl AntiRollback {
+    pub fn enabled(&self) -> bool { matches!(self, AntiRollback::Enabled) }
+}
+
+/// How updates are installed in the bootable bank. By default, the update image
+/// overwrites the current one. In swap mode, the bootable bank and the update bank
+/// are swapped through a scratch region of MCU flash, so the previous image is kept
+/// in the bank the update came from.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum UpdateMode {
+    Replace,
+    Swap,
+}
+
+impl Default for UpdateMode {
+    fn default() -> Self { UpdateMode::Replace }
+}
+
+impl UpdateMode {
+    pub fn swaps(&self) -> bool { matches!(self, UpdateMode::Swap) }
+}
+
+/// Trial boot feature. If enabled, updated images are on trial until the application
+/// confirms them. Images that aren't confirmed within `boots` boots are replaced
+/// with the previous image (if kept by swap updates) or the golden image. The trial
+/// state is kept in the reserved storage region of MCU flash.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum TrialBoot {
+    Disabled,
+    Enabled { boots: u32 },
+}
+
+/// Maximum number of boots an updated image can be given to be confirmed.
+pub const MAX_TRIAL_BOOTS: u32 = 16;
+
+impl Default for TrialBoot {
+    fn default() -> Self { TrialBoot::Disabled }
+}
+
+impl TrialBoot {
+    pub fn enabled(&self) -> bool { matches!(self, TrialBoot::Enabled { .. }) }
+
+    /// Number of boots an updated image is given to be confirmed, if enabled.
+    pub fn boots(&self) -> Option<u32> {
+        match self {
+            TrialBoot::Enabled { boots } => Some(*boots),
+            TrialBoot::Disabled => None,
+        }
+    }
+}
+
+/// Copy journal feature. If enabled, Loadstone records the progress of every copy
+/// between banks in the reserved storage region of MCU flash, so copies and swaps
+/// interrupted by a reset are resumed (or rolled back) on the next boot.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum CopyJournal {
+    Disabled,
+    Enabled,
+}
+
+impl Default for CopyJournal {
+    fn default() -> Self { CopyJournal::Disabled }
+}
+
+impl CopyJournal {
+    pub fn enabled(&self) -> bool { matches!(self, CopyJournal::Enabled) }
+}
+
+/// Hardware watchdog feature. Loadstone always feeds the watchdog during long
+/// operations (copying, verifying and receiving images), in case it's started by
+/// hardware. If enabled, Loadstone also starts the watchdog with a timeout of
+/// `timeout_ms` milliseconds, and leaves it running for the application to feed.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Watchdog {
+    Disabled,
+    Enabled { timeout_ms: u32 },
+}
+
+/// Shortest watchdog timeout, which must leave time for the longest uninterrupted
+/// operation (erasing a flash sector).
+pub const MIN_WATCHDOG_TIMEOUT_MS: u32 = 5_000;
+/// Longest watchdog timeout supported by the stm32f412 independent watchdog.
+pub const MAX_WATCHDOG_TIMEOUT_MS: u32 = 32_000;
+
+impl Default for Watchdog {
+    fn default() -> Self { Watchdog::Disabled }
+}
+
+impl Watchdog {
+    /// Whether a port is capable of supporting a hardware watchdog.
+    pub fn supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F412 => true,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { matches!(self, Watchdog::Enabled { .. }) }
+
+    /// Watchdog timeout in milliseconds, if enabled.
+    pub fn timeout_ms(&self) -> Option<u32> {
+        match self {
+            Watchdog::Enabled { timeout_ms } => Some(*timeout_ms),
+            Watchdog::Disabled => None,
+        }
+    }
+}
+
+/// Boot attempt counter feature. If enabled, Loadstone counts boots in the reserved
+/// storage region of MCU flash until the application resets the counter, reporting
+/// itself healthy. Once `attempts` boots go unreported, the golden image is restored
+/// and booted instead of the current image.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum BootAttempts {
+    Disabled,
+    Enabled { attempts: u32 },
+}
+
+/// Maximum number of boot attempts the application can be given to report itself healthy.
+pub const MAX_BOOT_ATTEMPTS: u32 = 16;
+
+impl Default for BootAttempts {
+    fn default() -> Self { BootAttempts::Disabled }
+}
+
+impl BootAttempts {
+    pub fn enabled(&self) -> bool { matches!(self, BootAttempts::Enabled { .. }) }
+
+    /// Number of boot attempts allowed before falling back to the golden image, if enabled.
+    pub fn attempts(&self) -> Option<u32> {
+        match self {
+            BootAttempts::Enabled { attempts } => Some(*attempts),
+            BootAttempts::Disabled => None,
+        }
+    }
+}
+
+/// Recovery timeout feature. If enabled, serial recovery gives up when no image sender
+/// starts a transfer within `window_s` seconds, and Loadstone either reboots or retries
+/// restoring an image. Timeouts are counted in the reserved storage region of MCU flash,
+/// and after `allowed_timeouts` in a row, recovery waits for an image indefinitely.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum RecoveryTimeout {
+    Disabled,
+    Enabled { window_s: u32, action: RecoveryTimeoutAction, allowed_timeouts: u32 },
+}
+
+/// What Loadstone does when the recovery window elapses.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RecoveryTimeoutAction {
+    /// Reboot, going through the normal boot process again.
+    Reboot,
+    /// Retry restoring an image from the other banks, then boot it.
+    RetryRestore,
+}
+
+/// Shortest recovery window, which must leave time to start an XMODEM sender.
+pub const MIN_RECOVERY_WINDOW_S: u32 = 10;
+/// Longest recovery window.
+pub const MAX_RECOVERY_WINDOW_S: u32 = 3_600;
+/// Maximum number of consecutive recovery timeouts before recovery becomes permanent.
+pub const MAX_RECOVERY_TIMEOUTS: u32 = 16;
+
+impl Default for RecoveryTimeout {
+    fn default() -> Self { RecoveryTimeout::Disabled }
+}
+
+impl RecoveryTimeout {
+    /// Whether a port is capable of timing out serial recovery.
+    pub fn supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F412 => true,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { matches!(self, RecoveryTimeout::Enabled { .. }) }
+}
+
+impl RecoveryTimeoutAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecoveryTimeoutAction::Reboot => "Reboot",
+            RecoveryTimeoutAction::RetryRestore => "Retry restore",
+        }
+    }
+}
@@ -11,10 +11,13 @@
 
 use std::{array::IntoIter, fmt::Display};
 
-use features::{BootMetrics, FeatureConfiguration, Serial};
-use memory::{external_flash, MemoryConfiguration};
+use features::{
+    BootMetrics, FeatureConfiguration, RecoveryButton, RecoveryTimeout, Serial, UpdateSignal,
+    UpdateSignalBackend,
+};
+use memory::{external_flash, Bank, MemoryConfiguration};
 use port::Port;
-use security::{SecurityConfiguration, SecurityMode};
+use security::SecurityConfiguration;
 use serde::{Deserialize, Serialize};
 
 pub mod port;
@@ -39,6 +42,10 @@ pub struct Configuration {
     pub feature_configuration: FeatureConfiguration,
     /// Image authenticity, integrity and (potentially) secrecy options (ECDSA, CRC, etc).
     pub security_configuration: SecurityConfiguration,
+    /// Identifier of the board Loadstone is built for. If present, images declaring
+    /// a different hardware ID are rejected.
+    #[serde(default)]
+    pub hardware_id: Option<u32>,
 }
 
 impl Configuration {
@@ -54,13 +61,65 @@ impl Configuration {
             Port::Wgm160P => flags.push("wgm160p"),
         };
 
-        if self.security_configuration.security_mode == SecurityMode::P256ECDSA {
-            flags.push("ecdsa-verify");
+        if let Some(flag) = self.security_configuration.security_mode.feature_flag() {
+            flags.push(flag);
+        };
+
+        if self.security_configuration.encryption_enabled() {
+            flags.push("encryption");
         };
 
         flags.into_iter()
     }
 
+    /// Region of MCU flash reserved for persistent Loadstone state, if any
+    /// enabled feature requires it.
+    pub fn storage_region(&self) -> Option<Bank> {
+        (self.feature_configuration.anti_rollback.enabled()
+            || self.feature_configuration.trial_boot.enabled()
+            || self.feature_configuration.copy_journal.enabled()
+            || self.feature_configuration.boot_attempts.enabled()
+            || self.feature_configuration.recovery_timeout.enabled()
+            || self.security_configuration.key_revocation)
+            .then(|| memory::storage_region(&self.port))
+    }
+
+    /// Region of MCU flash reserved as scratch space for swapping images, if updates
+    /// are configured to swap banks and there's a bootable bank to size it after.
+    pub fn scratch_region(&self) -> Option<Bank> {
+        if !self.feature_configuration.update_mode.swaps() {
+            return None;
+        }
+        let map = &self.memory_configuration.internal_memory_map;
+        let bootable_bank = map.banks.get(map.bootable_index?)?;
+        Some(memory::scratch_region(
+            &self.port,
+            bootable_bank.size_kb,
+            self.update_signal_region().or(self.storage_region()).as_ref(),
+        ))
+    }
+
+    /// Region of MCU flash reserved for the update signal, if enabled and kept in flash.
+    pub fn update_signal_region(&self) -> Option<Bank> {
+        (self.update_signal_backend() == Some(UpdateSignalBackend::FlashSector))
+            .then(|| memory::update_signal_region(&self.port, self.storage_region().as_ref()))
+    }
+
+    /// Address of the word of RAM reserved for the update signal, if enabled and kept
+    /// in RAM. It sits at the start of RAM, which the linker script then leaves out.
+    pub fn update_signal_ram_address(&self) -> Option<u32> {
+        if self.update_signal_backend() != Some(UpdateSignalBackend::NoInitRam) {
+            return None;
+        }
+        self.port.linker_script_constants().map(|constants| constants.ram.origin)
+    }
+
+    fn update_signal_backend(&self) -> Option<UpdateSignalBackend> {
+        let features = &self.feature_configuration;
+        matches!(features.update_signal, UpdateSignal::Enabled)
+            .then(|| features.update_signal_backend)
+    }
+
     /// Missing configuration steps to have enough information to generate a loadstone binary.
     pub fn required_configuration_steps(&self) -> impl Iterator<Item = RequiredConfigurationStep> {
         #[rustfmt::skip]
@@ -68,10 +127,14 @@ impl Configuration {
             self.memory_configuration.internal_memory_map.bootable_index.is_none()
                 .then_some(RequiredConfigurationStep::BootableBank),
 
-            (self.security_configuration.security_mode == SecurityMode::P256ECDSA
-                && self.security_configuration.verifying_key_raw.is_empty())
+            (self.security_configuration.security_mode.requires_key()
+                && self.security_configuration.key_ring().is_none())
                 .then_some(RequiredConfigurationStep::PublicKey),
 
+            (self.security_configuration.encryption_enabled()
+                && self.security_configuration.encryption_key().is_none())
+                .then_some(RequiredConfigurationStep::EncryptionKey),
+
         ])
         .flatten()
     }
@@ -84,6 +147,17 @@ impl Configuration {
             self.feature_configuration.serial = Serial::Disabled;
         }
 
+        let recovery_enabled = matches!(
+            self.feature_configuration.serial,
+            Serial::Enabled { recovery_enabled: true, .. }
+        );
+        if !RecoveryButton::supported(&self.port) || !recovery_enabled {
+            self.feature_configuration.recovery_button = RecoveryButton::Disabled;
+        }
+        if !RecoveryTimeout::supported(&self.port) || !recovery_enabled {
+            self.feature_configuration.recovery_timeout = RecoveryTimeout::Disabled;
+        }
+
         if !features::BootMetrics::timing_supported(&self.port) {
             if let BootMetrics::Enabled{timing} = &mut self.feature_configuration.boot_metrics {
                 *timing = false
@@ -95,6 +169,10 @@ impl Configuration {
             self.memory_configuration.external_flash = None;
         }
 
+        if !self.feature_configuration.update_signal_backend.supported(&self.port) {
+            self.feature_configuration.update_signal_backend = UpdateSignalBackend::NoInitRam;
+        }
+
         if self.memory_configuration.external_flash.is_none() {
             self.memory_configuration.external_memory_map.banks.clear();
         }
@@ -104,6 +182,7 @@ impl Configuration {
 /// Configuration steps that may be required to properly define a loadstone binary.
 pub enum RequiredConfigurationStep {
     PublicKey,
+    EncryptionKey,
     SerialTxPin,
     SerialRxPin,
     BootableBank,
@@ -113,7 +192,10 @@ impl Display for RequiredConfigurationStep {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
             RequiredConfigurationStep::PublicKey => {
-                "[Security] Provide P256 ECDSA public key or enable CRC32 mode"
+                "[Security] Provide valid public keys for the signature scheme or enable CRC32 mode"
+            }
+            RequiredConfigurationStep::EncryptionKey => {
+                "[Security] Provide a valid AES-128 key or disable image encryption"
             }
             RequiredConfigurationStep::SerialTxPin => "[Features] Define Serial Tx pin",
             RequiredConfigurationStep::SerialRxPin => "[Features] Define Serial Rx pin",
@@ -26,13 +26,24 @@ impl Bank {
 }
 
 /// Memory map for an internal (MCU) flash. This must contain the loadstone bootloader itself
-/// and a bootable bank.
+/// and a bootable bank. Additional bootable banks, following the main one, hold images
+/// linked to execute in place from them.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InternalMemoryMap {
     pub bootloader_location: u32,
     pub bootloader_length_kb: u32,
     pub banks: Vec<Bank>,
+    /// Main bootable bank, which updates and restored images are copied to.
     pub bootable_index: Option<usize>,
+    #[serde(default)]
+    pub additional_bootable_indices: Vec<usize>,
+}
+
+impl InternalMemoryMap {
+    /// Indices of all bootable banks, starting with the main one.
+    pub fn bootable_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bootable_index.into_iter().chain(self.additional_bootable_indices.iter().cloned())
+    }
 }
 
 /// Memory map for an optional external flash chip. This cannot contain a bootable
@@ -49,6 +60,7 @@ impl Default for InternalMemoryMap {
             bootloader_length_kb: 64,
             banks: Vec::new(),
             bootable_index: None,
+            additional_bootable_indices: Vec::new(),
         }
     }
 }
@@ -75,6 +87,16 @@ impl MemoryConfiguration {
                 .start_address,
         )
     }
+
+    /// Start address of the bootable bank with a given index, if it's bootable.
+    pub fn bootable_address_of(&self, index: usize) -> Option<u32> {
+        let map = &self.internal_memory_map;
+        if map.bootable_indices().any(|i| i == index) {
+            map.banks.get(index).map(|b| b.start_address)
+        } else {
+            None
+        }
+    }
 }
 
 /// Definition of a flash chip's hardware.
@@ -113,6 +135,90 @@ pub fn internal_flash(port: &Port) -> FlashChip {
     }
 }
 
+/// Erase sectors of the MCU flash available for a port, in ascending address order.
+/// Erasing any address within a sector erases all of it, so regions of flash that are
+/// erased independently of each other must never share a sector.
+pub fn internal_sectors(port: &Port) -> Vec<Bank> {
+    match port {
+        Port::Stm32F412 => {
+            let sizes_kb = [16, 16, 16, 16, 64, 128, 128, 128, 128, 128, 128, 128];
+            let mut start_address = internal_flash(port).start;
+            sizes_kb
+                .iter()
+                .map(|&size_kb| {
+                    let sector = Bank { start_address, size_kb };
+                    start_address = sector.end_address();
+                    sector
+                })
+                .collect()
+        }
+        Port::Wgm160P => (0..512).map(|i| Bank { start_address: i * KB!(4), size_kb: 4 }).collect(),
+    }
+}
+
+/// Smallest run of whole erase sectors of MCU flash that ends at `end` (which must be
+/// a sector boundary) and is at least `size_kb` in size.
+fn sectors_below(port: &Port, end: u32, size_kb: u32) -> Bank {
+    let sectors = internal_sectors(port);
+    assert!(
+        sectors.iter().any(|s| s.end_address() == end),
+        "0x{:x} is not the end of an MCU flash sector",
+        end
+    );
+    let mut start_address = end;
+    for sector in sectors.iter().rev().skip_while(|s| s.end_address() > end) {
+        if end - start_address >= KB!(size_kb) {
+            break;
+        }
+        start_address = sector.start_address;
+    }
+    assert!(end - start_address >= KB!(size_kb), "Not enough MCU flash for a reserved region");
+    Bank { start_address, size_kb: (end - start_address) / KB!(1) }
+}
+
+/// Region of MCU flash reserved for persistent Loadstone state (such as the anti-rollback
+/// security counter), for the features that require it. It spans the last two erase
+/// sectors of MCU flash, split as described in [`storage_sectors`], so no bank may
+/// extend into it.
+pub fn storage_region(port: &Port) -> Bank {
+    let (clearable, permanent) = storage_sectors(port);
+    Bank { start_address: clearable.start_address, size_kb: clearable.size_kb + permanent.size_kb }
+}
+
+/// The two halves of the storage region. The last sector of MCU flash holds the logs
+/// that are never erased (security counter and revoked keys), and the sector right
+/// below it those that are cleared when they run out of room, so clearing them can
+/// never erase the former.
+pub fn storage_sectors(port: &Port) -> (Bank, Bank) {
+    let permanent = sectors_below(port, internal_flash(port).end, 1);
+    let clearable = sectors_below(port, permanent.start_address, 1);
+    (clearable, permanent)
+}
+
+/// Region of MCU flash reserved for the update signal, when kept in flash. It takes a
+/// whole erase sector right below the storage region if there is one, or at the end of
+/// MCU flash otherwise, so changing the plan never erases anything else and no bank may
+/// extend into it.
+pub fn update_signal_region(port: &Port, storage_region: Option<&Bank>) -> Bank {
+    let end = storage_region.map_or(internal_flash(port).end, |region| region.start_address);
+    sectors_below(port, end, 1)
+}
+
+/// Size in bytes of the region at the start of RAM reserved for the update signal, when
+/// kept in RAM. It fits a record (magic value, plan and checksum), and keeps the rest of
+/// RAM 8 byte aligned.
+pub const UPDATE_SIGNAL_RAM_SIZE: u32 = 16;
+
+/// Region of MCU flash used as scratch space when swapping images, made of as few
+/// whole erase sectors as can hold the contents of a bootable bank of `bootable_size_kb`.
+/// It sits right below the lowest reserved region (storage or update signal) if there is
+/// one, or at the end of MCU flash otherwise, so it never shares a sector with them and
+/// no bank may extend into it.
+pub fn scratch_region(port: &Port, bootable_size_kb: u32, reserved_region: Option<&Bank>) -> Bank {
+    let end = reserved_region.map_or(internal_flash(port).end, |region| region.start_address);
+    sectors_below(port, end, bootable_size_kb)
+}
+
 /// Returns an iterator over all the flash chips compatible with the current
 /// port (a driver exists for them).
 pub fn external_flash(port: &Port) -> impl Iterator<Item = FlashChip> {
@@ -36,6 +36,38 @@ impl Display for PeripheralPin {
     }
 }
 
+/// A pin configured as a raw digital input.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct InputPin {
+    /// Pin bank (the "C" in PC13).
+    pub bank: Bank,
+    /// Pin index (the "13" in PC13).
+    pub index: u32,
+}
+
+impl Display for InputPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "P{}{}", self.bank, self.index)
+    }
+}
+
+impl InputPin {
+    /// Whether this is the same physical pin as a peripheral pin.
+    pub fn conflicts_with(&self, pin: &PeripheralPin) -> bool {
+        self.bank == pin.bank && self.index == pin.index
+    }
+}
+
+/// Returns an iterator over the possible recovery button pins for this port.
+pub fn recovery_button(port: &Port) -> Box<dyn Iterator<Item = InputPin>> {
+    match port {
+        Port::Stm32F412 => Box::new(('a'..='h').flat_map(|bank| {
+            (0..16).map(move |index| InputPin { bank: Cow::from(bank.to_string()), index })
+        })),
+        Port::Wgm160P => Box::new(None.into_iter()),
+    }
+}
+
 /// Returns an iterator over the possible serial transmission pins for this port.
 pub fn serial_tx(port: &Port) -> Box<dyn Iterator<Item = PeripheralPin>> {
     match port {
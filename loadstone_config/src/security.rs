@@ -1,4 +1,7 @@
+use p256::ecdsa::VerifyingKey;
+use p384::{elliptic_curve::sec1::ToEncodedPoint, pkcs8::DecodePublicKey};
 use serde::{Deserialize, Serialize};
+use std::{array::IntoIter, str::FromStr};
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum SecurityMode {
@@ -9,6 +12,43 @@ pub enum SecurityMode {
     /// Enforces P256 ECDSA signature verification. This ensures integrity
     /// and authenticity, but not secrecy (image is not encrypted).
     P256ECDSA,
+    /// Enforces P384 ECDSA signature verification (SHA-384 digest). Like
+    /// P256 ECDSA, but with a larger security margin and signature.
+    P384ECDSA,
+    /// Enforces Ed25519ph signature verification (SHA-512 digest). Offers
+    /// P256-equivalent security with faster verification.
+    Ed25519,
+}
+
+impl SecurityMode {
+    /// Loadstone feature flag that selects the image reader for this mode, if any.
+    pub fn feature_flag(&self) -> Option<&'static str> {
+        match self {
+            SecurityMode::Crc => None,
+            SecurityMode::P256ECDSA => Some("ecdsa-verify"),
+            SecurityMode::P384ECDSA => Some("p384-verify"),
+            SecurityMode::Ed25519 => Some("ed25519-verify"),
+        }
+    }
+
+    /// Feature flags for all security modes.
+    pub fn all_feature_flags() -> impl Iterator<Item = &'static str> {
+        IntoIter::new([SecurityMode::P256ECDSA, SecurityMode::P384ECDSA, SecurityMode::Ed25519])
+            .filter_map(|mode| mode.feature_flag())
+    }
+
+    /// Whether this mode requires a verifying public key.
+    pub fn requires_key(&self) -> bool { self.feature_flag().is_some() }
+
+    /// Human readable name of the signature scheme.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecurityMode::Crc => "CRC32",
+            SecurityMode::P256ECDSA => "P256 ECDSA",
+            SecurityMode::P384ECDSA => "P384 ECDSA",
+            SecurityMode::Ed25519 => "Ed25519",
+        }
+    }
 }
 
 impl Default for SecurityMode {
@@ -20,6 +60,97 @@ impl Default for SecurityMode {
 #[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct SecurityConfiguration {
     pub security_mode: SecurityMode,
+    /// String format (PEM) of the primary verifying public key (key ID zero).
+    /// Its type must match the security mode.
+    pub verifying_key_raw: String,
+    /// Further verifying keys Loadstone trusts, each with a unique, non-zero
+    /// key ID. Images name the key they're signed with in their TLV trailer.
+    #[serde(default)]
+    pub additional_verifying_keys: Vec<VerifyingKeyEntry>,
+    /// Whether verifying keys can be revoked at runtime. Revoked key IDs are
+    /// kept in a reserved region of MCU flash.
+    #[serde(default)]
+    pub key_revocation: bool,
+    /// Hex encoded AES-128 key used to decrypt encrypted images. If absent,
+    /// image encryption is not supported.
+    pub encryption_key_raw: Option<String>,
+}
+
+/// Verifying key with an explicit key ID, part of the key ring.
+#[derive(Default, Clone, Serialize, Deserialize, Debug)]
+pub struct VerifyingKeyEntry {
+    pub id: u8,
     /// String format (PEM) of the verifying public key.
     pub verifying_key_raw: String,
 }
+
+/// Size in bytes of the AES-128 image encryption key.
+pub const ENCRYPTION_KEY_SIZE: usize = 16;
+
+/// DER prefix of an Ed25519 public key in SubjectPublicKeyInfo format,
+/// preceding the 32 raw key bytes.
+const ED25519_PUBLIC_KEY_PREFIX: [u8; 12] =
+    [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// Parses a verifying key into the raw format embedded in Loadstone: SEC1
+/// (uncompressed) points for ECDSA modes, and the 32 key bytes for Ed25519.
+/// Returns `None` if the key is invalid or doesn't match the security mode.
+pub fn parse_verifying_key(security_mode: SecurityMode, raw: &str) -> Option<Vec<u8>> {
+    match security_mode {
+        SecurityMode::Crc => None,
+        SecurityMode::P256ECDSA => VerifyingKey::from_str(raw)
+            .ok()
+            .map(|key| key.to_encoded_point(false).as_bytes().to_vec()),
+        SecurityMode::P384ECDSA => p384::PublicKey::from_public_key_pem(raw.trim())
+            .ok()
+            .map(|key| key.to_encoded_point(false).as_bytes().to_vec()),
+        SecurityMode::Ed25519 => {
+            let body: String = raw.lines().filter(|l| !l.starts_with("-----")).collect();
+            let der = base64::decode(body.trim()).ok()?;
+            let key = der.strip_prefix(&ED25519_PUBLIC_KEY_PREFIX[..])?;
+            (key.len() == 32).then(|| key.to_vec())
+        }
+    }
+}
+
+impl SecurityConfiguration {
+    /// Parses the primary verifying key into the raw format embedded in Loadstone.
+    /// Returns `None` if the key is missing, invalid, or doesn't match the security mode.
+    pub fn verifying_key(&self) -> Option<Vec<u8>> {
+        parse_verifying_key(self.security_mode, &self.verifying_key_raw)
+    }
+
+    /// Serializes all verifying keys into the key ring embedded in Loadstone: a
+    /// sequence of records, each a key ID byte followed by the raw key. Returns
+    /// `None` if any key is invalid, or key IDs are not unique.
+    pub fn key_ring(&self) -> Option<Vec<u8>> {
+        let mut ring = vec![0u8];
+        ring.extend(self.verifying_key()?);
+        for (index, entry) in self.additional_verifying_keys.iter().enumerate() {
+            let id_taken = entry.id == 0
+                || self.additional_verifying_keys[..index].iter().any(|e| e.id == entry.id);
+            if id_taken {
+                return None;
+            }
+            ring.push(entry.id);
+            ring.extend(parse_verifying_key(self.security_mode, &entry.verifying_key_raw)?);
+        }
+        Some(ring)
+    }
+
+    /// Whether image encryption is enabled.
+    pub fn encryption_enabled(&self) -> bool { self.encryption_key_raw.is_some() }
+
+    /// Parses the image encryption key, if enabled and valid.
+    pub fn encryption_key(&self) -> Option<[u8; ENCRYPTION_KEY_SIZE]> {
+        let raw = self.encryption_key_raw.as_ref()?.trim();
+        if raw.len() != 2 * ENCRYPTION_KEY_SIZE || !raw.is_ascii() {
+            return None;
+        }
+        let mut key = [0u8; ENCRYPTION_KEY_SIZE];
+        for (byte, pair) in key.iter_mut().zip(raw.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(key)
+    }
+}
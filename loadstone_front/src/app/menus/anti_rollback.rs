@@ -0,0 +1,19 @@
+use eframe::egui;
+use loadstone_config::features::AntiRollback;
+
+pub fn configure_anti_rollback(ui: &mut egui::Ui, anti_rollback: &mut AntiRollback) {
+    let mut enabled = anti_rollback.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut enabled, "Anti-Rollback");
+        ui.label(
+            "Refuse to boot images older than the current security epoch. \
+            Reserves the last region of MCU flash for persistent storage.",
+        );
+        if enabled {
+            *anti_rollback = AntiRollback::Enabled;
+        } else {
+            *anti_rollback = AntiRollback::Disabled;
+        }
+    });
+}
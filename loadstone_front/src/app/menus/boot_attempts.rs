@@ -0,0 +1,34 @@
+use eframe::egui::{self, Slider};
+use loadstone_config::features::{BootAttempts, MAX_BOOT_ATTEMPTS};
+
+/// Default number of boots the application is given to report itself healthy.
+const DEFAULT_BOOT_ATTEMPTS: u32 = 3;
+
+pub fn configure_boot_attempts(ui: &mut egui::Ui, boot_attempts: &mut BootAttempts) {
+    let mut enabled = boot_attempts.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut enabled, "Boot Attempt Counter");
+        ui.label(
+            "Fall back to the golden image when the application doesn't report itself \
+            healthy within a number of boots. Reserves the last region of MCU flash for \
+            persistent storage.",
+        );
+        match (enabled, &boot_attempts) {
+            (true, BootAttempts::Disabled) => {
+                *boot_attempts = BootAttempts::Enabled { attempts: DEFAULT_BOOT_ATTEMPTS }
+            }
+            (false, BootAttempts::Enabled { .. }) => *boot_attempts = BootAttempts::Disabled,
+            _ => {}
+        }
+    });
+    if let BootAttempts::Enabled { attempts } = boot_attempts {
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(
+                Slider::new(attempts, 1..=MAX_BOOT_ATTEMPTS).clamp_to_range(true).suffix(" boots"),
+            );
+            ui.label("Boots allowed before the application must report itself healthy.");
+        });
+    }
+}
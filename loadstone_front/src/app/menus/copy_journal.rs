@@ -0,0 +1,20 @@
+use eframe::egui;
+use loadstone_config::features::CopyJournal;
+
+pub fn configure_copy_journal(ui: &mut egui::Ui, copy_journal: &mut CopyJournal) {
+    let mut enabled = copy_journal.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut enabled, "Copy Journal");
+        ui.label(
+            "Resume copies between banks interrupted by a reset, instead of leaving the \
+            bootable bank half written. Reserves the last region of MCU flash for \
+            persistent storage.",
+        );
+        if enabled {
+            *copy_journal = CopyJournal::Enabled;
+        } else {
+            *copy_journal = CopyJournal::Disabled;
+        }
+    });
+}
@@ -0,0 +1,37 @@
+use eframe::egui::{self, Color32};
+
+/// Renders the menu to configure the hardware ID Loadstone checks images against.
+pub fn configure_hardware_id(
+    ui: &mut egui::Ui,
+    hardware_id: &mut Option<u32>,
+    hardware_id_text_field: &mut String,
+) {
+    ui.horizontal_wrapped(|ui| {
+        ui.label("Hardware ID");
+        if ui.text_edit_singleline(hardware_id_text_field).lost_focus() {
+            *hardware_id = parse_hardware_id(hardware_id_text_field);
+        }
+        ui.label(
+            "Refuse to boot, update from or restore images built for different hardware \
+            (decimal, or hexadecimal prefixed with 0x). Leave empty to accept any image.",
+        );
+    });
+
+    match hardware_id {
+        Some(id) => {
+            ui.colored_label(Color32::GREEN, format!("\u{2714} Hardware ID {:#X}", id));
+        }
+        None if !hardware_id_text_field.trim().is_empty() => {
+            ui.colored_label(Color32::RED, "\u{26A0} Invalid hardware ID");
+        }
+        None => (),
+    }
+}
+
+fn parse_hardware_id(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
@@ -13,21 +13,31 @@ static BOOTLOADER_MAX_LENGTH_KB: u32 = 128;
 static GOLDEN_TOOLTIP: &'static str =
     "Mark this bank as golden (used as a fallback in case of corruption)\r\n \
     Only one non-bootable bank may be golden, and only golden banks can store golden images.";
+static IN_PLACE_TOOLTIP: &'static str =
+    "Mark this bank as an additional bootable bank, following the main one.\r\n \
+    Images linked for it are booted in place, without copying them to the main bootable bank.";
 
 mod normalize;
 
 /// Renders the menu to configure the entire memory map, consisting of a mandatory internal
 /// flash (and its bank distribution, which must contain a bootable bank) and an optional
-/// external flash.
+/// external flash. If storage, scratch or update signal regions are reserved, internal banks
+/// can't extend into them.
 pub fn configure_memory_map(
     ui: &mut egui::Ui,
     internal_memory_map: &mut InternalMemoryMap,
     external_memory_map: &mut ExternalMemoryMap,
     external_flash: &mut Option<FlashChip>,
     golden_index: &mut Option<usize>,
+    storage_region: Option<&Bank>,
+    scratch_region: Option<&Bank>,
+    update_signal_region: Option<&Bank>,
     port: &Port,
 ) {
-    let internal_flash = memory::internal_flash(port);
+    let mut internal_flash = memory::internal_flash(port);
+    if let Some(region) = scratch_region.or(update_signal_region).or(storage_region) {
+        internal_flash.end = region.start_address;
+    }
 
     normalize(
         internal_memory_map,
@@ -53,6 +63,30 @@ pub fn configure_memory_map(
         ui.label("Banks:");
         ui.separator();
         configure_internal_banks(ui, internal_memory_map, &internal_flash, golden_index);
+        if let Some(region) = scratch_region {
+            ui.separator();
+            ui.label(format!(
+                "Scratch (reserved): 0x{:x} - 0x{:x}",
+                region.start_address,
+                region.end_address()
+            ));
+        }
+        if let Some(region) = update_signal_region {
+            ui.separator();
+            ui.label(format!(
+                "Update signal (reserved): 0x{:x} - 0x{:x}",
+                region.start_address,
+                region.end_address()
+            ));
+        }
+        if let Some(region) = storage_region {
+            ui.separator();
+            ui.label(format!(
+                "Storage (reserved): 0x{:x} - 0x{:x}",
+                region.start_address,
+                region.end_address()
+            ));
+        }
     });
 
     ui.separator();
@@ -95,7 +129,8 @@ fn configure_internal_banks(
     internal_flash: &memory::FlashChip,
     golden_index: &mut Option<usize>,
 ) {
-    let InternalMemoryMap { banks, bootable_index, .. } = internal_memory_map;
+    let InternalMemoryMap { banks, bootable_index, additional_bootable_indices, .. } =
+        internal_memory_map;
     let mut to_delete: Option<usize> = None;
     for (i, bank) in banks.iter_mut().enumerate() {
         configure_internal_bank(
@@ -103,6 +138,7 @@ fn configure_internal_banks(
             bank,
             internal_flash,
             bootable_index,
+            additional_bootable_indices,
             i,
             golden_index,
             &mut to_delete,
@@ -111,6 +147,10 @@ fn configure_internal_banks(
 
     if let Some(to_delete) = to_delete {
         banks.remove(to_delete);
+        additional_bootable_indices.retain(|&index| index != to_delete);
+        for index in additional_bootable_indices.iter_mut().filter(|index| **index > to_delete) {
+            *index = *index - 1;
+        }
     }
 
     let bank_start_address =
@@ -161,6 +201,7 @@ fn configure_internal_bank(
     bank: &mut Bank,
     internal_flash: &FlashChip,
     bootable_index: &mut Option<usize>,
+    additional_bootable_indices: &mut Vec<usize>,
     i: usize,
     golden_index: &mut Option<usize>,
     to_delete: &mut Option<usize>,
@@ -180,8 +221,19 @@ fn configure_internal_bank(
                 .text_color(Color32::LIGHT_BLUE),
         );
         ui.radio_value(bootable_index, Some(i), "Bootable");
+        let mut in_place = additional_bootable_indices.contains(&i);
+        ui.scope(|ui| {
+            ui.set_enabled(bootable_index.map_or(false, |b| i > b) && *golden_index != Some(i));
+            if ui.checkbox(&mut in_place, "In place").on_hover_text(IN_PLACE_TOOLTIP).clicked() {
+                if in_place {
+                    additional_bootable_indices.push(i);
+                } else {
+                    additional_bootable_indices.retain(|&index| index != i);
+                }
+            }
+        });
         ui.scope(|ui| {
-            ui.set_enabled(*bootable_index != Some(i));
+            ui.set_enabled(*bootable_index != Some(i) && !in_place);
             if ui.radio(*golden_index == Some(i), "Golden").on_hover_text(GOLDEN_TOOLTIP).clicked()
             {
                 *golden_index = match *golden_index {
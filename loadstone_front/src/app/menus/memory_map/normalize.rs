@@ -18,6 +18,7 @@ pub fn normalize(
     port: &Port,
 ) {
     enforce_bootable_bank_not_golden(golden_index, internal_memory_map);
+    enforce_additional_bootable_banks_follow_main(golden_index, internal_memory_map);
     enforce_internal_banks_follow_bootloader(internal_memory_map, internal_flash);
     enforce_internal_banks_are_contiguous(internal_memory_map);
     enforce_internal_bank_ranges_are_maintained(internal_memory_map, internal_flash);
@@ -106,3 +107,19 @@ fn enforce_bootable_bank_not_golden(
         *golden_index = None;
     }
 }
+
+fn enforce_additional_bootable_banks_follow_main(
+    golden_index: &Option<usize>,
+    internal_memory_map: &mut InternalMemoryMap,
+) {
+    let bootable_index = internal_memory_map.bootable_index;
+    let number_of_banks = internal_memory_map.banks.len();
+    let additional_bootable_indices = &mut internal_memory_map.additional_bootable_indices;
+    additional_bootable_indices.retain(|&index| {
+        bootable_index.map_or(false, |b| index > b)
+            && index < number_of_banks
+            && Some(index) != *golden_index
+    });
+    additional_bootable_indices.sort_unstable();
+    additional_bootable_indices.dedup();
+}
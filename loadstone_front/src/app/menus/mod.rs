@@ -5,11 +5,20 @@ use loadstone_config::{
     port::Port,
 };
 
+pub mod anti_rollback;
+pub mod boot_attempts;
+pub mod copy_journal;
+pub mod hardware_id;
 pub mod memory_map;
 pub mod security;
 pub mod generate;
+pub mod recovery_button;
+pub mod recovery_timeout;
+pub mod update_mode;
 pub mod update_signal;
 pub mod serial;
+pub mod trial_boot;
+pub mod watchdog;
 
 /// Renders the dropdown menu to select one of the supported
 /// hardware ports.
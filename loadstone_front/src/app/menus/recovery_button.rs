@@ -0,0 +1,83 @@
+use eframe::egui;
+use itertools::Itertools;
+use loadstone_config::{
+    features::{RecoveryButton, Serial},
+    pins::{self, InputPin},
+    port::Port,
+};
+
+pub fn configure_recovery_button(
+    ui: &mut egui::Ui,
+    recovery_button: &mut RecoveryButton,
+    serial: &Serial,
+    port: &Port,
+) {
+    let recovery_enabled = matches!(serial, Serial::Enabled { recovery_enabled: true, .. });
+    let supported = RecoveryButton::supported(port) && recovery_enabled;
+    if !supported {
+        *recovery_button = RecoveryButton::Disabled;
+    }
+
+    // Pins already taken by the serial console can't double as the button.
+    let available_pins = pins::recovery_button(port)
+        .filter(|pin| match serial {
+            Serial::Enabled { tx_pin, rx_pin, .. } => {
+                !pin.conflicts_with(tx_pin) && !pin.conflicts_with(rx_pin)
+            }
+            Serial::Disabled => true,
+        })
+        .collect_vec();
+
+    let mut enabled = recovery_button.enabled();
+    ui.horizontal_wrapped(|ui| {
+        ui.set_enabled(supported && !available_pins.is_empty());
+        ui.checkbox(&mut enabled, "Recovery Button");
+        ui.label(
+            "Enter serial recovery mode straight away when a button is held at reset, \
+            even if a valid image is available. Requires serial recovery.",
+        );
+        match (enabled, &recovery_button) {
+            (true, RecoveryButton::Disabled) => {
+                *recovery_button = RecoveryButton::Enabled {
+                    pin: default_pin(&available_pins),
+                    active_low: true,
+                }
+            }
+            (false, RecoveryButton::Enabled { .. }) => *recovery_button = RecoveryButton::Disabled,
+            _ => {}
+        }
+    });
+
+    if let RecoveryButton::Enabled { pin, active_low } = recovery_button {
+        if !available_pins.contains(pin) {
+            *pin = default_pin(&available_pins);
+        }
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            egui::ComboBox::from_label("Recovery button pin")
+                .selected_text(pin.to_string())
+                .show_ui(ui, |ui| {
+                    for choice in available_pins.iter() {
+                        ui.selectable_value(pin, choice.clone(), choice.to_string());
+                    }
+                });
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.checkbox(active_low, "Active Low");
+            ui.label(
+                "The button pulls the pin low when held, and the pin is pulled up otherwise. \
+                If unchecked, the button pulls the pin high and the pin is pulled down.",
+            );
+        });
+    }
+}
+
+/// Defaults to the user button found on most development boards (PC13), if available.
+fn default_pin(available_pins: &[InputPin]) -> InputPin {
+    available_pins
+        .iter()
+        .find(|pin| pin.bank == "c" && pin.index == 13)
+        .unwrap_or(&available_pins[0])
+        .clone()
+}
@@ -0,0 +1,83 @@
+use eframe::egui::{self, Slider};
+use loadstone_config::{
+    features::{
+        RecoveryTimeout, RecoveryTimeoutAction, Serial, MAX_RECOVERY_TIMEOUTS,
+        MAX_RECOVERY_WINDOW_S, MIN_RECOVERY_WINDOW_S,
+    },
+    port::Port,
+};
+
+/// Default time given to an image sender to start a transfer during recovery.
+const DEFAULT_RECOVERY_WINDOW_S: u32 = 60;
+/// Default number of consecutive timeouts before recovery becomes permanent.
+const DEFAULT_RECOVERY_TIMEOUTS: u32 = 3;
+
+pub fn configure_recovery_timeout(
+    ui: &mut egui::Ui,
+    recovery_timeout: &mut RecoveryTimeout,
+    serial: &Serial,
+    port: &Port,
+) {
+    let recovery_enabled = matches!(serial, Serial::Enabled { recovery_enabled: true, .. });
+    let supported = RecoveryTimeout::supported(port) && recovery_enabled;
+    if !supported {
+        *recovery_timeout = RecoveryTimeout::Disabled;
+    }
+    let mut enabled = recovery_timeout.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.set_enabled(supported);
+        ui.checkbox(&mut enabled, "Recovery Timeout");
+        ui.label(
+            "Leave serial recovery when no image is sent within a time window. Repeated \
+            timeouts make recovery permanent. Requires serial recovery, and reserves the \
+            last region of MCU flash for persistent storage.",
+        );
+        match (enabled, &recovery_timeout) {
+            (true, RecoveryTimeout::Disabled) => {
+                *recovery_timeout = RecoveryTimeout::Enabled {
+                    window_s: DEFAULT_RECOVERY_WINDOW_S,
+                    action: RecoveryTimeoutAction::Reboot,
+                    allowed_timeouts: DEFAULT_RECOVERY_TIMEOUTS,
+                }
+            }
+            (false, RecoveryTimeout::Enabled { .. }) => {
+                *recovery_timeout = RecoveryTimeout::Disabled
+            }
+            _ => {}
+        }
+    });
+    if let RecoveryTimeout::Enabled { window_s, action, allowed_timeouts } = recovery_timeout {
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(
+                Slider::new(window_s, MIN_RECOVERY_WINDOW_S..=MAX_RECOVERY_WINDOW_S)
+                    .clamp_to_range(true)
+                    .suffix(" s"),
+            );
+            ui.label("Time allowed for an image sender to start the transfer.");
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            egui::ComboBox::from_label("Action on timeout").selected_text(action.name()).show_ui(
+                ui,
+                |ui| {
+                    for choice in
+                        [RecoveryTimeoutAction::Reboot, RecoveryTimeoutAction::RetryRestore].iter()
+                    {
+                        ui.selectable_value(action, *choice, choice.name());
+                    }
+                },
+            );
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(
+                Slider::new(allowed_timeouts, 1..=MAX_RECOVERY_TIMEOUTS)
+                    .clamp_to_range(true)
+                    .suffix(" timeouts"),
+            );
+            ui.label("Consecutive timeouts before recovery waits for an image indefinitely.");
+        });
+    }
+}
@@ -1,56 +1,150 @@
 use eframe::egui::{self, Button, Color32};
-use loadstone_config::security::SecurityMode;
-use p256::ecdsa::VerifyingKey;
-use std::str::FromStr;
+use loadstone_config::security::{
+    parse_verifying_key, SecurityConfiguration, SecurityMode, VerifyingKeyEntry,
+};
 
 /// Renders the menu to configure security options (at the moment,
-/// `CRC` and `ECDSA` image verification.
+/// `CRC`, `P256 ECDSA`, `P384 ECDSA` and `Ed25519` image verification,
+/// key rings with key revocation, and optional image encryption).
 pub fn configure_security(
     ui: &mut egui::Ui,
-    security_mode: &mut SecurityMode,
-    verifying_key_raw: &mut String,
+    security_configuration: &mut SecurityConfiguration,
     verifying_key_text_field: &mut String,
+    additional_key_text_field: &mut String,
 ) {
+    let previous_mode = security_configuration.security_mode;
+    let security_mode = &mut security_configuration.security_mode;
     ui.horizontal_wrapped(|ui| {
         ui.radio_value(security_mode, SecurityMode::P256ECDSA, "Enable P256 ECDSA mode.")
             .on_hover_text("Enable P256 ECDSA signature verification.");
+        ui.radio_value(security_mode, SecurityMode::P384ECDSA, "Enable P384 ECDSA mode.")
+            .on_hover_text("Enable P384 ECDSA signature verification.");
+        ui.radio_value(security_mode, SecurityMode::Ed25519, "Enable Ed25519 mode.")
+            .on_hover_text("Enable Ed25519 signature verification.");
         ui.radio_value(security_mode, SecurityMode::Crc, "Enable CRC32 mode.")
-            .on_hover_text("Disable ECDSA verification in favor of IEEE CRC32");
+            .on_hover_text("Disable signature verification in favor of IEEE CRC32");
     });
 
-    match security_mode {
-        SecurityMode::Crc => {
-            ui.colored_label(
-                Color32::YELLOW,
-                "WARNING: Disabling ECDSA Image Verification replaces cryptographic \
-                signatures with insecure CRC. This removes the guarantee of image authenticity.",
-            );
-        }
-        SecurityMode::P256ECDSA => {
-            ui.label("P256 ECDSA Public Key");
-
-            if !verifying_key_raw.is_empty() {
-                ui.horizontal_wrapped(|ui| {
-                    ui.colored_label(Color32::GREEN, "\u{1F5DD} Valid Key Supplied");
-                    if ui.add(Button::new("Delete").text_color(Color32::RED).small()).clicked() {
-                        verifying_key_raw.clear();
-                    };
-                });
-            } else {
-                if ui.text_edit_multiline(verifying_key_text_field).lost_focus() {
-                    // Preprocess the key to ensure spaces are maintained
-                    *verifying_key_text_field = verifying_key_text_field
-                        .replace("-----BEGIN PUBLIC KEY----- ", "-----BEGIN PUBLIC KEY-----\n")
-                        .replace(" -----END PUBLIC KEY-----", "\n-----END PUBLIC KEY-----");
-                    if VerifyingKey::from_str(&verifying_key_text_field).is_ok() {
-                        *verifying_key_raw = verifying_key_text_field.clone();
-                    } else {
-                        *verifying_key_text_field = String::new();
-                    }
-                }
+    // Keys are specific to a signature scheme, so they can't carry over.
+    if security_configuration.security_mode != previous_mode {
+        security_configuration.verifying_key_raw.clear();
+        security_configuration.additional_verifying_keys.clear();
+    }
+
+    if security_configuration.security_mode == SecurityMode::Crc {
+        ui.colored_label(
+            Color32::YELLOW,
+            "WARNING: Disabling Signature Verification replaces cryptographic \
+            signatures with insecure CRC. This removes the guarantee of image authenticity.",
+        );
+    } else {
+        ui.label(format!("{} Public Key", security_configuration.security_mode.name()));
 
-                ui.label("Please paste a valid public key in PEM format");
+        if !security_configuration.verifying_key_raw.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.colored_label(Color32::GREEN, "\u{1F5DD} Valid Key Supplied");
+                if ui.add(Button::new("Delete").text_color(Color32::RED).small()).clicked() {
+                    security_configuration.verifying_key_raw.clear();
+                };
+            });
+        } else {
+            if ui.text_edit_multiline(verifying_key_text_field).lost_focus() {
+                // Preprocess the key to ensure spaces are maintained
+                *verifying_key_text_field = verifying_key_text_field
+                    .replace("-----BEGIN PUBLIC KEY----- ", "-----BEGIN PUBLIC KEY-----\n")
+                    .replace(" -----END PUBLIC KEY-----", "\n-----END PUBLIC KEY-----");
+                security_configuration.verifying_key_raw = verifying_key_text_field.clone();
+                if security_configuration.verifying_key().is_none() {
+                    security_configuration.verifying_key_raw.clear();
+                    *verifying_key_text_field = String::new();
+                }
             }
+
+            ui.label("Please paste a valid public key in PEM format");
+        }
+
+        ui.separator();
+        configure_key_ring(ui, security_configuration, additional_key_text_field);
+    }
+
+    ui.separator();
+    configure_encryption(ui, security_configuration);
+}
+
+/// Renders the menu to add further verifying keys (identified by their key ID)
+/// and enable runtime key revocation.
+fn configure_key_ring(
+    ui: &mut egui::Ui,
+    security_configuration: &mut SecurityConfiguration,
+    additional_key_text_field: &mut String,
+) {
+    ui.label("Additional Public Keys");
+    let mut removed = None;
+    for (index, entry) in security_configuration.additional_verifying_keys.iter().enumerate() {
+        ui.horizontal_wrapped(|ui| {
+            ui.colored_label(Color32::GREEN, format!("\u{1F5DD} Key ID {}", entry.id));
+            if ui.add(Button::new("Delete").text_color(Color32::RED).small()).clicked() {
+                removed = Some(index);
+            };
+        });
+    }
+    if let Some(index) = removed {
+        security_configuration.additional_verifying_keys.remove(index);
+    }
+
+    if ui.text_edit_multiline(additional_key_text_field).lost_focus() {
+        // Preprocess the key to ensure spaces are maintained
+        let key = additional_key_text_field
+            .replace("-----BEGIN PUBLIC KEY----- ", "-----BEGIN PUBLIC KEY-----\n")
+            .replace(" -----END PUBLIC KEY-----", "\n-----END PUBLIC KEY-----");
+        let keys = &mut security_configuration.additional_verifying_keys;
+        let id = keys.iter().map(|e| e.id).max().unwrap_or(0).checked_add(1);
+        if let (Some(id), Some(_)) =
+            (id, parse_verifying_key(security_configuration.security_mode, &key))
+        {
+            keys.push(VerifyingKeyEntry { id, verifying_key_raw: key });
+        }
+        *additional_key_text_field = String::new();
+    }
+    ui.label("Paste a public key in PEM format to add it to the key ring with the next key ID");
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut security_configuration.key_revocation, "Key Revocation");
+        ui.label(
+            "Allow the application to revoke keys at runtime. \
+            Reserves the last region of MCU flash for persistent storage.",
+        );
+    });
+}
+
+/// Renders the menu to enable image encryption and supply the AES-128 key
+/// Loadstone uses to decrypt images.
+fn configure_encryption(ui: &mut egui::Ui, security_configuration: &mut SecurityConfiguration) {
+    let mut enabled = security_configuration.encryption_enabled();
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut enabled, "Image Encryption");
+        ui.label("Support AES-128-CTR encrypted images, decrypted when copied to the boot bank.");
+    });
+
+    let key = &mut security_configuration.encryption_key_raw;
+    match (enabled, key.is_some()) {
+        (false, true) => *key = None,
+        (true, false) => *key = Some(String::new()),
+        _ => {}
+    }
+
+    if let Some(key) = key {
+        ui.horizontal_wrapped(|ui| {
+            ui.text_edit_singleline(key);
+            ui.label("AES-128 key (32 hexadecimal characters).");
+        });
+    }
+
+    if enabled {
+        if security_configuration.encryption_key().is_some() {
+            ui.colored_label(Color32::GREEN, "\u{1F5DD} Valid Key Supplied");
+        } else {
+            ui.colored_label(Color32::YELLOW, "Please supply a valid AES-128 key");
         }
     }
 }
@@ -34,6 +34,7 @@ pub fn configure_serial(ui: &mut egui::Ui, serial: &mut Serial, port: &Port) {
             (true, Serial::Disabled) => {
                 *serial = Serial::Enabled {
                     recovery_enabled: false,
+                    in_place_recovery: false,
                     tx_pin: first_valid_tx_pin(),
                     rx_pin: first_valid_rx_pin(),
                 }
@@ -44,11 +45,12 @@ pub fn configure_serial(ui: &mut egui::Ui, serial: &mut Serial, port: &Port) {
 
         ui.label("Enable serial communications to retrieve information about the boot process.");
     });
-    if let Serial::Enabled { recovery_enabled, tx_pin, rx_pin } = serial {
+    if let Serial::Enabled { recovery_enabled, in_place_recovery, tx_pin, rx_pin } = serial {
         define_serial_options(
             ui,
             port,
             recovery_enabled,
+            in_place_recovery,
             tx_pin,
             rx_pin,
             available_peripherals.iter().cloned(),
@@ -60,6 +62,7 @@ fn define_serial_options(
     ui: &mut egui::Ui,
     port: &Port,
     recovery_enabled: &mut bool,
+    in_place_recovery: &mut bool,
     tx_pin: &mut PeripheralPin,
     rx_pin: &mut PeripheralPin,
     available_peripherals: impl Iterator<Item = Peripheral>,
@@ -68,7 +71,7 @@ fn define_serial_options(
         select_peripheral(ui, port, tx_pin, rx_pin, available_peripherals);
         select_tx_pins(ui, tx_pin, port);
         select_rx_pins(ui, rx_pin, port);
-        select_recovery_mode(ui, recovery_enabled, port);
+        select_recovery_mode(ui, recovery_enabled, in_place_recovery, port);
     });
 }
 
@@ -136,11 +139,28 @@ fn select_tx_pins(ui: &mut egui::Ui, tx_pin: &mut PeripheralPin, port: &Port) {
     });
 }
 
-fn select_recovery_mode(ui: &mut egui::Ui, recovery_enabled: &mut bool, port: &Port) {
+fn select_recovery_mode(
+    ui: &mut egui::Ui,
+    recovery_enabled: &mut bool,
+    in_place_recovery: &mut bool,
+    port: &Port,
+) {
     ui.horizontal_wrapped(|ui| {
         ui.set_enabled(features::Serial::supported(port));
         ui.separator();
         ui.checkbox(recovery_enabled, "Serial Recovery");
         ui.label("Allow recovering a device by sending a new image via XModem.");
     });
+    if !*recovery_enabled {
+        *in_place_recovery = false;
+    }
+    ui.horizontal_wrapped(|ui| {
+        ui.set_enabled(*recovery_enabled);
+        ui.separator();
+        ui.checkbox(in_place_recovery, "In-place Recovery");
+        ui.label(
+            "Without a scratch bank (swap updates), write received images straight into \
+            their target bank. A failed transfer destroys the image in that bank.",
+        );
+    });
 }
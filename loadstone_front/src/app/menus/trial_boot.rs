@@ -0,0 +1,31 @@
+use eframe::egui::{self, Slider};
+use loadstone_config::features::{TrialBoot, MAX_TRIAL_BOOTS};
+
+/// Default number of boots an updated image is given to be confirmed.
+const DEFAULT_TRIAL_BOOTS: u32 = 3;
+
+pub fn configure_trial_boot(ui: &mut egui::Ui, trial_boot: &mut TrialBoot) {
+    let mut enabled = trial_boot.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut enabled, "Trial Boot");
+        ui.label(
+            "Revert updated images the application doesn't confirm within a number of boots. \
+            Reserves the last region of MCU flash for persistent storage.",
+        );
+        match (enabled, &trial_boot) {
+            (true, TrialBoot::Disabled) => {
+                *trial_boot = TrialBoot::Enabled { boots: DEFAULT_TRIAL_BOOTS }
+            }
+            (false, TrialBoot::Enabled { .. }) => *trial_boot = TrialBoot::Disabled,
+            _ => {}
+        }
+    });
+    if let TrialBoot::Enabled { boots } = trial_boot {
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(Slider::new(boots, 1..=MAX_TRIAL_BOOTS).clamp_to_range(true).suffix(" boots"));
+            ui.label("Boots allowed before the image must be confirmed.");
+        });
+    }
+}
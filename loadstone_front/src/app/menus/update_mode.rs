@@ -0,0 +1,19 @@
+use eframe::egui;
+use loadstone_config::features::UpdateMode;
+
+pub fn configure_update_mode(ui: &mut egui::Ui, update_mode: &mut UpdateMode) {
+    let mut swap = update_mode.swaps();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut swap, "Swap Updates");
+        ui.label(
+            "Keep the previous image in the update bank after updating, by swapping banks. \
+            Reserves a region of MCU flash as large as the bootable bank for scratch space.",
+        );
+        if swap {
+            *update_mode = UpdateMode::Swap;
+        } else {
+            *update_mode = UpdateMode::Replace;
+        }
+    });
+}
@@ -1,7 +1,17 @@
 use eframe::egui;
-use loadstone_config::features::UpdateSignal;
+use loadstone_config::{
+    features::{DefaultUpdatePlan, UpdateSignal, UpdateSignalBackend, UpdateSignalMode},
+    port::Port,
+};
 
-pub fn configure_update_signal(ui: &mut egui::Ui, update_signal: &mut UpdateSignal) {
+pub fn configure_update_signal(
+    ui: &mut egui::Ui,
+    update_signal: &mut UpdateSignal,
+    update_signal_mode: &mut UpdateSignalMode,
+    update_signal_backend: &mut UpdateSignalBackend,
+    default_update_plan: &mut DefaultUpdatePlan,
+    port: &Port,
+) {
     let mut enabled = matches!(update_signal, UpdateSignal::Enabled);
 
     ui.horizontal_wrapped(|ui| {
@@ -13,4 +23,52 @@ pub fn configure_update_signal(ui: &mut egui::Ui, update_signal: &mut UpdateSign
             *update_signal = UpdateSignal::Disabled;
         }
     });
+
+    if enabled {
+        let mut one_shot = update_signal_mode.one_shot();
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut one_shot, "One-shot");
+            ui.label("Clear the update plan once Loadstone acts on it.");
+            if one_shot {
+                *update_signal_mode = UpdateSignalMode::OneShot;
+            } else {
+                *update_signal_mode = UpdateSignalMode::Persistent;
+            }
+        });
+
+        if !update_signal_backend.supported(port) {
+            *update_signal_backend = UpdateSignalBackend::NoInitRam;
+        }
+        ui.horizontal_wrapped(|ui| {
+            egui::ComboBox::from_label("Update signal backend")
+                .selected_text(update_signal_backend.name())
+                .show_ui(ui, |ui| {
+                    for backend in [
+                        UpdateSignalBackend::BackupRegisters,
+                        UpdateSignalBackend::NoInitRam,
+                        UpdateSignalBackend::FlashSector,
+                    ]
+                    .iter()
+                    .filter(|b| b.supported(port))
+                    {
+                        ui.selectable_value(update_signal_backend, *backend, backend.name());
+                    }
+                });
+        });
+        ui.label(
+            "Backup registers and no-init RAM keep the plan across resets, but not power loss. \
+            A flash sector keeps it across power loss, reserving a region of MCU flash.",
+        );
+
+        ui.horizontal_wrapped(|ui| {
+            egui::ComboBox::from_label("Default update plan")
+                .selected_text(default_update_plan.name())
+                .show_ui(ui, |ui| {
+                    for plan in [DefaultUpdatePlan::Any, DefaultUpdatePlan::None].iter() {
+                        ui.selectable_value(default_update_plan, *plan, plan.name());
+                    }
+                });
+        });
+        ui.label("Plan followed when the update signal holds none, or holds a corrupted one.");
+    }
 }
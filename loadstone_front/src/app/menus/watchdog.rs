@@ -0,0 +1,43 @@
+use eframe::egui::{self, Slider};
+use loadstone_config::{
+    features::{Watchdog, MAX_WATCHDOG_TIMEOUT_MS, MIN_WATCHDOG_TIMEOUT_MS},
+    port::Port,
+};
+
+/// Default time the application is given to feed the watchdog.
+const DEFAULT_WATCHDOG_TIMEOUT_MS: u32 = 10_000;
+
+pub fn configure_watchdog(ui: &mut egui::Ui, watchdog: &mut Watchdog, port: &Port) {
+    if !Watchdog::supported(port) {
+        *watchdog = Watchdog::Disabled;
+    }
+    let mut enabled = watchdog.enabled();
+
+    ui.horizontal_wrapped(|ui| {
+        ui.set_enabled(Watchdog::supported(port));
+        ui.checkbox(&mut enabled, "Watchdog");
+        ui.label(
+            "Start the hardware watchdog and leave it running for the application, which \
+            must then feed it. Loadstone feeds the watchdog regardless, in case it's \
+            started by hardware.",
+        );
+        match (enabled, &watchdog) {
+            (true, Watchdog::Disabled) => {
+                *watchdog = Watchdog::Enabled { timeout_ms: DEFAULT_WATCHDOG_TIMEOUT_MS }
+            }
+            (false, Watchdog::Enabled { .. }) => *watchdog = Watchdog::Disabled,
+            _ => {}
+        }
+    });
+    if let Watchdog::Enabled { timeout_ms } = watchdog {
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(
+                Slider::new(timeout_ms, MIN_WATCHDOG_TIMEOUT_MS..=MAX_WATCHDOG_TIMEOUT_MS)
+                    .clamp_to_range(true)
+                    .suffix(" ms"),
+            );
+            ui.label("Time allowed between feeds before the watchdog resets the MCU.");
+        });
+    }
+}
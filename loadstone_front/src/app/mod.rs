@@ -6,8 +6,13 @@ use self::menus::{
 };
 
 use crate::app::menus::{
-    generate, update_signal::configure_update_signal,
-    serial::configure_serial, configure_custom_greetings
+    anti_rollback::configure_anti_rollback, boot_attempts::configure_boot_attempts,
+    copy_journal::configure_copy_journal, generate,
+    hardware_id::configure_hardware_id, recovery_button::configure_recovery_button,
+    recovery_timeout::configure_recovery_timeout,
+    update_mode::configure_update_mode,
+    update_signal::configure_update_signal, serial::configure_serial, trial_boot::configure_trial_boot, configure_custom_greetings,
+    watchdog::configure_watchdog,
 };
 
 use eframe::{
@@ -31,6 +36,8 @@ mod utilities;
 pub struct LoadstoneApp {
     configuration: Configuration,
     verifying_key_text_field: String,
+    additional_key_text_field: String,
+    hardware_id_text_field: String,
     personal_access_token_field: String,
     git_fork_field: String,
     git_ref_field: String,
@@ -45,6 +52,8 @@ impl Default for LoadstoneApp {
         Self {
             configuration: Default::default(),
             verifying_key_text_field: Default::default(),
+            additional_key_text_field: Default::default(),
+            hardware_id_text_field: Default::default(),
             personal_access_token_field: Default::default(),
             git_ref_field: "staging".into(),
             git_fork_field: "absw".into(),
@@ -73,6 +82,8 @@ impl epi::App for LoadstoneApp {
         let LoadstoneApp {
             configuration,
             verifying_key_text_field,
+            additional_key_text_field,
+            hardware_id_text_field,
             personal_access_token_field,
             last_request_response,
             git_ref_field,
@@ -104,6 +115,22 @@ impl epi::App for LoadstoneApp {
                             &mut configuration.port,
                         );
                     });
+                    ui.group(|ui| {
+                        configure_recovery_button(
+                            ui,
+                            &mut configuration.feature_configuration.recovery_button,
+                            &configuration.feature_configuration.serial,
+                            &configuration.port,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_recovery_timeout(
+                            ui,
+                            &mut configuration.feature_configuration.recovery_timeout,
+                            &configuration.feature_configuration.serial,
+                            &configuration.port,
+                        );
+                    });
                     ui.group(|ui| {
                         configure_boot_metrics(
                             ui,
@@ -121,17 +148,71 @@ impl epi::App for LoadstoneApp {
                         configure_update_signal(
                             ui,
                             &mut configuration.feature_configuration.update_signal,
+                            &mut configuration.feature_configuration.update_signal_mode,
+                            &mut configuration.feature_configuration.update_signal_backend,
+                            &mut configuration.feature_configuration.default_update_plan,
+                            &configuration.port,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_anti_rollback(
+                            ui,
+                            &mut configuration.feature_configuration.anti_rollback,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_update_mode(
+                            ui,
+                            &mut configuration.feature_configuration.update_mode,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_trial_boot(
+                            ui,
+                            &mut configuration.feature_configuration.trial_boot,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_copy_journal(
+                            ui,
+                            &mut configuration.feature_configuration.copy_journal,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_boot_attempts(
+                            ui,
+                            &mut configuration.feature_configuration.boot_attempts,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_watchdog(
+                            ui,
+                            &mut configuration.feature_configuration.watchdog,
+                            &configuration.port,
+                        );
+                    });
+                    ui.group(|ui| {
+                        configure_hardware_id(
+                            ui,
+                            &mut configuration.hardware_id,
+                            hardware_id_text_field,
                         );
                     });
                 });
                 ui.separator();
                 ui.collapsing("Memory Map", |ui| {
+                    let storage_region = configuration.storage_region();
+                    let scratch_region = configuration.scratch_region();
+                    let update_signal_region = configuration.update_signal_region();
                     configure_memory_map(
                         ui,
                         &mut configuration.memory_configuration.internal_memory_map,
                         &mut configuration.memory_configuration.external_memory_map,
                         &mut configuration.memory_configuration.external_flash,
                         &mut configuration.memory_configuration.golden_index,
+                        storage_region.as_ref(),
+                        scratch_region.as_ref(),
+                        update_signal_region.as_ref(),
                         &configuration.port,
                     );
                 });
@@ -139,9 +220,9 @@ impl epi::App for LoadstoneApp {
                 ui.collapsing("Security", |ui| {
                     configure_security(
                         ui,
-                        &mut configuration.security_configuration.security_mode,
-                        &mut configuration.security_configuration.verifying_key_raw,
+                        &mut configuration.security_configuration,
                         verifying_key_text_field,
+                        additional_key_text_field,
                     );
                 });
                 ui.separator();
@@ -0,0 +1,367 @@
+//! Compressed image support.
+//!
+//! A compressed image body starts with a header, consisting of the [`COMPRESSION_STRING`]
+//! and the little endian `u32` sizes of the compressed and decompressed body. The body
+//! is compressed with a heatshrink style LZSS scheme, small enough to be decompressed
+//! as a stream with a [`WINDOW_SIZE`] byte history buffer. Decoration, magic string
+//! and CRC/signature are left uncompressed, and the CRC/signature is calculated over
+//! the *decompressed* image.
+//!
+//! The compressed body is a stream of bits, most significant first. Each entry is
+//! either a `1` bit followed by an 8 bit literal byte, or a `0` bit followed by a
+//! [`WINDOW_BITS`] back-reference distance and a [`LOOKAHEAD_BITS`] count (both stored
+//! minus one), repeating bytes already decompressed. The last byte is zero padded.
+
+use core::{cmp::min, convert::TryInto, mem::size_of};
+
+/// This string marks the start of a compressed image body.
+pub const COMPRESSION_STRING: &str = "cMpReSsD";
+
+/// Size of the header preceding a compressed image body.
+pub const COMPRESSION_HEADER_SIZE: usize = COMPRESSION_STRING.len() + 2 * size_of::<u32>();
+
+/// Number of bits encoding a back-reference distance.
+pub const WINDOW_BITS: u32 = 8;
+/// Number of bits encoding a back-reference count.
+pub const LOOKAHEAD_BITS: u32 = 4;
+/// Size of the decompression history buffer, and maximum back-reference distance.
+pub const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+/// Maximum back-reference count.
+const MAX_MATCH: usize = 1 << LOOKAHEAD_BITS;
+/// Back-references shorter than this take more bits than the equivalent literals.
+const MIN_MATCH: usize = 2;
+
+const LITERAL_BITS: u32 = 1 + 8;
+const BACKREFERENCE_BITS: u32 = 1 + WINDOW_BITS + LOOKAHEAD_BITS;
+
+/// Header of a compressed image body.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompressionHeader {
+    /// Size of the compressed body, excluding this header.
+    pub compressed_size: usize,
+    /// Size of the body once decompressed.
+    pub decompressed_size: usize,
+}
+
+impl CompressionHeader {
+    pub fn from_bytes(bytes: &[u8; COMPRESSION_HEADER_SIZE]) -> Option<Self> {
+        let (marker, sizes) = bytes.split_at(COMPRESSION_STRING.len());
+        if marker != COMPRESSION_STRING.as_bytes() {
+            return None;
+        }
+        Some(Self {
+            compressed_size: u32::from_le_bytes(sizes[..4].try_into().unwrap()) as usize,
+            decompressed_size: u32::from_le_bytes(sizes[4..].try_into().unwrap()) as usize,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; COMPRESSION_HEADER_SIZE] {
+        let mut bytes = [0u8; COMPRESSION_HEADER_SIZE];
+        let (marker, sizes) = bytes.split_at_mut(COMPRESSION_STRING.len());
+        marker.copy_from_slice(COMPRESSION_STRING.as_bytes());
+        sizes[..4].copy_from_slice(&(self.compressed_size as u32).to_le_bytes());
+        sizes[4..].copy_from_slice(&(self.decompressed_size as u32).to_le_bytes());
+        bytes
+    }
+}
+
+/// Compresses `input`, feeding the compressed bytes to `sink` in order.
+/// Returns the compressed size.
+pub fn compress(input: &[u8], mut sink: impl FnMut(u8)) -> usize {
+    let mut writer = BitWriter { sink: &mut sink, byte: 0, count: 0, written: 0 };
+    let mut position = 0;
+    while position < input.len() {
+        match longest_match(input, position) {
+            Some((distance, count)) => {
+                writer.write(0, 1);
+                writer.write(distance as u32 - 1, WINDOW_BITS);
+                writer.write(count as u32 - 1, LOOKAHEAD_BITS);
+                position += count;
+            }
+            None => {
+                writer.write(1, 1);
+                writer.write(input[position] as u32, 8);
+                position += 1;
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Finds the longest (and closest, among equally long) repetition of the bytes at
+/// `position` within the preceding window, if it's worth a back-reference.
+fn longest_match(input: &[u8], position: usize) -> Option<(usize, usize)> {
+    let limit = min(MAX_MATCH, input.len() - position);
+    let (mut best_distance, mut best_count) = (0, MIN_MATCH - 1);
+    for distance in 1..=min(WINDOW_SIZE, position) {
+        let start = position - distance;
+        // Matches may overlap the bytes being encoded.
+        let count = (0..limit).take_while(|i| input[start + i] == input[position + i]).count();
+        if count > best_count {
+            best_distance = distance;
+            best_count = count;
+            if count == limit {
+                break;
+            }
+        }
+    }
+    if best_count >= MIN_MATCH {
+        Some((best_distance, best_count))
+    } else {
+        None
+    }
+}
+
+struct BitWriter<'a, S: FnMut(u8)> {
+    sink: &'a mut S,
+    byte: u8,
+    count: u32,
+    written: usize,
+}
+
+impl<'a, S: FnMut(u8)> BitWriter<'a, S> {
+    fn write(&mut self, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.byte = (self.byte << 1) | ((value >> bit) & 1) as u8;
+            self.count += 1;
+            if self.count == 8 {
+                (self.sink)(self.byte);
+                self.written += 1;
+                self.byte = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> usize {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+        self.written
+    }
+}
+
+/// Decompresses an image as a stream, from the end of its compression header.
+/// The first `compressed_size` bytes are decompressed, and any bytes after them
+/// are passed through.
+pub struct Decompressor {
+    window: [u8; WINDOW_SIZE],
+    /// Decompressed bytes produced so far.
+    decompressed: usize,
+    decompressed_size: usize,
+    /// Compressed bytes not yet consumed.
+    compressed_remaining: usize,
+    /// Bits consumed but not yet decoded, aligned to the least significant end.
+    bits: u32,
+    bit_count: u32,
+    /// Distance and remaining count of the back-reference being expanded.
+    backreference: Option<(usize, usize)>,
+}
+
+impl Decompressor {
+    pub fn new(header: &CompressionHeader) -> Self {
+        Self {
+            window: [0u8; WINDOW_SIZE],
+            decompressed: 0,
+            decompressed_size: header.decompressed_size,
+            compressed_remaining: header.compressed_size,
+            bits: 0,
+            bit_count: 0,
+            backreference: None,
+        }
+    }
+
+    /// Processes the next bytes of the stream, writing the result to `output`. Returns
+    /// how many bytes were consumed from `input` and how many were written to `output`.
+    ///
+    /// Fewer bytes than available may be consumed if `output` fills up, and bytes may
+    /// still be produced from an empty `input`. No bytes are consumed nor produced only
+    /// when more input is needed, or `output` is empty.
+    pub fn apply(&mut self, input: &[u8], output: &mut [u8]) -> (usize, usize) {
+        let (mut consumed, mut produced) = (0usize, 0usize);
+        while produced < output.len() {
+            if self.decompressed == self.decompressed_size {
+                // Skip the padding (or any excess) at the end of the compressed body,
+                // then pass the rest of the stream through.
+                let skipped = min(self.compressed_remaining, input.len() - consumed);
+                self.compressed_remaining -= skipped;
+                consumed += skipped;
+                if self.compressed_remaining > 0 {
+                    break;
+                }
+                let length = min(input.len() - consumed, output.len() - produced);
+                output[produced..produced + length]
+                    .copy_from_slice(&input[consumed..consumed + length]);
+                return (consumed + length, produced + length);
+            }
+
+            if let Some((distance, count)) = self.backreference {
+                let byte = self.window[self.decompressed.wrapping_sub(distance) % WINDOW_SIZE];
+                output[produced] = self.emit(byte);
+                produced += 1;
+                self.backreference = if count > 1 { Some((distance, count - 1)) } else { None };
+                continue;
+            }
+
+            match self.next_entry() {
+                Some(Entry::Literal(byte)) => {
+                    output[produced] = self.emit(byte);
+                    produced += 1;
+                }
+                Some(Entry::Backreference { distance, count }) => {
+                    self.backreference = Some((distance, count))
+                }
+                None if consumed < input.len() && self.compressed_remaining > 0 => {
+                    self.bits = (self.bits << 8) | input[consumed] as u32;
+                    self.bit_count += 8;
+                    self.compressed_remaining -= 1;
+                    consumed += 1;
+                }
+                None if self.compressed_remaining == 0 => {
+                    // Truncated body. Pass the rest through, so the image fails verification.
+                    self.decompressed_size = self.decompressed;
+                }
+                None => break,
+            }
+        }
+        (consumed, produced)
+    }
+
+    /// Whether the whole compressed body has been decompressed.
+    pub fn is_finished(&self) -> bool {
+        self.decompressed == self.decompressed_size && self.compressed_remaining == 0
+    }
+
+    fn emit(&mut self, byte: u8) -> u8 {
+        self.window[self.decompressed % WINDOW_SIZE] = byte;
+        self.decompressed += 1;
+        byte
+    }
+
+    fn next_entry(&mut self) -> Option<Entry> {
+        if self.bit_count == 0 {
+            return None;
+        }
+        let literal = (self.bits >> (self.bit_count - 1)) & 1 == 1;
+        if literal && self.bit_count >= LITERAL_BITS {
+            let byte = self.take_bits(LITERAL_BITS) as u8;
+            Some(Entry::Literal(byte))
+        } else if !literal && self.bit_count >= BACKREFERENCE_BITS {
+            let entry = self.take_bits(BACKREFERENCE_BITS);
+            let distance = (entry >> LOOKAHEAD_BITS) as usize % WINDOW_SIZE + 1;
+            let count = (entry as usize % MAX_MATCH) + 1;
+            Some(Entry::Backreference { distance, count })
+        } else {
+            None
+        }
+    }
+
+    /// Takes the oldest `bits` buffered bits.
+    fn take_bits(&mut self, bits: u32) -> u32 {
+        self.bit_count -= bits;
+        let value = (self.bits >> self.bit_count) & ((1 << bits) - 1);
+        self.bits &= (1 << self.bit_count) - 1;
+        value
+    }
+}
+
+enum Entry {
+    Literal(u8),
+    Backreference { distance: usize, count: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    /// Firmware-like sample, mixing repetitive and noisy stretches.
+    fn sample(size: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..size)
+            .map(|i| match (i / 300) % 3 {
+                0 => (i % 7) as u8,
+                1 => 0xFF,
+                _ => {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                }
+            })
+            .collect()
+    }
+
+    fn compressed(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let size = compress(input, |byte| output.push(byte));
+        assert_eq!(size, output.len());
+        output
+    }
+
+    /// Decompresses a stream fed in chunks of `input_chunk` bytes, into an output
+    /// buffer of `output_chunk` bytes.
+    fn decompressed(
+        header: &CompressionHeader,
+        stream: &[u8],
+        input_chunk: usize,
+        output_chunk: usize,
+    ) -> Vec<u8> {
+        let mut decompressor = Decompressor::new(header);
+        let mut output = Vec::new();
+        let mut buffer = std::vec![0u8; output_chunk];
+        for mut chunk in stream.chunks(input_chunk) {
+            loop {
+                let (consumed, produced) = decompressor.apply(chunk, &mut buffer);
+                output.extend_from_slice(&buffer[..produced]);
+                chunk = &chunk[consumed..];
+                if consumed == 0 && produced == 0 {
+                    break;
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn images_survive_compression_and_decompression() {
+        let image = sample(5000);
+        let body = compressed(&image);
+        assert!(body.len() < image.len());
+        let header =
+            CompressionHeader { compressed_size: body.len(), decompressed_size: image.len() };
+        let header = CompressionHeader::from_bytes(&header.to_bytes()).unwrap();
+
+        // Bytes after the compressed body are passed through
+        let mut stream = body.clone();
+        stream.extend_from_slice(b"decoration");
+        let mut expected = image.clone();
+        expected.extend_from_slice(b"decoration");
+
+        for (input_chunk, output_chunk) in [(1, 1), (3, 64), (512, 7), (8192, 8192)].iter() {
+            assert_eq!(decompressed(&header, &stream, *input_chunk, *output_chunk), expected);
+        }
+        let mut decompressor = Decompressor::new(&header);
+        let mut buffer = std::vec![0u8; image.len()];
+        assert_eq!(decompressor.apply(&body, &mut buffer), (body.len(), image.len()));
+        assert!(decompressor.is_finished());
+    }
+
+    #[test]
+    fn truncated_bodies_decompress_short() {
+        let image = sample(1000);
+        let body = compressed(&image);
+        let header =
+            CompressionHeader { compressed_size: body.len(), decompressed_size: image.len() + 1 };
+        let output = decompressed(&header, &body, 16, 16);
+        assert_eq!(output, image);
+    }
+
+    #[test]
+    fn headers_require_the_compression_string() {
+        let mut bytes = CompressionHeader { compressed_size: 1, decompressed_size: 2 }.to_bytes();
+        bytes[0] ^= 0xFF;
+        assert_eq!(CompressionHeader::from_bytes(&bytes), None);
+    }
+}
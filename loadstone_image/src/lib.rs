@@ -0,0 +1,415 @@
+//! This loadstone sub-crate defines the decoration appended to firmware images.
+//! It is shared by Loadstone, which parses the decoration when verifying images,
+//! and the signing tool, which produces it.
+//!
+//! A decorated image is laid out as follows:
+//!
+//! ```text
+//! [body][trailer records][TRAILER_STRING][records size][inverted MAGIC_STRING][CRC/signature]
+//! ```
+//!
+//! The trailer is a sequence of type-length-value records: a one byte tag, a one byte
+//! length, and that many bytes of value. Multi-byte fields are little endian. The trailer
+//! precedes the magic string, so it is covered by the image CRC/signature. Records with
+//! unknown tags are skipped, so new records can be added without breaking older
+//! Loadstone builds.
+#![no_std]
+
+pub mod compression;
+pub mod patch;
+
+use core::{convert::TryInto, mem::size_of};
+
+/// This string, INVERTED BYTEWISE must terminate any valid images, after CRC/Signature
+///
+/// Note: Why inverted? Because if we used it as-is, no code that includes this
+/// constant could be used as a firmware image, as it contains the magic string
+/// halfway through.
+pub const MAGIC_STRING: &str = "HSc7c2ptydZH2QkqZWPcJgG3JtnJ6VuA";
+
+/// utility function to invert the [`MAGIC_STRING`].
+pub fn magic_string_inverted() -> [u8; MAGIC_STRING.len()] {
+    let mut inverted = [0u8; MAGIC_STRING.len()];
+    inverted.iter_mut().zip(MAGIC_STRING.as_bytes()).for_each(|(i, b)| *i = !b);
+    inverted
+}
+
+/// This string marks the footer placed at the end of a bank, which locates
+/// the image in the bank without scanning it.
+pub const FOOTER_STRING: &str = "fTr9wQbL";
+
+/// Size of the footer: the [`FOOTER_STRING`], followed by the little endian `u32`
+/// offsets from the start of the bank to the magic string and to the end of the image.
+pub const FOOTER_SIZE: usize = FOOTER_STRING.len() + 2 * size_of::<u32>();
+
+/// This string follows the trailer records, and precedes their total size.
+pub const TRAILER_STRING: &str = "tLvTrLr5";
+
+/// Size of the [`TRAILER_STRING`] plus the little endian `u16` size of the records.
+pub const TRAILER_INFO_SIZE: usize = TRAILER_STRING.len() + size_of::<u16>();
+
+/// Maximum total size of the trailer records. Loadstone reads the whole trailer
+/// into a buffer of this size.
+pub const MAX_TRAILER_SIZE: usize = 256;
+
+/// Maximum number of dependency records in a trailer.
+pub const MAX_DEPENDENCIES: usize = 4;
+
+/// Maximum size of an image identifier (a P-384 signature).
+pub const MAX_IDENTIFIER_SIZE: usize = 96;
+
+/// Tags identifying the trailer records.
+pub mod tag {
+    /// Marks a golden image. Empty value.
+    pub const GOLDEN: u8 = 0x01;
+    /// Semantic version, as three `u16` (major, minor, patch).
+    pub const VERSION: u8 = 0x02;
+    /// `u32` identifier of the hardware the image is built for.
+    pub const HARDWARE_ID: u8 = 0x03;
+    /// `u8` image type.
+    pub const IMAGE_TYPE: u8 = 0x04;
+    /// `u8` image type and minimum semantic version of an image this one depends on.
+    /// May appear multiple times.
+    pub const DEPENDENCY: u8 = 0x05;
+    /// `u64` build time, in seconds since the UNIX epoch.
+    pub const TIMESTAMP: u8 = 0x06;
+    /// `u32` anti-rollback security epoch.
+    pub const SECURITY_EPOCH: u8 = 0x07;
+    /// `u8` ID of the key ring entry the image is signed with.
+    pub const KEY_ID: u8 = 0x08;
+    /// Identifier (CRC or signature) of the image a patch applies to. Up to
+    /// [`MAX_IDENTIFIER_SIZE`](super::MAX_IDENTIFIER_SIZE) bytes.
+    pub const BASE_IDENTIFIER: u8 = 0x09;
+}
+
+/// Semantic version of a firmware image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SemanticVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl SemanticVersion {
+    pub fn from_bytes(bytes: &[u8; 6]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        Self { major: u16_at(0), minor: u16_at(2), patch: u16_at(4) }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let mut bytes = [0u8; 6];
+        bytes[0..2].copy_from_slice(&self.major.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.minor.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.patch.to_le_bytes());
+        bytes
+    }
+}
+
+/// Purpose of a firmware image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageType {
+    /// A regular, bootable application image.
+    Application,
+    /// A delta patch, reconstructing an image from the one it names as its base.
+    Patch,
+    /// An image type this version of Loadstone doesn't know about.
+    Unknown(u8),
+}
+
+impl From<u8> for ImageType {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0 => ImageType::Application,
+            1 => ImageType::Patch,
+            other => ImageType::Unknown(other),
+        }
+    }
+}
+
+impl From<ImageType> for u8 {
+    fn from(image_type: ImageType) -> Self {
+        match image_type {
+            ImageType::Application => 0,
+            ImageType::Patch => 1,
+            ImageType::Unknown(other) => other,
+        }
+    }
+}
+
+/// Trailer following the magic string of an image (its CRC or signature). This is
+/// also used as an unique identifier for the firmware image for the purposes of updating.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Identifier {
+    bytes: [u8; MAX_IDENTIFIER_SIZE],
+    size: usize,
+}
+
+impl Identifier {
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut identifier = Self { bytes: [0u8; MAX_IDENTIFIER_SIZE], size: bytes.len() };
+        identifier.bytes[..bytes.len()].copy_from_slice(bytes);
+        identifier
+    }
+
+    pub fn as_bytes(&self) -> &[u8] { &self.bytes[..self.size] }
+    /// Size of the identifier in bytes, as stored after the magic string.
+    pub fn size(&self) -> usize { self.size }
+}
+
+/// Requirement for another image to be present alongside this one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub image_type: ImageType,
+    pub minimum_version: SemanticVersion,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrailerError {
+    /// A record extends past the end of the trailer.
+    Truncated,
+    /// A known record has a value of the wrong size.
+    InvalidLength { tag: u8 },
+    /// More than [`MAX_DEPENDENCIES`] dependency records.
+    TooManyDependencies,
+    /// The records exceed [`MAX_TRAILER_SIZE`].
+    TooLarge,
+}
+
+/// Single type-length-value trailer record.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Record<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+}
+
+/// Iterator over the records of a trailer.
+pub struct Records<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Records<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self { Self { bytes } }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, TrailerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.bytes {
+            [] => None,
+            [tag, length, rest @ ..] if rest.len() >= *length as usize => {
+                let (value, rest) = rest.split_at(*length as usize);
+                self.bytes = rest;
+                Some(Ok(Record { tag: *tag, value }))
+            }
+            _ => {
+                self.bytes = &[];
+                Some(Err(TrailerError::Truncated))
+            }
+        }
+    }
+}
+
+/// Decoded contents of an image trailer. Every field is optional.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Trailer {
+    pub golden: bool,
+    pub version: Option<SemanticVersion>,
+    pub hardware_id: Option<u32>,
+    pub image_type: Option<ImageType>,
+    pub dependencies: [Option<Dependency>; MAX_DEPENDENCIES],
+    pub timestamp: Option<u64>,
+    pub security_epoch: Option<u32>,
+    pub key_id: Option<u8>,
+    pub base_identifier: Option<Identifier>,
+}
+
+impl Trailer {
+    /// Parses the trailer records (excluding the trailer info). Unknown tags are skipped.
+    pub fn parse(records: &[u8]) -> Result<Self, TrailerError> {
+        if records.len() > MAX_TRAILER_SIZE {
+            return Err(TrailerError::TooLarge);
+        }
+        let mut trailer = Self::default();
+        for record in Records::new(records) {
+            let Record { tag, value } = record?;
+            match tag {
+                tag::GOLDEN => {
+                    let []: [u8; 0] = field(tag, value)?;
+                    trailer.golden = true;
+                }
+                tag::VERSION => {
+                    trailer.version = Some(SemanticVersion::from_bytes(&field(tag, value)?))
+                }
+                tag::HARDWARE_ID => {
+                    trailer.hardware_id = Some(u32::from_le_bytes(field(tag, value)?))
+                }
+                tag::IMAGE_TYPE => {
+                    let [image_type] = field(tag, value)?;
+                    trailer.image_type = Some(ImageType::from(image_type));
+                }
+                tag::DEPENDENCY => {
+                    let bytes: [u8; 7] = field(tag, value)?;
+                    let dependency = Dependency {
+                        image_type: ImageType::from(bytes[0]),
+                        minimum_version: SemanticVersion::from_bytes(
+                            bytes[1..].try_into().unwrap(),
+                        ),
+                    };
+                    let slot = trailer
+                        .dependencies
+                        .iter_mut()
+                        .find(|d| d.is_none())
+                        .ok_or(TrailerError::TooManyDependencies)?;
+                    *slot = Some(dependency);
+                }
+                tag::TIMESTAMP => trailer.timestamp = Some(u64::from_le_bytes(field(tag, value)?)),
+                tag::SECURITY_EPOCH => {
+                    trailer.security_epoch = Some(u32::from_le_bytes(field(tag, value)?))
+                }
+                tag::KEY_ID => {
+                    let [key_id] = field(tag, value)?;
+                    trailer.key_id = Some(key_id);
+                }
+                tag::BASE_IDENTIFIER => {
+                    if value.is_empty() || value.len() > MAX_IDENTIFIER_SIZE {
+                        return Err(TrailerError::InvalidLength { tag });
+                    }
+                    trailer.base_identifier = Some(Identifier::new(value));
+                }
+                _ => {}
+            }
+        }
+        Ok(trailer)
+    }
+
+    /// Encodes the trailer records followed by the trailer info, feeding the bytes to
+    /// `sink` in order. Returns the number of bytes encoded.
+    pub fn encode(&self, mut sink: impl FnMut(&[u8])) -> Result<usize, TrailerError> {
+        let mut records_size = 0usize;
+        self.for_each_record(|_, value| records_size += 2 + value.len());
+        if records_size > MAX_TRAILER_SIZE {
+            return Err(TrailerError::TooLarge);
+        }
+
+        self.for_each_record(|tag, value| {
+            sink(&[tag, value.len() as u8]);
+            sink(value);
+        });
+        sink(TRAILER_STRING.as_bytes());
+        sink(&(records_size as u16).to_le_bytes());
+        Ok(records_size + TRAILER_INFO_SIZE)
+    }
+
+    fn for_each_record(&self, mut record: impl FnMut(u8, &[u8])) {
+        if self.golden {
+            record(tag::GOLDEN, &[]);
+        }
+        if let Some(version) = self.version {
+            record(tag::VERSION, &version.to_bytes());
+        }
+        if let Some(hardware_id) = self.hardware_id {
+            record(tag::HARDWARE_ID, &hardware_id.to_le_bytes());
+        }
+        if let Some(image_type) = self.image_type {
+            record(tag::IMAGE_TYPE, &[image_type.into()]);
+        }
+        for dependency in self.dependencies.iter().flatten() {
+            let mut bytes = [0u8; 7];
+            bytes[0] = dependency.image_type.into();
+            bytes[1..].copy_from_slice(&dependency.minimum_version.to_bytes());
+            record(tag::DEPENDENCY, &bytes);
+        }
+        if let Some(timestamp) = self.timestamp {
+            record(tag::TIMESTAMP, &timestamp.to_le_bytes());
+        }
+        if let Some(security_epoch) = self.security_epoch {
+            record(tag::SECURITY_EPOCH, &security_epoch.to_le_bytes());
+        }
+        if let Some(key_id) = self.key_id {
+            record(tag::KEY_ID, &[key_id]);
+        }
+        if let Some(base_identifier) = self.base_identifier {
+            record(tag::BASE_IDENTIFIER, base_identifier.as_bytes());
+        }
+    }
+}
+
+/// Parses the trailer info immediately preceding the magic string, returning
+/// the size of the trailer records if it's present.
+pub fn parse_trailer_info(info: &[u8; TRAILER_INFO_SIZE]) -> Option<usize> {
+    let (marker, size) = info.split_at(TRAILER_STRING.len());
+    if marker != TRAILER_STRING.as_bytes() {
+        return None;
+    }
+    Some(u16::from_le_bytes(size.try_into().unwrap()) as usize)
+}
+
+fn field<const N: usize>(tag: u8, value: &[u8]) -> Result<[u8; N], TrailerError> {
+    value.try_into().map_err(|_| TrailerError::InvalidLength { tag })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(trailer: &Trailer) -> ([u8; MAX_TRAILER_SIZE + TRAILER_INFO_SIZE], usize) {
+        let mut buffer = [0u8; MAX_TRAILER_SIZE + TRAILER_INFO_SIZE];
+        let mut size = 0;
+        trailer
+            .encode(|bytes| {
+                buffer[size..size + bytes.len()].copy_from_slice(bytes);
+                size += bytes.len();
+            })
+            .unwrap();
+        (buffer, size)
+    }
+
+    #[test]
+    fn trailers_survive_encoding_and_parsing() {
+        let dependency = Dependency {
+            image_type: ImageType::Unknown(3),
+            minimum_version: SemanticVersion { major: 2, minor: 0, patch: 1 },
+        };
+        let trailer = Trailer {
+            golden: true,
+            version: Some(SemanticVersion { major: 1, minor: 2, patch: 3 }),
+            hardware_id: Some(0xB0A4D),
+            image_type: Some(ImageType::Application),
+            dependencies: [Some(dependency), Some(dependency), None, None],
+            timestamp: Some(1625750000),
+            security_epoch: Some(4),
+            key_id: Some(2),
+            base_identifier: Some(Identifier::new(&[0xAB; MAX_IDENTIFIER_SIZE])),
+        };
+        let (buffer, size) = encoded(&trailer);
+        let records_size = size - TRAILER_INFO_SIZE;
+
+        let info = buffer[records_size..size].try_into().unwrap();
+        assert_eq!(parse_trailer_info(info), Some(records_size));
+        assert_eq!(Trailer::parse(&buffer[..records_size]), Ok(trailer));
+    }
+
+    #[test]
+    fn unknown_records_are_skipped() {
+        let records = [0xEE, 3, 1, 2, 3, tag::KEY_ID, 1, 7, 0xEF, 0];
+        let trailer = Trailer::parse(&records).unwrap();
+        assert_eq!(trailer, Trailer { key_id: Some(7), ..Trailer::default() });
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert_eq!(Trailer::parse(&[tag::KEY_ID, 2, 7]), Err(TrailerError::Truncated));
+        assert_eq!(
+            Trailer::parse(&[tag::KEY_ID, 2, 7, 7]),
+            Err(TrailerError::InvalidLength { tag: tag::KEY_ID })
+        );
+        let dependency = [tag::DEPENDENCY, 7, 0, 1, 0, 0, 0, 0, 0];
+        let records: [u8; 45] = {
+            let mut records = [0u8; 45];
+            records.chunks_mut(9).for_each(|r| r.copy_from_slice(&dependency));
+            records
+        };
+        assert_eq!(Trailer::parse(&records), Err(TrailerError::TooManyDependencies));
+    }
+}
@@ -0,0 +1,144 @@
+//! Delta patch support.
+//!
+//! A patch is a regular (decorated and signed) image of type [`ImageType::Patch`], whose
+//! trailer names the identifier of the base image it applies to. Its body is a
+//! [`PatchHeader`] followed by a sequence of [`Operation`]s, which reconstruct a new image
+//! (complete with its own decoration and CRC/signature) from the base image in the
+//! bootable bank. Each operation either copies a range of the base image, inserts the
+//! bytes following it in the patch body, or inserts the (inverted) magic string. The
+//! magic string is never inserted verbatim, as it would be mistaken for the end of the
+//! patch image itself.
+//!
+//! Patches are applied in place: the new image is produced in blocks of
+//! [`PATCH_BLOCK_SIZE`] bytes, each of them overwriting the base image once complete.
+//! Therefore, bytes of the output block starting at offset `N` can only be copied from
+//! base image offsets of `N` or higher.
+//!
+//! [`ImageType::Patch`]: super::ImageType::Patch
+
+use crate::MAGIC_STRING;
+use core::{convert::TryInto, mem::size_of};
+
+/// This string marks the start of a patch body.
+pub const PATCH_STRING: &str = "pAtChDlT";
+
+/// Size of the [`PATCH_STRING`] plus the little endian `u32` size of the new image.
+pub const PATCH_HEADER_SIZE: usize = PATCH_STRING.len() + size_of::<u32>();
+
+/// Size of an operation: a one byte tag, followed by the little endian `u32` length
+/// of the operation, and the `u32` offset to copy from (zero for other operations).
+pub const OPERATION_SIZE: usize = 1 + 2 * size_of::<u32>();
+
+/// Size of the blocks the new image is produced in.
+pub const PATCH_BLOCK_SIZE: usize = 16 * 1024;
+
+const COPY: u8 = 0x00;
+const INSERT: u8 = 0x01;
+const MAGIC: u8 = 0x02;
+
+/// Header of a patch body.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PatchHeader {
+    /// Total size of the new image, including decoration and CRC/signature.
+    pub size: usize,
+}
+
+impl PatchHeader {
+    pub fn from_bytes(bytes: &[u8; PATCH_HEADER_SIZE]) -> Option<Self> {
+        let (marker, size) = bytes.split_at(PATCH_STRING.len());
+        if marker != PATCH_STRING.as_bytes() {
+            return None;
+        }
+        Some(Self { size: u32::from_le_bytes(size.try_into().unwrap()) as usize })
+    }
+
+    pub fn to_bytes(&self) -> [u8; PATCH_HEADER_SIZE] {
+        let mut bytes = [0u8; PATCH_HEADER_SIZE];
+        let (marker, size) = bytes.split_at_mut(PATCH_STRING.len());
+        marker.copy_from_slice(PATCH_STRING.as_bytes());
+        size.copy_from_slice(&(self.size as u32).to_le_bytes());
+        bytes
+    }
+}
+
+/// Single step in the reconstruction of the new image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Copies `length` bytes of the base image, starting at `offset`.
+    Copy { length: usize, offset: usize },
+    /// Inserts the `length` bytes following this operation in the patch body.
+    Insert { length: usize },
+    /// Inserts the inverted magic string.
+    Magic,
+}
+
+impl Operation {
+    pub fn from_bytes(bytes: &[u8; OPERATION_SIZE]) -> Option<Self> {
+        let length = u32::from_le_bytes(bytes[1..5].try_into().unwrap()) as usize;
+        let offset = u32::from_le_bytes(bytes[5..9].try_into().unwrap()) as usize;
+        match bytes[0] {
+            COPY => Some(Operation::Copy { length, offset }),
+            INSERT => Some(Operation::Insert { length }),
+            MAGIC if length == MAGIC_STRING.len() => Some(Operation::Magic),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; OPERATION_SIZE] {
+        let (tag, length, offset) = match *self {
+            Operation::Copy { length, offset } => (COPY, length, offset),
+            Operation::Insert { length } => (INSERT, length, 0),
+            Operation::Magic => (MAGIC, MAGIC_STRING.len(), 0),
+        };
+        let mut bytes = [0u8; OPERATION_SIZE];
+        bytes[0] = tag;
+        bytes[1..5].copy_from_slice(&(length as u32).to_le_bytes());
+        bytes[5..9].copy_from_slice(&(offset as u32).to_le_bytes());
+        bytes
+    }
+
+    /// Number of bytes of the new image produced by this operation.
+    pub fn length(&self) -> usize {
+        match *self {
+            Operation::Copy { length, .. } | Operation::Insert { length } => length,
+            Operation::Magic => MAGIC_STRING.len(),
+        }
+    }
+}
+
+/// Whether copying base image bytes from `offset` to output `position` is safe, given
+/// that every output block overwrites the base image once complete.
+pub fn is_copy_in_place(position: usize, offset: usize) -> bool {
+    offset >= position - position % PATCH_BLOCK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_survive_encoding_and_parsing() {
+        let operations = [
+            Operation::Copy { length: 17, offset: 0x0801_0000 },
+            Operation::Insert { length: PATCH_BLOCK_SIZE },
+            Operation::Magic,
+        ];
+        for operation in operations.iter() {
+            assert_eq!(Operation::from_bytes(&operation.to_bytes()), Some(*operation));
+        }
+        let mut unknown = operations[0].to_bytes();
+        unknown[0] = 0x7F;
+        assert_eq!(Operation::from_bytes(&unknown), None);
+
+        let header = PatchHeader { size: 123_456 };
+        assert_eq!(PatchHeader::from_bytes(&header.to_bytes()), Some(header));
+    }
+
+    #[test]
+    fn copies_can_only_read_from_blocks_not_yet_overwritten() {
+        assert!(is_copy_in_place(0, 0));
+        assert!(is_copy_in_place(PATCH_BLOCK_SIZE - 1, 0));
+        assert!(!is_copy_in_place(PATCH_BLOCK_SIZE, PATCH_BLOCK_SIZE - 1));
+        assert!(is_copy_in_place(PATCH_BLOCK_SIZE + 10, PATCH_BLOCK_SIZE));
+    }
+}
@@ -20,6 +20,7 @@ use super::{
     boot_metrics::{boot_metrics, BootMetrics},
     cli::{Cli, DEFAULT_GREETING},
     image,
+    storage::{BootAttempts, RevocationList, TrialLog},
     traits::{Flash, Serial},
     update_signal::{UpdatePlan, WriteUpdateSignal},
 };
@@ -46,6 +47,9 @@ pub struct BootManager<
     pub(crate) greeting: Option<&'static str>,
     pub(crate) _marker: PhantomData<R>,
     pub(crate) update_signal: Option<WUS>,
+    pub(crate) revoked_keys: Option<RevocationList<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) trial_log: Option<TrialLog<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) boot_attempts: Option<BootAttempts<<MCUF as flash::ReadWrite>::Address>>,
 }
 
 impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSignal>
@@ -60,6 +64,19 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
         self.mcu_banks().find(|b| b.bootable).unwrap()
     }
 
+    /// Whether the application is running from a given bootable bank. Loadstone points
+    /// the vector table to the image it boots, so the bank is told apart by its location.
+    /// If the running image can't be located, it's assumed to run from any bootable bank.
+    pub fn is_running_from(&self, bank: &image::Bank<MCUF::Address>) -> bool {
+        // NOTE(Safety): Reading the VTOR register has no side effects.
+        let vector_table = unsafe { (*SCB::ptr()).vtor.read() } as usize;
+        let running_bank = self.mcu_banks().find(|b| {
+            let location: usize = b.location.into();
+            b.bootable && location == vector_table
+        });
+        running_bank.map_or(bank.bootable, |running_bank| running_bank.index == bank.index)
+    }
+
     /// Returns an iterator of all MCU flash banks.
     pub fn mcu_banks(&self) -> impl Iterator<Item = image::Bank<MCUF::Address>> {
         self.mcu_banks.iter().cloned()
@@ -67,30 +84,33 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
 
     /// Writes a firmware image to an external flash bank. Takes an iterator over byte
     /// blocks, to easily interface with serial or network protocols like XMODEM or TCP/IP
-    /// where information is received in chunks.
-    pub fn store_image_external<I: Iterator<Item = [u8; N]>, const N: usize>(
+    /// where information is received in chunks. Fails with [`Error::ImageTooBig`] if the
+    /// image doesn't fit in the bank, without writing past its end.
+    pub fn store_image_external<I: image::BlockStream<N>, const N: usize>(
         &mut self,
         blocks: I,
         bank: image::Bank<EXTF::Address>,
     ) -> Result<(), Error> {
         let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
-        external_flash.write_from_blocks(bank.location, blocks)?;
-        Ok(())
+        image::clear_footer(external_flash, bank)?;
+        image::write_from_blocks(external_flash, bank, blocks)
     }
 
-    /// Writes a firmware image to a MCU flash bank that is not bootable. Takes an iterator over byte
-    /// blocks, to easily interface with serial or network protocols like XMODEM or TCP/IP
-    /// where information is received in chunks.
-    pub fn store_image_mcu<I: Iterator<Item = [u8; N]>, const N: usize>(
+    /// Writes a firmware image to a MCU flash bank other than the one the application is
+    /// running from. Takes an iterator over byte blocks, to easily interface with serial or
+    /// network protocols like XMODEM or TCP/IP where information is received in chunks.
+    /// Fails with [`Error::ImageTooBig`] if the image doesn't fit in the bank, without
+    /// writing past its end.
+    pub fn store_image_mcu<I: image::BlockStream<N>, const N: usize>(
         &mut self,
         blocks: I,
         bank: image::Bank<MCUF::Address>,
     ) -> Result<(), Error> {
-        if bank.bootable {
+        if self.is_running_from(&bank) {
             Err(Error::BankInvalid)
         } else {
-            self.mcu_flash.write_from_blocks(bank.location, blocks)?;
-            Ok(())
+            image::clear_footer(&mut self.mcu_flash, bank)?;
+            image::write_from_blocks(&mut self.mcu_flash, bank, blocks)
         }
     }
 
@@ -117,6 +137,45 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
         }
     }
 
+    /// Permanently revokes a verifying key, so Loadstone stops trusting images signed
+    /// with it. Make sure a valid image signed with another key is available first!
+    pub fn revoke_key(&mut self, key_id: u8) -> Result<(), Error> {
+        if let Some(revoked_keys) = self.revoked_keys {
+            revoked_keys.revoke(&mut self.mcu_flash, key_id)
+        } else {
+            Err(Error::DeviceError(
+                "Key revocation commands are not supported without the key \
+                revocation feature enabled.",
+            ))
+        }
+    }
+
+    /// Confirms the current image after an update, so Loadstone stops counting its
+    /// trial boots and never reverts it. Has no effect if the image isn't on trial.
+    pub fn confirm_image(&mut self) -> Result<(), Error> {
+        if let Some(trial_log) = self.trial_log {
+            trial_log.confirm(&mut self.mcu_flash)
+        } else {
+            Err(Error::DeviceError(
+                "Image confirmation is not supported without the trial boot \
+                feature enabled.",
+            ))
+        }
+    }
+
+    /// Reports the current image as healthy, resetting the boot attempt counter so
+    /// Loadstone doesn't fall back to the golden image.
+    pub fn report_healthy(&mut self) -> Result<(), Error> {
+        if let Some(boot_attempts) = self.boot_attempts {
+            boot_attempts.reset(&mut self.mcu_flash)
+        } else {
+            Err(Error::DeviceError(
+                "Reporting the image healthy is not supported without the boot \
+                attempt counter feature enabled.",
+            ))
+        }
+    }
+
     /// Gathers metrics left over in memory by Loadstone, if available, and launches
     /// the command line interface.
     pub fn run(mut self) -> ! {
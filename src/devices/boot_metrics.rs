@@ -7,6 +7,8 @@
 //! these metrics immediately, as they exist in an untracked section of
 //! memory where they can be quickly clobbered by stack variables.
 
+use crate::devices::update_signal::UpdatePlan;
+
 /// Collection of boot metrics relayed by Loadstone to the booted application.
 #[repr(C)]
 #[derive(Clone)]
@@ -20,6 +22,13 @@ pub struct BootMetrics {
     /// Time from construction of Loadstone's driver suite to the target image
     /// being booted.
     pub boot_time_ms: Option<u32>,
+    /// Whether the booted image is on trial after an update, or replaced an
+    /// image that failed its trial.
+    pub trial: TrialStatus,
+    /// Whether the update signal held a valid plan when read.
+    pub update_signal: UpdateSignalStatus,
+    /// Whether Loadstone acted on the plan requested through the update signal.
+    pub update_plan: UpdatePlanStatus,
     /// Magic string to ensure the boot metrics' integrity when read. Must
     /// be equal to [`BOOT_MAGIC_END`] when read to guarantee validity.
     pub boot_magic_end: u32,
@@ -41,6 +50,61 @@ pub enum BootPath {
     Restored { bank: u8 },
     /// The image was initially updated from an external bank, then booted.
     Updated { bank: u8 },
+    /// The image was booted in place from an additional bootable bank, as it was
+    /// newer than the image in the main MCU flash bank.
+    InPlace { bank: u8 },
+    /// The image in the main MCU flash bank used up its boot attempts without the
+    /// application reporting itself healthy, so the golden image was restored from
+    /// `bank`, then booted.
+    GoldenFallback { bank: u8 },
+    /// The application requested a rollback through the update signal, so the newest
+    /// image older than the previous one was copied from `bank`, then booted.
+    RolledBack { bank: u8 },
+}
+
+/// Trial state of the booted image. When trial boots are enabled, updated images
+/// must be confirmed by the application within a number of boots, or Loadstone
+/// reverts to the previous (or golden) image.
+#[repr(C)]
+#[derive(Clone)]
+pub enum TrialStatus {
+    /// The image is not on trial, either because it was confirmed or because
+    /// trial boots are disabled.
+    None,
+    /// The image is on trial. This is its boot number `boot`, out of `allowed_boots`.
+    Pending { boot: u8, allowed_boots: u8 },
+    /// The previous image was never confirmed, so it was replaced by this one.
+    Reverted,
+}
+
+/// State of the update signal when read by Loadstone.
+#[repr(C)]
+#[derive(Clone)]
+pub enum UpdateSignalStatus {
+    /// The update signal is disabled, or wasn't read.
+    Disabled,
+    /// The update signal held a valid plan, set by the application.
+    Set,
+    /// The update signal held no valid plan, as it was never set, was cleared after a
+    /// one-shot plan, or was corrupted. The configured default plan was followed instead.
+    Defaulted,
+}
+
+/// Outcome of the plan requested through the update signal, so the application can
+/// tell whether its request was honoured.
+#[repr(C)]
+#[derive(Clone)]
+pub enum UpdatePlanStatus {
+    /// No plan was acted on, either because the update signal is disabled or because
+    /// the boot was decided before reading it (e.g. falling back to the golden image).
+    None,
+    /// Loadstone carried out `plan`. If `cleared`, the plan was one-shot, and later
+    /// boots follow the default plan until the application sets a new one.
+    Honoured { plan: UpdatePlan, cleared: bool },
+    /// Loadstone couldn't carry out `plan` (e.g. there was no older image to roll back
+    /// to), so it booted as if no update was available. If `cleared`, the plan was
+    /// one-shot, and won't be attempted again.
+    Failed { plan: UpdatePlan, cleared: bool },
 }
 
 impl Default for BootMetrics {
@@ -49,6 +113,9 @@ impl Default for BootMetrics {
             boot_magic_start: BOOT_MAGIC_START,
             boot_path: BootPath::Direct,
             boot_time_ms: None,
+            trial: TrialStatus::None,
+            update_signal: UpdateSignalStatus::Disabled,
+            update_plan: UpdatePlanStatus::None,
             boot_magic_end: BOOT_MAGIC_END,
         }
     }
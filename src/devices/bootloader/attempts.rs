@@ -0,0 +1,155 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Records an attempt to boot an image. Does nothing if the boot attempt
+    /// counter is disabled.
+    pub(super) fn record_boot_attempt(&mut self) {
+        if let Some(boot_attempts) = self.boot_attempts {
+            if let Err(e) = boot_attempts.record(&mut self.mcu_flash) {
+                warn!("Failed to record the boot attempt.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
+    }
+
+    /// If the application used up its boot attempts without resetting the counter, the
+    /// image in the boot bank is assumed to crash before becoming healthy, so it's
+    /// replaced with the golden image. Returns the golden image, if restored.
+    pub(super) fn fall_back_to_golden(&mut self) -> Option<Image<MCUF::Address>> {
+        let boot_attempts = self.boot_attempts?;
+        match boot_attempts.exhausted(&mut self.mcu_flash) {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(e) => {
+                warn!("Failed to read the boot attempt counter.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+                return None;
+            }
+        }
+
+        duprintln!(
+            self.serial,
+            "Current image was not reported healthy after {} boot attempts.",
+            boot_attempts.allowed_attempts()
+        );
+        let golden_bank_exists =
+            self.mcu_banks().any(|b| b.is_golden) || self.external_banks().any(|b| b.is_golden);
+        if !golden_bank_exists {
+            duprintln!(self.serial, "No golden bank to fall back to. Keeping the current image.");
+            return None;
+        }
+
+        // The current image is about to be replaced, so it's no longer on trial.
+        self.end_trial();
+        let image = self.restore_internal(true).or_else(|| self.restore_external(true))?;
+        if let BootPath::Restored { bank } = self.boot_metrics.boot_path {
+            self.boot_metrics.boot_path = BootPath::GoldenFallback { bank };
+        }
+        if let Err(e) = boot_attempts.reset(&mut self.mcu_flash) {
+            warn!("Failed to reset the boot attempt counter.");
+            if let Some(serial) = self.serial.as_mut() {
+                e.report(serial);
+            }
+        }
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{
+        bootloader::doubles::*,
+        storage::{Storage, Trial},
+    };
+    use blue_hal::hal::doubles::flash::Address;
+
+    const STORAGE: Storage<Address> =
+        Storage { clearable: Address(0), permanent: Address(TEST_SECTOR_SIZE as u32) };
+    const ALLOWED_ATTEMPTS: u32 = 3;
+    static MCU_BANKS: [Bank<Address>; 2] = [bank(1, true, false), bank(2, false, true)];
+
+    /// Bootloader counting boot attempts, with a version 1 image in the boot bank
+    /// and, if `golden`, a golden version 0 image.
+    fn bootloader(golden: bool) -> BootloaderDouble {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.boot_attempts = Some(STORAGE.boot_attempts(ALLOWED_ATTEMPTS));
+        write_versioned(&mut bootloader, MCU_BANKS[0], 1, false);
+        if golden {
+            write_versioned(&mut bootloader, MCU_BANKS[1], 0, true);
+        }
+        bootloader
+    }
+
+    fn attempts(bootloader: &mut BootloaderDouble) -> u32 {
+        STORAGE.boot_attempts(ALLOWED_ATTEMPTS).read(&mut bootloader.mcu_flash).unwrap()
+    }
+
+    #[test]
+    fn current_image_is_kept_while_it_has_attempts_left() {
+        let mut bootloader = bootloader(true);
+
+        for attempt in 1..=ALLOWED_ATTEMPTS {
+            assert!(bootloader.fall_back_to_golden().is_none());
+            bootloader.record_boot_attempt();
+            assert_eq!(attempts(&mut bootloader), attempt);
+        }
+        assert_eq!(current_version(&mut bootloader), Some(1));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Direct));
+    }
+
+    #[test]
+    fn healthy_applications_keep_their_attempts() {
+        let mut bootloader = bootloader(true);
+
+        for _ in 0..2 * ALLOWED_ATTEMPTS {
+            assert!(bootloader.fall_back_to_golden().is_none());
+            bootloader.record_boot_attempt();
+            STORAGE.boot_attempts(ALLOWED_ATTEMPTS).reset(&mut bootloader.mcu_flash).unwrap();
+        }
+        assert_eq!(current_version(&mut bootloader), Some(1));
+    }
+
+    #[test]
+    fn exhausted_attempts_fall_back_to_the_golden_image() {
+        let mut bootloader = bootloader(true);
+        let trial_log = STORAGE.trial_log(2);
+        bootloader.trial_log = Some(trial_log);
+        trial_log.start(&mut bootloader.mcu_flash, 2).unwrap();
+        for _ in 0..ALLOWED_ATTEMPTS {
+            bootloader.record_boot_attempt();
+        }
+
+        let image = bootloader.fall_back_to_golden().unwrap();
+        assert_eq!(image.version().map(|v| v.major), Some(0));
+        assert_eq!(current_version(&mut bootloader), Some(0));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::GoldenFallback { bank: 2 }));
+        assert_eq!(attempts(&mut bootloader), 0);
+        assert_eq!(trial_log.state(&mut bootloader.mcu_flash), Ok(Trial::None));
+    }
+
+    #[test]
+    fn exhausted_attempts_keep_the_current_image_without_a_golden_one() {
+        let mut bootloader = bootloader(false);
+        for _ in 0..ALLOWED_ATTEMPTS {
+            bootloader.record_boot_attempt();
+        }
+
+        assert!(bootloader.fall_back_to_golden().is_none());
+        assert_eq!(current_version(&mut bootloader), Some(1));
+    }
+}
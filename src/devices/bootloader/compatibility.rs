@@ -0,0 +1,37 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Rejects images built for different hardware. Images that don't declare
+    /// a hardware ID are compatible with any board. Always succeeds if Loadstone
+    /// isn't configured with a hardware ID.
+    pub fn check_compatibility<A: Address>(&mut self, image: &Image<A>) -> Result<(), Error> {
+        let (expected, declared) = match (self.hardware_id, image.hardware_id()) {
+            (Some(expected), Some(declared)) => (expected, declared),
+            _ => return Ok(()),
+        };
+
+        if declared != expected {
+            duprintln!(
+                self.serial,
+                "Image is built for hardware ID {:?}, but this board is {:?}.",
+                declared,
+                expected
+            );
+            if let Some(serial) = self.serial.as_mut() {
+                Error::ImageIncompatible.report(serial);
+            }
+            return Err(Error::ImageIncompatible);
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,76 @@
 use super::*;
-use crate::devices::update_signal::ReadUpdateSignal;
+use crate::devices::{
+    image::{
+        compression::Inflater,
+        encryption::{Decryptor, ENCRYPTION_HEADER_SIZE},
+    },
+    update_signal::ReadUpdateSignal,
+};
+
+/// Large transfer buffer ensures that the number of read-write cycles needed
+/// to guarantee flash integrity through the process is minimal. Images are
+/// written in chunks of at least this size, made of whole erase sectors.
+pub(super) const TRANSFER_BUFFER_SIZE: usize = KB!(64);
+/// Size of the blocks read from the input image, before decrypting and decompressing.
+const INPUT_BUFFER_SIZE: usize = KB!(1);
+
+/// Size of the plaintext header preceding the body of an image in flash. Encrypted
+/// images are decrypted on the fly, and their header is not copied over.
+pub(super) fn header_size<A: Address>(image: &Image<A>) -> usize {
+    if image.is_encrypted() {
+        ENCRYPTION_HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+/// Flash access needed to copy an image, relative to the start of the input
+/// image body and to the start of the output bank.
+trait Transfer {
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error>;
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error>;
+    /// Offset of the end of the output erase sector containing `offset`.
+    fn sector_end(&self, offset: usize) -> usize;
+}
+
+/// Transfer between two banks of the same flash chip.
+struct SingleFlashTransfer<'a, F: Flash> {
+    flash: &'a mut F,
+    input: F::Address,
+    output: F::Address,
+}
+
+impl<'a, F: Flash> Transfer for SingleFlashTransfer<'a, F> {
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+        Ok(block!(self.flash.read(self.input + offset, bytes))?)
+    }
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        Ok(block!(self.flash.write(self.output + offset, bytes))?)
+    }
+    fn sector_end(&self, offset: usize) -> usize {
+        F::sector_end(self.output + offset) - self.output
+    }
+}
+
+/// Transfer between banks of two different flash chips.
+struct DualFlashTransfer<'a, I: Flash, O: Flash> {
+    input_flash: &'a mut I,
+    output_flash: &'a mut O,
+    input: I::Address,
+    output: O::Address,
+}
+
+impl<'a, I: Flash, O: Flash> Transfer for DualFlashTransfer<'a, I, O> {
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+        Ok(block!(self.input_flash.read(self.input + offset, bytes))?)
+    }
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        Ok(block!(self.output_flash.write(self.output + offset, bytes))?)
+    }
+    fn sector_end(&self, offset: usize) -> usize {
+        O::sector_end(self.output + offset) - self.output
+    }
+}
 
 impl<
         EXTF: Flash,
@@ -8,14 +79,20 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
 {
+    /// Copies the image in a bank to another bank of the same flash chip, skipping the
+    /// first `resumed_chunks` chunks (already written by an interrupted copy). `progress`
+    /// is called after writing each chunk but the last.
     pub fn copy_image_single_flash<F: Flash>(
         serial: &mut Option<SRL>,
         flash: &mut F,
         input_bank: image::Bank<F::Address>,
         output_bank: image::Bank<F::Address>,
         must_be_golden: bool,
+        resumed_chunks: usize,
+        mut progress: impl FnMut(&mut F),
     ) -> Result<(), Error> {
         if input_bank.index == output_bank.index {
             return Err(Error::DeviceError("Attempted to copy a bank into itself"));
@@ -25,6 +102,9 @@ impl<
             duprintln!(serial, "Image is not golden.",);
             return Err(Error::DeviceError("Image is not golden"));
         }
+        if input_image.is_patch() {
+            return Err(Error::ImageIsPatch);
+        }
         duprintln!(
             serial,
             "Copying bank {:?} image [Address {:?}, size {:?}]\r\n* Input: [{}]\r\n* Output: [{}]",
@@ -34,28 +114,23 @@ impl<
             F::label(),
             F::label(),
         );
-        let input_image_start_address = input_bank.location;
-        let output_image_start_address = output_bank.location;
-
-        // Large transfer buffer ensures that the number of read-write cycles needed
-        // to guarantee flash integrity through the process is minimal.
-        const TRANSFER_BUFFER_SIZE: usize = KB!(64);
-        let mut buffer = [0u8; TRANSFER_BUFFER_SIZE];
-        let mut byte_index = 0usize;
-
-        let total_size = input_image.total_size();
-
-        while byte_index < total_size {
-            let bytes_to_read = min(TRANSFER_BUFFER_SIZE, total_size.saturating_sub(byte_index));
-            block!(
-                flash.read(input_image_start_address + byte_index, &mut buffer[0..bytes_to_read])
-            )?;
-            block!(flash.write(output_image_start_address + byte_index, &buffer[0..bytes_to_read]))?;
-            byte_index += bytes_to_read;
+        let footer = input_image.copied_footer();
+        if footer.total_size > output_bank.size {
+            return Err(Error::ImageTooBig);
         }
-        Ok(())
+        let mut transfer = SingleFlashTransfer {
+            flash: &mut *flash,
+            input: input_bank.location + header_size(&input_image),
+            output: output_bank.location,
+        };
+        Self::transfer(&input_image, &mut transfer, resumed_chunks, |t| progress(&mut *t.flash))?;
+        image::write_footer(flash, output_bank, footer)
     }
 
+    /// Copies the image in a bank to a bank of another flash chip, skipping the first
+    /// `resumed_chunks` chunks (already written by an interrupted copy). `progress` is
+    /// called after writing each chunk but the last.
+    #[allow(clippy::too_many_arguments)]
     pub fn copy_image<I: Flash, O: Flash>(
         serial: &mut Option<SRL>,
         input_flash: &mut I,
@@ -63,12 +138,17 @@ impl<
         input_bank: image::Bank<I::Address>,
         output_bank: image::Bank<O::Address>,
         must_be_golden: bool,
+        resumed_chunks: usize,
+        mut progress: impl FnMut(&mut I, &mut O),
     ) -> Result<(), Error> {
         let input_image = R::image_at(input_flash, input_bank)?;
         if must_be_golden && !input_image.is_golden() {
             duprintln!(serial, "Image is not golden.",);
             return Err(Error::DeviceError("Image is not golden"));
         }
+        if input_image.is_patch() {
+            return Err(Error::ImageIsPatch);
+        }
         duprintln!(
             serial,
             "Copying bank {:?} image [Address {:?}, size {:?}]\r\n* Input: [{}]\r\n* Output: [{}]",
@@ -78,24 +158,191 @@ impl<
             I::label(),
             O::label(),
         );
-        let input_image_start_address = input_bank.location;
-        let output_image_start_address = output_bank.location;
+        let footer = input_image.copied_footer();
+        if footer.total_size > output_bank.size {
+            return Err(Error::ImageTooBig);
+        }
+        let mut transfer = DualFlashTransfer {
+            input_flash,
+            output_flash: &mut *output_flash,
+            input: input_bank.location + header_size(&input_image),
+            output: output_bank.location,
+        };
+        Self::transfer(&input_image, &mut transfer, resumed_chunks, |t| {
+            progress(&mut *t.input_flash, &mut *t.output_flash)
+        })?;
+        image::write_footer(output_flash, output_bank, footer)
+    }
+
+    /// Copies the image in a bank to another bank of the same flash chip as it's
+    /// stored, without decrypting or decompressing it.
+    pub fn copy_image_verbatim_single_flash<F: Flash>(
+        flash: &mut F,
+        input_bank: image::Bank<F::Address>,
+        output_bank: image::Bank<F::Address>,
+    ) -> Result<(), Error> {
+        if input_bank.index == output_bank.index {
+            return Err(Error::DeviceError("Attempted to copy a bank into itself"));
+        }
+        let input_image = R::image_at(flash, input_bank)?;
+        if input_image.total_size() > output_bank.size {
+            return Err(Error::ImageTooBig);
+        }
+        let mut transfer = SingleFlashTransfer {
+            flash: &mut *flash,
+            input: input_bank.location,
+            output: output_bank.location,
+        };
+        Self::transfer_verbatim(&input_image, &mut transfer)?;
+        image::write_footer(flash, output_bank, input_image.footer())
+    }
 
-        // Large transfer buffer ensures that the number of read-write cycles needed
-        // to guarantee flash integrity through the process is minimal.
-        const TRANSFER_BUFFER_SIZE: usize = KB!(64);
+    /// Copies the image in a bank to a bank of another flash chip as it's stored,
+    /// without decrypting or decompressing it.
+    pub fn copy_image_verbatim<I: Flash, O: Flash>(
+        input_flash: &mut I,
+        output_flash: &mut O,
+        input_bank: image::Bank<I::Address>,
+        output_bank: image::Bank<O::Address>,
+    ) -> Result<(), Error> {
+        let input_image = R::image_at(input_flash, input_bank)?;
+        if input_image.total_size() > output_bank.size {
+            return Err(Error::ImageTooBig);
+        }
+        let mut transfer = DualFlashTransfer {
+            input_flash,
+            output_flash: &mut *output_flash,
+            input: input_bank.location,
+            output: output_bank.location,
+        };
+        Self::transfer_verbatim(&input_image, &mut transfer)?;
+        image::write_footer(output_flash, output_bank, input_image.footer())
+    }
+
+    /// Copies an image byte for byte, including its encryption header, if any.
+    fn transfer_verbatim<A: Address, X: Transfer>(
+        image: &Image<A>,
+        transfer: &mut X,
+    ) -> Result<(), Error> {
+        let size = image.total_size();
         let mut buffer = [0u8; TRANSFER_BUFFER_SIZE];
-        let mut byte_index = 0usize;
+        let mut written = 0usize;
+        while written < size {
+            let block = &mut buffer[..min(TRANSFER_BUFFER_SIZE, size - written)];
+            WD::feed();
+            transfer.read(written, block)?;
+            transfer.write(written, block)?;
+            written += block.len();
+        }
+        WD::feed();
+        Ok(())
+    }
+
+    /// Copies the plaintext of an image: its body is decrypted and decompressed on the
+    /// fly, and its decoration and CRC/signature are copied as they are.
+    ///
+    /// The output is written in chunks made of whole erase sectors, so a reset while
+    /// writing a chunk never loses any earlier one. `progress` is called after writing
+    /// each chunk but the last, as writing the footer of the output bank next could
+    /// still erase its sector. The first `resumed_chunks` chunks are skipped.
+    fn transfer<A: Address, X: Transfer>(
+        image: &Image<A>,
+        transfer: &mut X,
+        resumed_chunks: usize,
+        mut progress: impl FnMut(&mut X),
+    ) -> Result<(), Error> {
+        let output_size = image.copied_footer().total_size;
+        let mut reader = PlaintextReader::new(image);
+        let mut buffer = [0u8; TRANSFER_BUFFER_SIZE];
+        let (mut written, mut chunk) = (0usize, 0usize);
+        while written < output_size {
+            let chunk_end =
+                min(transfer.sector_end(written + TRANSFER_BUFFER_SIZE - 1), output_size);
+            while written < chunk_end {
+                let block = &mut buffer[..min(TRANSFER_BUFFER_SIZE, chunk_end - written)];
+                // Chunks written before an interruption are still read, as the plaintext
+                // can only be produced in order.
+                let read_flash = |offset, bytes: &mut [u8]| {
+                    WD::feed();
+                    transfer.read(offset, bytes)
+                };
+                reader.read(read_flash, block)?;
+                if chunk >= resumed_chunks {
+                    transfer.write(written, block)?;
+                    WD::feed();
+                }
+                written += block.len();
+            }
+            if chunk >= resumed_chunks && written < output_size {
+                progress(transfer);
+            }
+            chunk += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Plaintext of an image as a stream, decrypted and decompressed as it's read from flash.
+pub(super) struct PlaintextReader {
+    decryptor: Option<Decryptor>,
+    inflater: Option<Inflater>,
+    /// Size of the image in flash, excluding its encryption header.
+    size: usize,
+    input: [u8; INPUT_BUFFER_SIZE],
+    /// Bytes read from flash, and the range of them not yet processed.
+    read: usize,
+    pending_start: usize,
+    pending_end: usize,
+}
 
-        let total_size = input_image.total_size();
+impl PlaintextReader {
+    pub(super) fn new<A: Address>(image: &Image<A>) -> Self {
+        Self {
+            decryptor: image.encryption().as_ref().map(Decryptor::new),
+            inflater: image.compression().as_ref().map(Inflater::new),
+            size: image.total_size() - header_size(image),
+            input: [0u8; INPUT_BUFFER_SIZE],
+            read: 0,
+            pending_start: 0,
+            pending_end: 0,
+        }
+    }
+
+    /// Fills `bytes` with the next plaintext bytes. `read_flash` reads the image from
+    /// flash, at an offset from the end of its encryption header (if any).
+    pub(super) fn read(
+        &mut self,
+        mut read_flash: impl FnMut(usize, &mut [u8]) -> Result<(), Error>,
+        bytes: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut filled = 0usize;
+        while filled < bytes.len() {
+            if self.pending_start == self.pending_end && self.read < self.size {
+                let block = &mut self.input[..min(INPUT_BUFFER_SIZE, self.size - self.read)];
+                read_flash(self.read, block)?;
+                if let Some(decryptor) = self.decryptor.as_mut() {
+                    decryptor.apply(block);
+                }
+                self.read += block.len();
+                self.pending_start = 0;
+                self.pending_end = block.len();
+            }
 
-        while byte_index < total_size {
-            let bytes_to_read = min(TRANSFER_BUFFER_SIZE, total_size.saturating_sub(byte_index));
-            block!(input_flash
-                .read(input_image_start_address + byte_index, &mut buffer[0..bytes_to_read]))?;
-            block!(output_flash
-                .write(output_image_start_address + byte_index, &buffer[0..bytes_to_read]))?;
-            byte_index += bytes_to_read;
+            let pending = &self.input[self.pending_start..self.pending_end];
+            let output = &mut bytes[filled..];
+            let (consumed, produced) = match self.inflater.as_mut() {
+                Some(inflater) => inflater.apply(pending, output),
+                None => {
+                    let length = min(pending.len(), output.len());
+                    output[..length].copy_from_slice(&pending[..length]);
+                    (length, length)
+                }
+            };
+            if consumed == 0 && produced == 0 {
+                return Err(Error::DeviceError("Image ended unexpectedly"));
+            }
+            self.pending_start += consumed;
+            filled += produced;
         }
         Ok(())
     }
@@ -0,0 +1,49 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Picks the image to boot among all bootable banks, given the image in the main one
+    /// (if valid). Images in additional bootable banks are booted in place if they have a
+    /// strictly higher version, so unversioned images never supersede the main bank.
+    pub(super) fn select_bootable_image(
+        &mut self,
+        main_image: Option<Image<MCUF::Address>>,
+    ) -> Option<Image<MCUF::Address>> {
+        let boot_bank = self.boot_bank();
+        let mut selected: Option<(u8, Image<MCUF::Address>)> =
+            main_image.map(|image| (boot_bank.index, image));
+        for bank in self.bootable_banks().filter(|b| b.index != boot_bank.index) {
+            let image = match R::image_at(&mut self.mcu_flash, bank) {
+                Ok(image) => image,
+                Err(_) => continue,
+            };
+            let executable = !image.is_encrypted() && !image.is_compressed() && !image.is_patch();
+            if !executable || self.check_trust(&image).is_err() {
+                continue;
+            }
+            if selected.as_ref().is_none_or(|(_, s)| image.version() > s.version()) {
+                selected = Some((bank.index, image));
+            }
+        }
+
+        match selected {
+            Some((index, image)) if index != boot_bank.index => {
+                duprintln!(self.serial, "Booting the newer image in place from bank {:?}.", index);
+                self.boot_metrics.boot_path = BootPath::InPlace { bank: index };
+                // The image on trial (if any) is in the main bank, and isn't being booted.
+                self.end_trial();
+                Some(image)
+            }
+            selected => selected.map(|(_, image)| image),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use super::{copy::TRANSFER_BUFFER_SIZE, *};
+use crate::devices::{
+    storage::{CopyJournal, Interrupted, MAX_JOURNALED_CHUNKS},
+    update_signal::ReadUpdateSignal,
+};
+
+/// Index of the scratch bank, which is not part of the regular bank sequence.
+pub(super) const SCRATCH_INDEX: u8 = 0;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Copies the image in one bank to another, identified by index, across flash
+    /// chips if needed. If the copy journal is enabled, the copy can be resumed
+    /// should it be interrupted by a reset.
+    pub(super) fn copy_bank(
+        &mut self,
+        input: u8,
+        output: u8,
+        must_be_golden: bool,
+    ) -> Result<(), Error> {
+        let result = self.journaled_copy(input, output, must_be_golden, 0);
+        if result.is_err() {
+            self.record_in_journal(|journal, flash| journal.end(flash));
+        }
+        result
+    }
+
+    /// Copies the image in one bank to another, recording its progress in the copy
+    /// journal (if enabled). The first `resumed_chunks` chunks were already written
+    /// by an interrupted copy, and the journal already records its start.
+    pub(super) fn journaled_copy(
+        &mut self,
+        input: u8,
+        output: u8,
+        must_be_golden: bool,
+        resumed_chunks: usize,
+    ) -> Result<(), Error> {
+        if resumed_chunks == 0 {
+            self.record_in_journal(|journal, flash| journal.start_copy(flash, input, output));
+        }
+        let journal = self.journal;
+        let record_chunk = |flash: &mut MCUF| {
+            // A missing entry only means an interrupted copy resumes from an earlier chunk.
+            if let Some(journal) = journal {
+                journal.record_chunk(flash).ok();
+            }
+        };
+
+        let (mcu_input, mcu_output) = (self.mcu_bank(input), self.mcu_bank(output));
+        let external_input = self.external_bank(input);
+        let external_output = self.external_bank(output);
+        let serial = &mut self.serial;
+        match (mcu_input, mcu_output) {
+            (Some(input_bank), Some(output_bank)) => Self::copy_image_single_flash(
+                serial,
+                &mut self.mcu_flash,
+                input_bank,
+                output_bank,
+                must_be_golden,
+                resumed_chunks,
+                record_chunk,
+            )?,
+            (None, Some(output_bank)) => Self::copy_image(
+                serial,
+                self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?,
+                &mut self.mcu_flash,
+                external_input.ok_or(Error::BankInvalid)?,
+                output_bank,
+                must_be_golden,
+                resumed_chunks,
+                |_, flash| record_chunk(flash),
+            )?,
+            (Some(input_bank), None) => Self::copy_image(
+                serial,
+                &mut self.mcu_flash,
+                self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?,
+                input_bank,
+                external_output.ok_or(Error::BankInvalid)?,
+                must_be_golden,
+                resumed_chunks,
+                |flash, _| record_chunk(flash),
+            )?,
+            (None, None) => return Err(Error::BankInvalid),
+        }
+        self.record_in_journal(|journal, flash| journal.finish_copy(flash));
+        Ok(())
+    }
+
+    /// Resumes the copy or swap interrupted by the last reset, if any. If it can't be
+    /// resumed, it's rolled back as far as possible: an interrupted swap leaves the
+    /// previous image in the boot bank, and an interrupted copy is abandoned, leaving
+    /// its output bank to be restored as usual.
+    pub(super) fn resume_interrupted_operation(&mut self) {
+        let interrupted = match self.journal.map(|j| j.interrupted(&mut self.mcu_flash)) {
+            None | Some(Ok(Interrupted::None)) => return,
+            Some(Ok(interrupted)) => interrupted,
+            Some(Err(e)) => {
+                warn!("Failed to read the copy journal.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+                return;
+            }
+        };
+
+        match interrupted {
+            Interrupted::Swap { bank, copy, finished } => {
+                duprintln!(self.serial, "Resuming interrupted swap with bank {:?}...", bank);
+                let steps = self.swap_steps(bank);
+                let position = |copy| steps.iter().position(|&step| step == copy);
+                let (step, resumed_chunks) = match (copy, finished) {
+                    (Some(copy), _) => (position((copy.input, copy.output)), copy.chunks),
+                    (None, Some(last)) => (position(last).map(|step| step + 1), 0),
+                    (None, None) => (Some(0), 0),
+                };
+                match (step, copy) {
+                    (Some(step), _) => {
+                        self.continue_swap(bank, step, resumed_chunks).ok();
+                    }
+                    // Interrupted while rolling back a swap.
+                    (None, Some(copy)) => self.resume_copy(copy.input, copy.output, copy.chunks),
+                    (None, None) => self.record_in_journal(|journal, flash| journal.end(flash)),
+                }
+            }
+            Interrupted::Copy(copy) => self.resume_copy(copy.input, copy.output, copy.chunks),
+            Interrupted::None => (),
+        }
+    }
+
+    fn resume_copy(&mut self, input: u8, output: u8, resumed_chunks: usize) {
+        duprintln!(
+            self.serial,
+            "Resuming interrupted copy from bank {:?} to bank {:?}...",
+            input,
+            output
+        );
+        if self.journaled_copy(input, output, false, resumed_chunks).is_err() {
+            duprintln!(self.serial, "Failed to resume the copy. Abandoning it.");
+        }
+        self.record_in_journal(|journal, flash| journal.end(flash));
+    }
+
+    /// Records an entry in the copy journal, if enabled. Failing to do so is reported
+    /// but not fatal, as the journal only matters if the operation is interrupted.
+    pub(super) fn record_in_journal(
+        &mut self,
+        record: impl FnOnce(CopyJournal<MCUF::Address>, &mut MCUF) -> Result<(), Error>,
+    ) {
+        if let Some(journal) = self.journal {
+            if let Err(e) = record(journal, &mut self.mcu_flash) {
+                warn!("Failed to record progress in the copy journal.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
+    }
+
+    /// Whether the journal can record every chunk of a copy to the boot bank.
+    pub(super) fn journal_fits_boot_bank(&self) -> bool {
+        let max_size = MAX_JOURNALED_CHUNKS * TRANSFER_BUFFER_SIZE;
+        self.journal.is_none() || self.boot_bank().size <= max_size
+    }
+
+    /// MCU bank with a given index, including the scratch bank.
+    fn mcu_bank(&self, index: u8) -> Option<Bank<MCUF::Address>> {
+        if index == SCRATCH_INDEX {
+            self.scratch_bank
+        } else {
+            self.mcu_banks().find(|b| b.index == index)
+        }
+    }
+
+    fn external_bank(&self, index: u8) -> Option<Bank<EXTF::Address>> {
+        self.external_banks().find(|b| b.index == index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{
+        bootloader::doubles::*,
+        image::{image_crc::CrcImageReader, Reader, Trailer},
+        storage::Storage,
+    };
+    use blue_hal::hal::doubles::flash::Address;
+
+    const STORAGE: Storage<Address> =
+        Storage { clearable: Address(0), permanent: Address(TEST_SECTOR_SIZE as u32) };
+    const BANK_SIZE: usize = 3 * TEST_SECTOR_SIZE;
+    static MCU_BANKS: [Bank<Address>; 2] = [
+        Bank {
+            index: 1,
+            size: BANK_SIZE,
+            location: Address(2 * TEST_SECTOR_SIZE as u32),
+            bootable: true,
+            is_golden: false,
+        },
+        Bank {
+            index: 2,
+            size: BANK_SIZE,
+            location: Address(2 * TEST_SECTOR_SIZE as u32 + BANK_SIZE as u32),
+            bootable: false,
+            is_golden: false,
+        },
+    ];
+
+    #[test]
+    fn interrupted_copies_resume_without_losing_written_sectors() {
+        let previous = crc_image(&body(BANK_SIZE - KB!(1), 1), &Trailer::default());
+        let image = crc_image(&body(2 * TEST_SECTOR_SIZE + KB!(44), 0), &Trailer::default());
+        // Power is lost at every write after the first (recording the start of the copy).
+        for writes in 1.. {
+            let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+            bootloader.journal = Some(STORAGE.copy_journal());
+            write_image(&mut bootloader.mcu_flash, MCU_BANKS[0], &previous);
+            write_image(&mut bootloader.mcu_flash, MCU_BANKS[1], &image);
+            let original = CrcImageReader::image_at(&mut bootloader.mcu_flash, MCU_BANKS[1]);
+
+            bootloader.mcu_flash.lose_power_after(writes);
+            let result = bootloader.copy_bank(2, 1, false);
+            if bootloader.mcu_flash.powered() {
+                assert_eq!(result, Ok(()));
+                break;
+            }
+            bootloader.mcu_flash.restore_power();
+            bootloader.resume_interrupted_operation();
+
+            let copy = CrcImageReader::image_at(&mut bootloader.mcu_flash, MCU_BANKS[0]);
+            assert_eq!(copy.map(|c| c.identifier()), original.map(|o| o.identifier()));
+            let journal = STORAGE.copy_journal();
+            assert_eq!(journal.interrupted(&mut bootloader.mcu_flash), Ok(Interrupted::None));
+        }
+    }
+}
@@ -7,12 +7,19 @@
 use super::{
     boot_metrics::{boot_metrics_mut, BootMetrics, BootPath},
     image::{self, Bank, Image},
-    traits::{Flash, Serial},
+    storage::{
+        BootAttempts, CopyJournal, MonotonicCounter, RecoveryTimeouts, RevocationList, TrialLog,
+    },
+    traits::{Flash, Serial, Watchdog},
+};
+use crate::{
+    devices::update_signal::{ReadUpdateSignal, UpdatePlan},
+    error::Error,
 };
-use crate::{devices::update_signal::ReadUpdateSignal, error::Error};
 use blue_hal::{
     duprintln,
     hal::{flash, time},
+    utilities::memory::Address,
     KB,
 };
 use core::{cmp::min, marker::PhantomData, mem::size_of};
@@ -21,12 +28,32 @@ use defmt::{info, warn};
 use nb::block;
 use ufmt::uwriteln;
 
+/// Operations related to counting boot attempts, and falling back to the golden image.
+mod attempts;
+/// Operations related to rejecting images built for different hardware.
+mod compatibility;
 /// Operations related to copying images between flash chips.
 mod copy;
+/// Operations related to booting images in place from additional bootable banks.
+mod in_place;
+/// Operations related to journaling copies, and resuming them after a reset.
+mod journal;
+/// Operations related to applying delta patches to the current image.
+mod patch;
 /// Operations related to serial recovery when there's no fallback to restore to.
 mod recover;
+/// Operations requested by the application through the update signal, other than updating.
+mod signal;
+/// Operations related to anti-rollback protection.
+mod rollback;
+/// Operations related to verifying key revocation.
+mod revocation;
 /// Operations related to restoring an image when there's no current one to boot.
 mod restore;
+/// Operations related to swapping the current image with an update.
+mod swap;
+/// Operations related to the trial of updated images.
+mod trial;
 /// Operations related to updating images with newer ones.
 mod update;
 
@@ -39,6 +66,7 @@ pub struct Bootloader<
     T: time::Now,
     R: image::Reader,
     RUS: ReadUpdateSignal,
+    WD: Watchdog,
 > {
     pub(crate) mcu_flash: MCUF,
     pub(crate) external_banks: &'static [image::Bank<<EXTF as flash::ReadWrite>::Address>],
@@ -48,9 +76,23 @@ pub struct Bootloader<
     pub(crate) boot_metrics: BootMetrics,
     pub(crate) start_time: Option<T::I>,
     pub(crate) recovery_enabled: bool,
+    pub(crate) recovery_button_held: bool,
+    pub(crate) recovery_window_ms: Option<u32>,
+    pub(crate) restore_after_recovery_timeout: bool,
+    pub(crate) in_place_recovery: bool,
     pub(crate) update_signal: Option<RUS>,
+    pub(crate) one_shot_update_plans: bool,
+    pub(crate) default_update_plan: UpdatePlan,
+    pub(crate) security_counter: Option<MonotonicCounter<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) revoked_keys: Option<RevocationList<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) hardware_id: Option<u32>,
+    pub(crate) scratch_bank: Option<image::Bank<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) trial_log: Option<TrialLog<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) journal: Option<CopyJournal<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) boot_attempts: Option<BootAttempts<<MCUF as flash::ReadWrite>::Address>>,
+    pub(crate) recovery_timeouts: Option<RecoveryTimeouts<<MCUF as flash::ReadWrite>::Address>>,
     pub(crate) greeting: &'static str,
-    pub(crate) _marker: PhantomData<R>,
+    pub(crate) _marker: PhantomData<(R, WD)>,
 }
 
 impl<
@@ -60,15 +102,17 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
 {
     /// Main bootloader routine.
     ///
     /// In case the MCU flash's main bank contains a valid image, an update is attempted.
-    /// (Any valid image with a different signature in the top occupied external bank is
-    /// considered "newer" for the purposes of updating). The golden image, if available,
-    /// is *never* considered newer than the current MCU image, as it exists only as a final
-    /// resort fallback.
+    /// The highest versioned valid image across MCU and external banks is considered
+    /// "newer" for the purposes of updating, if its version is higher than the current one.
+    /// (Unversioned images fall back to considering any valid image with a different
+    /// signature as newer). The golden image, if available, is *never* considered newer
+    /// than the current MCU image, as it exists only as a final resort fallback.
     ///
     /// After attempting or skipping the update process, the bootloader attempts to boot
     /// the current MCU image. In case of failure, the following steps are attempted:
@@ -77,11 +121,56 @@ impl<
     /// image, copy it to bootable MCU flash bank and attempt to boot it.
     /// * Verify golden image. If valid, copy to bootable MCU flash bank and attempt to boot.
     /// * If golden image not available or invalid, proceed to recovery mode.
+    ///
+    /// If anti-rollback protection is enabled, non-golden images with a security epoch
+    /// lower than the security counter are never booted, and booting an image raises
+    /// the security counter to its epoch. If key revocation is enabled, images signed
+    /// with a revoked key are never booted. If Loadstone is configured with a hardware ID,
+    /// images built for different hardware are never booted.
+    ///
+    /// If trial boots are enabled, updated images are on trial until the application
+    /// confirms them. An image that isn't confirmed within the allowed number of boots
+    /// is replaced with the previous image if available, or the golden image otherwise.
+    ///
+    /// If the copy journal is enabled, a copy or swap interrupted by a reset is resumed
+    /// (or rolled back, if it can't be resumed) before any image is verified.
+    ///
+    /// If there are additional bootable banks, holding images linked to execute from them,
+    /// the newest valid image across all bootable banks is booted in place, with no copy.
+    /// Updates and restored images are only ever copied to the main bootable bank.
+    ///
+    /// If the boot attempt counter is enabled, every boot is counted until the application
+    /// resets the counter. Once the allowed attempts are used up, the golden image is
+    /// restored and booted instead of the current image, skipping any update.
+    ///
+    /// The update signal can also request restoring the golden image, entering recovery
+    /// mode, or rolling back to the newest image older than the current one, instead of
+    /// updating. If update plans are one-shot, the plan is cleared once Loadstone acts on
+    /// it. Either way, whether the plan was honoured is reported in the boot metrics. If
+    /// the update signal holds no valid plan, the configured default plan is followed.
     pub fn run(mut self) -> ! {
         self.verify_bank_correctness();
         duprintln!(self.serial, "");
         duprintln!(self.serial, "{}", self.greeting);
-        if let Some(image) = self.latest_bootable_image() {
+        self.resume_interrupted_operation();
+        if self.recovery_button_held && self.recovery_enabled {
+            duprintln!(self.serial, "Recovery button held at reset.");
+            self.recover();
+        }
+        if self.recovery_enabled && self.recovery_timeouts_exhausted() {
+            duprintln!(self.serial, "Recovery previously timed out too many times.");
+            self.recover();
+        }
+        self.revert_failed_trial();
+        let plan = self.read_update_plan();
+        let image = self
+            .fall_back_to_golden()
+            .or_else(|| self.follow_update_plan(plan))
+            .or_else(|| {
+                let image = self.latest_bootable_image(plan);
+                self.select_bootable_image(image)
+            });
+        if let Some(image) = image {
             duprintln!(self.serial, "Attempting to boot from default bank.");
             match self.boot(image).unwrap_err() {
                 Error::BankInvalid => {
@@ -93,10 +182,30 @@ impl<
                 Error::SignatureInvalid => {
                     info!("Signature invalid for stored image. Restoring image...")
                 }
+                Error::ImageRollbackRejected => {
+                    info!("Stored image is a rollback. Restoring image...")
+                }
+                Error::ImageIsEncrypted => {
+                    info!("Stored image is encrypted. Restoring image...")
+                }
+                Error::ImageIsCompressed => {
+                    info!("Stored image is compressed. Restoring image...")
+                }
+                Error::ImageIsPatch => {
+                    info!("Stored image is a patch. Restoring image...")
+                }
+                Error::KeyRevoked => {
+                    info!("Stored image is signed with a revoked key. Restoring image...")
+                }
+                Error::ImageIncompatible => {
+                    info!("Stored image is built for different hardware. Restoring image...")
+                }
                 _ => info!("Unexpected boot error. Restoring image..."),
             };
         }
 
+        // Whatever ends up booted from here on isn't the image on trial.
+        self.end_trial();
         match self.restore() {
             Ok(image) => self.boot(image).expect("FATAL: Failed to boot from verified image!"),
             Err(e) => {
@@ -117,8 +226,9 @@ impl<
             + self.mcu_banks.iter().filter(|b| b.is_golden).count();
         assert!(total_golden <= 1);
 
-        // There is only one bootable MCU bank
-        assert_eq!(self.mcu_banks().filter(|b| b.bootable).count(), 1);
+        // There is at least one bootable MCU bank, and none of them is golden
+        assert!(self.bootable_banks().count() >= 1, "No bootable bank!");
+        assert!(self.bootable_banks().all(|b| !b.is_golden), "Bootable banks can't be golden!");
 
         // Banks are sequential across flash chips
         let all_bank_indices =
@@ -128,6 +238,20 @@ impl<
             current
         });
 
+        // The scratch bank (if any) can hold the contents of the boot bank
+        if let Some(scratch_bank) = self.scratch_bank {
+            assert!(scratch_bank.size >= self.boot_bank().size, "Scratch bank is too small!");
+        }
+
+        // Recovery (if enabled) has a bank to stage images in, unless it may write them in place
+        assert!(
+            !self.recovery_enabled || self.in_place_recovery || self.can_stage_recovered_images(),
+            "No bank to stage recovered images in!"
+        );
+
+        // The copy journal (if any) can track copies to the boot bank
+        assert!(self.journal_fits_boot_bank(), "Boot bank is too large for the copy journal!");
+
         // Either there's external flash, or there's no external flash and no banks.
         assert!(
             self.external_flash.is_some()
@@ -138,6 +262,22 @@ impl<
 
     /// Boots into a given memory bank.
     pub fn boot(&mut self, image: Image<MCUF::Address>) -> Result<!, Error> {
+        if image.is_encrypted() {
+            return Err(Error::ImageIsEncrypted);
+        }
+        if image.is_compressed() {
+            return Err(Error::ImageIsCompressed);
+        }
+        if image.is_patch() {
+            return Err(Error::ImageIsPatch);
+        }
+        self.check_trust(&image)?;
+        // Images on trial don't raise the security counter, so they can be reverted.
+        if !self.record_trial_boot() {
+            self.raise_security_counter(&image);
+        }
+        self.record_boot_attempt();
+        self.reset_recovery_timeouts();
         warn!("Jumping to a new firmware image. This will break `defmt`.");
         let image_location_raw: usize = image.location().into();
         let time_ms = self.start_time.and_then(|t| Some((T::now() - t).0));
@@ -159,8 +299,22 @@ impl<
         }
     }
 
+    /// Checks that a verified image is still trusted: it must be built for this hardware,
+    /// not be signed with a revoked key, nor be rejected by anti-rollback protection.
+    pub fn check_trust<A: Address>(&mut self, image: &Image<A>) -> Result<(), Error> {
+        self.check_compatibility(image)?;
+        self.check_revocation(image)?;
+        self.check_rollback(image)
+    }
+
+    /// Returns the main bootable bank, which updates and restored images are copied to.
     pub fn boot_bank(&self) -> image::Bank<MCUF::Address> {
-        self.mcu_banks().find(|b| b.bootable).unwrap()
+        self.bootable_banks().next().unwrap()
+    }
+
+    /// Returns an iterator of all bootable MCU flash banks, starting with the main one.
+    pub fn bootable_banks(&self) -> impl Iterator<Item = image::Bank<MCUF::Address>> {
+        self.mcu_banks().filter(|b| b.bootable)
     }
 
     /// Returns an iterator of all MCU flash banks.
@@ -183,55 +337,220 @@ pub mod doubles {
             doubles::{
                 error::FakeError,
                 flash::{Address, FakeFlash},
-                serial::SerialStub,
                 time::MockSysTick,
             },
-            null::NullFlash,
+            flash::ReadWrite,
+            serial,
+            time::Milliseconds,
         },
-        utilities::memory::doubles::FakeAddress,
+        utilities::xmodem,
+        KB,
     };
+    use core::cmp::{max, min};
+    use crc::crc32;
+    use std::collections::VecDeque;
+
+    /// Update signal holding the plan set by the test, if any, until cleared.
+    pub struct FakeUpdateSignal(pub Option<UpdatePlan>);
+    impl ReadUpdateSignal for FakeUpdateSignal {
+        fn read_update_plan(&mut self) -> Option<UpdatePlan> { self.0 }
+        fn clear_update_plan(&mut self) { self.0 = None }
+    }
+
+    /// Fake flash writes bytes in place, so it never loses anything outside of them.
+    impl EraseSectors for FakeFlash {
+        fn sector_end(address: Address) -> Address { address + 1 }
+    }
+
+    /// Size of the erase sectors of [`SectorFlash`], as large as those of most MCU flash.
+    pub const TEST_SECTOR_SIZE: usize = KB!(128);
+
+    /// Flash that, like MCU flash, erases and rewrites a whole sector to write to it,
+    /// unless the write only clears bits, and rejects writes that don't start at a word
+    /// boundary. It can be made to lose power in the middle of a write, which leaves the
+    /// sector being rewritten erased, and fails any access until power is restored.
+    pub struct SectorFlash {
+        data: Vec<u8>,
+        writes_before_power_loss: Option<usize>,
+        powered: bool,
+    }
+
+    impl SectorFlash {
+        pub fn new(sectors: usize) -> Self {
+            Self {
+                data: vec![0xFF; sectors * TEST_SECTOR_SIZE],
+                writes_before_power_loss: None,
+                powered: true,
+            }
+        }
 
-    pub struct FakeReader;
+        /// Loses power in the middle of the write after the next `writes` ones.
+        pub fn lose_power_after(&mut self, writes: usize) {
+            self.writes_before_power_loss = Some(writes);
+        }
 
-    impl Reader for FakeReader {
-        fn image_at<A, F>(_flash: &mut F, _bank: Bank<A>) -> Result<Image<A>, error::Error>
-        where
-            A: blue_hal::utilities::memory::Address,
-            F: blue_hal::hal::flash::ReadWrite<Address = A>,
-            error::Error: From<F::Error>,
-        {
-            unimplemented!()
+        pub fn restore_power(&mut self) {
+            self.writes_before_power_loss = None;
+            self.powered = true;
         }
+
+        pub fn powered(&self) -> bool { self.powered }
     }
 
-    pub struct FakeUpdateSignal;
-    impl ReadUpdateSignal for FakeUpdateSignal {
-        fn read_update_plan(&self) -> UpdatePlan { UpdatePlan::Any }
+    impl ReadWrite for SectorFlash {
+        type Error = FakeError;
+        type Address = Address;
+
+        fn read(&mut self, address: Address, bytes: &mut [u8]) -> nb::Result<(), FakeError> {
+            let start = address.0 as usize;
+            if !self.powered || start + bytes.len() > self.data.len() {
+                return Err(nb::Error::Other(FakeError));
+            }
+            bytes.copy_from_slice(&self.data[start..start + bytes.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), FakeError> {
+            let (start, end) = (address.0 as usize, address.0 as usize + bytes.len());
+            if !self.powered || end > self.data.len() || start % 4 != 0 {
+                return Err(nb::Error::Other(FakeError));
+            }
+            let losing_power = self.writes_before_power_loss == Some(0);
+            self.writes_before_power_loss =
+                self.writes_before_power_loss.and_then(|w| w.checked_sub(1));
+            let mut sector = start - start % TEST_SECTOR_SIZE;
+            while sector < end {
+                let sector_end = sector + TEST_SECTOR_SIZE;
+                let (from, to) = (max(start, sector), min(end, sector_end));
+                let input = &bytes[from - start..to - start];
+                let only_clears_bits =
+                    self.data[from..to].iter().zip(input).all(|(old, new)| new & !old == 0);
+                let mut contents = self.data[sector..sector_end].to_vec();
+                contents[from - sector..to - sector].copy_from_slice(input);
+                if !only_clears_bits {
+                    self.data[sector..sector_end].iter_mut().for_each(|byte| *byte = 0xFF);
+                }
+                if losing_power {
+                    self.powered = false;
+                    return Err(nb::Error::Other(FakeError));
+                }
+                self.data[sector..sector_end].copy_from_slice(&contents);
+                sector = sector_end;
+            }
+            Ok(())
+        }
+
+        fn range(&self) -> (Address, Address) { (Address(0), Address(self.data.len() as u32)) }
+
+        fn erase(&mut self) -> nb::Result<(), FakeError> {
+            self.data.iter_mut().for_each(|byte| *byte = 0xFF);
+            Ok(())
+        }
+
+        fn write_from_blocks<I: Iterator<Item = [u8; N]>, const N: usize>(
+            &mut self,
+            address: Address,
+            blocks: I,
+        ) -> Result<(), FakeError> {
+            for (i, block) in blocks.enumerate() {
+                nb::block!(self.write(address + i * N, &block))?;
+            }
+            Ok(())
+        }
+
+        fn label() -> &'static str { "Sector Flash" }
+    }
+
+    impl EraseSectors for SectorFlash {
+        fn sector_end(address: Address) -> Address {
+            address + (TEST_SECTOR_SIZE - address.0 as usize % TEST_SECTOR_SIZE)
+        }
+    }
+
+    /// Serial that plays the sender of an XMODEM transfer. It sends the packets of the
+    /// image queued with [`SerialDouble::send`] regardless of what it's told, then ends
+    /// the transmission (straight away, if nothing was queued).
+    #[derive(Default)]
+    pub struct SerialDouble {
+        sent: VecDeque<u8>,
+    }
+
+    impl SerialDouble {
+        /// Queues an image to be sent in XMODEM packets, padding the last one with 0xFF.
+        pub fn send(&mut self, image: &[u8]) {
+            for (i, chunk) in image.chunks(xmodem::PAYLOAD_SIZE).enumerate() {
+                let mut payload = [0xFF; xmodem::PAYLOAD_SIZE];
+                payload[..chunk.len()].copy_from_slice(chunk);
+                let block_number = (i + 1) as u8;
+                let checksum = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                self.sent.extend([xmodem::SOH, block_number, !block_number]);
+                self.sent.extend(payload);
+                self.sent.push_back(checksum);
+            }
+        }
+    }
+
+    impl serial::Write for SerialDouble {
+        type Error = FakeError;
+        fn write_str(&mut self, _: &str) -> Result<(), FakeError> { Ok(()) }
+    }
+
+    impl serial::Read for SerialDouble {
+        type Error = FakeError;
+        fn read(&mut self) -> nb::Result<u8, FakeError> {
+            Ok(self.sent.pop_front().unwrap_or(xmodem::EOT))
+        }
+    }
+
+    impl serial::TimeoutRead for SerialDouble {
+        type Error = FakeError;
+        fn read<T: Copy + Into<Milliseconds>>(&mut self, _: T) -> Result<u8, FakeError> {
+            Ok(self.sent.pop_front().unwrap_or(xmodem::EOT))
+        }
     }
 
     pub type BootloaderDouble = super::Bootloader<
         FakeFlash,
-        FakeFlash,
-        SerialStub,
+        SectorFlash,
+        SerialDouble,
         MockSysTick,
-        FakeReader,
+        CrcImageReader,
         FakeUpdateSignal,
+        NullWatchdog,
     >;
 
+    impl Default for BootloaderDouble {
+        fn default() -> Self { Self::new() }
+    }
+
     impl BootloaderDouble {
         pub fn new() -> Self {
             BootloaderDouble {
-                mcu_flash: FakeFlash::new(Address(0)),
+                mcu_flash: SectorFlash::new(16),
                 external_banks: &[],
                 mcu_banks: &[],
                 external_flash: Some(FakeFlash::new(Address(0))),
-                serial: Some(SerialStub),
+                serial: Some(SerialDouble::default()),
                 boot_metrics: BootMetrics::default(),
                 start_time: None,
                 recovery_enabled: false,
+                recovery_button_held: false,
+                recovery_window_ms: None,
+                restore_after_recovery_timeout: false,
+                in_place_recovery: false,
                 greeting: "I'm a fake bootloader!",
                 _marker: Default::default(),
                 update_signal: None,
+                one_shot_update_plans: false,
+                default_update_plan: UpdatePlan::Any,
+                security_counter: None,
+                revoked_keys: None,
+                hardware_id: None,
+                scratch_bank: None,
+                trial_log: None,
+                journal: None,
+                boot_attempts: None,
+                recovery_timeouts: None,
             }
         }
 
@@ -244,10 +563,78 @@ pub mod doubles {
         }
     }
 
+    /// Image of a given body and trailer, followed by the magic string and its CRC.
+    pub fn crc_image(body: &[u8], trailer: &Trailer) -> Vec<u8> {
+        let mut image = body.to_vec();
+        trailer.encode(|bytes| image.extend_from_slice(bytes)).unwrap();
+        image.extend_from_slice(&magic_string_inverted());
+        let crc = crc32::checksum_ieee(&image);
+        image.extend_from_slice(&crc.to_le_bytes());
+        image
+    }
+
+    /// Image body of a given size, with the given seed and no magic string in it.
+    pub fn body(size: usize, seed: u8) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8 ^ seed).collect()
+    }
+
+    /// Writes an image to a bank, as if received by other means than a copy.
+    pub fn write_image<F: ReadWrite<Address = Address>>(
+        flash: &mut F,
+        bank: Bank<Address>,
+        image: &[u8],
+    ) where
+        error::Error: From<F::Error>,
+    {
+        image::clear_footer(flash, bank).unwrap();
+        nb::block!(flash.write(bank.location, image)).ok().unwrap();
+    }
+
+    /// Bank taking up a single sector of [`SectorFlash`]. Banks follow each other in index
+    /// order after the first two sectors, which are left for storage.
+    pub const fn bank(index: u8, bootable: bool, is_golden: bool) -> Bank<Address> {
+        let location = Address(((index as usize + 1) * TEST_SECTOR_SIZE) as u32);
+        Bank { index, size: TEST_SECTOR_SIZE, location, bootable, is_golden }
+    }
+
+    /// Image of a given major version. Its magic string doesn't start at a word boundary,
+    /// as is the case for most images.
+    pub fn versioned_image(major: u16, golden: bool) -> Vec<u8> {
+        let version = SemanticVersion { major, minor: 0, patch: 0 };
+        let trailer = Trailer { golden, version: Some(version), ..Trailer::default() };
+        crc_image(&body(KB!(4) + 17, major as u8), &trailer)
+    }
+
+    /// Writes an image of a given major version to an MCU bank.
+    pub fn write_versioned(
+        bootloader: &mut BootloaderDouble,
+        bank: Bank<Address>,
+        major: u16,
+        golden: bool,
+    ) {
+        write_image(&mut bootloader.mcu_flash, bank, &versioned_image(major, golden));
+    }
+
+    /// Major version of the image in an MCU bank, if there's a valid one.
+    pub fn version_in(bootloader: &mut BootloaderDouble, bank: Bank<Address>) -> Option<u16> {
+        let image = CrcImageReader::image_at(&mut bootloader.mcu_flash, bank).ok()?;
+        image.version().map(|v| v.major)
+    }
+
+    /// Major version of the image in the boot bank, if there's a valid one.
+    pub fn current_version(bootloader: &mut BootloaderDouble) -> Option<u16> {
+        let boot_bank = bootloader.boot_bank();
+        version_in(bootloader, boot_bank)
+    }
+
     use crate::{
         devices::{
             boot_metrics::BootMetrics,
-            image::{Bank, Image, Reader},
+            image::{
+                self, image_crc::CrcImageReader, magic_string_inverted, Bank, Reader,
+                SemanticVersion, Trailer,
+            },
+            traits::{EraseSectors, NullWatchdog},
         },
         error,
     };
@@ -0,0 +1,111 @@
+use super::{
+    copy::{header_size, PlaintextReader},
+    *,
+};
+use crate::devices::{image::magic_string_inverted, update_signal::ReadUpdateSignal};
+use loadstone_image::patch::{
+    is_copy_in_place, Operation, PatchHeader, OPERATION_SIZE, PATCH_BLOCK_SIZE, PATCH_HEADER_SIZE,
+};
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Applies the patch in an external bank to the current image, and verifies the result.
+    ///
+    /// The patch is applied in place, so the current image is lost once the patch
+    /// starts being written. If applying it fails halfway through, the boot bank is
+    /// left without a valid image, and must be restored.
+    pub(super) fn apply_patch(
+        &mut self,
+        bank: Bank<EXTF::Address>,
+        boot_bank: Bank<MCUF::Address>,
+    ) -> Result<Image<MCUF::Address>, Error> {
+        let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+        let patch = R::image_at(external_flash, bank)?;
+        let current_image = R::image_at(&mut self.mcu_flash, boot_bank)?;
+        if !patch.is_patch() || patch.base_identifier() != Some(current_image.identifier()) {
+            return Err(Error::PatchInvalid);
+        }
+
+        let body_location = bank.location + header_size(&patch);
+        let mut reader = PlaintextReader::new(&patch);
+        let mut read_patch = |bytes: &mut [u8]| {
+            reader.read(
+                |offset, bytes| Ok(block!(external_flash.read(body_location + offset, bytes))?),
+                bytes,
+            )
+        };
+
+        let mut header = [0u8; PATCH_HEADER_SIZE];
+        read_patch(&mut header)?;
+        let header = PatchHeader::from_bytes(&header).ok_or(Error::PatchInvalid)?;
+        if header.size > boot_bank.size {
+            return Err(Error::PatchInvalid);
+        }
+
+        duprintln!(
+            self.serial,
+            "Applying patch from bank {:?} to the current image [{}]",
+            bank.index,
+            MCUF::label()
+        );
+        image::clear_footer(&mut self.mcu_flash, boot_bank)?;
+
+        // Each block overwrites the current image once complete, so it must only be
+        // written after everything it copies from the current image has been read.
+        let mut output = [0u8; PATCH_BLOCK_SIZE];
+        let (mut written, mut staged) = (0usize, 0usize);
+        while written + staged < header.size {
+            WD::feed();
+            let mut operation = [0u8; OPERATION_SIZE];
+            read_patch(&mut operation)?;
+            let operation = Operation::from_bytes(&operation).ok_or(Error::PatchInvalid)?;
+            if written + staged + operation.length() > header.size {
+                return Err(Error::PatchInvalid);
+            }
+
+            let mut done = 0usize;
+            while done < operation.length() {
+                let chunk_size = min(PATCH_BLOCK_SIZE - staged, operation.length() - done);
+                let chunk = &mut output[staged..staged + chunk_size];
+                match operation {
+                    Operation::Copy { offset, .. } => {
+                        let offset = offset + done;
+                        if !is_copy_in_place(written + staged, offset)
+                            || offset + chunk_size > boot_bank.size
+                        {
+                            return Err(Error::PatchInvalid);
+                        }
+                        block!(self.mcu_flash.read(boot_bank.location + offset, chunk))?;
+                    }
+                    Operation::Insert { .. } => read_patch(chunk)?,
+                    Operation::Magic => {
+                        chunk.copy_from_slice(&magic_string_inverted()[done..done + chunk_size])
+                    }
+                }
+                done += chunk_size;
+                staged += chunk_size;
+
+                if staged == PATCH_BLOCK_SIZE || written + staged == header.size {
+                    let location = boot_bank.location + written;
+                    block!(self.mcu_flash.write(location, &output[..staged]))?;
+                    written += staged;
+                    staged = 0;
+                }
+            }
+        }
+
+        duprintln!(self.serial, "Verifying the patched image...");
+        let image = R::image_at(&mut self.mcu_flash, boot_bank)?;
+        self.check_trust(&image)?;
+        image::write_footer(&mut self.mcu_flash, boot_bank, image.footer())?;
+        Ok(image)
+    }
+}
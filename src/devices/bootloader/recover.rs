@@ -9,69 +9,125 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
 {
     /// Enters recovery mode, which requests a golden image to be transferred via serial through
     /// the XMODEM protocol, then reboot. If Loadstone has no golden image support, recovery
-    /// mode will allow flashing the bootable bank directly.
+    /// mode will allow flashing the bootable bank directly. If a recovery window is configured
+    /// and no image is received within it, Loadstone either reboots or retries restoring an
+    /// image, unless recovery timed out too many times in a row.
     pub fn recover(&mut self) -> ! {
         duprintln!(self.serial, "-- Loadstone Recovery Mode --");
 
         let mcu_golden_bank_exists = self.mcu_banks().any(|b| b.is_golden);
         let external_golden_bank_exists = self.external_banks().any(|b| b.is_golden);
         let no_golden_bank_support = !mcu_golden_bank_exists && !external_golden_bank_exists;
+        let window_ms = self.recovery_window();
 
-        if mcu_golden_bank_exists {
+        let (result, golden) = if mcu_golden_bank_exists {
             duprintln!(self.serial, "Attempting golden image recovery to MCU flash...");
-            match self.recover_internal(true) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing golden image.");
-                    self.reboot();
-                }
-                Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
-                    if let Some(serial) = self.serial.as_mut() {
-                        e.report(serial);
-                    }
-                    self.reboot();
+            (self.recover_internal(true, window_ms), true)
+        } else if self.external_flash.is_some() && external_golden_bank_exists {
+            duprintln!(self.serial, "Attempting golden image recovery to external flash...");
+            (self.recover_external(true, window_ms), true)
+        } else if no_golden_bank_support {
+            duprintln!(self.serial, "Attempting image recovery to MCU flash...");
+            (self.recover_internal(false, window_ms), false)
+        } else {
+            self.reboot();
+        };
+
+        match result {
+            Ok(_) => {
+                duprintln!(
+                    self.serial,
+                    "Finished flashing{} image.",
+                    if golden { " golden" } else { "" }
+                );
+                self.reset_recovery_timeouts();
+            }
+            Err(Error::RecoveryTimedOut) => self.recovery_timed_out(),
+            Err(Error::NoStagingBank) => {
+                // Rebooting would only lead back here, as nothing can be recovered.
+                panic!("FATAL: No bank to stage recovered images in.");
+            }
+            Err(e) => {
+                duprintln!(self.serial, "FATAL: Image did not flash correctly.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
                 }
             }
         }
 
-        if self.external_flash.is_some() && external_golden_bank_exists {
-            duprintln!(self.serial, "Attempting golden image recovery to external flash...");
-            match self.recover_external(true) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing golden image.");
-                    self.reboot();
-                }
-                Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
-                    if let Some(serial) = self.serial.as_mut() {
-                        e.report(serial);
-                    }
-                    self.reboot();
+        self.reboot();
+    }
+
+    /// Whether recovery timed out too many times in a row, so Loadstone must stay in
+    /// recovery mode until an image is received.
+    pub(super) fn recovery_timeouts_exhausted(&mut self) -> bool {
+        let recovery_timeouts = match self.recovery_timeouts {
+            Some(recovery_timeouts) => recovery_timeouts,
+            None => return false,
+        };
+        match recovery_timeouts.exhausted(&mut self.mcu_flash) {
+            Ok(exhausted) => exhausted,
+            Err(e) => {
+                warn!("Failed to read the recovery timeout counter.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
                 }
+                false
             }
         }
+    }
 
-        if no_golden_bank_support {
-            duprintln!(self.serial, "Attempting image recovery to MCU flash...");
-            match self.recover_internal(false) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing image.");
-                    self.reboot();
+    /// Resets the recovery timeout counter, as an image was received or booted. Does
+    /// nothing if the recovery timeout counter is disabled.
+    pub(super) fn reset_recovery_timeouts(&mut self) {
+        if let Some(recovery_timeouts) = self.recovery_timeouts {
+            if let Err(e) = recovery_timeouts.reset(&mut self.mcu_flash) {
+                warn!("Failed to reset the recovery timeout counter.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
                 }
-                Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
-                    if let Some(serial) = self.serial.as_mut() {
-                        e.report(serial);
-                    }
-                    self.reboot();
+            }
+        }
+    }
+
+    /// Time the image sender is given to start a transfer, or `None` to wait indefinitely.
+    fn recovery_window(&mut self) -> Option<u32> {
+        let window_ms = self.recovery_window_ms?;
+        if self.recovery_timeouts_exhausted() {
+            duprintln!(self.serial, "Recovery timed out too many times. Waiting for an image...");
+            None
+        } else {
+            Some(window_ms)
+        }
+    }
+
+    /// Records a recovery timeout, then either retries restoring an image or reboots,
+    /// so a device that hit a transient failure goes back to its normal boot path.
+    fn recovery_timed_out(&mut self) -> ! {
+        duprintln!(self.serial, "No image received within the recovery window.");
+        if let Some(recovery_timeouts) = self.recovery_timeouts {
+            if let Err(e) = recovery_timeouts.record(&mut self.mcu_flash) {
+                warn!("Failed to record the recovery timeout.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
                 }
             }
         }
 
+        if self.restore_after_recovery_timeout {
+            duprintln!(self.serial, "Retrying image restore...");
+            if let Ok(image) = self.restore() {
+                let e = self.boot(image).unwrap_err();
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
         self.reboot();
     }
 
@@ -80,75 +136,276 @@ impl<
         SCB::sys_reset();
     }
 
-    fn recover_internal(&mut self, golden: bool) -> Result<(), Error> {
+    /// Receives an image into an MCU flash bank. The image is staged in another bank (see
+    /// [`Self::staging_bank`]) and verified there first, so a failed transfer never destroys
+    /// the image in the target bank. Without a bank to stage the image in, it's only written
+    /// in place if explicitly configured.
+    fn recover_internal(&mut self, golden: bool, window_ms: Option<u32>) -> Result<(), Error> {
         if self.serial.is_none() {
             return Err(Error::NoRecoverySupport);
         }
+        let bank = *self
+            .mcu_banks
+            .iter()
+            .find(|b| b.is_golden == golden)
+            .ok_or(Error::NoGoldenBankSupport)?;
+        let staging = self.staging_bank(bank.index, bank.size);
+        if staging.is_none() && !self.in_place_recovery {
+            return Err(Error::NoStagingBank);
+        }
+        self.request_image(golden);
 
-        if let Some(bank) = self.mcu_banks.iter().find(|b| b.is_golden == golden) {
-            duprintln!(
-                self.serial,
-                "Please send{} firmware image via XMODEM.",
-                if golden { " golden" } else { "" }
-            );
-            let blocks = self.serial.as_mut().unwrap().blocks(None);
-            if self.mcu_flash.write_from_blocks(bank.location, blocks).is_err() {
-                duprintln!(
-                    self.serial,
-                    "FATAL: Failed to flash{} image during recovery mode.",
-                    if golden { " golden" } else { "" },
-                );
-                panic!();
+        let serial = &mut self.serial;
+        let flash = &mut self.mcu_flash;
+        match staging {
+            Some(staging) => {
+                let image = Self::receive_image(serial, flash, staging, golden, window_ms)?;
+                duprintln!(serial, "Image verified. Copying it to bank {:?}...", bank.index);
+                Self::copy_image_verbatim_single_flash(flash, staging, bank)?;
+                Self::discard_staged_image(serial, flash, staging, &image);
+                Self::verify_recovered_image(flash, bank, golden)
             }
-            match R::image_at(&mut self.mcu_flash, *bank) {
-                Ok(image) if golden && !image.is_golden() => {
-                    duprintln!(self.serial, "FATAL: Flashed image is not a golden image.");
-                    Err(Error::ImageIsNotGolden)
-                }
-                Err(e) => Err(e),
-                _ => Ok(()),
+            None => {
+                duprintln!(serial, "No bank to stage the image in. Writing it in place.");
+                Self::receive_image(serial, flash, bank, golden, window_ms).map(|_| ())
             }
-        } else {
-            Err(Error::NoGoldenBankSupport)
         }
     }
 
-    fn recover_external(&mut self, golden: bool) -> Result<(), Error> {
+    /// Receives an image into an external flash bank. The image is staged in an MCU flash
+    /// bank (see [`Self::staging_bank`]) and verified there first, so a failed transfer never
+    /// destroys the image in the target bank. Without a bank to stage the image in, it's only
+    /// written in place if explicitly configured.
+    fn recover_external(&mut self, golden: bool, window_ms: Option<u32>) -> Result<(), Error> {
         if self.serial.is_none() {
             return Err(Error::NoRecoverySupport);
         }
+        let bank = *self
+            .external_banks
+            .iter()
+            .find(|b| b.is_golden == golden)
+            .ok_or(Error::NoGoldenBankSupport)?;
+        let staging = self.staging_bank(bank.index, bank.size);
+        if staging.is_none() && !self.in_place_recovery {
+            return Err(Error::NoStagingBank);
+        }
+        self.request_image(golden);
 
-        if let Some(bank) = self.external_banks.iter().find(|b| b.is_golden == golden) {
-            duprintln!(
-                self.serial,
-                "Please send{} firmware image via XMODEM.",
-                if golden { " golden" } else { "" }
-            );
-            let blocks = self.serial.as_mut().unwrap().blocks(None);
-            if self
-                .external_flash
-                .as_mut()
-                .unwrap()
-                .write_from_blocks(bank.location, blocks)
-                .is_err()
-            {
-                duprintln!(
-                    self.serial,
-                    "FATAL: Failed to flash{} image during recovery mode.",
-                    if golden { " golden" } else { "" },
-                );
-                panic!();
+        let serial = &mut self.serial;
+        let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+        match staging {
+            Some(staging) => {
+                let flash = &mut self.mcu_flash;
+                let image = Self::receive_image(serial, flash, staging, golden, window_ms)?;
+                duprintln!(serial, "Image verified. Copying it to bank {:?}...", bank.index);
+                Self::copy_image_verbatim(flash, external_flash, staging, bank)?;
+                Self::discard_staged_image(serial, flash, staging, &image);
+                Self::verify_recovered_image(external_flash, bank, golden)
             }
-            match R::image_at(self.external_flash.as_mut().unwrap(), *bank) {
-                Ok(image) if golden && !image.is_golden() => {
-                    duprintln!(self.serial, "FATAL: Flashed image is not a golden image.");
-                    Err(Error::ImageIsNotGolden)
-                }
-                Err(e) => Err(e),
-                _ => Ok(()),
+            None => {
+                duprintln!(serial, "No bank to stage the image in. Writing it in place.");
+                Self::receive_image(serial, external_flash, bank, golden, window_ms).map(|_| ())
+            }
+        }
+    }
+
+    fn request_image(&mut self, golden: bool) {
+        duprintln!(
+            self.serial,
+            "Please send{} firmware image via XMODEM.",
+            if golden { " golden" } else { "" }
+        );
+    }
+
+    /// MCU bank to stage a received image in before copying it to the `target` bank, which
+    /// is `target_size` bytes large. That's the scratch bank if it can hold any image the
+    /// target bank can. Otherwise, it's the largest of the scratch bank and the banks that
+    /// are neither bootable nor golden, whose images are expendable during recovery. Either
+    /// way, the received image is only copied if it fit in the staging bank while being
+    /// transferred, and fits in the target bank.
+    fn staging_bank(&self, target: u8, target_size: usize) -> Option<image::Bank<MCUF::Address>> {
+        let scratch = self.scratch_bank.filter(|b| b.index != target);
+        if let Some(scratch) = scratch.filter(|b| b.size >= target_size) {
+            return Some(scratch);
+        }
+        let spare = self.mcu_banks().filter(|b| b.index != target && !b.bootable && !b.is_golden);
+        scratch.into_iter().chain(spare).fold(None, |largest, bank| match largest {
+            Some(largest) if largest.size >= bank.size => Some(largest),
+            _ => Some(bank),
+        })
+    }
+
+    /// Whether there's a bank to stage recovered images in, other than the bank
+    /// [`Self::recover`] writes them to.
+    pub(super) fn can_stage_recovered_images(&self) -> bool {
+        let mcu_golden = self.mcu_banks().find(|b| b.is_golden).map(|b| (b.index, b.size));
+        let external_golden =
+            self.external_banks().find(|b| b.is_golden).map(|b| (b.index, b.size));
+        let mcu_regular = self.mcu_banks().find(|b| !b.is_golden).map(|b| (b.index, b.size));
+        let target = mcu_golden.or(external_golden).or(mcu_regular);
+        target.is_some_and(|(index, size)| self.staging_bank(index, size).is_some())
+    }
+
+    /// Receives an image via XMODEM into a bank, then verifies it. Fails with
+    /// [`Error::RecoveryTimedOut`] if the sender doesn't start the transfer within
+    /// `window_ms` milliseconds.
+    fn receive_image<F: Flash>(
+        serial: &mut Option<SRL>,
+        flash: &mut F,
+        bank: image::Bank<F::Address>,
+        golden: bool,
+        window_ms: Option<u32>,
+    ) -> Result<Image<F::Address>, Error> {
+        image::clear_footer(flash, bank)?;
+        let start = T::now();
+        let expired = || window_ms.is_some_and(|window| (T::now() - start).0 >= window);
+        let mut blocks = serial.as_mut().unwrap().timed_blocks(None, WD::feed, &expired);
+        let written = image::write_from_blocks(flash, bank, &mut blocks);
+        if window_ms.is_some() && !blocks.started() {
+            return Err(Error::RecoveryTimedOut);
+        }
+        drop(blocks);
+        if let Err(e) = written {
+            duprintln!(serial, "Failed to write the received image to bank {:?}.", bank.index);
+            return Err(e);
+        }
+        let image = R::image_at(flash, bank)?;
+        if golden && !image.is_golden() {
+            duprintln!(serial, "Received image is not a golden image.");
+            return Err(Error::ImageIsNotGolden);
+        }
+        image::write_footer(flash, bank, image.footer())?;
+        Ok(image)
+    }
+
+    /// Verifies the image copied into its target bank once more.
+    fn verify_recovered_image<F: Flash>(
+        flash: &mut F,
+        bank: image::Bank<F::Address>,
+        golden: bool,
+    ) -> Result<(), Error> {
+        match R::image_at(flash, bank) {
+            Ok(image) if golden && !image.is_golden() => Err(Error::ImageIsNotGolden),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Makes the staged copy of a recovered image undiscoverable, so it isn't mistaken
+    /// for an update.
+    fn discard_staged_image<F: Flash>(
+        serial: &mut Option<SRL>,
+        flash: &mut F,
+        bank: image::Bank<F::Address>,
+        image: &Image<F::Address>,
+    ) {
+        if let Err(e) = image::discard(flash, bank, image) {
+            warn!("Failed to discard the staged image.");
+            if let Some(serial) = serial.as_mut() {
+                e.report(serial);
             }
-        } else {
-            Err(Error::NoGoldenBankSupport)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::bootloader::doubles::*;
+    use blue_hal::hal::doubles::flash::Address;
+
+    static MCU_BANKS: [Bank<Address>; 2] = [bank(1, true, false), bank(2, false, true)];
+    const SCRATCH_BANK: Bank<Address> = Bank { index: 0, ..bank(3, false, false) };
+    /// Spare bank, twice the size of the others, after the scratch bank.
+    const SPARE_BANK: Bank<Address> =
+        Bank { index: 3, size: 2 * TEST_SECTOR_SIZE, ..bank(4, false, false) };
+    static SPARE_MCU_BANKS: [Bank<Address>; 3] = [MCU_BANKS[0], MCU_BANKS[1], SPARE_BANK];
+
+    /// Bootloader with a golden version 0 image, staging recovered images in the scratch
+    /// bank if `scratch`, and about to receive `sent`.
+    fn bootloader(scratch: bool, sent: &[u8]) -> BootloaderDouble {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.scratch_bank = if scratch { Some(SCRATCH_BANK) } else { None };
+        write_versioned(&mut bootloader, MCU_BANKS[1], 0, true);
+        bootloader.serial.as_mut().unwrap().send(sent);
+        bootloader
+    }
+
+    #[test]
+    fn recovered_images_are_staged_before_replacing_the_golden_image() {
+        let mut bootloader = bootloader(true, &versioned_image(3, true));
+
+        assert_eq!(bootloader.recover_internal(true, None), Ok(()));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(3));
+        assert_eq!(version_in(&mut bootloader, SCRATCH_BANK), None);
+    }
+
+    #[test]
+    fn failed_transfers_leave_the_golden_image_untouched() {
+        let mut corrupted = versioned_image(3, true);
+        corrupted[100] ^= 0xFF;
+        let truncated = &versioned_image(3, true)[..KB!(2)];
+        for sent in [&corrupted[..], truncated, &[]] {
+            let mut bootloader = bootloader(true, sent);
+
+            assert!(bootloader.recover_internal(true, None).is_err());
+            assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(0));
+        }
+    }
+
+    #[test]
+    fn non_golden_images_are_not_recovered_into_the_golden_bank() {
+        let mut bootloader = bootloader(true, &versioned_image(3, false));
+
+        assert_eq!(bootloader.recover_internal(true, None), Err(Error::ImageIsNotGolden));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(0));
+    }
+
+    #[test]
+    fn recovery_without_staging_bank_only_writes_in_place_if_allowed() {
+        let mut bootloader = bootloader(false, &versioned_image(3, true));
+        assert!(!bootloader.can_stage_recovered_images());
+        assert_eq!(bootloader.recover_internal(true, None), Err(Error::NoStagingBank));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(0));
+
+        bootloader.in_place_recovery = true;
+        assert_eq!(bootloader.recover_internal(true, None), Ok(()));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(3));
+    }
+
+    #[test]
+    fn recovery_without_scratch_bank_stages_images_in_a_spare_bank() {
+        let mut bootloader =
+            bootloader(false, &versioned_image(3, true)).with_mcu_banks(&SPARE_MCU_BANKS);
+        assert!(bootloader.can_stage_recovered_images());
+
+        assert_eq!(bootloader.recover_internal(true, None), Ok(()));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(3));
+        assert_eq!(version_in(&mut bootloader, SPARE_BANK), None);
+    }
+
+    #[test]
+    fn scratch_bank_stages_images_unless_smaller_than_a_spare_bank() {
+        let location = |bank: Option<Bank<Address>>| bank.map(|b| b.location);
+        let mut bootloader = bootloader(true, &[]).with_mcu_banks(&SPARE_MCU_BANKS);
+        let staging = bootloader.staging_bank(2, TEST_SECTOR_SIZE);
+        assert_eq!(location(staging), Some(SCRATCH_BANK.location));
+        let staging = bootloader.staging_bank(2, 3 * TEST_SECTOR_SIZE);
+        assert_eq!(location(staging), Some(SPARE_BANK.location));
+
+        bootloader.scratch_bank = None;
+        let staging = bootloader.staging_bank(2, TEST_SECTOR_SIZE);
+        assert_eq!(location(staging), Some(SPARE_BANK.location));
+        assert!(bootloader.staging_bank(3, TEST_SECTOR_SIZE).is_none());
+    }
+
+    #[test]
+    fn images_too_large_for_the_staging_bank_are_not_recovered() {
+        let image = versioned_image(3, true);
+        let mut bootloader = bootloader(true, &image);
+        bootloader.scratch_bank = Some(Bank { size: image.len() - 1, ..SCRATCH_BANK });
+
+        assert_eq!(bootloader.recover_internal(true, None), Err(Error::ImageTooBig));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(0));
+    }
+}
@@ -8,10 +8,12 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
 {
     /// Restores the first image available in all banks, attempting to restore
-    /// from the golden image as a last resort.
+    /// from the golden image as a last resort. Images rejected by anti-rollback
+    /// protection or signed with a revoked key are skipped.
     pub fn restore(&mut self) -> Result<Image<MCUF::Address>, Error> {
         self.restore_internal(false)
             .or_else(|| self.restore_external(false))
@@ -20,7 +22,7 @@ impl<
             .ok_or(Error::NoImageToRestoreFrom)
     }
 
-    fn restore_external(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
+    pub(super) fn restore_external(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
         let output = self.boot_bank();
         for input_bank in self.external_banks.iter().filter(|b| b.is_golden == golden) {
             duprintln!(
@@ -29,16 +31,7 @@ impl<
                 if golden { " golden" } else { "" },
                 input_bank.index
             );
-            if Self::copy_image(
-                &mut self.serial,
-                self.external_flash.as_mut().unwrap(),
-                &mut self.mcu_flash,
-                *input_bank,
-                output,
-                golden,
-            )
-            .is_err()
-            {
+            if self.copy_bank(input_bank.index, output.index, golden).is_err() {
                 continue;
             }
 
@@ -49,32 +42,26 @@ impl<
                 EXTF::label()
             );
             duprintln!(self.serial, "Verifying the image again in the boot bank...");
+            let image = match R::image_at(&mut self.mcu_flash, output) {
+                Ok(image) if self.check_trust(&image).is_ok() => image,
+                _ => continue,
+            };
             self.boot_metrics.boot_path = BootPath::Restored { bank: input_bank.index };
-            return R::image_at(&mut self.mcu_flash, output).ok();
+            return Some(image);
         }
         None
     }
 
-    fn restore_internal(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
+    pub(super) fn restore_internal(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
         let output = self.boot_bank();
-        for input_bank in
-            self.mcu_banks.iter().filter(|b| b.is_golden == golden && b.index != output.index)
-        {
+        for input_bank in self.mcu_banks.iter().filter(|b| b.is_golden == golden && !b.bootable) {
             duprintln!(
                 self.serial,
                 "Attempting to restore from{} bank {:?}.",
                 if golden { " golden" } else { "" },
                 input_bank.index
             );
-            if Self::copy_image_single_flash(
-                &mut self.serial,
-                &mut self.mcu_flash,
-                *input_bank,
-                output,
-                golden,
-            )
-            .is_err()
-            {
+            if self.copy_bank(input_bank.index, output.index, golden).is_err() {
                 continue;
             }
 
@@ -85,8 +72,12 @@ impl<
                 MCUF::label()
             );
             duprintln!(self.serial, "Verifying the image again in the boot bank...");
+            let image = match R::image_at(&mut self.mcu_flash, output) {
+                Ok(image) if self.check_trust(&image).is_ok() => image,
+                _ => continue,
+            };
             self.boot_metrics.boot_path = BootPath::Restored { bank: input_bank.index };
-            return R::image_at(&mut self.mcu_flash, output).ok();
+            return Some(image);
         }
         None
     }
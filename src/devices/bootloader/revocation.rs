@@ -0,0 +1,31 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Rejects images signed with a revoked key. Always succeeds if key
+    /// revocation is disabled.
+    pub fn check_revocation<A: Address>(&mut self, image: &Image<A>) -> Result<(), Error> {
+        let revoked_keys = match self.revoked_keys {
+            Some(revoked_keys) => revoked_keys,
+            None => return Ok(()),
+        };
+
+        if revoked_keys.is_revoked(&mut self.mcu_flash, image.key_id())? {
+            duprintln!(self.serial, "Image is signed with revoked key {:?}.", image.key_id());
+            if let Some(serial) = self.serial.as_mut() {
+                Error::KeyRevoked.report(serial);
+            }
+            return Err(Error::KeyRevoked);
+        }
+        Ok(())
+    }
+}
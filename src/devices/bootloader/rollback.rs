@@ -0,0 +1,54 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Rejects images whose security epoch is lower than the security counter. Golden
+    /// images are exempt, as they exist as a last resort fallback. Always succeeds
+    /// if anti-rollback protection is disabled.
+    pub fn check_rollback<A: Address>(&mut self, image: &Image<A>) -> Result<(), Error> {
+        let counter = match self.security_counter {
+            Some(counter) if !image.is_golden() => counter,
+            _ => return Ok(()),
+        };
+
+        let minimum_epoch = counter.read(&mut self.mcu_flash)?;
+        if image.security_epoch() < minimum_epoch {
+            duprintln!(
+                self.serial,
+                "Image security epoch {:?} is lower than the security counter {:?}.",
+                image.security_epoch(),
+                minimum_epoch
+            );
+            if let Some(serial) = self.serial.as_mut() {
+                Error::ImageRollbackRejected.report(serial);
+            }
+            return Err(Error::ImageRollbackRejected);
+        }
+        Ok(())
+    }
+
+    /// Raises the security counter to the epoch of an image about to be booted,
+    /// so older images can't be booted from then on. Golden images don't raise it.
+    pub(super) fn raise_security_counter(&mut self, image: &Image<MCUF::Address>) {
+        let counter = match self.security_counter {
+            Some(counter) if !image.is_golden() => counter,
+            _ => return,
+        };
+
+        if let Err(e) = counter.raise(&mut self.mcu_flash, image.security_epoch()) {
+            warn!("Failed to raise the security counter.");
+            if let Some(serial) = self.serial.as_mut() {
+                e.report(serial);
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use super::*;
+use crate::devices::{
+    boot_metrics::{UpdatePlanStatus, UpdateSignalStatus},
+    update_signal::{ReadUpdateSignal, UpdatePlan},
+};
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Reads the plan requested through the update signal, if enabled. If it holds no
+    /// valid plan, the default plan is returned instead, as reported in the boot metrics.
+    pub(super) fn read_update_plan(&mut self) -> Option<UpdatePlan> {
+        let plan = self.update_signal.as_mut()?.read_update_plan();
+        self.boot_metrics.update_signal = match plan {
+            Some(_) => UpdateSignalStatus::Set,
+            None => {
+                duprintln!(self.serial, "Update signal holds no valid plan, using the default.");
+                UpdateSignalStatus::Defaulted
+            }
+        };
+        Some(plan.unwrap_or(self.default_update_plan))
+    }
+
+    /// Carries out the operation requested by the update signal instead of updating,
+    /// if any. Enters recovery mode if requested (and supported), and otherwise returns
+    /// the image left in the boot bank by the operation, if it succeeded.
+    pub(super) fn follow_update_plan(
+        &mut self,
+        plan: Option<UpdatePlan>,
+    ) -> Option<Image<MCUF::Address>> {
+        match plan? {
+            UpdatePlan::RestoreGolden => {
+                duprintln!(self.serial, "Update signal set to RestoreGolden.");
+                self.end_trial();
+                let image = self.restore_internal(true).or_else(|| self.restore_external(true));
+                if image.is_none() {
+                    duprintln!(self.serial, "Failed to restore the golden image.");
+                }
+                self.conclude_update_plan(UpdatePlan::RestoreGolden, image.is_some());
+                image
+            }
+            UpdatePlan::Recover if self.recovery_enabled => {
+                duprintln!(self.serial, "Update signal set to Recover.");
+                // Concluded before entering recovery, as it only ends with a reset.
+                self.conclude_update_plan(UpdatePlan::Recover, true);
+                self.recover();
+            }
+            UpdatePlan::Recover => {
+                duprintln!(self.serial, "Update signal set to Recover, which is not supported.");
+                self.conclude_update_plan(UpdatePlan::Recover, false);
+                None
+            }
+            UpdatePlan::Rollback => {
+                duprintln!(self.serial, "Update signal set to Rollback.");
+                let image = self.roll_back();
+                if image.is_none() {
+                    duprintln!(self.serial, "No older image to roll back to.");
+                }
+                self.conclude_update_plan(UpdatePlan::Rollback, image.is_some());
+                image
+            }
+            UpdatePlan::None | UpdatePlan::Any | UpdatePlan::Index(_) => None,
+        }
+    }
+
+    /// Reports whether Loadstone honoured the update plan in the boot metrics, and
+    /// clears the plan from the update signal if plans are one-shot.
+    pub(super) fn conclude_update_plan(&mut self, plan: UpdatePlan, honoured: bool) {
+        let cleared = match self.update_signal.as_mut() {
+            Some(update_signal) if self.one_shot_update_plans => {
+                update_signal.clear_update_plan();
+                true
+            }
+            _ => false,
+        };
+        self.boot_metrics.update_plan = if honoured {
+            UpdatePlanStatus::Honoured { plan, cleared }
+        } else {
+            UpdatePlanStatus::Failed { plan, cleared }
+        };
+    }
+
+    /// Replaces the current image with the newest valid image older than it, from any
+    /// non-golden bank. The current image is discarded from any other bank holding it,
+    /// so it isn't installed again by a later update. Unversioned images can't be
+    /// rolled back, as there's no telling which image is older.
+    fn roll_back(&mut self) -> Option<Image<MCUF::Address>> {
+        let boot_bank = self.boot_bank();
+        let current_image = R::image_at(&mut self.mcu_flash, boot_bank).ok()?;
+        let current_version = current_image.version()?;
+
+        let mut previous: Option<(u8, image::SemanticVersion)> = None;
+        let mut consider = |index: u8, version: Option<image::SemanticVersion>| match version {
+            Some(v) if v < current_version && previous.is_none_or(|(_, p)| v > p) => {
+                previous = Some((index, v))
+            }
+            _ => (),
+        };
+        for bank in self.mcu_banks().filter(|b| !b.bootable && !b.is_golden) {
+            if let Ok(image) = R::image_at(&mut self.mcu_flash, bank) {
+                if !image.is_patch() && self.check_trust(&image).is_ok() {
+                    consider(bank.index, image.version());
+                }
+            }
+        }
+        if self.external_flash.is_some() {
+            for bank in self.external_banks().filter(|b| !b.is_golden) {
+                if let Ok(image) = R::image_at(self.external_flash.as_mut().unwrap(), bank) {
+                    if !image.is_patch() && self.check_trust(&image).is_ok() {
+                        consider(bank.index, image.version());
+                    }
+                }
+            }
+        }
+        let (index, _) = previous?;
+
+        duprintln!(self.serial, "Rolling back to the image in bank {:?}.", index);
+        self.end_trial();
+        self.copy_bank(index, boot_bank.index, false).ok()?;
+        let image = R::image_at(&mut self.mcu_flash, boot_bank).ok()?;
+        self.check_trust(&image).ok()?;
+        self.discard_everywhere(&current_image);
+        self.boot_metrics.boot_path = BootPath::RolledBack { bank: index };
+        Some(image)
+    }
+
+    /// Discards an image from every non-bootable, non-golden bank holding it.
+    fn discard_everywhere(&mut self, discarded: &Image<MCUF::Address>) {
+        for bank in self.mcu_banks().filter(|b| !b.bootable && !b.is_golden) {
+            if let Ok(image) = R::image_at(&mut self.mcu_flash, bank) {
+                if image.identifier() == discarded.identifier() {
+                    if let Err(e) = image::discard(&mut self.mcu_flash, bank, &image) {
+                        warn!("Failed to discard the rolled back image.");
+                        if let Some(serial) = self.serial.as_mut() {
+                            e.report(serial);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(external_flash) = self.external_flash.as_mut() {
+            for bank in self.external_banks.iter().cloned().filter(|b| !b.is_golden) {
+                if let Ok(image) = R::image_at(external_flash, bank) {
+                    if image.identifier() == discarded.identifier() {
+                        if let Err(e) = image::discard(external_flash, bank, &image) {
+                            warn!("Failed to discard the rolled back image.");
+                            if let Some(serial) = self.serial.as_mut() {
+                                e.report(serial);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::bootloader::doubles::*;
+    use blue_hal::hal::doubles::flash::Address;
+
+    static MCU_BANKS: [Bank<Address>; 5] = [
+        bank(1, true, false),
+        bank(2, false, false),
+        bank(3, false, false),
+        bank(4, false, false),
+        bank(5, false, true),
+    ];
+
+    /// Bootloader with the update signal set to `plan`, and an image of each given version
+    /// in consecutive MCU banks, starting with the boot bank. The last bank is golden.
+    fn bootloader(plan: Option<UpdatePlan>, versions: &[u16]) -> BootloaderDouble {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.update_signal = Some(FakeUpdateSignal(plan));
+        for (&major, &bank) in versions.iter().zip(MCU_BANKS.iter()) {
+            write_versioned(&mut bootloader, bank, major, bank.is_golden);
+        }
+        bootloader
+    }
+
+    fn plan_status(bootloader: &BootloaderDouble) -> (bool, UpdatePlan, bool) {
+        match bootloader.boot_metrics.update_plan {
+            UpdatePlanStatus::Honoured { plan, cleared } => (true, plan, cleared),
+            UpdatePlanStatus::Failed { plan, cleared } => (false, plan, cleared),
+            UpdatePlanStatus::None => panic!("No update plan was concluded"),
+        }
+    }
+
+    fn signalled_plan(bootloader: &mut BootloaderDouble) -> Option<UpdatePlan> {
+        bootloader.update_signal.as_mut().unwrap().read_update_plan()
+    }
+
+    #[test]
+    fn unset_signal_follows_the_default_plan() {
+        let mut bootloader = bootloader(None, &[]);
+        bootloader.default_update_plan = UpdatePlan::Index(3);
+
+        assert_eq!(bootloader.read_update_plan(), Some(UpdatePlan::Index(3)));
+        assert!(matches!(bootloader.boot_metrics.update_signal, UpdateSignalStatus::Defaulted));
+    }
+
+    #[test]
+    fn honoured_plans_are_kept_unless_one_shot() {
+        for one_shot in [false, true] {
+            let mut bootloader = bootloader(Some(UpdatePlan::Index(3)), &[3, 4, 2]);
+            bootloader.one_shot_update_plans = one_shot;
+
+            let plan = bootloader.read_update_plan();
+            assert!(matches!(bootloader.boot_metrics.update_signal, UpdateSignalStatus::Set));
+            let image = bootloader.latest_bootable_image(plan).unwrap();
+            assert_eq!(image.version().map(|v| v.major), Some(2));
+            assert_eq!(plan_status(&bootloader), (true, UpdatePlan::Index(3), one_shot));
+            let expected = if one_shot { None } else { Some(UpdatePlan::Index(3)) };
+            assert_eq!(signalled_plan(&mut bootloader), expected);
+        }
+    }
+
+    #[test]
+    fn failed_plans_are_reported_and_cleared_if_one_shot() {
+        for one_shot in [false, true] {
+            let mut bootloader = bootloader(Some(UpdatePlan::Rollback), &[1, 2]);
+            bootloader.one_shot_update_plans = one_shot;
+
+            assert!(bootloader.roll_back().is_none());
+            bootloader.conclude_update_plan(UpdatePlan::Rollback, false);
+            assert_eq!(plan_status(&bootloader), (false, UpdatePlan::Rollback, one_shot));
+            let expected = if one_shot { None } else { Some(UpdatePlan::Rollback) };
+            assert_eq!(signalled_plan(&mut bootloader), expected);
+            assert_eq!(version_in(&mut bootloader, MCU_BANKS[0]), Some(1));
+        }
+    }
+
+    #[test]
+    fn rollback_selects_the_newest_older_image() {
+        let mut bootloader = bootloader(Some(UpdatePlan::Rollback), &[5, 2, 7, 4, 1]);
+        // A copy of the current image, as left behind by a swap.
+        write_versioned(&mut bootloader, MCU_BANKS[2], 5, false);
+
+        let image = bootloader.roll_back().unwrap();
+        assert_eq!(image.version().map(|v| v.major), Some(4));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::RolledBack { bank: 4 }));
+        // The current image is discarded, so it isn't installed again as an update.
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[2]), None);
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), Some(2));
+    }
+}
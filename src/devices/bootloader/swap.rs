@@ -0,0 +1,196 @@
+use super::{journal::SCRATCH_INDEX, *};
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Whether an update candidate can be installed by swapping it with the current
+    /// image, rather than replacing the current image.
+    ///
+    /// Swapping leaves the current image in the bank the candidate came from, so it must
+    /// fit there. Both images must also be versioned: two unversioned images are each
+    /// considered newer than the other, so they would be swapped back on the next boot.
+    pub(super) fn can_swap<A: Address>(
+        &mut self,
+        bank: &Bank<A>,
+        candidate: &Image<A>,
+        current_image: &Image<MCUF::Address>,
+    ) -> bool {
+        if self.scratch_bank.is_none() {
+            false
+        } else if candidate.version().is_none() || current_image.version().is_none() {
+            duprintln!(self.serial, "Unversioned images can't be swapped. Replacing instead.");
+            false
+        } else if current_image.total_size() > bank.size {
+            duprintln!(
+                self.serial,
+                "Current image doesn't fit in bank {:?}. Replacing instead.",
+                bank.index
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Swaps the current image with the image in another bank, through the scratch bank.
+    /// Updates from external flash are decrypted and decompressed into the boot bank, and
+    /// the current image is left in the external bank as plaintext. Returns the new
+    /// bootable image.
+    pub(super) fn swap_image(&mut self, bank: u8) -> Result<Image<MCUF::Address>, Error> {
+        duprintln!(self.serial, "Swapping current image with bank {:?}.", bank);
+        self.record_in_journal(|journal, flash| journal.start_swap(flash, bank));
+        self.continue_swap(bank, 0, 0)?;
+        let boot_bank = self.boot_bank();
+        R::image_at(&mut self.mcu_flash, boot_bank)
+    }
+
+    /// Copies making up a swap with a bank, in order: the current image is moved out
+    /// of the way to the scratch bank, replaced with the image in the bank, and finally
+    /// moved to the bank.
+    pub(super) fn swap_steps(&self, bank: u8) -> [(u8, u8); 3] {
+        let boot = self.boot_bank().index;
+        [(boot, SCRATCH_INDEX), (bank, boot), (SCRATCH_INDEX, bank)]
+    }
+
+    /// Carries out the steps of a swap from a given one, resuming its first copy from a
+    /// given chunk. If a step fails, the swap is rolled back as far as possible.
+    pub(super) fn continue_swap(
+        &mut self,
+        bank: u8,
+        first_step: usize,
+        resumed_chunks: usize,
+    ) -> Result<(), Error> {
+        let steps = self.swap_steps(bank);
+        for (step, &(input, output)) in steps.iter().enumerate().skip(first_step) {
+            let resumed_chunks = if step == first_step { resumed_chunks } else { 0 };
+            if let Err(e) = self.journaled_copy(input, output, false, resumed_chunks) {
+                self.roll_back_swap(bank, step);
+                return Err(e);
+            }
+        }
+        if let Some(scratch_bank) = self.scratch_bank {
+            image::clear_footer(&mut self.mcu_flash, scratch_bank)?;
+        }
+        self.record_in_journal(|journal, flash| journal.end(flash));
+        duprintln!(self.serial, "Swapped image with bank {:?}.", bank);
+        Ok(())
+    }
+
+    /// Rolls back a swap that failed at a given step. Until the boot bank is overwritten,
+    /// there is nothing to undo, and once the new image is in place, the swap is simply
+    /// left unfinished (losing the previous image).
+    fn roll_back_swap(&mut self, bank: u8, failed_step: usize) {
+        duprintln!(self.serial, "Failed to swap with bank {:?}. Rolling back...", bank);
+        let boot = self.boot_bank().index;
+        if failed_step == 1 && self.journaled_copy(SCRATCH_INDEX, boot, false, 0).is_err() {
+            duprintln!(self.serial, "Failed to restore the current image from scratch.");
+        }
+        self.record_in_journal(|journal, flash| journal.end(flash));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{
+        bootloader::doubles::*,
+        image::{image_crc::CrcImageReader, Identifier, Reader, Trailer},
+        storage::{Interrupted, Storage},
+    };
+    use blue_hal::hal::doubles::flash::Address;
+
+    const STORAGE: Storage<Address> =
+        Storage { clearable: Address(0), permanent: Address(TEST_SECTOR_SIZE as u32) };
+    const BANK_SIZE: usize = 3 * TEST_SECTOR_SIZE;
+    const fn bank(index: u8, bootable: bool) -> Bank<Address> {
+        let location = Address((2 * TEST_SECTOR_SIZE + (index as usize - 1) * BANK_SIZE) as u32);
+        Bank { index, size: BANK_SIZE, location, bootable, is_golden: false }
+    }
+    static MCU_BANKS: [Bank<Address>; 2] = [bank(1, true), bank(2, false)];
+    const SCRATCH_BANK: Bank<Address> = Bank { index: SCRATCH_INDEX, ..bank(3, false) };
+
+    /// Bootloader with a journal and a scratch bank, and different images in both banks.
+    /// Returns the identifiers of the current image and the image in bank 2.
+    fn bootloader() -> (BootloaderDouble, Identifier, Identifier) {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.journal = Some(STORAGE.copy_journal());
+        bootloader.scratch_bank = Some(SCRATCH_BANK);
+        let current = crc_image(&body(BANK_SIZE - KB!(1), 1), &Trailer::default());
+        let update = crc_image(&body(2 * TEST_SECTOR_SIZE + KB!(44), 0), &Trailer::default());
+        write_image(&mut bootloader.mcu_flash, MCU_BANKS[0], &current);
+        write_image(&mut bootloader.mcu_flash, MCU_BANKS[1], &update);
+        let current = identifier_in(&mut bootloader, MCU_BANKS[0]).unwrap();
+        let update = identifier_in(&mut bootloader, MCU_BANKS[1]).unwrap();
+        (bootloader, current, update)
+    }
+
+    fn identifier_in(bootloader: &mut BootloaderDouble, bank: Bank<Address>) -> Option<Identifier> {
+        CrcImageReader::image_at(&mut bootloader.mcu_flash, bank).ok().map(|i| i.identifier())
+    }
+
+    fn assert_no_operation_in_progress(bootloader: &mut BootloaderDouble) {
+        let journal = STORAGE.copy_journal();
+        assert_eq!(journal.interrupted(&mut bootloader.mcu_flash), Ok(Interrupted::None));
+    }
+
+    #[test]
+    fn swapping_exchanges_the_current_image_with_the_update() {
+        let (mut bootloader, current, update) = bootloader();
+
+        let image = bootloader.swap_image(2).unwrap();
+        assert_eq!(image.identifier(), update);
+        assert_eq!(identifier_in(&mut bootloader, MCU_BANKS[1]), Some(current));
+        assert_no_operation_in_progress(&mut bootloader);
+    }
+
+    #[test]
+    fn interrupted_swaps_are_resumed() {
+        // Power is lost at every write after the first (recording the start of the swap).
+        for writes in 1.. {
+            let (mut bootloader, current, update) = bootloader();
+
+            bootloader.mcu_flash.lose_power_after(writes);
+            let result = bootloader.swap_image(2);
+            if bootloader.mcu_flash.powered() {
+                assert_eq!(result.map(|i| i.identifier()), Ok(update));
+                break;
+            }
+            bootloader.mcu_flash.restore_power();
+            bootloader.resume_interrupted_operation();
+
+            assert_eq!(identifier_in(&mut bootloader, MCU_BANKS[0]), Some(update));
+            assert_eq!(identifier_in(&mut bootloader, MCU_BANKS[1]), Some(current));
+            assert_no_operation_in_progress(&mut bootloader);
+        }
+    }
+
+    #[test]
+    fn swaps_that_cannot_be_resumed_are_rolled_back() {
+        // Power is lost while the update overwrites the current image, and the update
+        // is lost too, so the current image must be restored from scratch.
+        for writes in 1.. {
+            let (mut bootloader, current, _) = bootloader();
+
+            bootloader.mcu_flash.lose_power_after(writes);
+            assert!(bootloader.swap_image(2).is_err(), "Swap finished before overwriting");
+            bootloader.mcu_flash.restore_power();
+            if identifier_in(&mut bootloader, MCU_BANKS[0]).is_some() {
+                continue;
+            }
+            write_image(&mut bootloader.mcu_flash, MCU_BANKS[1], &[0u8; KB!(1)]);
+            bootloader.resume_interrupted_operation();
+
+            assert_eq!(identifier_in(&mut bootloader, MCU_BANKS[0]), Some(current));
+            assert_no_operation_in_progress(&mut bootloader);
+            break;
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use super::*;
+use crate::devices::{boot_metrics::TrialStatus, storage::Trial, update_signal::ReadUpdateSignal};
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
+{
+    /// Starts the trial of an image just updated from a given bank. Does nothing
+    /// if trial boots are disabled.
+    pub(super) fn start_trial(&mut self, bank: u8) {
+        if let Some(trial_log) = self.trial_log {
+            if let Err(e) = trial_log.start(&mut self.mcu_flash, bank) {
+                warn!("Failed to start the trial of the updated image.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
+    }
+
+    /// Ends the trial of the current image without confirming it, as it's about
+    /// to be replaced by other means than an update.
+    pub(super) fn end_trial(&mut self) {
+        if let Some(trial_log) = self.trial_log {
+            if let Err(e) = trial_log.end(&mut self.mcu_flash) {
+                warn!("Failed to end the trial of the current image.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
+    }
+
+    /// Records a boot of the current image if it's on trial, and reports it in
+    /// the boot metrics. Returns whether the image is on trial.
+    pub(super) fn record_trial_boot(&mut self) -> bool {
+        let trial_log = match self.trial_log {
+            Some(trial_log) => trial_log,
+            None => return false,
+        };
+        let result = match trial_log.state(&mut self.mcu_flash) {
+            Ok(Trial::Pending { boots, .. }) => {
+                trial_log.record_boot(&mut self.mcu_flash).map(|_| Some(boots))
+            }
+            other => other.map(|_| None),
+        };
+        match result {
+            Ok(Some(boots)) => {
+                self.boot_metrics.trial = TrialStatus::Pending {
+                    boot: (boots + 1) as u8,
+                    allowed_boots: trial_log.allowed_boots() as u8,
+                };
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                warn!("Failed to record a boot of the image on trial.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+                false
+            }
+        }
+    }
+
+    /// If the current image used up its trial boots without being confirmed, replaces
+    /// it with the previous image (kept in the bank the update came from by swap
+    /// updates) or, failing that, with the golden image. The unconfirmed image is
+    /// discarded from the bank it came from, so it isn't installed again.
+    pub(super) fn revert_failed_trial(&mut self) {
+        let trial_log = match self.trial_log {
+            Some(trial_log) => trial_log,
+            None => return,
+        };
+        let bank = match trial_log.state(&mut self.mcu_flash) {
+            Ok(Trial::Pending { bank, boots }) if boots >= trial_log.allowed_boots() => bank,
+            _ => return,
+        };
+
+        duprintln!(
+            self.serial,
+            "Current image was not confirmed after {} boots. Reverting...",
+            trial_log.allowed_boots()
+        );
+        let boot_bank = self.boot_bank();
+        if let Ok(failed_image) = R::image_at(&mut self.mcu_flash, boot_bank) {
+            let reverted = self
+                .revert_from(bank, &failed_image)
+                .or_else(|| self.restore_internal(true))
+                .or_else(|| self.restore_external(true));
+            if reverted.is_some() {
+                self.boot_metrics.trial = TrialStatus::Reverted;
+            } else {
+                duprintln!(self.serial, "No image to revert to. Keeping the current image.");
+            }
+        }
+        self.end_trial();
+    }
+
+    /// Copies the previous image from the bank an update came from back to the boot
+    /// bank, unless the bank holds the unconfirmed image itself, which is discarded.
+    fn revert_from(
+        &mut self,
+        index: u8,
+        failed_image: &Image<MCUF::Address>,
+    ) -> Option<Image<MCUF::Address>> {
+        let boot_bank = self.boot_bank();
+        if let Some(bank) = self.mcu_banks().find(|b| b.index == index && !b.bootable) {
+            let image = R::image_at(&mut self.mcu_flash, bank).ok()?;
+            if image.identifier() == failed_image.identifier() {
+                if let Err(e) = image::discard(&mut self.mcu_flash, bank, &image) {
+                    warn!("Failed to discard the unconfirmed image.");
+                    if let Some(serial) = self.serial.as_mut() {
+                        e.report(serial);
+                    }
+                }
+                return None;
+            }
+        } else {
+            let bank = self.external_banks().find(|b| b.index == index)?;
+            let external_flash = self.external_flash.as_mut()?;
+            let image = R::image_at(external_flash, bank).ok()?;
+            if image.identifier() == failed_image.identifier() {
+                if let Err(e) = image::discard(external_flash, bank, &image) {
+                    warn!("Failed to discard the unconfirmed image.");
+                    if let Some(serial) = self.serial.as_mut() {
+                        e.report(serial);
+                    }
+                }
+                return None;
+            }
+        }
+        self.copy_bank(index, boot_bank.index, false).ok()?;
+
+        duprintln!(self.serial, "Reverted to the previous image from bank {:?}.", index);
+        let image = R::image_at(&mut self.mcu_flash, boot_bank).ok()?;
+        self.check_trust(&image).ok()?;
+        self.boot_metrics.boot_path = BootPath::Restored { bank: index };
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{bootloader::doubles::*, storage::Storage};
+    use blue_hal::hal::doubles::flash::Address;
+
+    const STORAGE: Storage<Address> =
+        Storage { clearable: Address(0), permanent: Address(TEST_SECTOR_SIZE as u32) };
+    const ALLOWED_BOOTS: u32 = 2;
+    static MCU_BANKS: [Bank<Address>; 3] =
+        [bank(1, true, false), bank(2, false, false), bank(3, false, true)];
+    const SCRATCH_BANK: Bank<Address> = Bank { index: 0, ..bank(4, false, false) };
+
+    /// Bootloader with trial boots, a version 1 image in the boot bank, a version 2
+    /// update in bank 2 and a golden version 0 image, updating by swapping if requested.
+    fn updated_bootloader(swap: bool) -> BootloaderDouble {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.trial_log = Some(STORAGE.trial_log(ALLOWED_BOOTS));
+        bootloader.scratch_bank = if swap { Some(SCRATCH_BANK) } else { None };
+        for (&bank, major) in MCU_BANKS.iter().zip([1, 2, 0]) {
+            write_versioned(&mut bootloader, bank, major, bank.is_golden);
+        }
+        let image = bootloader.latest_bootable_image(None).unwrap();
+        assert_eq!(image.version().map(|v| v.major), Some(2));
+        bootloader
+    }
+
+    fn trial_state(bootloader: &mut BootloaderDouble) -> Trial {
+        STORAGE.trial_log(ALLOWED_BOOTS).state(&mut bootloader.mcu_flash).unwrap()
+    }
+
+    #[test]
+    fn updates_are_put_on_trial_until_confirmed() {
+        let mut bootloader = updated_bootloader(true);
+        assert_eq!(trial_state(&mut bootloader), Trial::Pending { bank: 2, boots: 0 });
+
+        assert!(bootloader.record_trial_boot());
+        assert!(matches!(bootloader.boot_metrics.trial, TrialStatus::Pending {
+            boot: 1,
+            allowed_boots: 2
+        }));
+        assert_eq!(trial_state(&mut bootloader), Trial::Pending { bank: 2, boots: 1 });
+
+        STORAGE.trial_log(ALLOWED_BOOTS).confirm(&mut bootloader.mcu_flash).unwrap();
+        for _ in 0..ALLOWED_BOOTS {
+            bootloader.revert_failed_trial();
+            assert!(!bootloader.record_trial_boot());
+        }
+        assert_eq!(trial_state(&mut bootloader), Trial::None);
+        assert_eq!(current_version(&mut bootloader), Some(2));
+    }
+
+    #[test]
+    fn trials_are_not_reverted_before_using_up_their_boots() {
+        let mut bootloader = updated_bootloader(true);
+
+        for _ in 0..ALLOWED_BOOTS {
+            bootloader.revert_failed_trial();
+            assert!(bootloader.record_trial_boot());
+        }
+        assert_eq!(current_version(&mut bootloader), Some(2));
+        assert_eq!(trial_state(&mut bootloader), Trial::Pending { bank: 2, boots: ALLOWED_BOOTS });
+    }
+
+    #[test]
+    fn unconfirmed_swapped_updates_revert_to_the_previous_image() {
+        let mut bootloader = updated_bootloader(true);
+        for _ in 0..ALLOWED_BOOTS {
+            assert!(bootloader.record_trial_boot());
+        }
+
+        bootloader.revert_failed_trial();
+        assert_eq!(current_version(&mut bootloader), Some(1));
+        assert!(matches!(bootloader.boot_metrics.trial, TrialStatus::Reverted));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Restored { bank: 2 }));
+        assert_eq!(trial_state(&mut bootloader), Trial::None);
+    }
+
+    #[test]
+    fn unconfirmed_replaced_updates_are_discarded_for_the_golden_image() {
+        let mut bootloader = updated_bootloader(false);
+        for _ in 0..ALLOWED_BOOTS {
+            assert!(bootloader.record_trial_boot());
+        }
+
+        bootloader.revert_failed_trial();
+        assert_eq!(current_version(&mut bootloader), Some(0));
+        assert!(matches!(bootloader.boot_metrics.trial, TrialStatus::Reverted));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Restored { bank: 3 }));
+        assert_eq!(version_in(&mut bootloader, MCU_BANKS[1]), None);
+        assert_eq!(trial_state(&mut bootloader), Trial::None);
+    }
+}
@@ -1,11 +1,38 @@
 use super::*;
 use crate::devices::update_signal::{ReadUpdateSignal, UpdatePlan};
 
-enum UpdateResult<MCUF: Flash> {
-    AlreadyUpToDate(Image<MCUF::Address>),
-    NotUpdated(Image<MCUF::Address>),
-    UpdatedTo(Image<MCUF::Address>),
-    UpdateError,
+/// A valid image found in a bank other than the boot bank, that is
+/// a potential update candidate.
+enum Candidate<EXTF: Flash, MCUF: Flash> {
+    Internal(Bank<MCUF::Address>, Image<MCUF::Address>),
+    External(Bank<EXTF::Address>, Image<EXTF::Address>),
+    /// A patch to the current image, in an external bank.
+    Patch(Bank<EXTF::Address>, Image<EXTF::Address>),
+}
+
+impl<EXTF: Flash, MCUF: Flash> Candidate<EXTF, MCUF> {
+    fn identifier(&self) -> image::Identifier {
+        match self {
+            Candidate::Internal(_, image) => image.identifier(),
+            Candidate::External(_, image) | Candidate::Patch(_, image) => image.identifier(),
+        }
+    }
+
+    fn version(&self) -> Option<image::SemanticVersion> {
+        match self {
+            Candidate::Internal(_, image) => image.version(),
+            Candidate::External(_, image) | Candidate::Patch(_, image) => image.version(),
+        }
+    }
+
+    fn is_newer_than(&self, current_image: &Image<MCUF::Address>) -> bool {
+        match self {
+            Candidate::Internal(_, image) => image.is_newer_than(current_image),
+            Candidate::External(_, image) | Candidate::Patch(_, image) => {
+                image.is_newer_than(current_image)
+            }
+        }
+    }
 }
 
 impl<
@@ -15,13 +42,24 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        WD: Watchdog,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, WD>
 {
-    /// If the current bootable (MCU flash) image is different from the top
-    /// non-golden image, attempts to replace it. On failure, this process
-    /// is repeated for all non-golden banks. Returns the current
-    /// bootable image after the process, if available.
-    pub fn latest_bootable_image(&mut self) -> Option<Image<MCUF::Address>> {
+    /// Scans all non-golden, non-bootable banks and replaces the current image in the main
+    /// bootable (MCU flash) bank with the newest valid image found, if it is newer than the
+    /// current one. If the update signal selects a bank, only that bank is scanned, and its
+    /// image replaces the current one as long as they differ, even if it's older. Images
+    /// rejected by anti-rollback protection or signed with a revoked key are ignored. Patches
+    /// in external banks are candidates too, as long as they apply to the current image. If a
+    /// scratch bank is configured, updates are swapped with the current image rather than
+    /// overwriting it (patches are always applied in place). Updated images are put on trial,
+    /// if enabled. If the current image is no longer trusted, any valid image is an update,
+    /// whatever its version. `plan` is the plan read from the update signal, if enabled.
+    /// Returns the current bootable image after the process, if available and trusted.
+    pub fn latest_bootable_image(
+        &mut self,
+        plan: Option<UpdatePlan>,
+    ) -> Option<Image<MCUF::Address>> {
         let boot_bank = self.boot_bank();
         let current_image = if let Ok(image) = R::image_at(&mut self.mcu_flash, boot_bank) {
             image
@@ -30,18 +68,23 @@ impl<
             return None;
         };
 
-        let bank: Option<u8> = match self
-            .update_signal
-            .as_ref()
-            .map(ReadUpdateSignal::read_update_plan)
-        {
+        let current_trusted = self.check_trust(&current_image).is_ok();
+        if !current_trusted {
+            duprintln!(self.serial, "Current image is no longer trusted, looking for any other.");
+        }
+        // The current image is only left in place if it can still be booted.
+        let current = current_trusted.then_some(current_image);
+
+        let target_bank: Option<u8> = match plan {
             None => None,
             Some(UpdatePlan::None) => {
                 duprintln!(self.serial, "Update signal set to None, refusing to update.");
-                return Some(current_image);
+                self.conclude_update_plan(UpdatePlan::None, true);
+                return current;
             }
             Some(UpdatePlan::Any) => {
                 duprintln!(self.serial, "Update signal set to Any, checking for image updates.");
+                self.conclude_update_plan(UpdatePlan::Any, true);
                 None
             }
             Some(UpdatePlan::Index(i)) => {
@@ -51,165 +94,267 @@ impl<
                     that bank.",
                     i
                 );
+                self.conclude_update_plan(UpdatePlan::Index(i), true);
                 Some(i)
             }
+            // Plans replacing the update with another operation fall back to not updating.
+            // They have already been concluded when followed.
+            Some(UpdatePlan::RestoreGolden | UpdatePlan::Recover | UpdatePlan::Rollback) => {
+                duprintln!(self.serial, "Update signal requests no update.");
+                return current;
+            }
         };
 
-        let current_image = match self.update_internal(boot_bank, current_image, bank) {
-            UpdateResult::NotUpdated(current_image) => current_image,
-            UpdateResult::AlreadyUpToDate(current_image) => return Some(current_image),
-            UpdateResult::UpdatedTo(new_image) => return Some(new_image),
-            UpdateResult::UpdateError => return None,
-        };
+        let mut newest: Option<Candidate<EXTF, MCUF>> = None;
+        // Images in additional bootable banks are booted in place rather than copied.
+        for bank in self.mcu_banks().filter(|b| !b.bootable) {
+            if self.skip_bank(&bank, MCUF::label(), target_bank) {
+                continue;
+            }
+            if let Ok(image) = R::image_at(&mut self.mcu_flash, bank) {
+                // Patches are only applied from external flash.
+                if self.check_trust(&image).is_err() || image.is_patch() {
+                    continue;
+                }
+                let candidate = Candidate::Internal(bank, image);
+                Self::consider(&mut newest, candidate, current.as_ref(), target_bank);
+            }
+        }
 
-        match self.update_external(boot_bank, current_image, bank) {
-            UpdateResult::NotUpdated(current_image) => Some(current_image),
-            UpdateResult::AlreadyUpToDate(current_image) => Some(current_image),
-            UpdateResult::UpdatedTo(new_image) => Some(new_image),
-            UpdateResult::UpdateError => None,
+        if self.external_flash.is_some() {
+            for bank in self.external_banks() {
+                if self.skip_bank(&bank, EXTF::label(), target_bank) {
+                    continue;
+                }
+                if let Ok(image) = R::image_at(self.external_flash.as_mut().unwrap(), bank) {
+                    if self.check_trust(&image).is_err() {
+                        continue;
+                    }
+                    let candidate = if !image.is_patch() {
+                        Candidate::External(bank, image)
+                    } else if image.base_identifier() == Some(current_image.identifier()) {
+                        Candidate::Patch(bank, image)
+                    } else {
+                        duprintln!(
+                            self.serial,
+                            "[{}] Patch in bank {:?} doesn't apply to the current image.",
+                            EXTF::label(),
+                            bank.index
+                        );
+                        continue;
+                    };
+                    Self::consider(&mut newest, candidate, current.as_ref(), target_bank);
+                }
+            }
         }
-    }
 
-    fn update_internal(
-        &mut self,
-        boot_bank: Bank<MCUF::Address>,
-        current_image: Image<MCUF::Address>,
-        target_bank: Option<u8>,
-    ) -> UpdateResult<MCUF> {
-        for bank in self.mcu_banks().filter(|b| b.index != boot_bank.index) {
-            if bank.is_golden {
-                duprintln!(
-                    self.serial,
-                    "[{}] Skipping golden bank {:?} (Golden banks can't be updated from)...",
-                    MCUF::label(),
-                    bank.index
-                );
-                continue;
+        let (index, updated_image) = match newest {
+            None if target_bank.is_some() => {
+                duprintln!(self.serial, "No different image found in the selected bank.");
+                return current;
+            }
+            None => {
+                duprintln!(self.serial, "No newer image found. Current image is up to date.");
+                return current;
+            }
+            Some(Candidate::Internal(bank, image)) => {
+                let updated_image = if self.can_swap(&bank, &image, &current_image) {
+                    self.swap_image(bank.index)
+                } else {
+                    self.replace_image(bank.index, boot_bank)
+                };
+                (bank.index, updated_image)
             }
+            Some(Candidate::External(bank, image)) => {
+                let updated_image = if self.can_swap(&bank, &image, &current_image) {
+                    self.swap_image(bank.index)
+                } else {
+                    self.replace_image(bank.index, boot_bank)
+                };
+                (bank.index, updated_image)
+            }
+            Some(Candidate::Patch(bank, _)) => (bank.index, self.apply_patch(bank, boot_bank)),
+        };
 
-            let skip_nontarget_bank = target_bank.map(|t| t != bank.index).unwrap_or(false);
-            if skip_nontarget_bank {
-                duprintln!(
-                    self.serial,
-                    "[{}] Skipping bank {:?} (Update signal was set to a bank index)...",
-                    MCUF::label(),
-                    bank.index
-                );
-                continue;
+        match updated_image {
+            Ok(image) => {
+                self.boot_metrics.boot_path = BootPath::Updated { bank: index };
+                self.start_trial(index);
+                Some(image)
             }
+            Err(_) => {
+                duprintln!(self.serial, "Failed to update from bank {:?}.", index);
+                // Depending on when the update failed, the boot bank may still hold the
+                // current image, or no valid image at all (to be restored as usual).
+                R::image_at(&mut self.mcu_flash, boot_bank).ok()
+            }
+        }
+    }
 
+    /// Whether a bank must be skipped when looking for update candidates.
+    fn skip_bank<A: Address>(&mut self, bank: &Bank<A>, label: &str, target: Option<u8>) -> bool {
+        if bank.is_golden {
             duprintln!(
                 self.serial,
-                "[{}] Scanning bank {:?} for a newer image...",
-                MCUF::label(),
+                "[{}] Skipping golden bank {:?} (Golden banks can't be updated from)...",
+                label,
                 bank.index
             );
-            match R::image_at(&mut self.mcu_flash, bank) {
-                Ok(image) if image.identifier() != current_image.identifier() => {
-                    if let Some(updated_image) = self.replace_image_internal(bank, boot_bank) {
-                        self.boot_metrics.boot_path = BootPath::Updated { bank: bank.index };
-                        return UpdateResult::UpdatedTo(updated_image);
-                    } else {
-                        return UpdateResult::UpdateError;
-                    }
-                }
-                Ok(_image) => return UpdateResult::AlreadyUpToDate(current_image),
-                _ => (),
+            return true;
+        }
+
+        if target.map(|t| t != bank.index).unwrap_or(false) {
+            duprintln!(
+                self.serial,
+                "[{}] Skipping bank {:?} (Update signal was set to a bank index)...",
+                label,
+                bank.index
+            );
+            return true;
+        }
+
+        duprintln!(self.serial, "[{}] Scanning bank {:?} for a newer image...", label, bank.index);
+        false
+    }
+
+    /// Keeps track of the newest update candidate. Candidates that are not newer than the
+    /// current image are discarded, and ties are resolved in favour of the first candidate found.
+    /// If the update signal selects a bank, its candidate only needs to differ from the current
+    /// image, so older images can be installed on request. If there's no trusted current image,
+    /// every candidate is eligible.
+    fn consider(
+        newest: &mut Option<Candidate<EXTF, MCUF>>,
+        candidate: Candidate<EXTF, MCUF>,
+        current_image: Option<&Image<MCUF::Address>>,
+        target_bank: Option<u8>,
+    ) {
+        let eligible = match current_image {
+            None => true,
+            Some(current_image) if target_bank.is_some() => {
+                candidate.identifier() != current_image.identifier()
             }
+            Some(current_image) => candidate.is_newer_than(current_image),
+        };
+        if !eligible {
+            return;
+        }
+        if newest.as_ref().is_none_or(|n| candidate.version() > n.version()) {
+            *newest = Some(candidate);
         }
-        return UpdateResult::NotUpdated(current_image);
     }
 
-    fn update_external(
+    /// Copies the image in bank `index` over the current one, returning the copied image.
+    /// Fails if the copy fails or its result can't be verified.
+    fn replace_image(
         &mut self,
+        index: u8,
         boot_bank: Bank<MCUF::Address>,
-        current_image: Image<MCUF::Address>,
-        target_bank: Option<u8>,
-    ) -> UpdateResult<MCUF> {
-        if self.external_flash.is_some() {
-            for bank in self.external_banks() {
-                if bank.is_golden {
-                    duprintln!(
-                        self.serial,
-                        "[{}] Skipping golden bank {:?} (Golden banks can't be updated from)...",
-                        MCUF::label(),
-                        bank.index
-                    );
-                    continue;
-                }
+    ) -> Result<Image<MCUF::Address>, Error> {
+        duprintln!(self.serial, "Replacing current image with bank {:?}.", index);
+        self.copy_bank(index, boot_bank.index, false)?;
+        duprintln!(self.serial, "Replaced image with bank {:?} [{}]", index, MCUF::label());
+        R::image_at(&mut self.mcu_flash, boot_bank)
+    }
+}
 
-                let skip_nontarget_bank = target_bank.map(|t| t != bank.index).unwrap_or(false);
-                if skip_nontarget_bank {
-                    duprintln!(
-                        self.serial,
-                        "[{}] Skipping bank {:?} (Update signal was set to a bank index)...",
-                        MCUF::label(),
-                        bank.index
-                    );
-                    continue;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{
+        bootloader::doubles::*,
+        image::{SemanticVersion, Trailer},
+    };
+    use blue_hal::hal::doubles::flash::Address;
 
-                duprintln!(
-                    self.serial,
-                    "[{}] Scanning bank {:?} for a newer image...",
-                    EXTF::label(),
-                    bank.index
-                );
-                match R::image_at(self.external_flash.as_mut().unwrap(), bank) {
-                    Ok(image) if image.identifier() != current_image.identifier() => {
-                        if let Some(updated_image) = self.replace_image_external(bank, boot_bank) {
-                            self.boot_metrics.boot_path = BootPath::Updated { bank: bank.index };
-                            return UpdateResult::UpdatedTo(updated_image);
-                        } else {
-                            return UpdateResult::UpdateError;
-                        }
-                    }
-                    Ok(_image) => return UpdateResult::AlreadyUpToDate(current_image),
-                    _ => (),
-                }
-            }
+    const BANK_SIZE: usize = TEST_SECTOR_SIZE;
+    static MCU_BANKS: [Bank<Address>; 4] = [
+        Bank { index: 1, size: BANK_SIZE, location: Address(0), bootable: true, is_golden: false },
+        Bank {
+            index: 2,
+            size: BANK_SIZE,
+            location: Address(BANK_SIZE as u32),
+            bootable: false,
+            is_golden: false,
+        },
+        Bank {
+            index: 3,
+            size: BANK_SIZE,
+            location: Address(2 * BANK_SIZE as u32),
+            bootable: false,
+            is_golden: false,
+        },
+        Bank {
+            index: 4,
+            size: BANK_SIZE,
+            location: Address(3 * BANK_SIZE as u32),
+            bootable: false,
+            is_golden: false,
+        },
+    ];
+    const HARDWARE_ID: u32 = 0x412;
+
+    fn version(major: u16) -> SemanticVersion { SemanticVersion { major, minor: 0, patch: 0 } }
+
+    /// Bootloader for board `HARDWARE_ID`, with an image of each given version and hardware
+    /// ID in consecutive MCU banks, starting with the boot bank.
+    fn bootloader_with(images: &[(u16, u32)]) -> BootloaderDouble {
+        let mut bootloader = BootloaderDouble::new().with_mcu_banks(&MCU_BANKS);
+        bootloader.hardware_id = Some(HARDWARE_ID);
+        for (i, &(major, hardware_id)) in images.iter().enumerate() {
+            let trailer = Trailer {
+                version: Some(version(major)),
+                hardware_id: Some(hardware_id),
+                ..Trailer::default()
+            };
+            let image = crc_image(&body(KB!(4), i as u8), &trailer);
+            write_image(&mut bootloader.mcu_flash, MCU_BANKS[i], &image);
         }
-        return UpdateResult::NotUpdated(current_image);
+        bootloader
     }
 
-    fn replace_image_internal(
-        &mut self,
-        bank: Bank<MCUF::Address>,
-        boot_bank: Bank<MCUF::Address>,
-    ) -> Option<Image<MCUF::Address>> {
-        duprintln!(self.serial, "Replacing current image with bank {:?}.", bank.index,);
-        Self::copy_image_single_flash(
-            &mut self.serial,
-            &mut self.mcu_flash,
-            bank,
-            boot_bank,
-            false,
-        )
-        .expect("Failed to copy a valid image!");
-        duprintln!(self.serial, "Replaced image with bank {:?} [{}]", bank.index, MCUF::label(),);
-        let image = R::image_at(&mut self.mcu_flash, boot_bank)
-            .expect("Failed to verify an image after copy!");
-        Some(image)
+    #[test]
+    fn newest_image_replaces_the_current_one() {
+        let images = [(1, HARDWARE_ID), (3, HARDWARE_ID), (4, HARDWARE_ID), (2, HARDWARE_ID)];
+        let mut bootloader = bootloader_with(&images);
+
+        let image = bootloader.latest_bootable_image(None).unwrap();
+        assert_eq!(image.version(), Some(version(4)));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Updated { bank: 3 }));
     }
 
-    fn replace_image_external(
-        &mut self,
-        bank: Bank<EXTF::Address>,
-        boot_bank: Bank<MCUF::Address>,
-    ) -> Option<Image<MCUF::Address>> {
-        duprintln!(self.serial, "Replacing current image with bank {:?}.", bank.index,);
-        Self::copy_image(
-            &mut self.serial,
-            self.external_flash.as_mut().unwrap(),
-            &mut self.mcu_flash,
-            bank,
-            boot_bank,
-            false,
-        )
-        .expect("Failed to copy a valid image!");
-        duprintln!(self.serial, "Replaced image with bank {:?} [{}]", bank.index, MCUF::label(),);
-        let image = R::image_at(&mut self.mcu_flash, boot_bank)
-            .expect("Failed to verify an image after copy!");
-        Some(image)
+    #[test]
+    fn older_images_are_not_updates() {
+        let mut bootloader =
+            bootloader_with(&[(3, HARDWARE_ID), (1, HARDWARE_ID), (2, HARDWARE_ID)]);
+
+        let image = bootloader.latest_bootable_image(None).unwrap();
+        assert_eq!(image.version(), Some(version(3)));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Direct));
+    }
+
+    #[test]
+    fn untrusted_images_are_not_updates() {
+        let mut bootloader = bootloader_with(&[(1, HARDWARE_ID), (3, 0xBAD), (2, HARDWARE_ID)]);
+
+        let image = bootloader.latest_bootable_image(None).unwrap();
+        assert_eq!(image.version(), Some(version(2)));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Updated { bank: 3 }));
+    }
+
+    #[test]
+    fn untrusted_current_image_is_replaced_by_any_trusted_one() {
+        let mut bootloader = bootloader_with(&[(3, 0xBAD), (1, HARDWARE_ID), (4, 0xBAD)]);
+
+        let image = bootloader.latest_bootable_image(None).unwrap();
+        assert_eq!(image.version(), Some(version(1)));
+        assert!(matches!(bootloader.boot_metrics.boot_path, BootPath::Updated { bank: 2 }));
+    }
+
+    #[test]
+    fn untrusted_current_image_is_not_booted_without_a_replacement() {
+        let mut bootloader = bootloader_with(&[(3, 0xBAD), (4, 0xBAD)]);
+
+        assert!(bootloader.latest_bootable_image(Some(UpdatePlan::None)).is_none());
+        assert!(bootloader.latest_bootable_image(None).is_none());
     }
 }
@@ -1,7 +1,7 @@
 use crate::{
     devices::{
         boot_manager::BootManager,
-        boot_metrics::BootPath,
+        boot_metrics::{BootPath, TrialStatus, UpdatePlanStatus, UpdateSignalStatus},
         cli::{file_transfer::FileTransfer, ArgumentIterator, Cli, Error, Name, RetrieveArgument},
         image::{self, MAGIC_STRING},
         traits::{Flash, Serial},
@@ -9,8 +9,46 @@ use crate::{
     },
     error::Error as ApplicationError,
 };
-use blue_hal::uprintln;
-use ufmt::uwriteln;
+use blue_hal::{uprintln, utilities::memory::Address};
+use ufmt::{uwrite, uwriteln};
+
+/// Name of an update plan, as set through the `update_signal_*` commands.
+fn plan_name(plan: UpdatePlan) -> &'static str {
+    match plan {
+        UpdatePlan::None => "none",
+        UpdatePlan::Any => "any",
+        UpdatePlan::Index(_) => "bank",
+        UpdatePlan::RestoreGolden => "golden",
+        UpdatePlan::Recover => "recover",
+        UpdatePlan::Rollback => "rollback",
+    }
+}
+
+/// Prints a single line summary of a firmware image.
+fn print_image<SRL: Serial, A: Address>(serial: &mut SRL, bank_index: u8, image: &image::Image<A>) {
+    uwrite!(serial, "Bank {} - [IMAGE] - Size: {}b", bank_index, image.size()).ok().unwrap();
+    if let Some(version) = image.version() {
+        uwrite!(serial, " - Version: {}.{}.{}", version.major, version.minor, version.patch)
+            .ok()
+            .unwrap();
+    }
+    if image.trailer().key_id.is_some() {
+        uwrite!(serial, " - Key: {}", image.key_id()).ok().unwrap();
+    }
+    if let Some(hardware_id) = image.hardware_id() {
+        uwrite!(serial, " - Hardware: {}", hardware_id).ok().unwrap();
+    }
+    if image.is_encrypted() {
+        uwrite!(serial, " - ENCRYPTED").ok().unwrap();
+    }
+    if image.is_compressed() {
+        uwrite!(serial, " - COMPRESSED").ok().unwrap();
+    }
+    if image.is_patch() {
+        uwrite!(serial, " - PATCH").ok().unwrap();
+    }
+    uwriteln!(serial, "{}", if image.is_golden() { " - GOLDEN" } else { "" }).ok().unwrap();
+}
 
 commands!( cli, boot_manager, names, helpstrings [
 
@@ -40,46 +78,48 @@ commands!( cli, boot_manager, names, helpstrings [
         }
     },
 
-    images ["Displays image information (WARNING: Slow)"] (){
+    images ["Displays image information"] (){
         uprintln!(cli.serial, "[{}] Images:", MCUF::label());
         for bank in boot_manager.mcu_banks() {
             if let Ok(image) = R::image_at(&mut boot_manager.mcu_flash, bank) {
-                uwriteln!(cli.serial, "Bank {} - [IMAGE] - Size: {}b - {}",
-                    bank.index,
-                    image.size(),
-                    if image.is_golden() { " - GOLDEN" } else { "" }).ok().unwrap();
+                print_image(&mut cli.serial, bank.index, &image);
             }
         }
         if let Some(ref mut external_flash) = boot_manager.external_flash {
             uprintln!(cli.serial, "[{}] Images:", EXTF::label());
             for bank in boot_manager.external_banks.iter().cloned() {
                 if let Ok(image) = R::image_at(external_flash, bank) {
-                    uwriteln!(cli.serial, "Bank {} - [IMAGE] - Size: {}b - {}",
-                        bank.index,
-                        image.size(),
-                        if image.is_golden() { " - GOLDEN" } else { "" }).ok().unwrap();
+                    print_image(&mut cli.serial, bank.index, &image);
                 }
             }
         }
     },
 
-    flash ["Stores a FW image in a non-bootable bank."] (
+    flash ["Stores a FW image in a bank other than the one running."] (
         bank: u8 ["Bank index."],
         )
     {
         if let Some(bank) = boot_manager.external_banks().find(|b| b.index == bank) {
             uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM client.");
-            boot_manager.store_image_external(cli.serial.blocks(None), bank)?;
+            let result = boot_manager.store_image_external(cli.serial.blocks(None), bank);
+            if result == Err(ApplicationError::ImageTooBig) {
+                uprintln!(cli.serial, "Image transfer cancelled: bank {} holds {} bytes.", bank.index, bank.size);
+            }
+            result?;
             uprintln!(cli.serial, "Image transfer complete!");
         } else if let Some(bank) = boot_manager.mcu_banks().find(|b| b.index == bank) {
-            if bank.bootable {
+            if bank.bootable && boot_manager.is_running_from(&bank) {
                 uprintln!(cli.serial, "You can't erase the bootable image, it's what you are");
                 uprintln!(cli.serial, "currently running! You can still corrupt its signature");
                 uprintln!(cli.serial, "to force it to be invalid.");
                 return Err(Error::ApplicationError(ApplicationError::BankInvalid));
             }
             uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM client.");
-            boot_manager.store_image_mcu(cli.serial.blocks(None), bank)?;
+            let result = boot_manager.store_image_mcu(cli.serial.blocks(None), bank);
+            if result == Err(ApplicationError::ImageTooBig) {
+                uprintln!(cli.serial, "Image transfer cancelled: bank {} holds {} bytes.", bank.index, bank.size);
+            }
+            result?;
             uprintln!(cli.serial, "Image transfer complete!");
         } else {
             uprintln!(cli.serial, "Index supplied does not correspond to any bank.");
@@ -167,17 +207,49 @@ commands!( cli, boot_manager, names, helpstrings [
         bank: u8 ["Updatable bank index."],
     ) {
         return boot_manager.set_update_signal(UpdatePlan::Index(bank))
-            .map_err(|e| Error::ApplicationError(e));
+            .map_err(Error::ApplicationError);
     },
 
     update_signal_none ["Disallow loadstone from updating."] ( ) {
         return boot_manager.set_update_signal(UpdatePlan::None)
-            .map_err(|e| Error::ApplicationError(e));
+            .map_err(Error::ApplicationError);
     },
 
     update_signal_any ["Allow loadstone to update from any bank."] ( ) {
         return boot_manager.set_update_signal(UpdatePlan::Any)
-            .map_err(|e| Error::ApplicationError(e));
+            .map_err(Error::ApplicationError);
+    },
+
+    update_signal_golden ["Make loadstone restore the golden image instead of updating."] ( ) {
+        return boot_manager.set_update_signal(UpdatePlan::RestoreGolden)
+            .map_err(Error::ApplicationError);
+    },
+
+    update_signal_recover ["Make loadstone enter serial recovery mode instead of updating."] ( ) {
+        return boot_manager.set_update_signal(UpdatePlan::Recover)
+            .map_err(Error::ApplicationError);
+    },
+
+    update_signal_rollback ["Make loadstone roll back to the previous image instead of updating."] ( ) {
+        return boot_manager.set_update_signal(UpdatePlan::Rollback)
+            .map_err(Error::ApplicationError);
+    },
+
+    revoke_key ["Permanently stops Loadstone from trusting a verifying key."] (
+        key: u8 ["Key ID."],
+    ) {
+        boot_manager.revoke_key(key).map_err(Error::ApplicationError)?;
+        uprintln!(cli.serial, "Revoked key {}.", key);
+    },
+
+    confirm ["Confirms the current image after an update, so Loadstone keeps booting it."] ( ) {
+        boot_manager.confirm_image().map_err(Error::ApplicationError)?;
+        uprintln!(cli.serial, "Confirmed the current image.");
+    },
+
+    healthy ["Resets the boot attempt counter, so Loadstone doesn't fall back to the golden image."] ( ) {
+        boot_manager.report_healthy().map_err(Error::ApplicationError)?;
+        uprintln!(cli.serial, "Reset the boot attempt counter.");
     },
 
     metrics ["Displays boot process metrics relayed by Loadstone."] ( )
@@ -224,6 +296,49 @@ commands!( cli, boot_manager, names, helpstrings [
                         );
                     }
                 },
+                BootPath::InPlace { bank } => {
+                    uprintln!(cli.serial, "* Application was booted in place from bank {}.", bank);
+                },
+                BootPath::RolledBack { bank } => {
+                    uprintln!(cli.serial, "* Application was rolled back to the image in bank {}, then booted.", bank);
+                },
+                BootPath::GoldenFallback { bank } => {
+                    uprintln!(cli.serial,
+                        "* Previous application never reported healthy, so the golden image was restored from bank {}.",
+                        bank
+                    );
+                },
+            }
+            match metrics.trial {
+                TrialStatus::None => {},
+                TrialStatus::Pending { boot, allowed_boots } => {
+                    uprintln!(cli.serial,
+                        "* Application is on trial (boot {} of {}). Use `confirm` to keep it.",
+                        boot,
+                        allowed_boots
+                    );
+                },
+                TrialStatus::Reverted => {
+                    uprintln!(cli.serial, "* Previous application was never confirmed, so it was reverted.");
+                },
+            }
+            if let UpdateSignalStatus::Defaulted = metrics.update_signal {
+                uprintln!(cli.serial, "* Update signal held no valid plan, so the default plan was followed.");
+            }
+            match metrics.update_plan {
+                UpdatePlanStatus::None => {},
+                UpdatePlanStatus::Honoured { plan, cleared } => {
+                    uprintln!(cli.serial, "* Update plan {} was honoured{}.",
+                        plan_name(plan),
+                        if cleared { " and cleared" } else { "" }
+                    );
+                },
+                UpdatePlanStatus::Failed { plan, cleared } => {
+                    uprintln!(cli.serial, "* Update plan {} could not be carried out{}.",
+                        plan_name(plan),
+                        if cleared { ", and was cleared" } else { "" }
+                    );
+                },
             }
             if let Some(boot_time_ms) = metrics.boot_time_ms {
                 uprintln!(cli.serial, "* Boot process took {} milliseconds.", boot_time_ms);
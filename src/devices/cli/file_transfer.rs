@@ -3,6 +3,7 @@
 //! Provides methods to receive arbitrary byte streams through serial
 //! via the XMODEM protocol.
 
+use crate::devices::image::BlockStream;
 use blue_hal::{
     hal::serial::{TimeoutRead, Write},
     utilities::xmodem,
@@ -11,15 +12,54 @@ use blue_hal::{
 /// The size of a single byte block retrieved from an XMODEM stream.
 pub const BLOCK_SIZE: usize = xmodem::PAYLOAD_SIZE;
 
+/// XMODEM cancel character. Senders abort the transfer after receiving it twice in a row.
+const CAN: u8 = 0x18;
+/// Number of cancel characters sent to abort a transfer, with a margin for line noise.
+const CANCEL_SEQUENCE_LENGTH: usize = 3;
+
 /// Generic file transfer iterator trait, returning an iterator over byte blocks.
 pub trait FileTransfer: TimeoutRead + Write {
     fn blocks(&mut self, max_retries: Option<u32>) -> BlockIterator<Self> {
+        self.watched_blocks(max_retries, || {})
+    }
+
+    /// Like [`FileTransfer::blocks`], calling `feed_watchdog` on every attempt to
+    /// receive a block, so waiting on the sender doesn't reset the MCU.
+    fn watched_blocks(
+        &mut self,
+        max_retries: Option<u32>,
+        feed_watchdog: fn(),
+    ) -> BlockIterator<'_, Self> {
+        BlockIterator {
+            serial: self,
+            received_block: false,
+            started: false,
+            finished: false,
+            block_number: 0,
+            max_retries,
+            feed_watchdog,
+            expired: None,
+        }
+    }
+
+    /// Like [`FileTransfer::watched_blocks`], giving up if the sender hasn't started
+    /// the transfer by the time `expired` returns true. Once the first block is
+    /// received, the transfer is no longer subject to `expired`.
+    fn timed_blocks<'a>(
+        &'a mut self,
+        max_retries: Option<u32>,
+        feed_watchdog: fn(),
+        expired: &'a dyn Fn() -> bool,
+    ) -> BlockIterator<'a, Self> {
         BlockIterator {
             serial: self,
             received_block: false,
+            started: false,
             finished: false,
             block_number: 0,
             max_retries,
+            feed_watchdog,
+            expired: Some(expired),
         }
     }
 }
@@ -30,9 +70,12 @@ impl<T: TimeoutRead + Write> FileTransfer for T {}
 pub struct BlockIterator<'a, S: TimeoutRead + Write + ?Sized> {
     serial: &'a mut S,
     received_block: bool,
+    started: bool,
     finished: bool,
     block_number: u8,
     max_retries: Option<u32>,
+    feed_watchdog: fn(),
+    expired: Option<&'a dyn Fn() -> bool>,
 }
 
 impl<'a, S: TimeoutRead + Write + ?Sized> Iterator for BlockIterator<'a, S> {
@@ -48,6 +91,10 @@ impl<'a, S: TimeoutRead + Write + ?Sized> Iterator for BlockIterator<'a, S> {
 
         'block_loop: while self.max_retries.is_none() || retries < self.max_retries.unwrap() {
             let mut buffer_index = 0usize;
+            (self.feed_watchdog)();
+            if self.expired.is_some_and(|expired| expired()) {
+                break 'block_loop;
+            }
 
             let message = if self.received_block { xmodem::ACK } else { xmodem::NAK };
             if self.serial.write_char(message as char).is_err() {
@@ -68,6 +115,8 @@ impl<'a, S: TimeoutRead + Write + ?Sized> Iterator for BlockIterator<'a, S> {
                 if buffer_index == 0 || buffer_index == (xmodem::MAX_PACKET_SIZE - 1) {
                     if let Some(block) = self.process_message(&buffer) {
                         self.received_block = true;
+                        self.started = true;
+                        self.expired = None;
                         return Some(block);
                     }
 
@@ -89,6 +138,9 @@ impl<'a, S: TimeoutRead + Write + ?Sized> Iterator for BlockIterator<'a, S> {
 }
 
 impl<'a, S: TimeoutRead + Write + ?Sized> BlockIterator<'a, S> {
+    /// Whether the sender started the transfer, sending at least one block.
+    pub fn started(&self) -> bool { self.started }
+
     fn process_message(&mut self, buffer: &[u8]) -> Option<[u8; BLOCK_SIZE]> {
         match xmodem::parse_message(&buffer) {
             Ok((_, xmodem::Message::EndOfTransmission)) => {
@@ -125,6 +177,18 @@ impl<'a, S: TimeoutRead + Write + ?Sized> BlockIterator<'a, S> {
     }
 }
 
+impl<'a, S: TimeoutRead + Write + ?Sized> BlockStream<BLOCK_SIZE> for BlockIterator<'a, S> {
+    /// Aborts the transfer, telling the sender to stop.
+    fn cancel(&mut self) {
+        self.finished = true;
+        for _ in 0..CANCEL_SEQUENCE_LENGTH {
+            if self.serial.write_char(CAN as char).is_err() {
+                return;
+            }
+        }
+    }
+}
+
 impl<'a, S: TimeoutRead + Write + ?Sized> Drop for BlockIterator<'a, S> {
     // Must fully consume the iterator on drop
     // to close the xmodem communication cleanly
@@ -0,0 +1,80 @@
+//! Compressed image support.
+//!
+//! Compressed image bodies start with a [`CompressionHeader`], followed by the
+//! compressed body (see [`loadstone_image::compression`] for the format). Compressed
+//! images can be stored in any bank, but are only bootable once decompressed into
+//! the bootable bank. Since the CRC/Signature is calculated over the *decompressed*
+//! image, the copy can be verified just like the original.
+//!
+//! Compression is applied before encryption, so the compression header of an
+//! encrypted image is only readable after decrypting it.
+
+use super::*;
+use encryption::Decryptor;
+use loadstone_image::compression::Decompressor;
+pub use loadstone_image::compression::{CompressionHeader, COMPRESSION_HEADER_SIZE};
+
+/// Size of the blocks of decompressed bytes fed to a digest.
+const DIGEST_CHUNK_SIZE: usize = 64;
+
+/// Reads the compression header at the start of an image body, decrypting it
+/// if necessary.
+pub fn read_compression_header<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    body_offset: usize,
+    encryption: Option<&EncryptionHeader>,
+) -> Result<Option<CompressionHeader>, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if bank.size < body_offset + COMPRESSION_HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut header = [0u8; COMPRESSION_HEADER_SIZE];
+    block!(flash.read(bank.location + body_offset, &mut header))?;
+    if let Some(encryption) = encryption {
+        Decryptor::new(encryption).apply(&mut header);
+    }
+    Ok(CompressionHeader::from_bytes(&header))
+}
+
+/// Decompresses an image as a stream, from the start of its (plaintext) body.
+/// The compression header is skipped, the compressed body is decompressed, and
+/// any bytes after it are passed through.
+pub struct Inflater {
+    header_remaining: usize,
+    decompressor: Decompressor,
+}
+
+impl Inflater {
+    pub fn new(header: &CompressionHeader) -> Self {
+        Self { header_remaining: COMPRESSION_HEADER_SIZE, decompressor: Decompressor::new(header) }
+    }
+
+    /// Processes the next bytes of the stream, writing the result to `output`. Returns
+    /// how many bytes were consumed from `input` and how many were written to `output`.
+    /// No bytes are consumed nor produced only when more input is needed, or `output`
+    /// is empty.
+    pub fn apply(&mut self, input: &[u8], output: &mut [u8]) -> (usize, usize) {
+        let skipped = min(self.header_remaining, input.len());
+        self.header_remaining -= skipped;
+        let (consumed, produced) = self.decompressor.apply(&input[skipped..], output);
+        (skipped + consumed, produced)
+    }
+
+    /// Processes the next bytes of the stream, feeding the result to `digest`.
+    pub fn digest(&mut self, mut input: &[u8], mut digest: impl FnMut(&[u8])) {
+        let mut buffer = [0u8; DIGEST_CHUNK_SIZE];
+        loop {
+            let (consumed, produced) = self.apply(input, &mut buffer);
+            if consumed == 0 && produced == 0 {
+                return;
+            }
+            digest(&buffer[..produced]);
+            input = &input[consumed..];
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Encrypted image support.
+//!
+//! Encrypted images are prefixed by a plaintext header, consisting of the
+//! [`ENCRYPTION_STRING`], a 16 byte AES-128-CTR initial counter block and the
+//! little endian `u32` size of the encrypted body. Only the image body is
+//! encrypted: decoration (the TLV trailer and magic string) and the
+//! CRC/Signature are left in plaintext. The CRC/Signature is calculated over
+//! the *plaintext* image, so it can be verified in both encrypted (external)
+//! and decrypted (bootable) banks.
+//!
+//! The AES key is provisioned at build time (`src/devices/assets/image_key.aes`).
+#[cfg(feature = "encryption")]
+use aes::Aes128;
+#[cfg(feature = "encryption")]
+use ctr::cipher::{NewCipher, StreamCipher};
+
+use super::*;
+use core::cmp::min;
+
+/// This string marks the start of an encrypted image.
+pub const ENCRYPTION_STRING: &str = "eNcRyPtD";
+/// Size of the AES-128-CTR initial counter block.
+pub const IV_SIZE: usize = 16;
+/// Total size of the plaintext header preceding an encrypted image body.
+pub const ENCRYPTION_HEADER_SIZE: usize = ENCRYPTION_STRING.len() + IV_SIZE + size_of::<u32>();
+
+#[cfg(feature = "encryption")]
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+#[cfg(all(feature = "encryption", not(test)))]
+const KEY: &[u8; 16] = include_bytes!("../assets/image_key.aes");
+#[cfg(all(feature = "encryption", test))]
+const KEY: &[u8; 16] = b"loadstone-testky";
+
+/// Header of an encrypted image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EncryptionHeader {
+    pub iv: [u8; IV_SIZE],
+    /// Size of the encrypted body, excluding decoration and CRC/Signature.
+    pub body_size: usize,
+}
+
+/// Reads the encryption header at the start of a bank, if present. Always returns
+/// `None` if Loadstone is built without encryption support, in which case encrypted
+/// images fail verification.
+pub fn read_encryption_header<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+) -> Result<Option<EncryptionHeader>, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if !cfg!(feature = "encryption") || bank.size < ENCRYPTION_HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut header = [0u8; ENCRYPTION_HEADER_SIZE];
+    block!(flash.read(bank.location, &mut header))?;
+    let (marker, fields) = header.split_at(ENCRYPTION_STRING.len());
+    if marker != ENCRYPTION_STRING.as_bytes() {
+        return Ok(None);
+    }
+    let (iv, body_size) = fields.split_at(IV_SIZE);
+    Ok(Some(EncryptionHeader {
+        iv: iv.try_into().unwrap(),
+        body_size: u32::from_le_bytes(body_size.try_into().unwrap()) as usize,
+    }))
+}
+
+/// Decrypts an image as a stream, from the start of its body. Only the first
+/// `body_size` bytes are decrypted, and any bytes after them are passed through.
+pub struct Decryptor {
+    #[cfg(feature = "encryption")]
+    cipher: Aes128Ctr,
+    remaining: usize,
+}
+
+impl Decryptor {
+    pub fn new(header: &EncryptionHeader) -> Self {
+        Self {
+            #[cfg(feature = "encryption")]
+            cipher: Aes128Ctr::new(KEY.into(), (&header.iv).into()),
+            remaining: header.body_size,
+        }
+    }
+
+    /// Decrypts the next bytes of the stream in place.
+    pub fn apply(&mut self, bytes: &mut [u8]) {
+        let length = min(self.remaining, bytes.len());
+        #[cfg(feature = "encryption")]
+        self.cipher.apply_keystream(&mut bytes[..length]);
+        self.remaining -= length;
+    }
+
+    /// Decrypts the next byte of the stream.
+    pub fn apply_byte(&mut self, byte: u8) -> u8 {
+        let mut bytes = [byte];
+        self.apply(&mut bytes);
+        bytes[0]
+    }
+}
@@ -2,48 +2,31 @@ use crate::error::Error;
 use core::mem::size_of;
 
 use super::*;
-use blue_hal::{
-    hal::flash,
-    utilities::{iterator::UntilSequence, memory::Address},
-};
+use blue_hal::{hal::flash, utilities::memory::Address};
 use crc::{crc32, Hasher32};
 use nb::block;
 
 pub struct CrcImageReader;
 
 impl super::Reader for CrcImageReader {
-    fn image_at<A, F>(flash: &mut F, bank: Bank<A>) -> Result<Image<A>, error::Error>
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        mut feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
     where
         A: Address,
         F: flash::ReadWrite<Address = A>,
         error::Error: From<F::Error>,
     {
-        // Generic buffer to hold temporary slices read from flash memory.
-        const BUFFER_SIZE: usize = 256;
-        let mut buffer = [0u8; BUFFER_SIZE];
-
-        let (mut digest, mut image_size) = flash
-            .bytes(bank.location)
-            .take(bank.size)
-            .until_sequence(&magic_string_inverted())
-            .fold(
-                (crc32::Digest::new(crc32::IEEE), 0usize),
-                |(mut digest, mut byte_count), byte| {
-                    digest.write(&[byte]);
-                    byte_count += 1;
-                    (digest, byte_count)
-                },
-            );
-
-        if image_size == bank.size {
-            return Err(Error::BankEmpty);
-        }
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        let scan = scan(flash, bank, |bytes| {
+            feed_watchdog();
+            digest.write(bytes)
+        })?;
 
-        // Magic string is part of the digest
-        digest.write(&magic_string_inverted());
-        let digest_position = bank.location + image_size + MAGIC_STRING.len();
         let mut digest_bytes = [0; size_of::<u32>()];
-        block!(flash.read(digest_position, &mut digest_bytes))?;
+        block!(flash.read(scan.trailer_location(), &mut digest_bytes))?;
 
         let retrieved_crc = u32::from_le_bytes(digest_bytes);
         let calculated_crc = digest.sum32();
@@ -51,22 +34,7 @@ impl super::Reader for CrcImageReader {
             return Err(Error::CrcInvalid);
         }
 
-        let golden_string_position = bank.location + image_size.saturating_sub(GOLDEN_STRING.len());
-        let golden_bytes = &mut buffer[0..GOLDEN_STRING.len()];
-        block!(flash.read(golden_string_position, golden_bytes))?;
-        let golden = golden_bytes == GOLDEN_STRING.as_bytes();
-
-        if golden {
-            image_size = image_size.saturating_sub(GOLDEN_STRING.len());
-        }
-
-        Ok(Image {
-            size: image_size,
-            location: bank.location,
-            bootable: bank.bootable,
-            golden,
-            crc: calculated_crc,
-        })
+        verified_image(flash, bank, scan, Identifier::new(&digest_bytes))
     }
 }
 
@@ -109,18 +77,88 @@ mod tests {
         0x77, 0xc9, 0x42, 0xad
     ];
 
+    #[rustfmt::skip]
+    const TEST_IMAGE_WITH_METADATA: &[u8] = &[
+        // Image
+        0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a,
+        // Version record (1.2.3)
+        0x02, 0x06, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00,
+        // Image type record (application)
+        0x04, 0x01, 0x00,
+        // Timestamp record
+        0x06, 0x08, 0xf0, 0xf9, 0xe6, 0x60, 0x00, 0x00, 0x00, 0x00,
+        // Security epoch record
+        0x07, 0x04, 0x04, 0x00, 0x00, 0x00,
+        // Key ID record
+        0x08, 0x01, 0x00,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x1e, 0x00,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e, 0xa5, 0xa8,
+        0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc, 0xb5, 0x8b, 0x91, 0xb5,
+        0xc9, 0xa9, 0x8a, 0xbe,
+        // CRC
+        0xb0, 0x16, 0xc5, 0x01
+    ];
+
+    #[rustfmt::skip]
+    const TEST_IMAGE_WITH_TRAILER: &[u8] = &[
+        // Image
+        0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a,
+        // Golden record
+        0x01, 0x00,
+        // Version record (1.2.3)
+        0x02, 0x06, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00,
+        // Security epoch record
+        0x07, 0x04, 0x04, 0x00, 0x00, 0x00,
+        // Record with an unknown tag
+        0xee, 0x02, 0xab, 0xcd,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x14, 0x00,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e, 0xa5, 0xa8,
+        0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc, 0xb5, 0x8b, 0x91, 0xb5,
+        0xc9, 0xa9, 0x8a, 0xbe,
+        // CRC
+        0x80, 0x3a, 0xb8, 0x7b
+    ];
+
+    #[cfg(feature = "encryption")]
+    #[rustfmt::skip]
+    const TEST_ENCRYPTED_IMAGE: &[u8] = &[
+        // Encryption string
+        0x65, 0x4e, 0x63, 0x52, 0x79, 0x50, 0x74, 0x44,
+        // Initial counter block
+        0x65, 0x30, 0x71, 0xbf, 0x7e, 0x03, 0x5e, 0xc1,
+        0xa4, 0xbb, 0xbd, 0x1c, 0xaf, 0xeb, 0xf0, 0x45,
+        // Encrypted body size
+        0x0c, 0x00, 0x00, 0x00,
+        // Encrypted image ("hello world\n")
+        0x6a, 0x03, 0xe5, 0x77, 0xd6, 0xb8, 0x08, 0xf4, 0xa3, 0x59, 0xe5, 0xff,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e, 0xa5, 0xa8,
+        0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc, 0xb5, 0x8b, 0x91, 0xb5,
+        0xc9, 0xa9, 0x8a, 0xbe,
+        // CRC (of the plaintext image)
+        0xf0, 0xc9, 0x42, 0xad
+    ];
+
     #[test]
     fn retrieving_image_with_correct_crc_succeeds() {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
-        flash.write(Address(0), &TEST_IMAGE_WITH_CORRECT_CRC).unwrap();
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), TEST_IMAGE_WITH_CORRECT_CRC).unwrap();
 
         let image = CrcImageReader::image_at(&mut flash, bank).unwrap();
         assert_eq!(image.size, 12usize);
         assert_eq!(image.location, bank.location);
-        assert_eq!(image.bootable, false);
-        assert_eq!(image.is_golden(), false);
+        assert!(!image.bootable);
+        assert!(!image.is_golden());
     }
 
     #[test]
@@ -128,8 +166,82 @@ mod tests {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
 
-        flash.write(Address(0), &TEST_IMAGE_WITH_BAD_CRC).unwrap();
+        flash.write(Address(0), TEST_IMAGE_WITH_BAD_CRC).unwrap();
         assert_eq!(Err(Error::CrcInvalid), CrcImageReader::image_at(&mut flash, bank));
     }
+
+    #[test]
+    fn retrieving_image_with_metadata_parses_version() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), TEST_IMAGE_WITH_METADATA).unwrap();
+
+        let image = CrcImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 12usize);
+        assert_eq!(image.total_size(), TEST_IMAGE_WITH_METADATA.len());
+        assert_eq!(image.version(), Some(SemanticVersion { major: 1, minor: 2, patch: 3 }));
+        let trailer = image.trailer();
+        assert_eq!(trailer.image_type, Some(ImageType::Application));
+        assert_eq!(trailer.timestamp, Some(1625750000));
+        assert_eq!(image.security_epoch(), 4);
+    }
+
+    #[test]
+    fn retrieving_image_with_trailer_parses_known_records() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), TEST_IMAGE_WITH_TRAILER).unwrap();
+
+        let image = CrcImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 12usize);
+        assert_eq!(image.total_size(), TEST_IMAGE_WITH_TRAILER.len());
+        assert!(image.is_golden());
+        assert_eq!(image.version(), Some(SemanticVersion { major: 1, minor: 2, patch: 3 }));
+        assert_eq!(image.security_epoch(), 4);
+        assert_eq!(image.key_id(), 0);
+        assert_eq!(image.trailer().image_type, None);
+    }
+
+    #[test]
+    fn versioned_images_are_newer_than_unversioned_ones() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+
+        flash.write(Address(0), TEST_IMAGE_WITH_CORRECT_CRC).unwrap();
+        let unversioned = CrcImageReader::image_at(&mut flash, bank).unwrap();
+        flash.write(Address(0), TEST_IMAGE_WITH_METADATA).unwrap();
+        let versioned = CrcImageReader::image_at(&mut flash, bank).unwrap();
+
+        assert!(versioned.is_newer_than(&unversioned));
+        assert!(!unversioned.is_newer_than(&versioned));
+        assert!(!versioned.is_newer_than(&versioned));
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn encrypted_image_is_verified_against_its_plaintext() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+
+        flash.write(Address(0), TEST_IMAGE_WITH_CORRECT_CRC).unwrap();
+        let plaintext = CrcImageReader::image_at(&mut flash, bank).unwrap();
+        flash.write(Address(0), &TEST_ENCRYPTED_IMAGE).unwrap();
+        let encrypted = CrcImageReader::image_at(&mut flash, bank).unwrap();
+
+        assert!(encrypted.is_encrypted());
+        assert!(!plaintext.is_encrypted());
+        assert_eq!(encrypted.size(), plaintext.size());
+        assert_eq!(encrypted.total_size(), TEST_ENCRYPTED_IMAGE.len());
+        assert_eq!(encrypted.identifier(), plaintext.identifier());
+    }
 }
@@ -1,10 +1,7 @@
 use crate::error::Error;
 
 use super::*;
-use blue_hal::{
-    hal::flash,
-    utilities::{iterator::UntilSequence, memory::Address},
-};
+use blue_hal::{hal::flash, utilities::memory::Address};
 
 pub use ::ecdsa::{elliptic_curve::generic_array::typenum::Unsigned, SignatureSize};
 pub use ecdsa::signature::Signature as EcdsaSignature;
@@ -16,84 +13,52 @@ pub use p256::{
 };
 pub use sha2::Digest;
 
-fn retrieve_key() -> VerifyingKey {
-    #[allow(unused)]
-    use core::str::FromStr;
+/// Size of a raw (uncompressed SEC1) P-256 public key.
+const KEY_SIZE: usize = 65;
 
+fn retrieve_key(id: u8) -> Option<VerifyingKey> {
     #[cfg(test)]
-    return VerifyingKey::from_str(include_str!("../assets/test_key.pem"))
-        .expect("Invalic public key supplied on compilation");
+    let ring: &[u8] = &tests::TEST_KEY_RING;
 
     #[cfg(not(test))]
-    return VerifyingKey::from_encoded_point(
-        &EncodedPoint::from_bytes(include_bytes!("../assets/key.sec1"))
-            .expect("Invalic public key supplied on compilation"),
-    )
-    .expect("Invalic public key supplied on compilation");
+    let ring: &[u8] = include_bytes!("../assets/key_ring.p256");
+
+    key_ring::find_key(ring, KEY_SIZE, id).map(|key| {
+        VerifyingKey::from_encoded_point(
+            &EncodedPoint::from_bytes(key).expect("Invalid public key supplied on compilation"),
+        )
+        .expect("Invalid public key supplied on compilation")
+    })
 }
 
 pub struct EcdsaImageReader;
 
 impl Reader for EcdsaImageReader {
-    fn image_at<A, F>(flash: &mut F, bank: Bank<A>) -> Result<Image<A>, error::Error>
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        mut feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
     where
         A: Address,
         F: flash::ReadWrite<Address = A>,
         error::Error: From<F::Error>,
     {
-        // Development build shorcut: We're checking that the image does *not* start with 0xFF. This
-        // will not be part of the final Loadstone release build, but it helps speed up the
-        // verification for invalid images during development.
-        if flash.bytes(bank.location).next().ok_or(Error::BankInvalid)? == 0xFF {
-            return Err(Error::BankEmpty);
-        }
-        let key = retrieve_key();
-
-        // Generic buffer to hold temporary slices read from flash memory.
-        const BUFFER_SIZE: usize = 256;
-        let mut buffer = [0u8; BUFFER_SIZE];
-
-        let (mut digest, mut image_size) = flash
-            .bytes(bank.location)
-            .take(bank.size)
-            .until_sequence(&magic_string_inverted())
-            .fold((sha2::Sha256::default(), 0usize), |(mut digest, mut byte_count), byte| {
-                digest.update(&[byte]);
-                byte_count += 1;
-                (digest, byte_count)
-            });
+        let mut digest = sha2::Sha256::default();
+        let scan = scan(flash, bank, |bytes| {
+            feed_watchdog();
+            digest.update(bytes)
+        })?;
+        let key = retrieve_key(signing_key_id(flash, &scan)?).ok_or(Error::SignatureInvalid)?;
 
-        if image_size == bank.size {
-            return Err(Error::BankEmpty);
-        }
-
-        // Magic string is part of the digest
-        digest.update(&magic_string_inverted());
-
-        let signature_position = bank.location + image_size + MAGIC_STRING.len();
-        let signature_bytes = &mut buffer[0..SignatureSize::<NistP256>::to_usize()];
-        block!(flash.read(signature_position, signature_bytes))?;
+        let mut signature_bytes = [0u8; <SignatureSize<NistP256> as Unsigned>::USIZE];
+        block!(flash.read(scan.trailer_location(), &mut signature_bytes))?;
 
         let signature =
-            Signature::from_bytes(signature_bytes).map_err(|_| Error::SignatureInvalid)?;
+            Signature::from_bytes(&signature_bytes).map_err(|_| Error::SignatureInvalid)?;
         key.verify_digest(digest, &signature).map_err(|_| Error::SignatureInvalid)?;
 
-        let golden_string_position = bank.location + image_size.saturating_sub(GOLDEN_STRING.len());
-        let golden_bytes = &mut buffer[0..GOLDEN_STRING.len()];
-        block!(flash.read(golden_string_position, golden_bytes))?;
-        let golden = golden_bytes == GOLDEN_STRING.as_bytes();
-
-        if golden {
-            image_size = image_size.saturating_sub(GOLDEN_STRING.len());
-        }
-
-        Ok(Image {
-            size: image_size,
-            location: bank.location,
-            bootable: bank.bootable,
-            golden,
-            signature,
-        })
+        verified_image(flash, bank, scan, Identifier::new(&signature_bytes))
     }
 }
 
@@ -109,6 +74,22 @@ mod tests {
     };
     use std::convert::TryInto;
 
+    // Public key for the secret scalar 0x0102..20, so fixtures can be signed again.
+    #[rustfmt::skip]
+    pub(super) const TEST_KEY_RING: [u8; 66] = [
+        // Key ID
+        0x00,
+        0x04, 0x51, 0x5c, 0x3d, 0x6e, 0xb9, 0xe3, 0x96,
+        0xb9, 0x04, 0xd3, 0xfe, 0xca, 0x7f, 0x54, 0xfd,
+        0xcd, 0x0c, 0xc1, 0xe9, 0x97, 0xbf, 0x37, 0x5d,
+        0xca, 0x51, 0x5a, 0xd0, 0xa6, 0xc3, 0xb4, 0x03,
+        0x5f, 0x45, 0x36, 0xbe, 0x3a, 0x50, 0xf3, 0x18,
+        0xfb, 0xf9, 0xa5, 0x47, 0x59, 0x02, 0xa2, 0x21,
+        0x50, 0x2b, 0xef, 0x0d, 0x57, 0xe0, 0x8c, 0x53,
+        0xb2, 0xcc, 0x0a, 0x56, 0xf1, 0x7d, 0x9f, 0x93,
+        0x54,
+    ];
+
     #[rustfmt::skip]
     const TEST_SIGNED_IMAGE: &[u8] = &[
         // Image
@@ -116,83 +97,85 @@ mod tests {
         // Magic string inverted
         0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
         0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
-        // Signature
         0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
         0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
-        0x49, 0xdb, 0xc3, 0x82, 0x37, 0xff, 0x13, 0x9a,
-        0x96, 0xb1, 0xb2, 0x37, 0x4a, 0x41, 0x35, 0x36,
-        0xd4, 0xed, 0xc7, 0xdf, 0x00, 0x80, 0x54, 0xde,
-        0x95, 0xbe, 0xc5, 0x1b, 0xbb, 0x89, 0xa9, 0x35,
-        0x03, 0x62, 0xb0, 0xef, 0x73, 0x1f, 0x32, 0x4a,
-        0x5e, 0x93, 0x8c, 0x78, 0x4e, 0xf5, 0x6a, 0x3f,
-        0xf5, 0x8f, 0x99, 0xf6, 0x11, 0x67, 0xa6, 0xc2,
-        0x12, 0xc7, 0xf5, 0xb3, 0x3b, 0xb0, 0x12, 0x8e,
+        // Signature
+        0x94, 0x29, 0xd9, 0x58, 0xa5, 0x53, 0x28, 0xe5,
+        0x25, 0xd2, 0x26, 0x6d, 0xd8, 0xc9, 0x12, 0x58,
+        0x4a, 0xa0, 0xa9, 0x55, 0x32, 0x1c, 0x94, 0xf5,
+        0xaa, 0xc1, 0xba, 0x86, 0x3b, 0xc1, 0x35, 0xd2,
+        0x5e, 0x6d, 0x15, 0x6c, 0xe7, 0x92, 0x23, 0x1f,
+        0xad, 0xdb, 0xc5, 0xa3, 0x20, 0xc3, 0x30, 0xf7,
+        0xf5, 0x37, 0x27, 0x68, 0x10, 0x9f, 0x9f, 0xfc,
+        0xa5, 0x67, 0x41, 0x45, 0x3b, 0xd7, 0x7a, 0x7b,
     ];
 
     #[rustfmt::skip]
     const TEST_SIGNED_GOLDEN_IMAGE: &[u8] = &[
         // Image
         0xaa, 0xbb,
-        // Golden String
-        0x58, 0x50, 0x49, 0x63, 0x62, 0x4f, 0x55, 0x72, 0x70, 0x47,
-        // Magic String Inverted
+        // Golden record
+        0x01, 0x00,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x02, 0x00,
+        // Magic string inverted
         0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
         0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
-        // Signature
         0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
         0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
-        0x8a, 0xb7, 0xcb, 0x03, 0x03, 0x53, 0xd2, 0xa3,
-        0x9d, 0x42, 0x99, 0x3f, 0x94, 0xfc, 0x2d, 0x91,
-        0x4b, 0x91, 0x50, 0xfb, 0xdc, 0x28, 0xaa, 0x11,
-        0x31, 0xca, 0x4b, 0x4f, 0x74, 0x94, 0xe4, 0xeb,
-        0x42, 0x93, 0x24, 0xd1, 0x73, 0x85, 0xcd, 0xd8,
-        0x1f, 0x12, 0xbe, 0xcd, 0x4b, 0xdb, 0x9f, 0xcb,
-        0x58, 0x0e, 0xef, 0xc6, 0x9e, 0xf2, 0xa3, 0x0e,
-        0x7f, 0xa8, 0xbb, 0xf1, 0x26, 0x30, 0xec, 0x5a
+        // Signature
+        0x6f, 0x4e, 0xd5, 0xf6, 0xc9, 0xe0, 0x0c, 0xf3,
+        0x29, 0x48, 0x79, 0xc4, 0x20, 0x6d, 0xde, 0x25,
+        0xda, 0x9b, 0xb6, 0x42, 0xb2, 0x0d, 0x7b, 0xf5,
+        0xcc, 0x17, 0xec, 0xad, 0x62, 0x9d, 0x74, 0x13,
+        0xb8, 0xe6, 0x5e, 0x1f, 0x79, 0x0d, 0x8d, 0x4e,
+        0x26, 0x82, 0x7e, 0x31, 0x97, 0xd7, 0x6a, 0x84,
+        0x28, 0x3c, 0x5a, 0x2e, 0x32, 0xd4, 0x1e, 0x8c,
+        0xd4, 0x86, 0x04, 0xff, 0xc4, 0x0a, 0x0c, 0x17,
     ];
 
     #[rustfmt::skip]
     const TEST_IMAGE_SIGNED_BY_ANOTHER_KEY: &[u8] = &[
         // Image
         0xaa, 0xbb,
-
         // Magic string inverted
         0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
         0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
-
-        // Signature
         0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
         0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
-        0x12, 0x77, 0x26, 0xc9, 0x13, 0x89, 0x38, 0xca,
-        0x23, 0xb9, 0x3d, 0xc9, 0xdc, 0xad, 0xbc, 0x8b,
-        0x41, 0x99, 0xe0, 0x89, 0x97, 0xf4, 0x7d, 0x88,
-        0xaf, 0xc7, 0x8a, 0x5d, 0xf5, 0xaf, 0x37, 0xdd,
-        0x45, 0x0e, 0x38, 0xdc, 0x74, 0x85, 0x72, 0x28,
-        0x28, 0x54, 0x15, 0xdd, 0x15, 0x6c, 0x1b, 0x22,
-        0xfe, 0x18, 0x40, 0x88, 0xcb, 0x26, 0x4e, 0x22,
-        0x3b, 0x0a, 0xbd, 0x09, 0x73, 0x1d, 0x1b, 0x35,
+        // Signature
+        0x33, 0x35, 0xfe, 0x08, 0x60, 0xbb, 0x1c, 0x3b,
+        0x1a, 0xf6, 0xfc, 0x7a, 0x2d, 0x23, 0x32, 0x25,
+        0x73, 0x5d, 0x5e, 0xd1, 0x08, 0xad, 0xe6, 0xcf,
+        0x57, 0x31, 0x64, 0x53, 0x5b, 0xad, 0x78, 0x3e,
+        0xde, 0xf9, 0x21, 0x90, 0xcd, 0x68, 0x7a, 0xc1,
+        0x40, 0xd5, 0x05, 0x3b, 0x1a, 0x25, 0x2c, 0xea,
+        0x08, 0x96, 0x34, 0x72, 0x20, 0x35, 0x63, 0x94,
+        0xda, 0x48, 0x6d, 0xe5, 0x05, 0x5f, 0x19, 0xce,
     ];
 
     #[rustfmt::skip]
     const TEST_GOLDEN_IMAGE_SIGNED_BY_ANOTHER_KEY: &[u8] = &[
         // Image
         0xaa, 0xbb,
-        // Golden string
-        0x58, 0x50, 0x49, 0x63, 0x62, 0x4f, 0x55, 0x72, 0x70, 0x47,
+        // Golden record
+        0x01, 0x00,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x02, 0x00,
         // Magic string inverted
         0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
         0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
-        // Signature
         0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
         0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
-        0xcf, 0x71, 0x77, 0x7f, 0x47, 0x4b, 0x3e, 0xd4,
-        0x01, 0xaa, 0x65, 0x22, 0x78, 0x4a, 0x0f, 0x4a,
-        0x84, 0x11, 0x65, 0xba, 0x7c, 0x85, 0x00, 0x8b,
-        0x9c, 0x87, 0x78, 0xb3, 0x47, 0x36, 0xe8, 0x4d,
-        0xb9, 0x24, 0x9f, 0x51, 0x2b, 0x34, 0x2f, 0x70,
-        0x75, 0xe7, 0xdf, 0x77, 0x5e, 0x23, 0x8e, 0x92,
-        0xf4, 0xe8, 0x3f, 0x79, 0xc2, 0xa3, 0x50, 0x5a,
-        0xc7, 0x62, 0x74, 0x6e, 0xd2, 0x0b, 0x96, 0x84
+        // Signature
+        0xff, 0xab, 0x85, 0xcb, 0x6a, 0x0d, 0x20, 0x13,
+        0xe5, 0x0c, 0x27, 0x72, 0x3d, 0x40, 0xb5, 0x8b,
+        0x32, 0xf3, 0xe6, 0xa2, 0xdd, 0x16, 0x95, 0xc9,
+        0x16, 0x03, 0xd9, 0x51, 0x85, 0xab, 0x94, 0x8e,
+        0x8c, 0xf2, 0x5a, 0xeb, 0x52, 0x98, 0x19, 0x32,
+        0x52, 0x12, 0xb0, 0x94, 0x02, 0xa5, 0x9c, 0x42,
+        0x10, 0x6a, 0x62, 0x01, 0x15, 0x86, 0x83, 0xc3,
+        0xb1, 0xab, 0xcf, 0xe4, 0x36, 0x5c, 0x3b, 0xd5,
     ];
 
     #[test]
@@ -200,6 +183,7 @@ mod tests {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
         flash.write(Address(0), &TEST_SIGNED_IMAGE).unwrap();
 
         let image = EcdsaImageReader::image_at(&mut flash, bank).unwrap();
@@ -214,6 +198,7 @@ mod tests {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
         flash.write(Address(0), &TEST_SIGNED_GOLDEN_IMAGE).unwrap();
 
         let image = EcdsaImageReader::image_at(&mut flash, bank).unwrap();
@@ -228,6 +213,7 @@ mod tests {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
 
         flash.write(Address(0), &TEST_IMAGE_SIGNED_BY_ANOTHER_KEY).unwrap();
         assert_eq!(Err(Error::SignatureInvalid), EcdsaImageReader::image_at(&mut flash, bank));
@@ -241,6 +227,7 @@ mod tests {
         let mut flash = FakeFlash::new(Address(0));
         let bank =
             Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
 
         let mut image: [u8; 98] = TEST_SIGNED_IMAGE.try_into().unwrap();
         image[0] = 0xCC; // Corrupted image body;
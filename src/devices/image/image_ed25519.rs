@@ -0,0 +1,185 @@
+use crate::error::Error;
+
+use super::*;
+use blue_hal::{hal::flash, utilities::memory::Address};
+use core::convert::TryFrom;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use nb::block;
+use sha2::{Digest, Sha512};
+
+fn retrieve_key(id: u8) -> Option<VerifyingKey> {
+    #[cfg(test)]
+    let ring: &[u8] = &tests::TEST_KEY_RING;
+
+    #[cfg(not(test))]
+    let ring: &[u8] = include_bytes!("../assets/key_ring.ed25519");
+
+    key_ring::find_key(ring, PUBLIC_KEY_LENGTH, id)
+        .map(|key| VerifyingKey::try_from(key).expect("Invalid public key supplied on compilation"))
+}
+
+/// Image reader for images signed with Ed25519ph (Ed25519 over the SHA-512 hash of
+/// the image, with an empty context), so images can be verified as a stream.
+pub struct Ed25519ImageReader;
+
+impl Reader for Ed25519ImageReader {
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        mut feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
+    where
+        A: Address,
+        F: flash::ReadWrite<Address = A>,
+        error::Error: From<F::Error>,
+    {
+        let mut digest = Sha512::default();
+        let scan = scan(flash, bank, |bytes| {
+            feed_watchdog();
+            digest.update(bytes)
+        })?;
+        let key = retrieve_key(signing_key_id(flash, &scan)?).ok_or(Error::SignatureInvalid)?;
+
+        let mut signature_bytes = [0u8; SIGNATURE_LENGTH];
+        block!(flash.read(scan.trailer_location(), &mut signature_bytes))?;
+
+        let signature =
+            Signature::try_from(&signature_bytes[..]).map_err(|_| Error::SignatureInvalid)?;
+        key.verify_prehashed(digest, None, &signature).map_err(|_| Error::SignatureInvalid)?;
+
+        verified_image(flash, bank, scan, Identifier::new(&signature_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blue_hal::hal::{
+        doubles::flash::{Address, FakeFlash},
+        flash::ReadWrite,
+    };
+    use std::convert::TryInto;
+
+    // Public key for the secret seed 0x0001..1f, so fixtures can be signed again.
+    #[rustfmt::skip]
+    pub(super) const TEST_KEY_RING: [u8; 33] = [
+        // Key ID
+        0x00,
+        0x03, 0xa1, 0x07, 0xbf, 0xf3, 0xce, 0x10, 0xbe,
+        0x1d, 0x70, 0xdd, 0x18, 0xe7, 0x4b, 0xc0, 0x99,
+        0x67, 0xe4, 0xd6, 0x30, 0x9b, 0xa5, 0x0d, 0x5f,
+        0x1d, 0xdc, 0x86, 0x64, 0x12, 0x55, 0x31, 0xb8,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_SIGNED_IMAGE: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0xed, 0xa6, 0xd6, 0x8f, 0xb7, 0x1a, 0xfd, 0xad,
+        0x32, 0xac, 0xdc, 0x56, 0x33, 0x82, 0xc4, 0x75,
+        0x30, 0x18, 0x7f, 0xc4, 0x9b, 0x54, 0x60, 0xd9,
+        0x5b, 0x58, 0x21, 0x50, 0x94, 0x06, 0xc5, 0x55,
+        0x8b, 0xaa, 0xc1, 0x74, 0x92, 0x5f, 0x7c, 0x8f,
+        0xcc, 0xbf, 0x1f, 0x18, 0x67, 0x85, 0x73, 0x3c,
+        0xd3, 0xb1, 0xe2, 0xf0, 0x7a, 0x57, 0xa1, 0x46,
+        0x07, 0x73, 0xe8, 0x8b, 0x28, 0xba, 0x70, 0x01,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_SIGNED_GOLDEN_IMAGE: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Golden record
+        0x01, 0x00,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x02, 0x00,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0x57, 0xbb, 0x0a, 0x4e, 0xe2, 0xc9, 0xc3, 0x27,
+        0xc7, 0x26, 0x49, 0xa5, 0x44, 0x62, 0xe0, 0xe3,
+        0xf5, 0x2b, 0xf6, 0x24, 0x85, 0xdf, 0x5b, 0x30,
+        0xf8, 0x9a, 0xb8, 0xd2, 0xd5, 0x4f, 0x87, 0xe1,
+        0x67, 0x5e, 0xe8, 0x28, 0xa9, 0x42, 0xb3, 0xfc,
+        0x29, 0x5b, 0x9a, 0x01, 0x4e, 0xce, 0xdf, 0x36,
+        0xab, 0x05, 0x40, 0xe0, 0x8b, 0x8e, 0x44, 0xb0,
+        0x65, 0xe9, 0x51, 0x5f, 0x57, 0x27, 0xa7, 0x06,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_IMAGE_SIGNED_BY_ANOTHER_KEY: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0xef, 0x44, 0xee, 0x8f, 0x56, 0xbf, 0xf5, 0x54,
+        0x80, 0x99, 0x8e, 0x6d, 0x21, 0xce, 0x90, 0x2e,
+        0x5f, 0xff, 0x09, 0xc0, 0x19, 0x89, 0x99, 0xea,
+        0x48, 0xc9, 0xc5, 0xee, 0x96, 0x69, 0x19, 0x09,
+        0x50, 0x17, 0xf6, 0xfb, 0x74, 0xe2, 0x05, 0xca,
+        0x25, 0x55, 0xd5, 0x23, 0xff, 0x79, 0x90, 0x5d,
+        0xc8, 0x25, 0x66, 0x44, 0x7c, 0x06, 0xe3, 0xd4,
+        0x7a, 0x72, 0x96, 0xcf, 0x73, 0x5d, 0xd2, 0x00,
+    ];
+
+    #[test]
+    fn retrieving_signed_image_succeeds() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), &TEST_SIGNED_IMAGE).unwrap();
+
+        let image = Ed25519ImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 2usize);
+        assert_eq!(image.total_size(), TEST_SIGNED_IMAGE.len());
+        assert_eq!(image.is_golden(), false);
+    }
+
+    #[test]
+    fn retrieving_signed_golden_image_succeeds() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), &TEST_SIGNED_GOLDEN_IMAGE).unwrap();
+
+        let image = Ed25519ImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 2usize);
+        assert_eq!(image.is_golden(), true);
+    }
+
+    #[test]
+    fn retrieving_broken_or_foreign_images_fails() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+
+        flash.write(Address(0), &TEST_IMAGE_SIGNED_BY_ANOTHER_KEY).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), Ed25519ImageReader::image_at(&mut flash, bank));
+
+        let mut image: [u8; 98] = TEST_SIGNED_IMAGE.try_into().unwrap();
+        image[0] = 0xCC; // Corrupted image body
+        flash.write(Address(0), &image).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), Ed25519ImageReader::image_at(&mut flash, bank));
+
+        let mut image: [u8; 98] = TEST_SIGNED_IMAGE.try_into().unwrap();
+        image[96] = 0xCC; // Corrupted signature
+        flash.write(Address(0), &image).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), Ed25519ImageReader::image_at(&mut flash, bank));
+    }
+}
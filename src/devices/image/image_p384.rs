@@ -0,0 +1,212 @@
+use crate::error::Error;
+
+use super::*;
+use blue_hal::{hal::flash, utilities::memory::Address};
+use core::convert::TryFrom;
+use nb::block;
+use p384::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
+use sha2::{Digest, Sha384};
+
+/// Size of a raw (big endian `r || s`) P-384 ECDSA signature.
+const SIGNATURE_SIZE: usize = 96;
+
+/// Size of a raw (uncompressed SEC1) P-384 public key.
+const KEY_SIZE: usize = 97;
+
+fn retrieve_key(id: u8) -> Option<VerifyingKey> {
+    #[cfg(test)]
+    let ring: &[u8] = &tests::TEST_KEY_RING;
+
+    #[cfg(not(test))]
+    let ring: &[u8] = include_bytes!("../assets/key_ring.p384");
+
+    key_ring::find_key(ring, KEY_SIZE, id).map(|key| {
+        VerifyingKey::from_sec1_bytes(key).expect("Invalid public key supplied on compilation")
+    })
+}
+
+/// Image reader for images signed with ECDSA over the NIST P-384 curve, using SHA-384.
+pub struct P384ImageReader;
+
+impl Reader for P384ImageReader {
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        mut feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
+    where
+        A: Address,
+        F: flash::ReadWrite<Address = A>,
+        error::Error: From<F::Error>,
+    {
+        let mut digest = Sha384::default();
+        let scan = scan(flash, bank, |bytes| {
+            feed_watchdog();
+            digest.update(bytes)
+        })?;
+        let key = retrieve_key(signing_key_id(flash, &scan)?).ok_or(Error::SignatureInvalid)?;
+
+        let mut signature_bytes = [0u8; SIGNATURE_SIZE];
+        block!(flash.read(scan.trailer_location(), &mut signature_bytes))?;
+
+        let signature =
+            Signature::try_from(&signature_bytes[..]).map_err(|_| Error::SignatureInvalid)?;
+        key.verify_digest(digest, &signature).map_err(|_| Error::SignatureInvalid)?;
+
+        verified_image(flash, bank, scan, Identifier::new(&signature_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blue_hal::hal::{
+        doubles::flash::{Address, FakeFlash},
+        flash::ReadWrite,
+    };
+    use std::convert::TryInto;
+
+    // Public key for the secret scalar 0x0102..30, so fixtures can be signed again.
+    #[rustfmt::skip]
+    pub(super) const TEST_KEY_RING: [u8; 98] = [
+        // Key ID
+        0x00,
+        0x04, 0xc7, 0x6f, 0x22, 0x83, 0xdd, 0xa9, 0x5c,
+        0xd4, 0x9b, 0x0e, 0xd9, 0xe7, 0x33, 0xd2, 0x90,
+        0x44, 0x74, 0xe3, 0x72, 0x16, 0xf1, 0x24, 0xe1,
+        0x3d, 0x2c, 0x9a, 0xb4, 0xcf, 0x01, 0x02, 0x1c,
+        0x49, 0xad, 0x9c, 0xab, 0xb3, 0xd0, 0xb9, 0x74,
+        0x99, 0xae, 0xf2, 0xf0, 0xab, 0x31, 0x3f, 0xa0,
+        0x28, 0x26, 0xbc, 0x1f, 0x83, 0x45, 0x1b, 0x5c,
+        0x89, 0x62, 0xa7, 0x5c, 0xaf, 0xf7, 0x35, 0x88,
+        0xd4, 0x40, 0x0a, 0x62, 0x96, 0x43, 0x61, 0x54,
+        0xfb, 0x34, 0x3c, 0x39, 0x3e, 0x91, 0x04, 0x8a,
+        0x6c, 0x7b, 0xcb, 0xad, 0xc8, 0x3c, 0xd8, 0xa5,
+        0xf2, 0x6f, 0xea, 0xe8, 0x83, 0x15, 0x6f, 0x92,
+        0xa1,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_SIGNED_IMAGE: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0xd6, 0x7b, 0x84, 0xe9, 0x60, 0x6f, 0x4f, 0x8c,
+        0xe8, 0x02, 0x39, 0x56, 0x54, 0xd9, 0xae, 0xc5,
+        0x99, 0x1a, 0x02, 0xd1, 0xdb, 0x01, 0x8d, 0xbc,
+        0x85, 0xdd, 0xd4, 0x5b, 0xb7, 0xe7, 0x0c, 0xe1,
+        0x3e, 0x7e, 0xaa, 0xe8, 0x7c, 0x0b, 0x15, 0x69,
+        0x53, 0x43, 0x29, 0x61, 0xdf, 0x6a, 0xa0, 0x07,
+        0x73, 0x5c, 0x30, 0xe6, 0x3e, 0x3c, 0x51, 0x1f,
+        0x44, 0x6a, 0xa7, 0x06, 0x57, 0xd7, 0xb1, 0x73,
+        0x82, 0xc7, 0xb6, 0x28, 0x6b, 0x3f, 0xb8, 0xfe,
+        0x15, 0xfc, 0x7b, 0x81, 0x6b, 0xcb, 0x6d, 0x8b,
+        0x3d, 0xa5, 0x4d, 0xc2, 0x8e, 0xe7, 0xc5, 0xf3,
+        0x61, 0x2f, 0x23, 0x5b, 0x63, 0x0c, 0xe3, 0xe4,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_SIGNED_GOLDEN_IMAGE: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Golden record
+        0x01, 0x00,
+        // Trailer string and records size
+        0x74, 0x4c, 0x76, 0x54, 0x72, 0x4c, 0x72, 0x35, 0x02, 0x00,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0x0d, 0x1f, 0x1e, 0xd8, 0x98, 0x34, 0xc7, 0x5b,
+        0xb8, 0x70, 0xfb, 0xc8, 0x40, 0xfe, 0x81, 0xfb,
+        0xb7, 0x5c, 0x76, 0x09, 0x74, 0x20, 0xb6, 0x97,
+        0x66, 0xe4, 0x62, 0xf4, 0x5a, 0x90, 0x51, 0xca,
+        0x6b, 0x41, 0xf1, 0xba, 0x8e, 0x59, 0x9a, 0x52,
+        0x29, 0x4b, 0xd0, 0x56, 0x11, 0x6d, 0xb1, 0x9a,
+        0xa9, 0x8e, 0x59, 0xfa, 0x2d, 0x58, 0xd0, 0x17,
+        0x57, 0xd7, 0x4a, 0xaf, 0x82, 0x66, 0xca, 0xad,
+        0x96, 0xd7, 0x80, 0x04, 0x5f, 0x72, 0x69, 0x90,
+        0x3c, 0x62, 0x58, 0x14, 0x7b, 0x47, 0x67, 0x88,
+        0xa5, 0x29, 0x41, 0xdf, 0xed, 0x89, 0x8f, 0x19,
+        0x7c, 0xf7, 0x89, 0x51, 0xe5, 0x5a, 0x92, 0x33,
+    ];
+
+    #[rustfmt::skip]
+    const TEST_IMAGE_SIGNED_BY_ANOTHER_KEY: &[u8] = &[
+        // Image
+        0xaa, 0xbb,
+        // Magic string inverted
+        0xb7, 0xac, 0x9c, 0xc8, 0x9c, 0xcd, 0x8f, 0x8b,
+        0x86, 0x9b, 0xa5, 0xb7, 0xcd, 0xae, 0x94, 0x8e,
+        0xa5, 0xa8, 0xaf, 0x9c, 0xb5, 0x98, 0xb8, 0xcc,
+        0xb5, 0x8b, 0x91, 0xb5, 0xc9, 0xa9, 0x8a, 0xbe,
+        // Signature
+        0x7c, 0x75, 0x82, 0x46, 0x67, 0x73, 0x23, 0xb2,
+        0x93, 0xf2, 0x81, 0x53, 0xe5, 0xee, 0xd5, 0x68,
+        0x66, 0x03, 0xeb, 0x4c, 0x7b, 0x3f, 0x48, 0xa1,
+        0x0c, 0x53, 0x0d, 0xdd, 0x65, 0x9c, 0xee, 0x33,
+        0x4e, 0xbe, 0x83, 0x90, 0xdc, 0xa4, 0xcb, 0x61,
+        0x09, 0xb9, 0xb2, 0x65, 0x51, 0x39, 0x94, 0xbe,
+        0x9f, 0x1d, 0xd0, 0x6d, 0x73, 0xe7, 0xd2, 0x9c,
+        0x29, 0xb2, 0x97, 0x48, 0xb4, 0x5d, 0xbf, 0xfb,
+        0x82, 0x48, 0xb9, 0xea, 0xee, 0x64, 0x5c, 0x96,
+        0xdc, 0x66, 0xb7, 0x0b, 0xd3, 0xc3, 0xd8, 0xd8,
+        0xad, 0x93, 0xe7, 0x57, 0x8f, 0x1a, 0x08, 0x6e,
+        0x15, 0x23, 0xdc, 0xd9, 0x38, 0xa6, 0xd5, 0x2d,
+    ];
+
+    #[test]
+    fn retrieving_signed_image_succeeds() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), &TEST_SIGNED_IMAGE).unwrap();
+
+        let image = P384ImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 2usize);
+        assert_eq!(image.total_size(), TEST_SIGNED_IMAGE.len());
+        assert_eq!(image.is_golden(), false);
+    }
+
+    #[test]
+    fn retrieving_signed_golden_image_succeeds() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+        flash.write(Address(0), &TEST_SIGNED_GOLDEN_IMAGE).unwrap();
+
+        let image = P384ImageReader::image_at(&mut flash, bank).unwrap();
+        assert_eq!(image.size, 2usize);
+        assert_eq!(image.is_golden(), true);
+    }
+
+    #[test]
+    fn retrieving_broken_or_foreign_images_fails() {
+        let mut flash = FakeFlash::new(Address(0));
+        let bank =
+            Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+        clear_footer(&mut flash, bank).unwrap();
+
+        flash.write(Address(0), &TEST_IMAGE_SIGNED_BY_ANOTHER_KEY).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), P384ImageReader::image_at(&mut flash, bank));
+
+        let mut image: [u8; 130] = TEST_SIGNED_IMAGE.try_into().unwrap();
+        image[0] = 0xCC; // Corrupted image body
+        flash.write(Address(0), &image).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), P384ImageReader::image_at(&mut flash, bank));
+
+        let mut image: [u8; 130] = TEST_SIGNED_IMAGE.try_into().unwrap();
+        image[128] = 0xCC; // Corrupted signature
+        flash.write(Address(0), &image).unwrap();
+        assert_eq!(Err(Error::SignatureInvalid), P384ImageReader::image_at(&mut flash, bank));
+    }
+}
@@ -0,0 +1,26 @@
+//! Key rings of verifying keys.
+//!
+//! Loadstone can trust several verifying keys at once, so signing keys can be
+//! rotated (and compromised ones revoked) without replacing the bootloader. Key
+//! rings are provisioned at build time, as a sequence of records consisting of a
+//! key ID byte followed by the raw key, whose size depends on the signature scheme.
+//! Images name the key they were signed with through a key ID record in their
+//! TLV trailer, and are otherwise signed with the primary key (ID zero).
+
+/// Finds the raw key with a given ID in a key ring.
+pub fn find_key(ring: &[u8], key_size: usize, id: u8) -> Option<&[u8]> {
+    ring.chunks_exact(1 + key_size).find(|record| record[0] == id).map(|record| &record[1..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_found_by_id() {
+        let ring = [0x00, 0xAA, 0xBB, 0x05, 0xCC, 0xDD];
+        assert_eq!(find_key(&ring, 2, 0), Some(&[0xAA, 0xBB][..]));
+        assert_eq!(find_key(&ring, 2, 5), Some(&[0xCC, 0xDD][..]));
+        assert_eq!(find_key(&ring, 2, 1), None);
+    }
+}
@@ -3,43 +3,72 @@
 //! This module offers tools to partition flash memory spaces
 //! into image banks and scan those banks for valid images.
 
-#[cfg(not(feature = "ecdsa-verify"))]
+pub mod compression;
+pub mod encryption;
+pub mod key_ring;
+// Tests use CRC images whatever the security mode, as they're easy to produce.
+#[cfg(any(
+    test,
+    not(any(feature = "ecdsa-verify", feature = "p384-verify", feature = "ed25519-verify"))
+))]
 pub mod image_crc;
 #[cfg(feature = "ecdsa-verify")]
 pub mod image_ecdsa;
+#[cfg(feature = "ed25519-verify")]
+pub mod image_ed25519;
+#[cfg(feature = "p384-verify")]
+pub mod image_p384;
 
-#[cfg(not(feature = "ecdsa-verify"))]
+#[cfg(not(any(
+    feature = "ecdsa-verify",
+    feature = "p384-verify",
+    feature = "ed25519-verify"
+)))]
 pub use image_crc::CrcImageReader;
 #[cfg(feature = "ecdsa-verify")]
 pub use image_ecdsa::EcdsaImageReader;
+#[cfg(feature = "ed25519-verify")]
+pub use image_ed25519::Ed25519ImageReader;
+#[cfg(feature = "p384-verify")]
+pub use image_p384::P384ImageReader;
 
+/// Image reader for the security mode Loadstone is built with.
+#[cfg(not(any(
+    feature = "ecdsa-verify",
+    feature = "p384-verify",
+    feature = "ed25519-verify"
+)))]
+pub type ImageReader = CrcImageReader;
+/// Image reader for the security mode Loadstone is built with.
 #[cfg(feature = "ecdsa-verify")]
-use ecdsa::elliptic_curve::generic_array::typenum::Unsigned;
+pub type ImageReader = EcdsaImageReader;
+/// Image reader for the security mode Loadstone is built with.
+#[cfg(feature = "ed25519-verify")]
+pub type ImageReader = Ed25519ImageReader;
+/// Image reader for the security mode Loadstone is built with.
+#[cfg(feature = "p384-verify")]
+pub type ImageReader = P384ImageReader;
 
-use blue_hal::{
-    hal::flash,
-    utilities::{buffer::CollectSlice, memory::Address},
-};
-
-use crate::error;
+#[cfg(any(
+    all(feature = "ecdsa-verify", feature = "p384-verify"),
+    all(feature = "ecdsa-verify", feature = "ed25519-verify"),
+    all(feature = "p384-verify", feature = "ed25519-verify"),
+))]
+compile_error!("Only one signature verification feature can be enabled at a time");
 
-/// This string precedes the CRC/Signature for golden images only
-pub const GOLDEN_STRING: &str = "XPIcbOUrpG";
+use blue_hal::{hal::flash, utilities::memory::Address};
+use core::{cmp::min, convert::TryInto, marker::PhantomData};
+use loadstone_image::{parse_trailer_info, MAX_TRAILER_SIZE, TRAILER_INFO_SIZE};
+use nb::block;
 
-/// This string, INVERTED BYTEWISE must terminate any valid images, after CRC/Signature
-///
-/// Note: Why inverted? Because if we used it as-is, no code that includes this
-/// constant could be used as a firmware image, as it contains the magic string
-/// halfway through.
-pub const MAGIC_STRING: &str = "HSc7c2ptydZH2QkqZWPcJgG3JtnJ6VuA";
+use crate::{devices::traits::Watchdog, error};
+use compression::{CompressionHeader, Inflater};
+use encryption::{EncryptionHeader, ENCRYPTION_HEADER_SIZE};
 
-/// utility function to invert the [`MAGIC_STRING`].
-pub fn magic_string_inverted() -> [u8; MAGIC_STRING.len()] {
-    let mut inverted = [0u8; MAGIC_STRING.len()];
-    let mut bytes = MAGIC_STRING.as_bytes().iter().map(|b| !b);
-    bytes.collect_slice(&mut inverted);
-    inverted
-}
+pub use loadstone_image::{
+    magic_string_inverted, Dependency, Identifier, ImageType, SemanticVersion, Trailer,
+    FOOTER_SIZE, FOOTER_STRING, MAGIC_STRING, MAX_IDENTIFIER_SIZE,
+};
 
 /// Image bank descriptor.
 ///
@@ -80,6 +109,406 @@ impl<A: Address> Bank<A> {
     }
 }
 
+/// Decoration of an image, preceding its magic string.
+struct Decoration {
+    trailer: Trailer,
+    /// Size of the decoration, excluding the magic string.
+    size: usize,
+}
+
+/// Reads the decoration of an image, given the offset from the start of the image
+/// body to its magic string. Images without a TLV trailer, or with a malformed one,
+/// are treated as undecorated, as the trailer bytes are then just image bytes.
+fn read_decoration<A, F>(
+    flash: &mut F,
+    body_location: A,
+    magic_offset: usize,
+) -> Result<Decoration, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if magic_offset >= TRAILER_INFO_SIZE {
+        let info_offset = magic_offset - TRAILER_INFO_SIZE;
+        let mut info = [0u8; TRAILER_INFO_SIZE];
+        block!(flash.read(body_location + info_offset, &mut info))?;
+        match parse_trailer_info(&info) {
+            Some(size) if size <= min(MAX_TRAILER_SIZE, info_offset) => {
+                let mut records = [0u8; MAX_TRAILER_SIZE];
+                let records = &mut records[..size];
+                block!(flash.read(body_location + (info_offset - size), records))?;
+                if let Ok(trailer) = Trailer::parse(records) {
+                    return Ok(Decoration { trailer, size: size + TRAILER_INFO_SIZE });
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(Decoration { trailer: Trailer::default(), size: 0 })
+}
+
+/// Footer at a fixed position (the last [`FOOTER_SIZE`] bytes) of a bank, recording
+/// where the image in the bank is, so it can be found without scanning the bank.
+///
+/// The footer is not covered by the CRC/signature, so it's only a hint: the image
+/// it points to is verified as usual, and a stale or cleared footer (see [`clear_footer`])
+/// is ignored in favour of scanning the bank. So is an erased footer, as images written
+/// to a bank by other means than Loadstone (such as a debug probe) may have none, unless
+/// the start of the bank is erased too (see [`scan`]).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Footer {
+    /// Offset from the start of the bank to the magic string.
+    pub magic_offset: usize,
+    /// Offset from the start of the bank to the end of the CRC/signature.
+    pub total_size: usize,
+}
+
+impl Footer {
+    pub fn to_bytes(&self) -> [u8; FOOTER_SIZE] {
+        let mut bytes = [0u8; FOOTER_SIZE];
+        let (marker, offsets) = bytes.split_at_mut(FOOTER_STRING.len());
+        marker.copy_from_slice(FOOTER_STRING.as_bytes());
+        offsets[..4].copy_from_slice(&(self.magic_offset as u32).to_le_bytes());
+        offsets[4..].copy_from_slice(&(self.total_size as u32).to_le_bytes());
+        bytes
+    }
+
+    /// Whether an image of this size leaves room for the footer in a bank.
+    pub fn fits<A: Address>(&self, bank: &Bank<A>) -> bool {
+        self.magic_offset + MAGIC_STRING.len() <= self.total_size
+            && self.total_size + FOOTER_SIZE <= bank.size
+    }
+
+    fn location<A: Address>(bank: &Bank<A>) -> A { bank.location + (bank.size - FOOTER_SIZE) }
+}
+
+/// Reads the footer at the end of a bank, if present and consistent with the bank size.
+pub fn read_footer<A, F>(flash: &mut F, bank: Bank<A>) -> Result<Option<Footer>, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if bank.size < FOOTER_SIZE {
+        return Ok(None);
+    }
+    let mut bytes = [0u8; FOOTER_SIZE];
+    block!(flash.read(Footer::location(&bank), &mut bytes))?;
+    let (marker, offsets) = bytes.split_at(FOOTER_STRING.len());
+    if marker != FOOTER_STRING.as_bytes() {
+        return Ok(None);
+    }
+    let footer = Footer {
+        magic_offset: u32::from_le_bytes(offsets[..4].try_into().unwrap()) as usize,
+        total_size: u32::from_le_bytes(offsets[4..].try_into().unwrap()) as usize,
+    };
+    Ok(footer.fits(&bank).then_some(footer))
+}
+
+/// Writes a footer to the end of a bank. Images that leave no room for
+/// the footer are left without one, and will be found by scanning the bank.
+pub fn write_footer<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    footer: Footer,
+) -> Result<(), error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if footer.fits(&bank) {
+        block!(flash.write(Footer::location(&bank), &footer.to_bytes()))?;
+    }
+    Ok(())
+}
+
+/// Clears the footer at the end of a bank, marking the bank as holding an image
+/// without one, to be found by scanning. Must be called before writing an image to a
+/// bank by other means than copying it, as a stale footer could point to a magic
+/// string left behind by a previous image.
+pub fn clear_footer<A, F>(flash: &mut F, bank: Bank<A>) -> Result<(), error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if bank.size >= FOOTER_SIZE {
+        block!(flash.write(Footer::location(&bank), &[0x00; FOOTER_SIZE]))?;
+    }
+    Ok(())
+}
+
+/// Stream of blocks an image is received as, which can be cancelled to tell its
+/// sender to stop.
+pub trait BlockStream<const N: usize>: Iterator<Item = [u8; N]> {
+    /// Abandons the stream before it ends.
+    fn cancel(&mut self);
+}
+
+impl<I: BlockStream<N>, const N: usize> BlockStream<N> for &mut I {
+    fn cancel(&mut self) { (**self).cancel() }
+}
+
+/// Writes an image received as a stream of blocks to the start of a bank, failing with
+/// [`Error::ImageTooBig`](error::Error::ImageTooBig) if the stream goes past the end of
+/// the bank. Blocks past the end of the bank are never written, and the stream is
+/// cancelled at the first of them.
+pub fn write_from_blocks<A, F, I, const N: usize>(
+    flash: &mut F,
+    bank: Bank<A>,
+    mut blocks: I,
+) -> Result<(), error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+    I: BlockStream<N>,
+{
+    let mut received = 0usize;
+    let mut too_big = false;
+    let fitting_blocks = blocks.by_ref().take_while(|_| {
+        received += N;
+        too_big = received > bank.size;
+        !too_big
+    });
+    flash.write_from_blocks(bank.location, fitting_blocks)?;
+    if too_big {
+        blocks.cancel();
+        Err(error::Error::ImageTooBig)
+    } else {
+        Ok(())
+    }
+}
+
+/// Size of a flash word. Flash drivers reject writes that don't start at a word boundary.
+const WORD_SIZE: usize = 4;
+
+/// Makes an image undiscoverable, by zeroing the words its magic string spans and
+/// clearing the footer of its bank. Used to discard images that must never be booted
+/// again. Banks start at a word boundary, so the writes are always word aligned.
+pub fn discard<A, F>(flash: &mut F, bank: Bank<A>, image: &Image<A>) -> Result<(), error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    let magic_offset = image.footer().magic_offset;
+    let start = magic_offset - magic_offset % WORD_SIZE;
+    let end = magic_offset + MAGIC_STRING.len();
+    let end = end + (WORD_SIZE - end % WORD_SIZE) % WORD_SIZE;
+    let zeroes = [0u8; MAGIC_STRING.len() + 2 * WORD_SIZE];
+    block!(flash.write(bank.location + start, &zeroes[..end - start]))?;
+    clear_footer(flash, bank)
+}
+
+/// Result of scanning a bank for an image terminated by the magic string,
+/// prior to verifying its CRC/signature.
+struct Scan<A: Address> {
+    /// Plaintext header of an encrypted image, if any.
+    encryption: Option<EncryptionHeader>,
+    /// Header of a compressed image, if any.
+    compression: Option<CompressionHeader>,
+    body_location: A,
+    /// Size from the start of the image body to the magic string.
+    size: usize,
+}
+
+impl<A: Address> Scan<A> {
+    /// Location of the CRC/signature, immediately after the magic string.
+    fn trailer_location(&self) -> A { self.body_location + self.size + MAGIC_STRING.len() }
+}
+
+/// Size of the blocks read from flash while scanning a bank for an image.
+pub const SCAN_CHUNK_SIZE: usize = 512;
+
+/// Locates the image in a bank, feeding all image bytes up to and including the
+/// magic string to `digest`. Encrypted image bodies are decrypted, and compressed
+/// image bodies decompressed, as they're read, so the digest is always over the
+/// plaintext image.
+///
+/// If the bank has a [`Footer`] pointing at a magic string, the image is read directly.
+/// If both the footer and the first word of the bank are erased, the bank is empty, as
+/// images start at the start of their bank and none starts with an erased word (it holds
+/// the initial stack pointer, or the encryption or compression header). Otherwise (the footer is stale or
+/// cleared, or it's erased but the bank isn't, as when an image was written by a debug
+/// probe), the bank is scanned for the magic string.
+fn scan<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    mut digest: impl FnMut(&[u8]),
+) -> Result<Scan<A>, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    let encryption = encryption::read_encryption_header(flash, bank)?;
+    let body_offset = if encryption.is_some() { ENCRYPTION_HEADER_SIZE } else { 0 };
+    let body_location = bank.location + body_offset;
+    let mut decryptor = encryption.as_ref().map(encryption::Decryptor::new);
+    let compression =
+        compression::read_compression_header(flash, bank, body_offset, encryption.as_ref())?;
+    let mut inflater = compression.as_ref().map(Inflater::new);
+    // Anything fed to `digest` from here on is decompressed first, if need be.
+    let mut digest = |bytes: &[u8]| match inflater.as_mut() {
+        Some(inflater) => inflater.digest(bytes, &mut digest),
+        None => digest(bytes),
+    };
+
+    if let Some(footer) = read_footer(flash, bank)? {
+        let mut magic = [0u8; MAGIC_STRING.len()];
+        block!(flash.read(bank.location + footer.magic_offset, &mut magic))?;
+        if footer.magic_offset >= body_offset && magic == magic_string_inverted() {
+            let size = footer.magic_offset - body_offset;
+            let mut buffer = [0u8; SCAN_CHUNK_SIZE];
+            let mut read = 0usize;
+            while read < size {
+                let block = &mut buffer[..min(SCAN_CHUNK_SIZE, size - read)];
+                block!(flash.read(body_location + read, block))?;
+                if let Some(decryptor) = decryptor.as_mut() {
+                    decryptor.apply(block);
+                }
+                digest(block);
+                read += block.len();
+            }
+            // Magic string is part of the digest
+            digest(&magic);
+            return Ok(Scan { encryption, compression, body_location, size });
+        }
+    }
+
+    if is_erased(flash, bank)? {
+        return Err(error::Error::BankEmpty);
+    }
+    let size = search(flash, bank, body_offset, decryptor, digest)?;
+    Ok(Scan { encryption, compression, body_location, size })
+}
+
+/// Whether both the first word and the footer of a bank are erased.
+fn is_erased<A, F>(flash: &mut F, bank: Bank<A>) -> Result<bool, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    if bank.size < FOOTER_SIZE {
+        return Ok(false);
+    }
+    let mut first_word = [0u8; WORD_SIZE];
+    block!(flash.read(bank.location, &mut first_word))?;
+    let mut footer = [0u8; FOOTER_SIZE];
+    block!(flash.read(Footer::location(&bank), &mut footer))?;
+    Ok(first_word.iter().chain(footer.iter()).all(|&byte| byte == 0xFF))
+}
+
+/// Scans a bank for the magic string, feeding all image bytes up to and including it
+/// to `digest`. Returns the size from the start of the image body to the magic string.
+///
+/// The bank is read in blocks of [`SCAN_CHUNK_SIZE`] bytes. The last bytes of each
+/// block are carried over to the next one, so a magic string straddling two blocks
+/// is still found.
+fn search<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    body_offset: usize,
+    mut decryptor: Option<encryption::Decryptor>,
+    mut digest: impl FnMut(&[u8]),
+) -> Result<usize, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    const MAGIC_SIZE: usize = MAGIC_STRING.len();
+    let magic = magic_string_inverted();
+    let body_location = bank.location + body_offset;
+    let limit = bank.size - body_offset;
+
+    // Holds the bytes carried over from the previous block, followed by a new block.
+    let mut buffer = [0u8; SCAN_CHUNK_SIZE + MAGIC_SIZE];
+    let mut carried = 0usize;
+    // Bytes read from flash, and bytes already fed to the digest.
+    let mut read = 0usize;
+    let mut digested = 0usize;
+
+    while read < limit {
+        let block_size = min(SCAN_CHUNK_SIZE, limit - read);
+        let filled = carried + block_size;
+        block!(flash.read(body_location + read, &mut buffer[carried..filled]))?;
+        read += block_size;
+
+        // The magic string is matched against the raw (possibly encrypted) bytes.
+        let position = buffer[..filled].windows(MAGIC_SIZE).position(|window| window == magic);
+        let (consumed, found) = match position {
+            Some(position) => (position, true),
+            None => (filled - min(filled, MAGIC_SIZE - 1), false),
+        };
+
+        let plaintext = &mut buffer[..consumed];
+        if let Some(decryptor) = decryptor.as_mut() {
+            decryptor.apply(plaintext);
+        }
+        digest(plaintext);
+        digested += consumed;
+
+        if found {
+            // Magic string is part of the digest
+            digest(&magic);
+            return Ok(digested);
+        }
+
+        buffer.copy_within(consumed..filled, 0);
+        carried = filled - consumed;
+    }
+
+    Err(error::Error::BankEmpty)
+}
+
+/// ID of the key an image claims to be signed with, as named by its decoration.
+/// Images that don't name a key are signed with the primary key (ID zero).
+///
+/// NOTE: This is read *before* verifying the signature, so it must only be used
+/// to select the verifying key. It can be trusted once the signature is verified.
+#[cfg(any(feature = "ecdsa-verify", feature = "p384-verify", feature = "ed25519-verify"))]
+fn signing_key_id<A, F>(flash: &mut F, scan: &Scan<A>) -> Result<u8, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    Ok(read_decoration(flash, scan.body_location, scan.size)?.trailer.key_id.unwrap_or(0))
+}
+
+/// Constructs the descriptor of an image after its CRC/signature has been
+/// verified, parsing its trailer.
+fn verified_image<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    scan: Scan<A>,
+    identifier: Identifier,
+) -> Result<Image<A>, error::Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    error::Error: From<F::Error>,
+{
+    let Decoration { trailer, size: decoration_size } =
+        read_decoration(flash, scan.body_location, scan.size)?;
+
+    Ok(Image {
+        size: scan.size - decoration_size,
+        location: bank.location,
+        bootable: bank.bootable,
+        trailer,
+        decoration_size,
+        encryption: scan.encryption,
+        compression: scan.compression,
+        identifier,
+    })
+}
+
 /// Image descriptor.
 ///
 /// An image descriptor can only be constructed by scanning the flash and finding
@@ -89,19 +518,56 @@ pub struct Image<A: Address> {
     size: usize,
     location: A,
     bootable: bool,
-    golden: bool,
-    #[cfg(feature = "ecdsa-verify")]
-    signature: image_ecdsa::Signature,
-    #[cfg(not(feature = "ecdsa-verify"))]
-    crc: u32,
+    trailer: Trailer,
+    /// Size of the trailer preceding the magic string.
+    decoration_size: usize,
+    encryption: Option<EncryptionHeader>,
+    compression: Option<CompressionHeader>,
+    identifier: Identifier,
 }
 
 pub trait Reader {
-    fn image_at<A, F>(flash: &mut F, bank: Bank<A>) -> Result<Image<A>, error::Error>
+    /// Verifies the image in a bank, calling `feed_watchdog` as each block of it is read.
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
     where
         A: Address,
         F: flash::ReadWrite<Address = A>,
         error::Error: From<F::Error>;
+
+    fn image_at<A, F>(flash: &mut F, bank: Bank<A>) -> Result<Image<A>, error::Error>
+    where
+        A: Address,
+        F: flash::ReadWrite<Address = A>,
+        error::Error: From<F::Error>,
+    {
+        Self::watched_image_at(flash, bank, || {})
+    }
+}
+
+/// Image reader feeding a hardware watchdog while verifying images, as verifying
+/// a large image can take longer than the watchdog timeout.
+pub struct WatchedReader<R: Reader, W: Watchdog>(PhantomData<(R, W)>);
+
+impl<R: Reader, W: Watchdog> Reader for WatchedReader<R, W> {
+    fn watched_image_at<A, F>(
+        flash: &mut F,
+        bank: Bank<A>,
+        mut feed_watchdog: impl FnMut(),
+    ) -> Result<Image<A>, error::Error>
+    where
+        A: Address,
+        F: flash::ReadWrite<Address = A>,
+        error::Error: From<F::Error>,
+    {
+        R::watched_image_at(flash, bank, || {
+            W::feed();
+            feed_watchdog();
+        })
+    }
 }
 
 impl<A: Address> Image<A> {
@@ -110,32 +576,269 @@ impl<A: Address> Image<A> {
     pub fn location(&self) -> A { self.location }
     /// Size of the firmware image, excluding decoration and signature/crc.
     pub fn size(&self) -> usize { self.size }
-    /// Size of the firmware image, including decoration and signature.
-    #[cfg(feature = "ecdsa-verify")]
-    pub fn total_size(&self) -> usize {
-        self.size()
-            + image_ecdsa::SignatureSize::<image_ecdsa::NistP256>::to_usize()
-            + MAGIC_STRING.len()
-            + if self.is_golden() { GOLDEN_STRING.len() } else { 0 }
-    }
-    /// Size of the firmware image, including decoration and crc.
-    #[cfg(not(feature = "ecdsa-verify"))]
+    /// Size of the firmware image, including decoration, encryption header and CRC/signature.
     pub fn total_size(&self) -> usize {
         self.size()
-            + core::mem::size_of::<u32>()
+            + self.identifier.size()
             + MAGIC_STRING.len()
-            + if self.is_golden() { GOLDEN_STRING.len() } else { 0 }
+            + self.decoration_size
+            + if self.is_encrypted() { ENCRYPTION_HEADER_SIZE } else { 0 }
     }
-    /// Whether the image is verified to be golden (contains a golden string).
+    /// Whether the image is verified to be golden (its trailer says so).
     /// A golden image is a high reliability, 'blessed' image able
     /// to be used as a last resort fallback.
-    pub fn is_golden(&self) -> bool { self.golden }
-    #[cfg(feature = "ecdsa-verify")]
-    /// ECDSA signature of the firmware image. This is also used as an unique
-    /// identifier for the firmware image for the purposes of updating.
-    pub fn identifier(&self) -> image_ecdsa::Signature { self.signature }
-    #[cfg(not(feature = "ecdsa-verify"))]
-    /// Firmware image CRC. This is also used as an unique
+    pub fn is_golden(&self) -> bool { self.trailer.golden }
+    /// Whether the image body is encrypted. Encrypted images can't be booted
+    /// in place, and are decrypted when copied to the bootable bank.
+    pub fn is_encrypted(&self) -> bool { self.encryption.is_some() }
+    /// Encryption header, if the image is encrypted.
+    pub fn encryption(&self) -> Option<EncryptionHeader> { self.encryption }
+    /// Whether the image body is compressed. Compressed images can't be booted
+    /// in place, and are decompressed when copied to the bootable bank.
+    pub fn is_compressed(&self) -> bool { self.compression.is_some() }
+    /// Compression header, if the image is compressed.
+    pub fn compression(&self) -> Option<CompressionHeader> { self.compression }
+    /// Signed trailer, covered by the image CRC/signature.
+    pub fn trailer(&self) -> &Trailer { &self.trailer }
+    /// Semantic version of the image. Unversioned images are considered older
+    /// than any versioned image.
+    pub fn version(&self) -> Option<SemanticVersion> { self.trailer.version }
+    /// Anti-rollback security epoch of the image. Images without a security epoch
+    /// belong to epoch zero.
+    pub fn security_epoch(&self) -> u32 { self.trailer.security_epoch.unwrap_or(0) }
+    /// ID of the key the image was signed with. Images that don't name a key
+    /// are signed with the primary key (ID zero).
+    pub fn key_id(&self) -> u8 { self.trailer.key_id.unwrap_or(0) }
+    /// Identifier of the hardware the image is built for, if it declares one.
+    pub fn hardware_id(&self) -> Option<u32> { self.trailer.hardware_id }
+    /// Whether the image is a delta patch. Patches can't be booted nor copied, only
+    /// applied to the image they name as their base.
+    pub fn is_patch(&self) -> bool { self.trailer.image_type == Some(ImageType::Patch) }
+    /// Identifier of the image a patch applies to.
+    pub fn base_identifier(&self) -> Option<Identifier> { self.trailer.base_identifier }
+    /// Whether this image should supersede another for the purposes of updating.
+    ///
+    /// A versioned image is newer than any image with a lower (or no) version. Two
+    /// unversioned images fall back to the legacy behaviour, where any image with a
+    /// different identifier is considered newer.
+    pub fn is_newer_than<B: Address>(&self, other: &Image<B>) -> bool {
+        match (self.version(), other.version()) {
+            (None, None) => self.identifier() != other.identifier(),
+            (mine, theirs) => mine > theirs,
+        }
+    }
+    /// Firmware image CRC or signature. This is also used as an unique
     /// identifier for the firmware image for the purposes of updating.
-    pub fn identifier(&self) -> u32 { self.crc }
+    pub fn identifier(&self) -> Identifier { self.identifier }
+    /// Footer locating this image in its bank.
+    pub fn footer(&self) -> Footer {
+        let total_size = self.total_size();
+        let magic_offset = total_size - self.identifier.size() - MAGIC_STRING.len();
+        Footer { magic_offset, total_size }
+    }
+    /// Footer locating the plaintext copy of this image, as left in the bootable bank:
+    /// without encryption header, and with its body decompressed.
+    pub fn copied_footer(&self) -> Footer {
+        let body_size =
+            self.compression.map_or(self.size, |compression| compression.decompressed_size);
+        let magic_offset = body_size + self.decoration_size;
+        let total_size = magic_offset + MAGIC_STRING.len() + self.identifier.size();
+        Footer { magic_offset, total_size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::bootloader::doubles::SectorFlash;
+    use blue_hal::hal::{
+        doubles::flash::{Address, FakeFlash},
+        flash::ReadWrite,
+    };
+
+    const BANK_SIZE: usize = 4 * SCAN_CHUNK_SIZE;
+    const BANK: Bank<Address> =
+        Bank { index: 1, size: BANK_SIZE, location: Address(0), bootable: false, is_golden: false };
+
+    /// Image body of a given size, sprinkled with partial magic strings.
+    fn body(size: usize) -> Vec<u8> {
+        let magic = magic_string_inverted();
+        (0..size).map(|i| if i % 40 < 20 { magic[i % 40] } else { i as u8 }).collect()
+    }
+
+    /// Search over the whole bank at once, as a reference for the chunked scan. Unlike
+    /// blue_hal's `until_sequence`, this finds magic strings that overlap a partial one.
+    fn reference_scan(flash: &mut FakeFlash) -> Option<(usize, Vec<u8>)> {
+        let magic = magic_string_inverted();
+        let bank: Vec<u8> = flash.bytes(Address(0)).take(BANK_SIZE).collect();
+        let size = bank.windows(magic.len()).position(|window| window == magic)?;
+        Some((size, bank[..size + magic.len()].to_vec()))
+    }
+
+    #[test]
+    fn chunked_scan_finds_magic_string_across_chunk_boundaries() {
+        let boundaries = [SCAN_CHUNK_SIZE, 2 * SCAN_CHUNK_SIZE, BANK_SIZE - MAGIC_STRING.len()];
+        for boundary in boundaries.iter() {
+            for size in (boundary - MAGIC_STRING.len() - 1)..=(*boundary) {
+                let mut flash = FakeFlash::new(Address(0));
+                flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+                clear_footer(&mut flash, BANK).unwrap();
+                let mut image = body(size);
+                image.extend_from_slice(&magic_string_inverted());
+                flash.write(Address(0), &image).unwrap();
+
+                let mut digested = Vec::new();
+                let scan =
+                    scan(&mut flash, BANK, |bytes| digested.extend_from_slice(bytes)).unwrap();
+                assert_eq!(Some((scan.size, digested)), reference_scan(&mut flash));
+                assert_eq!(scan.size, size);
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_scan_fails_without_a_complete_magic_string() {
+        let mut flash = FakeFlash::new(Address(0));
+        let mut image = body(BANK_SIZE - MAGIC_STRING.len() + 1);
+        image.extend_from_slice(&magic_string_inverted());
+        flash.write(Address(0), &image).unwrap();
+
+        assert!(reference_scan(&mut flash).is_none());
+        assert_eq!(scan(&mut flash, BANK, |_| {}).err(), Some(error::Error::BankEmpty));
+    }
+
+    #[test]
+    fn footer_locates_image_without_scanning() {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+        let size = SCAN_CHUNK_SIZE + 3;
+        let mut image = body(size);
+        image.extend_from_slice(&magic_string_inverted());
+        flash.write(Address(0), &image).unwrap();
+        let footer = Footer { magic_offset: size, total_size: image.len() + 4 };
+        write_footer(&mut flash, BANK, footer).unwrap();
+        assert_eq!(read_footer(&mut flash, BANK).unwrap(), Some(footer));
+
+        let mut digested = Vec::new();
+        let scan = scan(&mut flash, BANK, |bytes| digested.extend_from_slice(bytes)).unwrap();
+        assert_eq!(Some((scan.size, digested)), reference_scan(&mut flash));
+    }
+
+    #[test]
+    fn stale_footer_falls_back_to_scanning() {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+        let size = 2 * SCAN_CHUNK_SIZE;
+        let mut image = body(size);
+        image.extend_from_slice(&magic_string_inverted());
+        flash.write(Address(0), &image).unwrap();
+        let stale = Footer { magic_offset: size - 100, total_size: size };
+        write_footer(&mut flash, BANK, stale).unwrap();
+
+        let scan = scan(&mut flash, BANK, |_| {}).unwrap();
+        assert_eq!(scan.size, size);
+    }
+
+    #[test]
+    fn bank_with_erased_footer_is_scanned_unless_its_start_is_erased() {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+        assert_eq!(scan(&mut flash, BANK, |_| {}).err(), Some(error::Error::BankEmpty));
+
+        // As written by a debug probe, with no footer
+        let mut image = body(64);
+        image.extend_from_slice(&magic_string_inverted());
+        flash.write(Address(0), &image).unwrap();
+        assert_eq!(scan(&mut flash, BANK, |_| {}).unwrap().size, 64);
+
+        // Empty banks are told apart without scanning them, so what lies past their
+        // first word is never looked at.
+        flash.write(Address(0), &[0xFFu8; WORD_SIZE]).unwrap();
+        assert_eq!(scan(&mut flash, BANK, |_| {}).err(), Some(error::Error::BankEmpty));
+    }
+
+    #[test]
+    fn compressed_images_are_digested_decompressed() {
+        let plaintext = body(3 * SCAN_CHUNK_SIZE);
+        let mut compressed = Vec::new();
+        let compressed_size =
+            loadstone_image::compression::compress(&plaintext, |byte| compressed.push(byte));
+        let header = CompressionHeader { compressed_size, decompressed_size: plaintext.len() };
+
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(&compressed);
+        image.extend_from_slice(&magic_string_inverted());
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+        clear_footer(&mut flash, BANK).unwrap();
+        flash.write(Address(0), &image).unwrap();
+
+        let mut expected = plaintext.clone();
+        expected.extend_from_slice(&magic_string_inverted());
+        let mut digested = Vec::new();
+        let scan = scan(&mut flash, BANK, |bytes| digested.extend_from_slice(bytes)).unwrap();
+        assert_eq!(scan.compression, Some(header));
+        assert_eq!(scan.size, image.len() - MAGIC_STRING.len());
+        assert_eq!(digested, expected);
+    }
+
+    #[test]
+    fn footers_that_overflow_the_bank_are_ignored() {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; BANK_SIZE]).unwrap();
+        let footer = Footer { magic_offset: 0, total_size: BANK_SIZE };
+        flash.write(BANK.location + (BANK_SIZE - FOOTER_SIZE), &footer.to_bytes()).unwrap();
+        assert_eq!(read_footer(&mut flash, BANK).unwrap(), None);
+    }
+
+    /// Stream of identical blocks, recording how many were pulled and whether it
+    /// was cancelled.
+    struct Blocks {
+        value: u8,
+        remaining: usize,
+        pulled: usize,
+        cancelled: bool,
+    }
+
+    impl Blocks {
+        fn new(value: u8, count: usize) -> Self {
+            Self { value, remaining: count, pulled: 0, cancelled: false }
+        }
+    }
+
+    impl Iterator for Blocks {
+        type Item = [u8; 128];
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.cancelled || self.remaining == 0 {
+                return None;
+            }
+            self.remaining -= 1;
+            self.pulled += 1;
+            Some([self.value; 128])
+        }
+    }
+
+    impl BlockStream<128> for Blocks {
+        fn cancel(&mut self) { self.cancelled = true; }
+    }
+
+    #[test]
+    fn streams_past_the_end_of_the_bank_are_rejected() {
+        let mut flash = SectorFlash::new(1);
+        let mut blocks = Blocks::new(0x55, BANK_SIZE / 128);
+        assert_eq!(write_from_blocks(&mut flash, BANK, &mut blocks), Ok(()));
+        assert!(!blocks.cancelled);
+
+        let mut blocks = Blocks::new(0xAA, 2 * BANK_SIZE / 128);
+        assert_eq!(
+            write_from_blocks(&mut flash, BANK, &mut blocks),
+            Err(error::Error::ImageTooBig)
+        );
+        assert_eq!(blocks.pulled, BANK_SIZE / 128 + 1);
+        assert!(blocks.cancelled);
+        let mut byte = [0u8; 1];
+        flash.read(BANK.location + BANK_SIZE, &mut byte).unwrap();
+        assert_eq!(byte, [0xFF]);
+    }
 }
@@ -8,18 +8,33 @@ pub mod boot_metrics;
 pub mod bootloader;
 pub mod cli;
 pub mod image;
+pub mod storage;
 pub mod update_signal;
 
 /// General purpose traits that summarize requirements on devices.
 pub mod traits {
     use crate::error;
-    use blue_hal::hal::{flash, serial};
+    use blue_hal::hal::{flash, null::NullFlash, serial};
     use marker_blanket::marker_blanket;
 
-    /// A supported flash must be able to read, write, and report errors
-    /// to the bootloader or boot manager.
+    /// A supported flash must be able to read, write, report errors
+    /// to the bootloader or boot manager, and describe its erase sectors.
     #[marker_blanket]
-    pub trait Flash: flash::ReadWrite<Error: error::Convertible> {}
+    pub trait Flash: flash::ReadWrite<Error: error::Convertible> + EraseSectors {}
+
+    /// Layout of the erase sectors of a flash chip. Writing to a sector may erase and
+    /// rewrite all of it, so a reset in the middle of a write can lose anything in the
+    /// sectors it touches, and only whole sectors are known to survive one.
+    pub trait EraseSectors: flash::ReadWrite {
+        /// Address right after the end of the erase sector containing `address`.
+        fn sector_end(address: Self::Address) -> Self::Address;
+    }
+
+    /// Null flash has no sector layout, so its whole address space counts as a single
+    /// sector, and nothing written to it is assumed to survive a reset.
+    impl EraseSectors for NullFlash {
+        fn sector_end(_: Self::Address) -> Self::Address { Self::Address::MAX }
+    }
 
     /// A supported serial must be able to read, write, read with a timeout,
     /// and report errors to the bootloader or boot manager.
@@ -30,4 +45,18 @@ pub mod traits {
         + serial::TimeoutRead<Error: error::Convertible>
     {
     }
+
+    /// A hardware watchdog, which resets the MCU unless fed regularly. Feeding must
+    /// be possible from anywhere, so it happens throughout long running operations
+    /// (copying, verifying and receiving images) without threading a driver around.
+    pub trait Watchdog {
+        fn feed();
+    }
+
+    /// Watchdog for ports without one, or that don't support it.
+    pub struct NullWatchdog;
+
+    impl Watchdog for NullWatchdog {
+        fn feed() {}
+    }
 }
@@ -0,0 +1,871 @@
+//! Persistent Loadstone state.
+//!
+//! Some features (such as anti-rollback protection) require Loadstone to remember
+//! values across resets. These are kept in a region of MCU flash reserved for this
+//! purpose, split into fixed size append-only logs of 32 bit entries. Values are only
+//! ever appended to erased words, so appending never erases flash, and a reset in the
+//! middle of it can at worst lose the entry being written.
+//!
+//! The region spans two erase sectors. The security counter and the revoked keys live
+//! in a sector that is never erased. The trial log, the copy journal and the boot
+//! attempt and recovery timeout counters live in the other, as they are cleared to
+//! make room for new entries (the counters only when they are reset, so counting boots
+//! never erases flash). Clearing a log erases the whole sector and writes the other
+//! logs in it back, so a reset in the middle of a clear can lose the contents of any
+//! clearable log, but never those of the security counter or the revoked keys.
+use crate::error::Error;
+use blue_hal::{hal::flash::ReadWrite, utilities::memory::Address};
+use core::{convert::TryInto, mem::size_of};
+use nb::block;
+
+/// Size of each append-only log in the storage region.
+pub const LOG_SIZE: usize = 512;
+const ENTRY_SIZE: usize = size_of::<u32>();
+/// Value of an erased (unwritten) entry, which marks the end of a log.
+const ERASED_ENTRY: u32 = 0xFFFF_FFFF;
+
+/// Index of the log holding the anti-rollback security counter, in the permanent sector.
+const SECURITY_COUNTER_LOG: usize = 0;
+/// Index of the log holding the IDs of revoked verifying keys, in the permanent sector.
+const REVOKED_KEYS_LOG: usize = 1;
+/// Index of the log tracking the trial of updated images, in the clearable sector.
+const TRIAL_LOG: usize = 0;
+
+/// Maximum number of boots an updated image can be given to be confirmed.
+pub const MAX_TRIAL_BOOTS: u32 = 16;
+
+const TRIAL_STARTED: u32 = 0x0100_0000;
+const TRIAL_BOOTED: u32 = 0x0200_0000;
+const TRIAL_CONFIRMED: u32 = 0x0300_0000;
+const TRIAL_ENDED: u32 = 0x0400_0000;
+/// Entries needed to track a trial from start to end.
+const TRIAL_ENTRIES: usize = MAX_TRIAL_BOOTS as usize + 2;
+
+/// Index of the log journaling copies and swaps between banks, in the clearable sector.
+const JOURNAL_LOG: usize = 1;
+
+const COPY_STARTED: u32 = 0x0100_0000;
+const CHUNK_COPIED: u32 = 0x0200_0000;
+const COPY_FINISHED: u32 = 0x0300_0000;
+const SWAP_STARTED: u32 = 0x0400_0000;
+const OPERATION_ENDED: u32 = 0x0500_0000;
+/// Maximum number of chunks a journaled copy can be split into.
+pub const MAX_JOURNALED_CHUNKS: usize = 32;
+/// Entries a journaled copy can take: its start, every chunk but the last (which is
+/// recorded by finishing the copy) and its end.
+const COPY_ENTRIES: usize = MAX_JOURNALED_CHUNKS + 1;
+/// Entries needed to journal a swap from start to end in the worst case: its start,
+/// three copies and its end. The copies are either the three making up the swap, or
+/// the first two (the second failing before it finishes) and the one rolling it back.
+const SWAP_ENTRIES: usize = 1 + 3 * COPY_ENTRIES + 1;
+/// Entries kept spare on top of a swap, for copies restarted after a reset interrupted
+/// them before they recorded any chunk, which record their start again.
+const JOURNAL_HEADROOM: usize = 16;
+/// Entries that must be left in the journal before starting an operation.
+const JOURNAL_ENTRIES: usize = SWAP_ENTRIES + JOURNAL_HEADROOM;
+const _: () = assert!(JOURNAL_ENTRIES <= LOG_SIZE / ENTRY_SIZE, "Journal log is too small");
+
+/// Index of the log counting boot attempts the application didn't report as healthy,
+/// in the clearable sector.
+const BOOT_ATTEMPTS_LOG: usize = 2;
+
+/// Maximum number of boot attempts an image can be given before falling back to
+/// the golden image.
+pub const MAX_BOOT_ATTEMPTS: u32 = 16;
+
+const ATTEMPT_RECORDED: u32 = 0x0100_0000;
+const ATTEMPTS_RESET: u32 = 0x0200_0000;
+
+/// Index of the log counting consecutive recovery windows that elapsed without
+/// receiving an image, in the clearable sector.
+const RECOVERY_TIMEOUTS_LOG: usize = 3;
+
+/// Maximum number of recovery timeouts allowed before recovery becomes permanent.
+pub const MAX_RECOVERY_TIMEOUTS: u32 = 16;
+
+const TIMEOUT_RECORDED: u32 = 0x0100_0000;
+const TIMEOUTS_RESET: u32 = 0x0200_0000;
+
+/// Region of MCU flash reserved for persistent Loadstone state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Storage<A: Address> {
+    /// Start of the erase sector holding the logs that are never cleared.
+    pub permanent: A,
+    /// Start of the erase sector holding the logs that are cleared when full.
+    pub clearable: A,
+}
+
+impl<A: Address> Storage<A> {
+    /// Monotonic counter holding the minimum security epoch an image must
+    /// have to be booted.
+    pub fn security_counter(&self) -> MonotonicCounter<A> {
+        MonotonicCounter(self.permanent_log(SECURITY_COUNTER_LOG))
+    }
+
+    /// List of verifying key IDs Loadstone must no longer trust.
+    pub fn revoked_keys(&self) -> RevocationList<A> {
+        RevocationList(self.permanent_log(REVOKED_KEYS_LOG))
+    }
+
+    /// Log tracking the trial of updated images, which must be confirmed within
+    /// `allowed_boots` boots (up to [`MAX_TRIAL_BOOTS`]).
+    pub fn trial_log(&self, allowed_boots: u32) -> TrialLog<A> {
+        assert!(allowed_boots > 0 && allowed_boots <= MAX_TRIAL_BOOTS, "Invalid trial length");
+        TrialLog { log: self.clearable_log(TRIAL_LOG), allowed_boots }
+    }
+
+    /// Journal of the copies and swaps between banks, so they can be resumed
+    /// if interrupted by a reset.
+    pub fn copy_journal(&self) -> CopyJournal<A> {
+        CopyJournal { log: self.clearable_log(JOURNAL_LOG) }
+    }
+
+    /// Counter of consecutive boot attempts, which allows `allowed_attempts` attempts
+    /// (up to [`MAX_BOOT_ATTEMPTS`]) before the application must reset it.
+    pub fn boot_attempts(&self, allowed_attempts: u32) -> BootAttempts<A> {
+        assert!(
+            allowed_attempts > 0 && allowed_attempts <= MAX_BOOT_ATTEMPTS,
+            "Invalid number of boot attempts"
+        );
+        BootAttempts { log: self.clearable_log(BOOT_ATTEMPTS_LOG), allowed_attempts }
+    }
+
+    /// Counter of consecutive recovery timeouts, which allows `allowed_timeouts` timeouts
+    /// (up to [`MAX_RECOVERY_TIMEOUTS`]) before recovery mode becomes permanent.
+    pub fn recovery_timeouts(&self, allowed_timeouts: u32) -> RecoveryTimeouts<A> {
+        assert!(
+            allowed_timeouts > 0 && allowed_timeouts <= MAX_RECOVERY_TIMEOUTS,
+            "Invalid number of recovery timeouts"
+        );
+        RecoveryTimeouts { log: self.clearable_log(RECOVERY_TIMEOUTS_LOG), allowed_timeouts }
+    }
+
+    fn permanent_log(&self, index: usize) -> Log<A> {
+        Log { location: self.permanent + index * LOG_SIZE }
+    }
+
+    fn clearable_log(&self, index: usize) -> Log<A> {
+        Log { location: self.clearable + index * LOG_SIZE }
+    }
+}
+
+/// Fixed size sequence of 32 bit entries, which can only be appended to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Log<A: Address> {
+    location: A,
+}
+
+impl<A: Address> Log<A> {
+    /// Returns the most recently appended entry, if any.
+    pub fn last<F>(&self, flash: &mut F) -> Result<Option<u32>, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let buffer = self.read(flash)?;
+        Ok(Self::entries(&buffer).last())
+    }
+
+    /// Whether an entry was ever appended to the log.
+    pub fn contains<F>(&self, flash: &mut F, value: u32) -> Result<bool, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let buffer = self.read(flash)?;
+        let mut entries = Self::entries(&buffer);
+        Ok(entries.any(|entry| entry == value))
+    }
+
+    /// Appends an entry to the log. The erased value (`0xFFFFFFFF`) can't be stored.
+    pub fn append<F>(&self, flash: &mut F, value: u32) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if value == ERASED_ENTRY {
+            return Err(Error::DeviceError("Attempted to store an erased word"));
+        }
+        let buffer = self.read(flash)?;
+        let length = Self::entries(&buffer).count();
+        if length * ENTRY_SIZE >= LOG_SIZE {
+            return Err(Error::StorageFull);
+        }
+        block!(flash.write(self.location + length * ENTRY_SIZE, &value.to_le_bytes()))?;
+        Ok(())
+    }
+
+    /// Number of entries that can still be appended to the log.
+    pub fn remaining<F>(&self, flash: &mut F) -> Result<usize, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let buffer = self.read(flash)?;
+        Ok(LOG_SIZE / ENTRY_SIZE - Self::entries(&buffer).count())
+    }
+
+    /// Removes all entries from the log. Unlike appending, this requires erasing flash.
+    pub fn clear<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        block!(flash.write(self.location, &[0xFF; LOG_SIZE]))?;
+        Ok(())
+    }
+
+    fn read<F>(&self, flash: &mut F) -> Result<[u8; LOG_SIZE], Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let mut buffer = [0u8; LOG_SIZE];
+        block!(flash.read(self.location, &mut buffer))?;
+        Ok(buffer)
+    }
+
+    fn entries(buffer: &[u8; LOG_SIZE]) -> impl Iterator<Item = u32> + '_ {
+        buffer
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .take_while(|entry| *entry != ERASED_ENTRY)
+    }
+}
+
+/// Counter that can only be raised, backed by an append-only log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MonotonicCounter<A: Address>(Log<A>);
+
+impl<A: Address> MonotonicCounter<A> {
+    /// Current value of the counter. A counter that was never raised is zero.
+    pub fn read<F>(&self, flash: &mut F) -> Result<u32, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        Ok(self.0.last(flash)?.unwrap_or(0))
+    }
+
+    /// Raises the counter to a given value. Values lower than or equal
+    /// to the current one are ignored, so the counter never goes down.
+    pub fn raise<F>(&self, flash: &mut F, value: u32) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if value <= self.read(flash)? {
+            return Ok(());
+        }
+        self.0.append(flash, value)
+    }
+}
+
+/// Set of revoked key IDs, backed by an append-only log. Keys can't be un-revoked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RevocationList<A: Address>(Log<A>);
+
+impl<A: Address> RevocationList<A> {
+    /// Whether the key with a given ID has been revoked.
+    pub fn is_revoked<F>(&self, flash: &mut F, key_id: u8) -> Result<bool, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.0.contains(flash, key_id as u32)
+    }
+
+    /// Revokes the key with a given ID. Revoking a key twice has no effect.
+    pub fn revoke<F>(&self, flash: &mut F, key_id: u8) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.is_revoked(flash, key_id)? {
+            return Ok(());
+        }
+        self.0.append(flash, key_id as u32)
+    }
+}
+
+/// State of the trial of the current image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trial {
+    /// The current image isn't on trial: it was confirmed by the application, the
+    /// trial ended, or it was never updated.
+    None,
+    /// The current image was updated from `bank`, and has been booted `boots` times
+    /// without being confirmed.
+    Pending { bank: u8, boots: u32 },
+}
+
+/// Trial of updated images, backed by an append-only log. Each trial starts when an
+/// update is installed, counts the boots of the new image, and ends when the
+/// application confirms it or Loadstone gives up on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrialLog<A: Address> {
+    log: Log<A>,
+    allowed_boots: u32,
+}
+
+impl<A: Address> TrialLog<A> {
+    /// Number of boots the current image is given to be confirmed.
+    pub fn allowed_boots(&self) -> u32 { self.allowed_boots }
+
+    /// State of the most recent trial.
+    pub fn state<F>(&self, flash: &mut F) -> Result<Trial, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let buffer = self.log.read(flash)?;
+        let trial = Log::<A>::entries(&buffer).fold(Trial::None, |trial, entry| {
+            match (entry & 0xFF00_0000, trial) {
+                (TRIAL_STARTED, _) => Trial::Pending { bank: entry as u8, boots: 0 },
+                (TRIAL_BOOTED, Trial::Pending { bank, boots }) => {
+                    Trial::Pending { bank, boots: boots + 1 }
+                }
+                (TRIAL_CONFIRMED, _) | (TRIAL_ENDED, _) => Trial::None,
+                (_, trial) => trial,
+            }
+        });
+        Ok(trial)
+    }
+
+    /// Starts the trial of an image updated from a given bank. If the log is running
+    /// out of room, it's cleared first.
+    pub fn start<F>(&self, flash: &mut F, bank: u8) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.log.remaining(flash)? < TRIAL_ENTRIES {
+            self.log.clear(flash)?;
+        }
+        self.log.append(flash, TRIAL_STARTED | bank as u32)
+    }
+
+    /// Records a boot of the image on trial.
+    pub fn record_boot<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.log.append(flash, TRIAL_BOOTED)
+    }
+
+    /// Confirms the image on trial, so it's trusted from now on. Has no effect
+    /// if no image is on trial.
+    pub fn confirm<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.conclude(flash, TRIAL_CONFIRMED)
+    }
+
+    /// Ends the trial without confirming the image. Has no effect if no image
+    /// is on trial.
+    pub fn end<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.conclude(flash, TRIAL_ENDED)
+    }
+
+    fn conclude<F>(&self, flash: &mut F, entry: u32) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        match self.state(flash)? {
+            Trial::None => Ok(()),
+            Trial::Pending { .. } => self.log.append(flash, entry),
+        }
+    }
+}
+
+/// Copy between two banks, identified by index, that didn't finish.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterruptedCopy {
+    pub input: u8,
+    pub output: u8,
+    /// Number of chunks of the output image known to be written.
+    pub chunks: usize,
+}
+
+/// Operation interrupted by a reset, according to the copy journal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupted {
+    /// All operations finished.
+    None,
+    /// A copy between two banks.
+    Copy(InterruptedCopy),
+    /// A swap between the boot bank and another bank, either in the middle of one
+    /// of its copies, or between them (after the last copy that finished, if any).
+    Swap { bank: u8, copy: Option<InterruptedCopy>, finished: Option<(u8, u8)> },
+}
+
+/// Journal of the progress of copies and swaps between banks, backed by an append-only
+/// log. Copies record every chunk written, so they can be resumed where they left off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CopyJournal<A: Address> {
+    log: Log<A>,
+}
+
+impl<A: Address> CopyJournal<A> {
+    /// Operation interrupted by a reset, if any.
+    pub fn interrupted<F>(&self, flash: &mut F) -> Result<Interrupted, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        let buffer = self.log.read(flash)?;
+        let (mut swap, mut copy, mut finished) = (None, None, None);
+        for entry in Log::<A>::entries(&buffer) {
+            let (input, output) = ((entry >> 8) as u8, entry as u8);
+            match entry & 0xFF00_0000 {
+                COPY_STARTED => copy = Some(InterruptedCopy { input, output, chunks: 0 }),
+                CHUNK_COPIED => copy.iter_mut().for_each(|copy| copy.chunks += 1),
+                COPY_FINISHED => finished = copy.take().map(|copy| (copy.input, copy.output)),
+                SWAP_STARTED => {
+                    swap = Some(output);
+                    copy = None;
+                    finished = None;
+                }
+                OPERATION_ENDED => {
+                    swap = None;
+                    copy = None;
+                    finished = None;
+                }
+                _ => (),
+            }
+        }
+        Ok(match (swap, copy) {
+            (Some(bank), copy) => Interrupted::Swap { bank, copy, finished },
+            (None, Some(copy)) => Interrupted::Copy(copy),
+            (None, None) => Interrupted::None,
+        })
+    }
+
+    /// Records the start of a swap between the boot bank and another bank.
+    pub fn start_swap<F>(&self, flash: &mut F, bank: u8) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.begin(flash, SWAP_STARTED | bank as u32)
+    }
+
+    /// Records the start of a copy between two banks, on its own or as part of a swap.
+    pub fn start_copy<F>(&self, flash: &mut F, input: u8, output: u8) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.begin(flash, COPY_STARTED | (input as u32) << 8 | output as u32)
+    }
+
+    /// Records that one more chunk of the current copy was written.
+    pub fn record_chunk<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.log.append(flash, CHUNK_COPIED)
+    }
+
+    /// Records the end of the current copy.
+    pub fn finish_copy<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.log.append(flash, COPY_FINISHED)
+    }
+
+    /// Records the end of the current operation, whether it finished or was abandoned.
+    pub fn end<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        self.log.append(flash, OPERATION_ENDED)
+    }
+
+    /// Appends the first entry of an operation. If no other operation is in progress
+    /// and the log is running out of room, it's cleared first.
+    fn begin<F>(&self, flash: &mut F, entry: u32) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.interrupted(flash)? == Interrupted::None
+            && self.log.remaining(flash)? < JOURNAL_ENTRIES
+        {
+            self.log.clear(flash)?;
+        }
+        self.log.append(flash, entry)
+    }
+}
+
+/// Counter of boot attempts since the application last reported itself healthy,
+/// backed by an append-only log. Each attempt records the running count, and only
+/// resetting the counter ever clears the log, making sure there's room for all the
+/// attempts allowed afterwards. Recording an attempt (done on every boot) never erases
+/// flash, and a reset in the middle of clearing the log leaves the counter at zero,
+/// which is what clearing it was for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BootAttempts<A: Address> {
+    log: Log<A>,
+    allowed_attempts: u32,
+}
+
+impl<A: Address> BootAttempts<A> {
+    /// Number of boot attempts allowed before falling back to the golden image.
+    pub fn allowed_attempts(&self) -> u32 { self.allowed_attempts }
+
+    /// Number of boot attempts since the counter was last reset.
+    pub fn read<F>(&self, flash: &mut F) -> Result<u32, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        Ok(match self.log.last(flash)? {
+            Some(entry) if entry & 0xFF00_0000 == ATTEMPT_RECORDED => entry & 0x00FF_FFFF,
+            _ => 0,
+        })
+    }
+
+    /// Whether the boot attempts allowed have been used up.
+    pub fn exhausted<F>(&self, flash: &mut F) -> Result<bool, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        Ok(self.read(flash)? >= self.allowed_attempts)
+    }
+
+    /// Records a boot attempt. If the log is full, the attempts allowed are already
+    /// used up, so there's nothing left to record.
+    pub fn record<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.log.remaining(flash)? == 0 {
+            return Ok(());
+        }
+        let attempts = self.read(flash)?;
+        self.log.append(flash, ATTEMPT_RECORDED | (attempts + 1).min(0x00FF_FFFF))
+    }
+
+    /// Resets the counter, as the application is healthy. Has no effect if no
+    /// attempts were recorded since the last reset. If the log doesn't have room
+    /// for all the attempts allowed after the reset, it's cleared instead.
+    pub fn reset<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.read(flash)? == 0 {
+            Ok(())
+        } else if self.log.remaining(flash)? <= self.allowed_attempts as usize {
+            self.log.clear(flash)
+        } else {
+            self.log.append(flash, ATTEMPTS_RESET)
+        }
+    }
+}
+
+/// Counter of consecutive recovery windows that elapsed without receiving an image,
+/// backed by an append-only log. Like [`BootAttempts`], each timeout records the running
+/// count, and only resetting the counter ever clears the log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecoveryTimeouts<A: Address> {
+    log: Log<A>,
+    allowed_timeouts: u32,
+}
+
+impl<A: Address> RecoveryTimeouts<A> {
+    /// Number of recovery timeouts allowed before recovery mode becomes permanent.
+    pub fn allowed_timeouts(&self) -> u32 { self.allowed_timeouts }
+
+    /// Number of recovery timeouts since the counter was last reset.
+    pub fn read<F>(&self, flash: &mut F) -> Result<u32, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        Ok(match self.log.last(flash)? {
+            Some(entry) if entry & 0xFF00_0000 == TIMEOUT_RECORDED => entry & 0x00FF_FFFF,
+            _ => 0,
+        })
+    }
+
+    /// Whether the recovery timeouts allowed have been used up, so recovery mode
+    /// must wait for an image indefinitely.
+    pub fn exhausted<F>(&self, flash: &mut F) -> Result<bool, Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        Ok(self.read(flash)? >= self.allowed_timeouts)
+    }
+
+    /// Records a recovery timeout. If the log is full, the timeouts allowed are already
+    /// used up, so there's nothing left to record.
+    pub fn record<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.log.remaining(flash)? == 0 {
+            return Ok(());
+        }
+        let timeouts = self.read(flash)?;
+        self.log.append(flash, TIMEOUT_RECORDED | (timeouts + 1).min(0x00FF_FFFF))
+    }
+
+    /// Resets the counter, as an image was either received or booted. Has no effect
+    /// if no timeouts were recorded since the last reset. If the log doesn't have room
+    /// for all the timeouts allowed after the reset, it's cleared instead.
+    pub fn reset<F>(&self, flash: &mut F) -> Result<(), Error>
+    where
+        F: ReadWrite<Address = A>,
+        Error: From<F::Error>,
+    {
+        if self.read(flash)? == 0 {
+            Ok(())
+        } else if self.log.remaining(flash)? <= self.allowed_timeouts as usize {
+            self.log.clear(flash)
+        } else {
+            self.log.append(flash, TIMEOUTS_RESET)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blue_hal::hal::doubles::flash::{Address, FakeFlash};
+
+    /// Four clearable logs, followed by the permanent ones.
+    const STORAGE: Storage<Address> = Storage { permanent: Address(0x800), clearable: Address(0) };
+
+    fn erased_flash() -> FakeFlash {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; 6 * LOG_SIZE]).unwrap();
+        flash
+    }
+
+    #[test]
+    fn counter_starts_at_zero_in_erased_storage() {
+        let mut flash = erased_flash();
+        assert_eq!(STORAGE.security_counter().read(&mut flash).unwrap(), 0);
+    }
+
+    #[test]
+    fn counter_can_only_be_raised() {
+        let mut flash = erased_flash();
+        let counter = STORAGE.security_counter();
+        counter.raise(&mut flash, 3).unwrap();
+        assert_eq!(counter.read(&mut flash).unwrap(), 3);
+        counter.raise(&mut flash, 1).unwrap();
+        assert_eq!(counter.read(&mut flash).unwrap(), 3);
+        counter.raise(&mut flash, 7).unwrap();
+        assert_eq!(counter.read(&mut flash).unwrap(), 7);
+    }
+
+    #[test]
+    fn appending_to_a_full_log_fails() {
+        let mut flash = erased_flash();
+        let counter = STORAGE.security_counter();
+        for value in 1..=(LOG_SIZE / ENTRY_SIZE) as u32 {
+            counter.raise(&mut flash, value).unwrap();
+        }
+        assert_eq!(counter.raise(&mut flash, u32::MAX - 1), Err(Error::StorageFull));
+        assert_eq!(counter.read(&mut flash).unwrap(), (LOG_SIZE / ENTRY_SIZE) as u32);
+    }
+
+    #[test]
+    fn revoked_keys_stay_revoked() {
+        let mut flash = erased_flash();
+        let revoked_keys = STORAGE.revoked_keys();
+        assert!(!revoked_keys.is_revoked(&mut flash, 2).unwrap());
+        revoked_keys.revoke(&mut flash, 2).unwrap();
+        revoked_keys.revoke(&mut flash, 2).unwrap();
+        assert!(revoked_keys.is_revoked(&mut flash, 2).unwrap());
+        assert!(!revoked_keys.is_revoked(&mut flash, 0).unwrap());
+        assert_eq!(STORAGE.security_counter().read(&mut flash).unwrap(), 0);
+    }
+
+    #[test]
+    fn trials_count_boots_until_concluded() {
+        let mut flash = erased_flash();
+        let trial_log = STORAGE.trial_log(3);
+        assert_eq!(trial_log.state(&mut flash).unwrap(), Trial::None);
+        trial_log.confirm(&mut flash).unwrap();
+        assert_eq!(trial_log.log.remaining(&mut flash).unwrap(), LOG_SIZE / ENTRY_SIZE);
+
+        trial_log.start(&mut flash, 4).unwrap();
+        trial_log.record_boot(&mut flash).unwrap();
+        trial_log.record_boot(&mut flash).unwrap();
+        assert_eq!(trial_log.state(&mut flash).unwrap(), Trial::Pending { bank: 4, boots: 2 });
+        trial_log.confirm(&mut flash).unwrap();
+        assert_eq!(trial_log.state(&mut flash).unwrap(), Trial::None);
+
+        trial_log.start(&mut flash, 5).unwrap();
+        assert_eq!(trial_log.state(&mut flash).unwrap(), Trial::Pending { bank: 5, boots: 0 });
+        trial_log.end(&mut flash).unwrap();
+        assert_eq!(trial_log.state(&mut flash).unwrap(), Trial::None);
+        assert_eq!(STORAGE.security_counter().read(&mut flash).unwrap(), 0);
+    }
+
+    #[test]
+    fn trial_log_is_cleared_when_running_out_of_room() {
+        let mut flash = erased_flash();
+        let trial_log = STORAGE.trial_log(MAX_TRIAL_BOOTS);
+        for _ in 0..LOG_SIZE / ENTRY_SIZE / 2 {
+            trial_log.start(&mut flash, 2).unwrap();
+            trial_log.confirm(&mut flash).unwrap();
+        }
+        trial_log.start(&mut flash, 3).unwrap();
+        for _ in 0..MAX_TRIAL_BOOTS {
+            trial_log.record_boot(&mut flash).unwrap();
+        }
+        trial_log.end(&mut flash).unwrap();
+        assert!(trial_log.log.remaining(&mut flash).unwrap() > 0);
+    }
+
+    #[test]
+    fn journal_tracks_interrupted_copies() {
+        let mut flash = erased_flash();
+        let journal = STORAGE.copy_journal();
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), Interrupted::None);
+
+        journal.start_copy(&mut flash, 3, 1).unwrap();
+        journal.record_chunk(&mut flash).unwrap();
+        journal.record_chunk(&mut flash).unwrap();
+        let copy = InterruptedCopy { input: 3, output: 1, chunks: 2 };
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), Interrupted::Copy(copy));
+        journal.finish_copy(&mut flash).unwrap();
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), Interrupted::None);
+    }
+
+    #[test]
+    fn journal_tracks_interrupted_swaps() {
+        let mut flash = erased_flash();
+        let journal = STORAGE.copy_journal();
+        journal.start_swap(&mut flash, 4).unwrap();
+        let swap = Interrupted::Swap { bank: 4, copy: None, finished: None };
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), swap);
+
+        journal.start_copy(&mut flash, 1, 0).unwrap();
+        journal.finish_copy(&mut flash).unwrap();
+        journal.start_copy(&mut flash, 4, 1).unwrap();
+        journal.record_chunk(&mut flash).unwrap();
+        let copy = Some(InterruptedCopy { input: 4, output: 1, chunks: 1 });
+        let swap = Interrupted::Swap { bank: 4, copy, finished: Some((1, 0)) };
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), swap);
+
+        journal.end(&mut flash).unwrap();
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), Interrupted::None);
+    }
+
+    #[test]
+    fn journal_is_cleared_between_operations_when_running_out_of_room() {
+        let mut flash = erased_flash();
+        let journal = STORAGE.copy_journal();
+        for _ in 0..LOG_SIZE / ENTRY_SIZE / 2 {
+            journal.start_copy(&mut flash, 2, 1).unwrap();
+            journal.finish_copy(&mut flash).unwrap();
+        }
+        // Worst case swap: the second copy fails right before finishing, and the
+        // previous image is restored from scratch.
+        journal.start_swap(&mut flash, 2).unwrap();
+        for (input, output) in [(1, 0), (2, 1), (0, 1)].iter().cloned() {
+            journal.start_copy(&mut flash, input, output).unwrap();
+            for _ in 0..MAX_JOURNALED_CHUNKS - 1 {
+                journal.record_chunk(&mut flash).unwrap();
+            }
+            if (input, output) != (2, 1) {
+                journal.finish_copy(&mut flash).unwrap();
+            }
+        }
+        let swap = Interrupted::Swap { bank: 2, copy: None, finished: Some((0, 1)) };
+        assert_eq!(journal.interrupted(&mut flash).unwrap(), swap);
+        journal.end(&mut flash).unwrap();
+        assert!(journal.log.remaining(&mut flash).unwrap() >= JOURNAL_HEADROOM);
+    }
+
+    #[test]
+    fn boot_attempts_count_until_reset() {
+        let mut flash = erased_flash();
+        let attempts = STORAGE.boot_attempts(2);
+        assert_eq!(attempts.read(&mut flash).unwrap(), 0);
+        attempts.reset(&mut flash).unwrap();
+        assert_eq!(attempts.log.remaining(&mut flash).unwrap(), LOG_SIZE / ENTRY_SIZE);
+
+        attempts.record(&mut flash).unwrap();
+        assert!(!attempts.exhausted(&mut flash).unwrap());
+        attempts.record(&mut flash).unwrap();
+        assert_eq!(attempts.read(&mut flash).unwrap(), 2);
+        assert!(attempts.exhausted(&mut flash).unwrap());
+
+        attempts.reset(&mut flash).unwrap();
+        assert_eq!(attempts.read(&mut flash).unwrap(), 0);
+        assert_eq!(STORAGE.trial_log(1).state(&mut flash).unwrap(), Trial::None);
+    }
+
+    #[test]
+    fn recording_boot_attempts_never_clears_the_log() {
+        let mut flash = erased_flash();
+        let attempts = STORAGE.boot_attempts(MAX_BOOT_ATTEMPTS);
+        for _ in 0..2 * LOG_SIZE / ENTRY_SIZE {
+            attempts.record(&mut flash).unwrap();
+        }
+        assert_eq!(attempts.log.remaining(&mut flash).unwrap(), 0);
+        assert_eq!(attempts.read(&mut flash).unwrap(), (LOG_SIZE / ENTRY_SIZE) as u32);
+        assert!(attempts.exhausted(&mut flash).unwrap());
+
+        attempts.reset(&mut flash).unwrap();
+        assert_eq!(attempts.read(&mut flash).unwrap(), 0);
+        assert_eq!(attempts.log.remaining(&mut flash).unwrap(), LOG_SIZE / ENTRY_SIZE);
+    }
+
+    #[test]
+    fn boot_attempts_reset_leaves_room_for_all_attempts_allowed() {
+        let mut flash = erased_flash();
+        let attempts = STORAGE.boot_attempts(MAX_BOOT_ATTEMPTS);
+        for _ in 0..LOG_SIZE / ENTRY_SIZE {
+            attempts.record(&mut flash).unwrap();
+            attempts.reset(&mut flash).unwrap();
+            assert!(attempts.log.remaining(&mut flash).unwrap() >= MAX_BOOT_ATTEMPTS as usize);
+        }
+        for _ in 0..MAX_BOOT_ATTEMPTS {
+            assert!(!attempts.exhausted(&mut flash).unwrap());
+            attempts.record(&mut flash).unwrap();
+        }
+        assert_eq!(attempts.read(&mut flash).unwrap(), MAX_BOOT_ATTEMPTS);
+        assert!(attempts.exhausted(&mut flash).unwrap());
+    }
+
+    #[test]
+    fn recovery_timeouts_count_until_reset() {
+        let mut flash = erased_flash();
+        let timeouts = STORAGE.recovery_timeouts(2);
+        assert!(!timeouts.exhausted(&mut flash).unwrap());
+
+        timeouts.record(&mut flash).unwrap();
+        timeouts.record(&mut flash).unwrap();
+        assert_eq!(timeouts.read(&mut flash).unwrap(), 2);
+        assert!(timeouts.exhausted(&mut flash).unwrap());
+        assert_eq!(STORAGE.boot_attempts(1).read(&mut flash).unwrap(), 0);
+
+        timeouts.reset(&mut flash).unwrap();
+        assert_eq!(timeouts.read(&mut flash).unwrap(), 0);
+        assert!(!timeouts.exhausted(&mut flash).unwrap());
+    }
+}
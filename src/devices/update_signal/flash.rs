@@ -0,0 +1,66 @@
+//! Update signal kept in a dedicated sector of MCU flash.
+use super::{ReadUpdateSignal, Record, UpdatePlan, WriteUpdateSignal};
+use blue_hal::hal::flash::ReadWrite;
+use nb::block;
+
+/// Update signal kept at the start of a flash sector reserved for it. The plan survives
+/// power loss, at the cost of erasing the sector whenever it changes. An erased sector,
+/// or one whose write was interrupted, holds no valid record.
+///
+/// The signal owns a flash handle of its own. When it drives the same flash as the
+/// bootloader or application, the port must make sure the two handles are never
+/// mid-operation at once (see the ports' update signal constructors).
+pub struct FlashUpdateSignal<F: ReadWrite> {
+    flash: F,
+    location: F::Address,
+}
+
+impl<F: ReadWrite> FlashUpdateSignal<F> {
+    pub fn new(flash: F, location: F::Address) -> Self { Self { flash, location } }
+}
+
+impl<F: ReadWrite> ReadUpdateSignal for FlashUpdateSignal<F> {
+    fn read_update_plan(&mut self) -> Option<UpdatePlan> {
+        let mut bytes = [0u8; Record::SIZE];
+        block!(self.flash.read(self.location, &mut bytes)).ok()?;
+        Record::from_bytes(&bytes).plan()
+    }
+
+    fn clear_update_plan(&mut self) {
+        // A failed write leaves a corrupted record at worst, which holds no plan.
+        block!(self.flash.write(self.location, &[0xFF; Record::SIZE])).ok();
+    }
+}
+
+impl<F: ReadWrite> WriteUpdateSignal for FlashUpdateSignal<F> {
+    fn write_update_plan(&mut self, plan: UpdatePlan) {
+        // A failed write leaves a corrupted record at worst, which holds no plan.
+        block!(self.flash.write(self.location, &Record::new(plan).to_bytes())).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blue_hal::hal::doubles::flash::{Address, FakeFlash};
+
+    fn erased_signal() -> FlashUpdateSignal<FakeFlash> {
+        let mut flash = FakeFlash::new(Address(0));
+        flash.write(Address(0), &[0xFFu8; Record::SIZE]).unwrap();
+        FlashUpdateSignal::new(flash, Address(0))
+    }
+
+    #[test]
+    fn erased_sector_holds_no_plan() {
+        assert_eq!(erased_signal().read_update_plan(), None);
+    }
+
+    #[test]
+    fn written_plans_are_read_until_cleared() {
+        let mut signal = erased_signal();
+        signal.write_update_plan(UpdatePlan::Rollback);
+        assert_eq!(signal.read_update_plan(), Some(UpdatePlan::Rollback));
+        signal.clear_update_plan();
+        assert_eq!(signal.read_update_plan(), None);
+    }
+}
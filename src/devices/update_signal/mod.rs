@@ -0,0 +1,188 @@
+//! Update signal, through which the application steers the next boot.
+//!
+//! The application writes an update plan somewhere that survives a reset, and
+//! Loadstone reads it on boot. Ports may provide their own backends (such as the
+//! stm32f412 RTC backup registers), while the generic backends in this module
+//! work on any port: a reserved word of RAM, or a dedicated sector of MCU flash.
+use core::{convert::TryInto, mem::size_of};
+use crc::crc32;
+
+pub mod flash;
+pub mod ram;
+
+pub use flash::FlashUpdateSignal;
+pub use ram::RamUpdateSignal;
+
+/// Indicates the state of an update signal. Part of the boot metrics, so its layout is fixed.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdatePlan {
+    /// Do not update.
+    None,
+
+    /// Allow updates, if one is available.
+    Any,
+
+    /// Update from a specific bank, as long as its image differs from the current one
+    /// (even if it's older).
+    Index(u8),
+
+    /// Do not update. Restore the golden image instead, then boot it.
+    RestoreGolden,
+
+    /// Do not update. Enter serial recovery mode instead, if supported.
+    Recover,
+
+    /// Do not update. Roll back to the newest image older than the current one instead,
+    /// discarding the current image so it isn't installed again.
+    Rollback,
+}
+
+// Update plans are encoded in 32 bit words as follows. These values must never change,
+// as the application writing the plan may be built separately.
+const PLAN_NONE: u32 = 0x0000_0000;
+const PLAN_ANY: u32 = 0xFFFF_FFFF;
+/// "GOLD" in ASCII.
+const PLAN_RESTORE_GOLDEN: u32 = 0x474F_4C44;
+/// "RECV" in ASCII.
+const PLAN_RECOVER: u32 = 0x5245_4356;
+/// "ROLL" in ASCII.
+const PLAN_ROLLBACK: u32 = 0x524F_4C4C;
+// Any other value selects the bank with the index in its lowest byte.
+
+impl UpdatePlan {
+    /// Encodes the plan as a 32 bit word.
+    pub fn to_word(self) -> u32 {
+        match self {
+            UpdatePlan::None => PLAN_NONE,
+            UpdatePlan::Any => PLAN_ANY,
+            UpdatePlan::Index(x) => x as u32,
+            UpdatePlan::RestoreGolden => PLAN_RESTORE_GOLDEN,
+            UpdatePlan::Recover => PLAN_RECOVER,
+            UpdatePlan::Rollback => PLAN_ROLLBACK,
+        }
+    }
+
+    /// Decodes a plan from a 32 bit word.
+    pub fn from_word(word: u32) -> Self {
+        match word {
+            PLAN_NONE => UpdatePlan::None,
+            PLAN_ANY => UpdatePlan::Any,
+            PLAN_RESTORE_GOLDEN => UpdatePlan::RestoreGolden,
+            PLAN_RECOVER => UpdatePlan::Recover,
+            PLAN_ROLLBACK => UpdatePlan::Rollback,
+            x => UpdatePlan::Index(x as u8),
+        }
+    }
+}
+
+/// Marks a written update signal record. "PLAN" in ASCII.
+const RECORD_MAGIC: u32 = 0x504C_414E;
+
+/// An update plan stored alongside a magic value and a checksum, so uninitialised
+/// or corrupted memory is never mistaken for a plan.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Record {
+    magic: u32,
+    plan: u32,
+    checksum: u32,
+}
+
+impl Record {
+    /// Size of an encoded record, in bytes.
+    pub const SIZE: usize = size_of::<Record>();
+    /// Size of an encoded record, in 32 bit words.
+    pub const WORDS: usize = Self::SIZE / size_of::<u32>();
+    /// A record holding no plan, as left behind when clearing the update signal.
+    pub const EMPTY: Record = Record { magic: 0, plan: 0, checksum: 0 };
+
+    pub fn new(plan: UpdatePlan) -> Self {
+        let plan = plan.to_word();
+        Self { magic: RECORD_MAGIC, plan, checksum: Self::checksum(RECORD_MAGIC, plan) }
+    }
+
+    /// The recorded plan, or `None` if the record was never written, was cleared or
+    /// is corrupted.
+    pub fn plan(&self) -> Option<UpdatePlan> {
+        let valid =
+            self.magic == RECORD_MAGIC && self.checksum == Self::checksum(self.magic, self.plan);
+        valid.then(|| UpdatePlan::from_word(self.plan))
+    }
+
+    pub fn to_words(&self) -> [u32; Self::WORDS] { [self.magic, self.plan, self.checksum] }
+
+    pub fn from_words(words: [u32; Self::WORDS]) -> Self {
+        let [magic, plan, checksum] = words;
+        Self { magic, plan, checksum }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        for (chunk, word) in bytes.chunks_exact_mut(size_of::<u32>()).zip(self.to_words().iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let mut words = [0u32; Self::WORDS];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(size_of::<u32>())) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Self::from_words(words)
+    }
+
+    fn checksum(magic: u32, plan: u32) -> u32 {
+        let mut bytes = [0u8; 2 * size_of::<u32>()];
+        bytes[..size_of::<u32>()].copy_from_slice(&magic.to_le_bytes());
+        bytes[size_of::<u32>()..].copy_from_slice(&plan.to_le_bytes());
+        crc32::checksum_ieee(&bytes)
+    }
+}
+
+pub trait ReadUpdateSignal {
+    /// Reads the plan set by the application, or `None` if the signal holds no valid
+    /// plan (it was never set, was cleared, or is corrupted). Loadstone then follows
+    /// its configured default plan.
+    fn read_update_plan(&mut self) -> Option<UpdatePlan>;
+
+    /// Clears the update plan once Loadstone has acted on it, so later boots follow
+    /// the default plan until the application sets a new one.
+    fn clear_update_plan(&mut self);
+}
+
+pub trait WriteUpdateSignal {
+    fn write_update_plan(&mut self, plan: UpdatePlan);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_survive_encoding() {
+        let plans = [
+            UpdatePlan::None,
+            UpdatePlan::Any,
+            UpdatePlan::Index(3),
+            UpdatePlan::RestoreGolden,
+            UpdatePlan::Recover,
+            UpdatePlan::Rollback,
+        ];
+        for plan in plans.iter().cloned() {
+            let record = Record::from_bytes(&Record::new(plan).to_bytes());
+            assert_eq!(record.plan(), Some(plan));
+        }
+    }
+
+    #[test]
+    fn corrupted_records_hold_no_plan() {
+        let mut bytes = Record::new(UpdatePlan::Index(2)).to_bytes();
+        bytes[4] = 5;
+        assert_eq!(Record::from_bytes(&bytes).plan(), None);
+        assert_eq!(Record::from_bytes(&[0xFF; Record::SIZE]).plan(), None);
+        assert_eq!(Record::from_bytes(&[0x00; Record::SIZE]).plan(), None);
+        assert_eq!(Record::EMPTY.plan(), None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Update signal kept in a reserved region of RAM.
+use super::{ReadUpdateSignal, Record, UpdatePlan, WriteUpdateSignal};
+use core::ptr;
+
+/// Update signal kept in a region of RAM reserved by the linker script, which neither
+/// Loadstone nor the application initialise on startup. The plan survives resets, but
+/// not power loss, after which the record fails its checksum and holds no plan.
+pub struct RamUpdateSignal {
+    record: *mut Record,
+}
+
+impl RamUpdateSignal {
+    /// # Safety
+    ///
+    /// `address` must be word aligned, and point to at least [`Record::SIZE`] bytes of
+    /// RAM reserved for the update signal, not used for anything else.
+    pub unsafe fn new(address: usize) -> Self { Self { record: address as *mut Record } }
+}
+
+impl ReadUpdateSignal for RamUpdateSignal {
+    fn read_update_plan(&mut self) -> Option<UpdatePlan> {
+        // NOTE(Safety): The record is reserved for the update signal, as required on
+        // construction. Uninitialised contents are rejected by the checksum.
+        unsafe { ptr::read_volatile(self.record) }.plan()
+    }
+
+    fn clear_update_plan(&mut self) {
+        // NOTE(Safety): The record is reserved for the update signal, as required on
+        // construction.
+        unsafe { ptr::write_volatile(self.record, Record::EMPTY) };
+    }
+}
+
+impl WriteUpdateSignal for RamUpdateSignal {
+    fn write_update_plan(&mut self, plan: UpdatePlan) {
+        // NOTE(Safety): The record is reserved for the update signal, as required on
+        // construction.
+        unsafe { ptr::write_volatile(self.record, Record::new(plan)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uninitialised_ram_holds_no_plan() {
+        let mut ram = [0xA5A5_A5A5u32; Record::WORDS];
+        let mut signal = unsafe { RamUpdateSignal::new(ram.as_mut_ptr() as usize) };
+        assert_eq!(signal.read_update_plan(), None);
+    }
+
+    #[test]
+    fn written_plans_are_read_until_cleared() {
+        let mut ram = [0u32; Record::WORDS];
+        let mut signal = unsafe { RamUpdateSignal::new(ram.as_mut_ptr() as usize) };
+        signal.write_update_plan(UpdatePlan::Index(2));
+        assert_eq!(signal.read_update_plan(), Some(UpdatePlan::Index(2)));
+        signal.clear_update_plan();
+        assert_eq!(signal.read_update_plan(), None);
+    }
+}
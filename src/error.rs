@@ -26,6 +26,16 @@ pub enum Error {
     NoRecoverySupport,
     SignatureInvalid,
     CrcInvalid,
+    ImageRollbackRejected,
+    ImageIsEncrypted,
+    ImageIsCompressed,
+    ImageIsPatch,
+    PatchInvalid,
+    StorageFull,
+    KeyRevoked,
+    ImageIncompatible,
+    RecoveryTimedOut,
+    NoStagingBank,
 }
 
 pub trait Convertible {
@@ -113,6 +123,37 @@ impl Error {
             Error::CrcInvalid => {
                 uwriteln!(serial, "[Logic Error] -> Image CRC is invalid")
             }
+            Error::ImageRollbackRejected => uwriteln!(
+                serial,
+                "[Logic Error] -> Image security epoch is lower than the security counter"
+            ),
+            Error::ImageIsEncrypted => {
+                uwriteln!(serial, "[Logic Error] -> Image is encrypted and can't be booted in place")
+            }
+            Error::ImageIsCompressed => {
+                uwriteln!(serial, "[Logic Error] -> Image is compressed and can't be booted in place")
+            }
+            Error::ImageIsPatch => {
+                uwriteln!(serial, "[Logic Error] -> Image is a patch and can't be booted or copied")
+            }
+            Error::PatchInvalid => {
+                uwriteln!(serial, "[Logic Error] -> Patch is malformed or doesn't fit the boot bank")
+            }
+            Error::StorageFull => {
+                uwriteln!(serial, "[Logic Error] -> Persistent storage is full")
+            }
+            Error::KeyRevoked => {
+                uwriteln!(serial, "[Logic Error] -> Image is signed with a revoked key")
+            }
+            Error::ImageIncompatible => {
+                uwriteln!(serial, "[Logic Error] -> Image is built for different hardware")
+            }
+            Error::RecoveryTimedOut => {
+                uwriteln!(serial, "[Logic Error] -> No image was received during recovery")
+            }
+            Error::NoStagingBank => {
+                uwriteln!(serial, "[Logic Error] -> No scratch bank to stage the recovered image")
+            }
         }
         .ok()
         .unwrap();
@@ -28,6 +28,22 @@ use panic_semihosting as _;
 #[cfg(target_arch = "arm")]
 use defmt_rtt as _; // global logger
 
+/// Tests run natively, with no RTT channel to log to, so their logs are discarded.
+#[cfg(test)]
+mod test_logger {
+    use core::ptr::NonNull;
+
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() -> Option<NonNull<dyn defmt::Write>> { None }
+        unsafe fn release(_: NonNull<dyn defmt::Write>) {}
+    }
+
+    defmt::timestamp!("");
+}
+
 pub mod devices;
 pub mod error;
 
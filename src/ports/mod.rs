@@ -6,7 +6,7 @@
 use blue_hal::port;
 
 #[cfg(feature = "stm32f412")]
-port!(stm32f412: [bootloader, boot_manager, autogenerated, update_signal,]);
+port!(stm32f412: [bootloader, boot_manager, autogenerated, update_signal, watchdog,]);
 
 #[cfg(feature = "wgm160p")]
 port!(wgm160p: [bootloader, autogenerated, update_signal,]);
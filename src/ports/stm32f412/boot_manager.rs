@@ -3,18 +3,15 @@
 use crate::devices::{boot_manager::BootManager, cli::Cli};
 use blue_hal::{drivers::stm32f4::{flash, rcc::Clocks, systick::SysTick}, hal::time, stm32pac};
 
-use super::autogenerated::{self, devices, memory_map::{EXTERNAL_BANKS, MCU_BANKS}, pin_configuration::{self, *}, UPDATE_SIGNAL_ENABLED};
-#[cfg(feature="ecdsa-verify")]
-use crate::devices::image::EcdsaImageReader as ImageReader;
-#[cfg(not(feature="ecdsa-verify"))]
-use crate::devices::image::CrcImageReader as ImageReader;
-use super::update_signal::{UpdateSignalWriter, initialize_rtc_backup_domain};
+use super::autogenerated::{self, devices, memory_map::{EXTERNAL_BANKS, MCU_BANKS, STORAGE}, pin_configuration::{self, *}, UPDATE_SIGNAL_ENABLED, KEY_REVOCATION_ENABLED, TRIAL_BOOTS, BOOT_ATTEMPTS};
+use crate::devices::image::ImageReader;
+use super::update_signal::{UpdateSignal, initialize_rtc_backup_domain};
 
-impl Default for BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignalWriter> {
+impl Default for BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignal> {
     fn default() -> Self { Self::new() }
 }
 
-impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignalWriter> {
+impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignal> {
     pub fn new() -> Self {
         let mut peripherals = stm32pac::Peripherals::take().unwrap();
         let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
@@ -22,7 +19,7 @@ impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSign
 
         initialize_rtc_backup_domain(&mut peripherals.RCC, &mut peripherals.PWR);
 
-        let (serial_pins, qspi_pins) = pin_configuration::pins(
+        let (serial_pins, qspi_pins, _) = pin_configuration::pins(
                 peripherals.GPIOA,
                 peripherals.GPIOB,
                 peripherals.GPIOC,
@@ -49,11 +46,19 @@ impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSign
 
         let update_signal = if UPDATE_SIGNAL_ENABLED {
             let rtc = peripherals.RTC;
-            Some(UpdateSignalWriter::new(rtc))
+            Some(UpdateSignal::new(rtc))
         } else {
             None
         };
 
+        let revoked_keys = if KEY_REVOCATION_ENABLED {
+            STORAGE.map(|storage| storage.revoked_keys())
+        } else {
+            None
+        };
+        let trial_log = TRIAL_BOOTS.and_then(|boots| STORAGE.map(|storage| storage.trial_log(boots)));
+        let boot_attempts = BOOT_ATTEMPTS.and_then(|attempts| STORAGE.map(|storage| storage.boot_attempts(attempts)));
+
         BootManager {
             external_flash,
             mcu_flash,
@@ -64,6 +69,9 @@ impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSign
             greeting: Some(autogenerated::DEMO_APP_GREETING),
             _marker: Default::default(),
             update_signal,
+            revoked_keys,
+            trial_log,
+            boot_attempts,
         }
     }
 }
@@ -1,38 +1,59 @@
 //! Concrete bootloader construction and flash bank layout for stm32f412
-use crate::{devices::bootloader::Bootloader, error};
+use crate::{devices::{bootloader::Bootloader, traits::EraseSectors}, error};
 use crate::error::Error;
 use blue_hal::hal::null::NullError;
 use blue_hal::hal::time::Now;
-use blue_hal::{drivers::{micron::n25q128a_flash,
-    stm32f4::{flash, rcc::Clocks, serial, systick::SysTick}}, hal::time, stm32pac
+use blue_hal::{drivers::{micron::n25q128a_flash::{self, MicronN25q128a},
+    stm32f4::{flash, rcc::Clocks, serial, systick::SysTick}}, hal::{qspi, time}, stm32pac, KB
 };
 use super::autogenerated::{
     self,
     BOOT_TIME_METRICS_ENABLED,
     UPDATE_SIGNAL_ENABLED,
+    UPDATE_SIGNAL_ONE_SHOT,
+    UPDATE_SIGNAL_DEFAULT_PLAN,
+    ANTI_ROLLBACK_ENABLED,
+    KEY_REVOCATION_ENABLED,
+    HARDWARE_ID,
+    SWAP_UPDATES_ENABLED,
+    TRIAL_BOOTS,
+    COPY_JOURNAL_ENABLED,
+    BOOT_ATTEMPTS,
+    WATCHDOG_TIMEOUT_MS,
+    RECOVERY_WINDOW_MS,
+    RECOVERY_TIMEOUT_RESTORES,
+    RECOVERY_IN_PLACE,
+    RECOVERY_TIMEOUTS,
     RECOVERY_ENABLED, devices,
-    memory_map::{EXTERNAL_BANKS, MCU_BANKS},
+    memory_map::{EXTERNAL_BANKS, MCU_BANKS, SCRATCH_BANK, STORAGE},
     pin_configuration::{self, *},
 };
-#[cfg(feature="ecdsa-verify")]
-use crate::devices::image::EcdsaImageReader as ImageReader;
-#[cfg(not(feature="ecdsa-verify"))]
-use crate::devices::image::CrcImageReader as ImageReader;
+use crate::devices::image::{ImageReader, WatchedReader};
 use super::update_signal::{UpdateSignal, initialize_rtc_backup_domain};
+use super::watchdog::IndependentWatchdog;
 
-impl Default for Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, UpdateSignal> {
+type Reader = WatchedReader<ImageReader, IndependentWatchdog>;
+
+impl Default for Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, Reader, UpdateSignal, IndependentWatchdog> {
     fn default() -> Self { Self::new() }
 }
 
-impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, UpdateSignal> {
+impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, Reader, UpdateSignal, IndependentWatchdog> {
     pub fn new() -> Self {
         let mut peripherals = stm32pac::Peripherals::take().unwrap();
         let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
+
+        // The watchdog is fed throughout the bootloader whether Loadstone starts it or
+        // not, as it may also have been started by the option bytes.
+        if let Some(timeout_ms) = WATCHDOG_TIMEOUT_MS {
+            IndependentWatchdog::start(peripherals.IWDG, timeout_ms);
+        }
+
         let mcu_flash = flash::McuFlash::new(peripherals.FLASH).unwrap();
 
         initialize_rtc_backup_domain(&mut peripherals.RCC, &mut peripherals.PWR);
 
-        let (serial_pins, qspi_pins) = pin_configuration::pins(
+        let (serial_pins, qspi_pins, recovery_button) = pin_configuration::pins(
                 peripherals.GPIOA,
                 peripherals.GPIOB,
                 peripherals.GPIOC,
@@ -43,6 +64,7 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
                 peripherals.GPIOH,
                 &mut peripherals.RCC,
             );
+        let recovery_button_held = pin_configuration::recovery_button_held(&recovery_button);
         let clocks = Clocks::hardcoded(peripherals.RCC);
         SysTick::init(cortex_peripherals.SYST, clocks);
         SysTick::wait(time::Seconds(1)); // Gives time for the flash chip to stabilize after powerup
@@ -62,6 +84,26 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
             None
         };
 
+        let security_counter = if ANTI_ROLLBACK_ENABLED {
+            STORAGE.map(|storage| storage.security_counter())
+        } else {
+            None
+        };
+        let revoked_keys = if KEY_REVOCATION_ENABLED {
+            STORAGE.map(|storage| storage.revoked_keys())
+        } else {
+            None
+        };
+        let scratch_bank = if SWAP_UPDATES_ENABLED { SCRATCH_BANK } else { None };
+        let trial_log = TRIAL_BOOTS.and_then(|boots| STORAGE.map(|storage| storage.trial_log(boots)));
+        let journal = if COPY_JOURNAL_ENABLED {
+            STORAGE.map(|storage| storage.copy_journal())
+        } else {
+            None
+        };
+        let boot_attempts = BOOT_ATTEMPTS.and_then(|attempts| STORAGE.map(|storage| storage.boot_attempts(attempts)));
+        let recovery_timeouts = RECOVERY_TIMEOUTS.and_then(|timeouts| STORAGE.map(|storage| storage.recovery_timeouts(timeouts)));
+
         Bootloader {
             mcu_flash,
             external_banks: &EXTERNAL_BANKS,
@@ -71,10 +113,46 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
             boot_metrics: Default::default(),
             start_time,
             recovery_enabled: RECOVERY_ENABLED,
+            recovery_button_held,
+            recovery_window_ms: RECOVERY_WINDOW_MS,
+            restore_after_recovery_timeout: RECOVERY_TIMEOUT_RESTORES,
+            in_place_recovery: RECOVERY_IN_PLACE,
             greeting: autogenerated::LOADSTONE_GREETING,
             _marker: Default::default(),
             update_signal,
+            one_shot_update_plans: UPDATE_SIGNAL_ONE_SHOT,
+            default_update_plan: UPDATE_SIGNAL_DEFAULT_PLAN,
+            security_counter,
+            revoked_keys,
+            hardware_id: HARDWARE_ID,
+            scratch_bank,
+            trial_log,
+            journal,
+            boot_attempts,
+            recovery_timeouts,
+        }
+    }
+}
+
+impl EraseSectors for flash::McuFlash {
+    fn sector_end(address: flash::Address) -> flash::Address {
+        // Sectors of the main memory area, as listed in the reference manual.
+        const SECTOR_SIZES_KB: [u32; 12] = [16, 16, 16, 16, 64, 128, 128, 128, 128, 128, 128, 128];
+        let mut end = 0x0800_0000;
+        for size_kb in SECTOR_SIZES_KB.iter() {
+            end += KB!(size_kb);
+            if address.0 < end {
+                return flash::Address(end);
+            }
         }
+        flash::MemoryMap::writable_end()
+    }
+}
+
+impl<QSPI: qspi::Indirect, NOW: time::Now> EraseSectors for MicronN25q128a<QSPI, NOW> {
+    fn sector_end(address: n25q128a_flash::Address) -> n25q128a_flash::Address {
+        n25q128a_flash::Sector::at(address)
+            .map_or(n25q128a_flash::MemoryMap::end(), |sector| sector.end())
     }
 }
 
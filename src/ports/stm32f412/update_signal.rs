@@ -1,44 +1,78 @@
-use crate::devices::update_signal::{self, UpdatePlan};
-use blue_hal::stm32pac::RTC;
+use super::autogenerated::memory_map::{UPDATE_SIGNAL_RAM_ADDRESS, UPDATE_SIGNAL_SECTOR};
+use crate::devices::update_signal::{
+    FlashUpdateSignal, RamUpdateSignal, ReadUpdateSignal, Record, UpdatePlan, WriteUpdateSignal,
+};
+use blue_hal::{drivers::stm32f4::flash::McuFlash, stm32pac::{self, RTC}};
 
-pub struct UpdateSignal {
-    rtc: RTC,
+/// Update signal kept in the backend selected in the configuration.
+pub enum UpdateSignal {
+    /// The first three RTC backup registers, holding a record with a magic value and checksum,
+    /// so garbage left by a cold start or backup domain glitch isn't taken for a plan.
+    BackupRegisters(RTC),
+    Ram(RamUpdateSignal),
+    Flash(FlashUpdateSignal<McuFlash>),
 }
 
 impl UpdateSignal {
+    /// Constructs the update signal in the configured backend. The RTC is only used when
+    /// keeping the plan in its backup registers.
     pub fn new(rtc: RTC) -> Self {
-        Self { rtc }
+        if let Some(address) = UPDATE_SIGNAL_RAM_ADDRESS {
+            // NOTE(Safety): The linker script reserves this region of RAM for the update signal.
+            UpdateSignal::Ram(unsafe { RamUpdateSignal::new(address) })
+        } else if let Some(location) = UPDATE_SIGNAL_SECTOR {
+            // NOTE(Safety): This driver aliases the main MCU flash driver, which is sound as
+            // both are used from a single thread and block until each operation completes,
+            // so neither is ever mid-operation when the other is used. It only ever
+            // accesses the sector reserved for the update signal.
+            let flash = unsafe { stm32pac::Peripherals::steal() }.FLASH;
+            UpdateSignal::Flash(FlashUpdateSignal::new(McuFlash::new(flash).unwrap(), location))
+        } else {
+            UpdateSignal::BackupRegisters(rtc)
+        }
     }
 }
 
-impl update_signal::ReadUpdateSignal for UpdateSignal {
-    fn read_update_plan(&self) -> UpdatePlan {
-        match self.rtc.bkpr[0].read().bits() {
-            0x00000000 => UpdatePlan::None,
-            0xFFFFFFFF => UpdatePlan::Any,
-            x => UpdatePlan::Index(x as u8),
+impl ReadUpdateSignal for UpdateSignal {
+    fn read_update_plan(&mut self) -> Option<UpdatePlan> {
+        match self {
+            UpdateSignal::BackupRegisters(rtc) => read_record(rtc).plan(),
+            UpdateSignal::Ram(signal) => signal.read_update_plan(),
+            UpdateSignal::Flash(signal) => signal.read_update_plan(),
+        }
+    }
+
+    fn clear_update_plan(&mut self) {
+        match self {
+            UpdateSignal::BackupRegisters(rtc) => write_record(rtc, Record::EMPTY),
+            UpdateSignal::Ram(signal) => signal.clear_update_plan(),
+            UpdateSignal::Flash(signal) => signal.clear_update_plan(),
         }
     }
 }
 
-pub struct UpdateSignalWriter {
-    rtc: RTC,
+impl WriteUpdateSignal for UpdateSignal {
+    fn write_update_plan(&mut self, plan: UpdatePlan) {
+        match self {
+            UpdateSignal::BackupRegisters(rtc) => write_record(rtc, Record::new(plan)),
+            UpdateSignal::Ram(signal) => signal.write_update_plan(plan),
+            UpdateSignal::Flash(signal) => signal.write_update_plan(plan),
+        }
+    }
 }
 
-impl UpdateSignalWriter {
-    pub fn new(rtc: RTC) -> Self {
-        Self { rtc }
+fn read_record(rtc: &RTC) -> Record {
+    let mut words = [0u32; Record::WORDS];
+    for (word, register) in words.iter_mut().zip(rtc.bkpr.iter()) {
+        *word = register.read().bits();
     }
+    Record::from_words(words)
 }
 
-impl update_signal::WriteUpdateSignal for UpdateSignalWriter {
-    fn write_update_plan(&mut self, plan: UpdatePlan) {
-        let bits = match plan {
-            UpdatePlan::None => 0x00000000,
-            UpdatePlan::Any => 0xFFFFFFFF,
-            UpdatePlan::Index(x) => x as u32,
-        };
-        self.rtc.bkpr[0].write(|w| unsafe { w.bits(bits) });
+fn write_record(rtc: &RTC, record: Record) {
+    for (register, word) in rtc.bkpr.iter().zip(record.to_words().iter()) {
+        // NOTE(Safety): Backup registers have no reserved bits.
+        register.write(|w| unsafe { w.bits(*word) });
     }
 }
 
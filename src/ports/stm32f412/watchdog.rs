@@ -0,0 +1,52 @@
+//! Independent watchdog (IWDG) support for the stm32f412.
+use crate::devices::traits::Watchdog;
+use blue_hal::stm32pac::IWDG;
+
+/// Frequency of the low speed internal oscillator clocking the watchdog.
+const LSI_FREQUENCY_KHZ: u32 = 32;
+/// Largest prescaler setting, dividing the oscillator frequency by 256.
+const MAX_PRESCALER: u32 = 6;
+/// Largest number of ticks the 12 bit reload register can count.
+const MAX_TICKS: u32 = 0x1000;
+
+const FEED_KEY: u32 = 0xAAAA;
+const UNLOCK_KEY: u32 = 0x5555;
+const START_KEY: u32 = 0xCCCC;
+
+/// The independent watchdog. Once started, either by Loadstone or by the option
+/// bytes, it can't be stopped until the next reset.
+pub struct IndependentWatchdog;
+
+impl IndependentWatchdog {
+    /// Starts the watchdog, or changes its timeout if it's already running. The
+    /// timeout is rounded up to the prescaler resolution, up to about 32 seconds.
+    pub fn start(iwdg: IWDG, timeout_ms: u32) {
+        let ticks = |prescaler: u32| {
+            let divider = 4u32 << prescaler;
+            (timeout_ms * LSI_FREQUENCY_KHZ + divider - 1) / divider
+        };
+        let prescaler =
+            (0..MAX_PRESCALER).find(|&p| ticks(p) <= MAX_TICKS).unwrap_or(MAX_PRESCALER);
+        let reload = ticks(prescaler).clamp(1, MAX_TICKS) - 1;
+
+        // NOTE(Safety): The key, prescaler and reload registers have no reserved bits
+        // within the range of the values written.
+        unsafe {
+            iwdg.kr.write(|w| w.bits(START_KEY));
+            iwdg.kr.write(|w| w.bits(UNLOCK_KEY));
+            iwdg.pr.write(|w| w.bits(prescaler));
+            iwdg.rlr.write(|w| w.bits(reload));
+        }
+        // The new settings only apply once the status register is cleared.
+        while iwdg.sr.read().bits() != 0 {}
+        Self::feed();
+    }
+}
+
+impl Watchdog for IndependentWatchdog {
+    fn feed() {
+        // NOTE(Safety): Writing the feed key only reloads the watchdog counter, and
+        // has no effect if the watchdog isn't running.
+        unsafe { (*IWDG::ptr()).kr.write(|w| w.bits(FEED_KEY)) };
+    }
+}
@@ -1,21 +1,42 @@
 //! Concrete bootloader construction and flash bank layout for the wgm160p
 
-use blue_hal::{drivers::efm32gg11b::{clocks, flash::{self, Flash}}, efm32pac, hal::null::{NullError, NullFlash, NullSerial, NullSystick}};
-use crate::{devices::{bootloader::Bootloader}, error::{self, Error}};
-use super::autogenerated;
-use super::autogenerated::memory_map::{EXTERNAL_BANKS, MCU_BANKS};
+use blue_hal::{drivers::efm32gg11b::{clocks, flash::{self, Flash}}, efm32pac, hal::null::{NullError, NullFlash, NullSerial, NullSystick}, KB};
+use crate::{devices::{bootloader::Bootloader, traits::{EraseSectors, NullWatchdog}}, error::{self, Error}};
+use super::autogenerated::{
+    self, ANTI_ROLLBACK_ENABLED, BOOT_ATTEMPTS, COPY_JOURNAL_ENABLED, HARDWARE_ID,
+    KEY_REVOCATION_ENABLED, SWAP_UPDATES_ENABLED, TRIAL_BOOTS, UPDATE_SIGNAL_ENABLED,
+    UPDATE_SIGNAL_DEFAULT_PLAN, UPDATE_SIGNAL_ONE_SHOT,
+};
+use super::autogenerated::memory_map::{EXTERNAL_BANKS, MCU_BANKS, SCRATCH_BANK, STORAGE};
 
-#[cfg(feature="ecdsa-verify")]
-use crate::devices::image::EcdsaImageReader as ImageReader;
-#[cfg(not(feature="ecdsa-verify"))]
-use crate::devices::image::CrcImageReader as ImageReader;
-use super::update_signal::NullUpdateSignal;
+use crate::devices::image::ImageReader;
+use super::update_signal::UpdateSignal;
 
-impl Bootloader<NullFlash, Flash, NullSerial, NullSystick, ImageReader, NullUpdateSignal> {
+impl Bootloader<NullFlash, Flash, NullSerial, NullSystick, ImageReader, UpdateSignal, NullWatchdog> {
     pub fn new() -> Self {
         let mut peripherals = efm32pac::Peripherals::take().unwrap();
         let clocks = clocks::Clocks::new(peripherals.CMU, &mut peripherals.MSC);
         let mcu_flash = flash::Flash::new(peripherals.MSC, &clocks);
+        let update_signal =
+            if UPDATE_SIGNAL_ENABLED { UpdateSignal::new(&clocks) } else { None };
+        let security_counter = if ANTI_ROLLBACK_ENABLED {
+            STORAGE.map(|storage| storage.security_counter())
+        } else {
+            None
+        };
+        let revoked_keys = if KEY_REVOCATION_ENABLED {
+            STORAGE.map(|storage| storage.revoked_keys())
+        } else {
+            None
+        };
+        let scratch_bank = if SWAP_UPDATES_ENABLED { SCRATCH_BANK } else { None };
+        let trial_log = TRIAL_BOOTS.and_then(|boots| STORAGE.map(|storage| storage.trial_log(boots)));
+        let journal = if COPY_JOURNAL_ENABLED {
+            STORAGE.map(|storage| storage.copy_journal())
+        } else {
+            None
+        };
+        let boot_attempts = BOOT_ATTEMPTS.and_then(|attempts| STORAGE.map(|storage| storage.boot_attempts(attempts)));
         Bootloader {
             mcu_flash,
             external_banks: &EXTERNAL_BANKS,
@@ -25,13 +46,34 @@ impl Bootloader<NullFlash, Flash, NullSerial, NullSystick, ImageReader, NullUpda
             boot_metrics: Default::default(),
             start_time: None,
             recovery_enabled: false,
+            recovery_button_held: false,
+            recovery_window_ms: None,
+            restore_after_recovery_timeout: false,
+            in_place_recovery: false,
             greeting: autogenerated::LOADSTONE_GREETING,
             _marker: Default::default(),
-            update_signal: None,
+            update_signal,
+            one_shot_update_plans: UPDATE_SIGNAL_ONE_SHOT,
+            default_update_plan: UPDATE_SIGNAL_DEFAULT_PLAN,
+            security_counter,
+            revoked_keys,
+            hardware_id: HARDWARE_ID,
+            scratch_bank,
+            trial_log,
+            journal,
+            boot_attempts,
+            recovery_timeouts: None,
         }
     }
 }
 
+impl EraseSectors for Flash {
+    fn sector_end(address: flash::Address) -> flash::Address {
+        const PAGE_SIZE: u32 = KB!(4);
+        flash::Address(address.0 - address.0 % PAGE_SIZE + PAGE_SIZE)
+    }
+}
+
 impl error::Convertible for flash::Error {
     fn into(self) -> Error {
         match self {
@@ -1,8 +1,59 @@
-use crate::devices::update_signal::{ReadUpdateSignal, UpdatePlan};
+use super::autogenerated::memory_map::{UPDATE_SIGNAL_RAM_ADDRESS, UPDATE_SIGNAL_SECTOR};
+use crate::devices::update_signal::{
+    FlashUpdateSignal, RamUpdateSignal, ReadUpdateSignal, UpdatePlan, WriteUpdateSignal,
+};
+use blue_hal::{
+    drivers::efm32gg11b::{clocks::Clocks, flash::Flash},
+    efm32pac,
+};
 
-#[derive(Default)]
-pub struct NullUpdateSignal;
+/// Update signal kept in the backend selected in the configuration. The wgm160p
+/// has no backup registers, so it's kept in RAM or flash.
+pub enum UpdateSignal {
+    Ram(RamUpdateSignal),
+    Flash(FlashUpdateSignal<Flash>),
+}
+
+impl UpdateSignal {
+    /// Constructs the update signal in the configured backend, if any.
+    pub fn new(clocks: &Clocks) -> Option<Self> {
+        if let Some(address) = UPDATE_SIGNAL_RAM_ADDRESS {
+            // NOTE(Safety): The linker script reserves this region of RAM for the update signal.
+            Some(UpdateSignal::Ram(unsafe { RamUpdateSignal::new(address) }))
+        } else if let Some(location) = UPDATE_SIGNAL_SECTOR {
+            // NOTE(Safety): This driver aliases the main MCU flash driver, which is sound as
+            // both are used from a single thread and block until each operation completes,
+            // so neither is ever mid-operation when the other is used. It only ever
+            // accesses the sector reserved for the update signal.
+            let msc = unsafe { efm32pac::Peripherals::steal() }.MSC;
+            Some(UpdateSignal::Flash(FlashUpdateSignal::new(Flash::new(msc, clocks), location)))
+        } else {
+            None
+        }
+    }
+}
+
+impl ReadUpdateSignal for UpdateSignal {
+    fn read_update_plan(&mut self) -> Option<UpdatePlan> {
+        match self {
+            UpdateSignal::Ram(signal) => signal.read_update_plan(),
+            UpdateSignal::Flash(signal) => signal.read_update_plan(),
+        }
+    }
+
+    fn clear_update_plan(&mut self) {
+        match self {
+            UpdateSignal::Ram(signal) => signal.clear_update_plan(),
+            UpdateSignal::Flash(signal) => signal.clear_update_plan(),
+        }
+    }
+}
 
-impl ReadUpdateSignal for NullUpdateSignal {
-    fn read_update_plan(&self) -> UpdatePlan { UpdatePlan::Any }
+impl WriteUpdateSignal for UpdateSignal {
+    fn write_update_plan(&mut self, plan: UpdatePlan) {
+        match self {
+            UpdateSignal::Ram(signal) => signal.write_update_plan(plan),
+            UpdateSignal::Flash(signal) => signal.write_update_plan(plan),
+        }
+    }
 }
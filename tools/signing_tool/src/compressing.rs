@@ -0,0 +1,26 @@
+use crate::error::{self, Error};
+use loadstone_image::compression::{compress, CompressionHeader};
+
+/// Compresses the body (the first `body_size` bytes) of an already signed image, and
+/// prepends the compression header. Decoration and signature are left uncompressed.
+/// Returns the size of the new body, including the compression header.
+pub fn compress_file(image_filename: &str, body_size: usize) -> Result<usize, Error> {
+    let image =
+        std::fs::read(image_filename).map_err(|_| Error::FileReadFailed(error::File::Image))?;
+    let (body, rest) = image.split_at(body_size);
+
+    let mut compressed = Vec::new();
+    let compressed_size = compress(body, |byte| compressed.push(byte));
+    let header = CompressionHeader { compressed_size, decompressed_size: body_size };
+
+    let mut output = header.to_bytes().to_vec();
+    output.extend_from_slice(&compressed);
+    output.extend_from_slice(rest);
+    std::fs::write(image_filename, output)
+        .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+    println!(
+        "Successfully compressed image body ({} bytes to {} bytes).",
+        body_size, compressed_size
+    );
+    Ok(header.to_bytes().len() + compressed_size)
+}
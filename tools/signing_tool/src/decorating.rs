@@ -3,36 +3,109 @@ use crate::{
     open_image,
 };
 use blue_hal::utilities::iterator::UntilSequence;
-use std::io::{Read, Write};
+use loadstone_image::{
+    magic_string_inverted, Dependency, ImageType, SemanticVersion, Trailer, MAX_DEPENDENCIES,
+};
+use std::{
+    io::{Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Parses a `major.minor.patch` version string.
+pub fn parse_version(version: &str) -> Result<SemanticVersion, Error> {
+    let mut numbers = version.split('.').map(|n| n.parse::<u16>());
+    match (numbers.next(), numbers.next(), numbers.next(), numbers.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => {
+            Ok(SemanticVersion { major, minor, patch })
+        }
+        _ => Err(Error::VersionParseFailed),
+    }
+}
+
+/// Parses a `type:major.minor.patch` dependency string, where `type` is the
+/// numeric image type of the required image.
+pub fn parse_dependency(dependency: &str) -> Result<Dependency, Error> {
+    let mut fields = dependency.splitn(2, ':');
+    let image_type = fields
+        .next()
+        .and_then(|t| t.parse::<u8>().ok())
+        .ok_or(Error::DependencyParseFailed)?;
+    let minimum_version = fields
+        .next()
+        .map(parse_version)
+        .ok_or(Error::DependencyParseFailed)?
+        .map_err(|_| Error::DependencyParseFailed)?;
+    Ok(Dependency { image_type: ImageType::from(image_type), minimum_version })
+}
+
+/// Parses a hardware ID, in decimal or `0x` prefixed hexadecimal.
+pub fn parse_hardware_id(hardware_id: &str) -> Result<u32, Error> {
+    let hardware_id = hardware_id.trim();
+    match hardware_id.strip_prefix("0x").or_else(|| hardware_id.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => hardware_id.parse(),
+    }
+    .map_err(|_| Error::HardwareIdParseFailed)
+}
 
-/// This string identifies a golden image, and must precede the magic string.
-const GOLDEN_STRING: &str = "XPIcbOUrpG";
-/// This string, INVERTED BYTEWISE must terminate any valid image, before the signature.
-///
-/// Note: Why inverted? Because if we used it as-is, no code that includes this
-/// constant could be used as a firmware image, as it contains the magic string
-/// halfway through.
-pub const MAGIC_STRING: &str = "HSc7c2ptydZH2QkqZWPcJgG3JtnJ6VuA";
-pub fn magic_string_inverted() -> Vec<u8> { MAGIC_STRING.as_bytes().iter().map(|b| !b).collect() }
+/// Builds the trailer of an application image, timestamped with the current time.
+pub fn application_trailer(
+    golden: bool,
+    version: Option<SemanticVersion>,
+    hardware_id: Option<u32>,
+    security_epoch: Option<u32>,
+    key_id: Option<u8>,
+    dependencies: &[Dependency],
+) -> Result<Trailer, Error> {
+    if dependencies.len() > MAX_DEPENDENCIES {
+        return Err(Error::TooManyDependencies);
+    }
+    let timestamp =
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut trailer = Trailer {
+        golden,
+        version,
+        hardware_id,
+        image_type: Some(ImageType::Application),
+        timestamp: Some(timestamp),
+        security_epoch,
+        key_id,
+        ..Trailer::default()
+    };
+    for (slot, dependency) in trailer.dependencies.iter_mut().zip(dependencies) {
+        *slot = Some(*dependency);
+    }
+    Ok(trailer)
+}
 
-pub fn decorate_file(image_filename: &str, is_golden: bool) -> Result<(), Error> {
+pub fn decorate_file(image_filename: &str, trailer: &Trailer) -> Result<(), Error> {
     let file = open_image(image_filename)?;
     if file
         .bytes()
         .map(|b| b.unwrap())
-        .until_sequence(magic_string_inverted().as_slice())
+        .until_sequence(&magic_string_inverted())
         .contains_sequence()
     {
         return Err(Error::FileAlreadySigned(error::File::Image));
     }
+
+    let mut decoration = Vec::new();
+    trailer
+        .encode(|bytes| decoration.extend_from_slice(bytes))
+        .expect("Trailer records can't exceed the maximum size");
+    decoration.extend_from_slice(&magic_string_inverted());
+
     let mut file = open_image(image_filename)?;
-    if is_golden {
-        file.write(GOLDEN_STRING.as_bytes())
-            .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
-        println!("Successfully appended golden string.");
+    file.write_all(&decoration).map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+    if trailer.golden {
+        println!("Successfully labelled image as golden.");
+    }
+    if let Some(version) = trailer.version {
+        println!(
+            "Successfully appended version {}.{}.{}.",
+            version.major, version.minor, version.patch
+        );
     }
-    file.write(magic_string_inverted().as_slice())
-        .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
-    println!("Successfully appended magic string.");
+    println!("Successfully appended trailer and magic string ({} bytes).", decoration.len());
     Ok(())
 }
@@ -0,0 +1,51 @@
+use crate::error::{self, Error};
+use aes::Aes128;
+use ctr::cipher::{NewCipher, StreamCipher};
+use sha2::{Digest, Sha256};
+use std::{convert::TryInto, fs::File, io::Read};
+
+/// This string marks the start of an encrypted image, preceding the initial
+/// counter block and the size of the encrypted body.
+const ENCRYPTION_STRING: &str = "eNcRyPtD";
+const KEY_SIZE: usize = 16;
+const IV_SIZE: usize = 16;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// Reads a hex encoded AES-128 key (32 hexadecimal characters).
+pub fn read_key(mut file: File) -> Result<[u8; KEY_SIZE], Error> {
+    let mut string = String::new();
+    file.read_to_string(&mut string).map_err(|_| Error::EncryptionKeyParseFailed)?;
+    let string = string.trim();
+    if string.len() != 2 * KEY_SIZE || !string.is_ascii() {
+        return Err(Error::EncryptionKeyParseFailed);
+    }
+    let mut key = [0u8; KEY_SIZE];
+    for (byte, pair) in key.iter_mut().zip(string.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| Error::EncryptionKeyParseFailed)?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| Error::EncryptionKeyParseFailed)?;
+    }
+    Ok(key)
+}
+
+/// Encrypts the body (the first `body_size` bytes) of an already signed image with
+/// AES-128-CTR, and prepends the encryption header. Decoration and signature are left
+/// in plaintext. The initial counter block is derived from the hash of the signed image,
+/// so it is unique per image.
+pub fn encrypt_file(image_filename: &str, key: [u8; KEY_SIZE], body_size: usize) -> Result<(), Error> {
+    let mut image =
+        std::fs::read(image_filename).map_err(|_| Error::FileReadFailed(error::File::Image))?;
+    let iv: [u8; IV_SIZE] = Sha256::digest(&image)[..IV_SIZE].try_into().unwrap();
+
+    let mut cipher = Aes128Ctr::new(&key.into(), &iv.into());
+    cipher.apply_keystream(&mut image[..body_size]);
+
+    let mut encrypted = ENCRYPTION_STRING.as_bytes().to_vec();
+    encrypted.extend_from_slice(&iv);
+    encrypted.extend_from_slice(&(body_size as u32).to_le_bytes());
+    encrypted.extend_from_slice(&image);
+    std::fs::write(image_filename, encrypted)
+        .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+    println!("Successfully encrypted image body ({} bytes).", body_size);
+    Ok(())
+}
@@ -3,6 +3,7 @@ use std::fmt::{self, Display, Formatter};
 pub enum File {
     Key,
     Image,
+    Base,
 }
 
 impl Display for File {
@@ -11,6 +12,7 @@ impl Display for File {
         match self {
             Key => write!(f, "key"),
             Image => write!(f, "image"),
+            Base => write!(f, "base image"),
         }
     }
 }
@@ -21,6 +23,18 @@ pub enum Error {
     FileWriteFailed(File),
     FileAlreadySigned(File),
     KeyParseFailed,
+    VersionParseFailed,
+    SecurityEpochParseFailed,
+    KeyIdParseFailed,
+    EncryptionKeyParseFailed,
+    SchemeParseFailed,
+    BankSizeParseFailed,
+    DependencyParseFailed,
+    HardwareIdParseFailed,
+    TooManyDependencies,
+    ImageTooBigForBank,
+    BaseImageInvalid,
+    GoldenPatch,
 }
 
 impl Display for Error {
@@ -32,6 +46,28 @@ impl Display for Error {
             FileWriteFailed(file) => write!(f, "Failed to write {} file.", file),
             FileAlreadySigned(file) => write!(f, "File already signed ({} file).", file),
             KeyParseFailed => write!(f, "Failed to parse the private key."),
+            VersionParseFailed => {
+                write!(f, "Failed to parse the image version (expected major.minor.patch).")
+            }
+            SecurityEpochParseFailed => write!(f, "Failed to parse the security epoch."),
+            KeyIdParseFailed => write!(f, "Failed to parse the key ID (expected 0 to 255)."),
+            EncryptionKeyParseFailed => {
+                write!(f, "Failed to parse the encryption key (expected 32 hex characters).")
+            }
+            SchemeParseFailed => {
+                write!(f, "Unknown signature scheme (expected p256, p384 or ed25519).")
+            }
+            BankSizeParseFailed => write!(f, "Failed to parse the bank size."),
+            DependencyParseFailed => {
+                write!(f, "Failed to parse a dependency (expected type:major.minor.patch).")
+            }
+            HardwareIdParseFailed => write!(f, "Failed to parse the hardware ID."),
+            TooManyDependencies => write!(f, "Too many dependencies (at most 4 are supported)."),
+            ImageTooBigForBank => write!(f, "Image (plus footer) doesn't fit in the bank."),
+            BaseImageInvalid => {
+                write!(f, "Base image is not signed (or is signed with a different scheme).")
+            }
+            GoldenPatch => write!(f, "Patches can't be golden."),
         }
     }
 }
@@ -0,0 +1,28 @@
+use crate::error::{self, Error};
+use loadstone_image::{FOOTER_SIZE, FOOTER_STRING, MAGIC_STRING};
+
+/// Pads a finished image with erased flash bytes (`0xFF`) to the size of the bank
+/// it will be stored in, and places the footer in the last bytes of the bank. The
+/// footer records the offsets to the magic string and to the end of the image.
+pub fn pad_with_footer(
+    image_filename: &str,
+    bank_size: usize,
+    trailer_size: usize,
+) -> Result<(), Error> {
+    let mut image =
+        std::fs::read(image_filename).map_err(|_| Error::FileReadFailed(error::File::Image))?;
+    let total_size = image.len();
+    if total_size + FOOTER_SIZE > bank_size {
+        return Err(Error::ImageTooBigForBank);
+    }
+    let magic_offset = total_size - trailer_size - MAGIC_STRING.len();
+
+    image.resize(bank_size - FOOTER_SIZE, 0xFF);
+    image.extend_from_slice(FOOTER_STRING.as_bytes());
+    image.extend_from_slice(&(magic_offset as u32).to_le_bytes());
+    image.extend_from_slice(&(total_size as u32).to_le_bytes());
+    std::fs::write(image_filename, image)
+        .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+    println!("Successfully padded image to bank size ({} bytes).", bank_size);
+    Ok(())
+}
@@ -1,13 +1,21 @@
 mod error;
 mod signing;
 mod decorating;
+mod encrypting;
+mod compressing;
+mod patching;
+mod footer;
 
 use crate::{
-    decorating::decorate_file,
+    decorating::{
+        application_trailer, decorate_file, parse_dependency, parse_hardware_id, parse_version,
+    },
     error::{self as e, Error},
-    signing::sign_file,
+    patching::Base,
+    signing::{identifier_size, sign_file, Key, Scheme},
 };
-use clap::clap_app;
+use clap::{clap_app, App, ArgMatches};
+use loadstone_image::{ImageType, Trailer};
 use signing::calculate_and_append_crc;
 use std::fs::{File, OpenOptions};
 
@@ -19,50 +27,281 @@ fn open_image(filename: &str) -> Result<File, Error> {
         .map_err(|_| Error::FileOpenFailed(e::File::Image))
 }
 
-fn process_image_file(
+/// How to process an image, parsed from the command line. Every argument (and
+/// combination of arguments) is validated before the image file is modified.
+struct Options {
     image_filename: String,
-    private_key_filename: Option<String>,
-    image_is_golden: bool,
-) -> Result<usize, Error> {
-    decorate_file(&image_filename, image_is_golden)?;
-
-    if let Some(private_key_filename) = private_key_filename {
-        let key_file =
-            File::open(private_key_filename).map_err(|_| Error::FileOpenFailed(e::File::Key))?;
-        let key = signing::read_key(key_file)?;
-        sign_file(&image_filename, key)
-    } else {
-        calculate_and_append_crc(&image_filename)
+    /// Key to sign the image with. If absent, a CRC is appended instead.
+    private_key: Option<Key>,
+    trailer: Trailer,
+    /// Base image to replace the image with a patch against.
+    base: Option<Base>,
+    compress: bool,
+    encryption_key: Option<[u8; 16]>,
+    /// Size of the bank to pad the image to, placing a footer at its end.
+    bank_size: Option<usize>,
+}
+
+impl Options {
+    fn parse(matches: &ArgMatches) -> Result<Self, Error> {
+        let image_filename = matches.value_of("image").unwrap().to_owned();
+        open_image(&image_filename)?;
+        let scheme = matches.value_of("scheme").map(str::parse::<Scheme>).transpose()?;
+        let private_key = matches
+            .value_of("private_key")
+            .map(|f| File::open(f).map_err(|_| Error::FileOpenFailed(e::File::Key)))
+            .transpose()?
+            .map(|file| Key::read(file, scheme.unwrap_or(Scheme::P256)))
+            .transpose()?;
+        let encryption_key = matches
+            .value_of("encryption_key")
+            .map(|f| File::open(f).map_err(|_| Error::FileOpenFailed(e::File::Key)))
+            .transpose()?
+            .map(encrypting::read_key)
+            .transpose()?;
+        let image_version = matches.value_of("image_version").map(parse_version).transpose()?;
+        let hardware_id = matches.value_of("hardware_id").map(parse_hardware_id).transpose()?;
+        let security_epoch = matches
+            .value_of("security_epoch")
+            .map(|e| e.parse::<u32>().map_err(|_| Error::SecurityEpochParseFailed))
+            .transpose()?;
+        let key_id = matches
+            .value_of("key_id")
+            .map(|id| id.parse::<u8>().map_err(|_| Error::KeyIdParseFailed))
+            .transpose()?;
+        let dependencies = matches
+            .values_of("dependency")
+            .map(|values| values.map(parse_dependency).collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+        let bank_size = matches
+            .value_of("bank_size")
+            .map(|s| s.parse::<usize>().map_err(|_| Error::BankSizeParseFailed))
+            .transpose()?;
+        let golden = matches.occurrences_of("golden") > 0;
+        if golden && matches.is_present("base") {
+            return Err(Error::GoldenPatch);
+        }
+        let base = matches
+            .value_of("base")
+            .map(|f| Base::read(f, identifier_size(private_key.as_ref())))
+            .transpose()?;
+        let trailer = application_trailer(
+            golden,
+            image_version,
+            hardware_id,
+            security_epoch,
+            key_id,
+            &dependencies,
+        )?;
+
+        Ok(Self {
+            image_filename,
+            private_key,
+            trailer,
+            base,
+            compress: matches.occurrences_of("compress") > 0,
+            encryption_key,
+            bank_size,
+        })
     }
 }
 
-fn main() -> Result<(), String> {
-    let matches = clap_app!(app =>
+/// Appends a signature to the image if a private key is supplied, or a CRC otherwise.
+/// Returns the number of bytes appended.
+fn append_identifier(image_filename: &str, private_key: Option<&Key>) -> Result<usize, Error> {
+    match private_key {
+        Some(key) => sign_file(image_filename, key),
+        None => calculate_and_append_crc(image_filename),
+    }
+}
+
+fn process_image_file(options: &Options) -> Result<usize, Error> {
+    let image_filename = options.image_filename.as_str();
+    let private_key = options.private_key.as_ref();
+    let mut body_size = open_image(image_filename)?
+        .metadata()
+        .map_err(|_| Error::FileReadFailed(e::File::Image))?
+        .len() as usize;
+    decorate_file(image_filename, &options.trailer)?;
+    let mut written_size = append_identifier(image_filename, private_key)?;
+
+    if let Some(base) = &options.base {
+        patching::patch_file(image_filename, base)?;
+        body_size = open_image(image_filename)?
+            .metadata()
+            .map_err(|_| Error::FileReadFailed(e::File::Image))?
+            .len() as usize;
+        let trailer = Trailer {
+            image_type: Some(ImageType::Patch),
+            base_identifier: Some(base.identifier()),
+            ..options.trailer
+        };
+        decorate_file(image_filename, &trailer)?;
+        written_size = append_identifier(image_filename, private_key)?;
+    }
+
+    if options.compress {
+        body_size = compressing::compress_file(image_filename, body_size)?;
+    }
+
+    if let Some(encryption_key) = options.encryption_key {
+        encrypting::encrypt_file(image_filename, encryption_key, body_size)?;
+    }
+
+    if let Some(bank_size) = options.bank_size {
+        footer::pad_with_footer(image_filename, bank_size, written_size)?;
+    }
+    Ok(written_size)
+}
+
+/// Command line interface of the tool.
+fn app() -> App<'static, 'static> {
+    clap_app!(app =>
         (name: env!("CARGO_PKG_NAME"))
         (version: env!("CARGO_PKG_VERSION"))
         (author: env!("CARGO_PKG_AUTHORS"))
         (about: env!("CARGO_PKG_DESCRIPTION"))
         (@arg image: +required "The firmware image to be signed.")
         (@arg golden: -g --golden "Label the image as golden (Loadstone firmware fallback)")
+        (@arg image_version: -i --("image-version") +takes_value "Semantic version of the image \
+            (major.minor.patch), stored in the signed trailer. Loadstone updates to the \
+            highest versioned image available.")
+        (@arg hardware_id: --("hardware-id") +takes_value "Identifier of the hardware the \
+            image is built for (decimal, or hexadecimal prefixed with 0x), stored in the signed \
+            trailer. Loadstone refuses images built for different hardware.")
+        (@arg security_epoch: -e --("security-epoch") +takes_value
+            "Anti-rollback security epoch of the image, stored in the signed trailer. \
+            Loadstone refuses to boot images with an epoch lower than the last booted one.")
+        (@arg key_id: -k --("key-id") +takes_value
+            "ID of the Loadstone key ring entry matching the signing key (defaults to 0, the \
+            primary key), stored in the signed trailer.")
+        (@arg dependency: -d --dependency +takes_value +multiple number_of_values(1)
+            "Dependency on another image (type:major.minor.patch, where type is the numeric \
+            image type), stored in the signed trailer. Can be supplied up to 4 times.")
+        (@arg base: --base +takes_value "Signed image (as found in the bootable bank) to \
+            produce a patch against. The signed image is replaced with a patch that Loadstone \
+            applies to the base image, reconstructing the signed image.")
+        (@arg compress: -c --compress "Compress the image body, after signing. Loadstone \
+            decompresses the image when copying it to the bootable bank.")
+        (@arg encryption_key: -x --("encryption-key") +takes_value "File containing the hex \
+            encoded AES-128 key used to encrypt the image body, after signing. Loadstone \
+            decrypts the image when copying it to the bootable bank.")
+        (@arg scheme: -s --scheme +takes_value possible_values(&["p256", "p384", "ed25519"])
+            "Signature scheme to sign the image with (defaults to p256). Must match the \
+            security mode Loadstone is configured with.")
+        (@arg bank_size: -b --("bank-size") +takes_value "Size in bytes of the bank the image \
+            will be stored in. The image is padded to this size, and a footer is placed at the \
+            end of the bank so Loadstone can locate the image without scanning the bank.")
         (@arg private_key: "The PKCS8 private key used to sign the image. \
             If absent, an IEEE CRC32 code will be appended instead of a signature.")
     )
-    .get_matches();
-
-    let image_filename = matches.value_of("image").unwrap().to_owned();
-    let private_key_filename = matches.value_of("private_key").map(str::to_owned);
+}
 
-    match process_image_file(
-        image_filename,
-        private_key_filename.clone(),
-        matches.occurrences_of("golden") > 0,
-    ) {
+fn main() -> Result<(), String> {
+    let matches = app().get_matches();
+    let options = Options::parse(&matches).map_err(|e| e.to_string())?;
+    match process_image_file(&options) {
         Ok(written_size) => {
             println!("Successfully appended {} to image ({} bytes).", if
-                     private_key_filename.is_some() { "signature " } else { "CRC" },
+                     options.private_key.is_some() { "signature " } else { "CRC" },
                      written_size);
             Ok(())
         }
         Err(e) => Err(e.to_string()),
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes a file in the temporary directory, named uniquely for this test run.
+    pub fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let name = format!("signing_tool_{}_{}", std::process::id(), name);
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn parse(name: &str, arguments: &[&str]) -> Result<Options, Error> {
+        let image = temp_file(name, &[0xAA; 64]);
+        let mut command_line = vec!["signing_tool", image.to_str().unwrap()];
+        command_line.extend_from_slice(arguments);
+        Options::parse(&app().get_matches_from_safe(command_line).unwrap())
+    }
+
+    #[test]
+    fn parsing_valid_options() {
+        let options = parse(
+            "valid_options",
+            &["-g", "-i", "1.2.3", "--hardware-id", "0x412", "-e", "7", "-k", "2", "-b", "4096"],
+        )
+        .ok()
+        .unwrap();
+        assert!(options.trailer.golden);
+        assert_eq!(options.trailer.version.map(|v| (v.major, v.minor, v.patch)), Some((1, 2, 3)));
+        assert_eq!(options.trailer.hardware_id, Some(0x412));
+        assert_eq!(options.trailer.security_epoch, Some(7));
+        assert_eq!(options.trailer.key_id, Some(2));
+        assert_eq!(options.bank_size, Some(4096));
+        assert!(options.private_key.is_none() && options.base.is_none());
+    }
+
+    #[test]
+    fn golden_patches_are_rejected() {
+        let result = parse("golden_patch", &["-g", "--base", "missing_base.bin"]);
+        assert!(matches!(result, Err(Error::GoldenPatch)));
+    }
+
+    #[test]
+    fn too_many_dependencies_are_rejected() {
+        let dependencies = ["-d", "1:1.0.0", "-d", "2:1.0.0", "-d", "3:1.0.0", "-d", "4:1.0.0"];
+        assert!(parse("four_dependencies", &dependencies).is_ok());
+        let dependencies = [&dependencies[..], &["-d", "5:1.0.0"]].concat();
+        let result = parse("five_dependencies", &dependencies);
+        assert!(matches!(result, Err(Error::TooManyDependencies)));
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        let result = parse("bad_version", &["-i", "1.2"]);
+        assert!(matches!(result, Err(Error::VersionParseFailed)));
+        let result = parse("bad_hardware_id", &["--hardware-id", "0xZZ"]);
+        assert!(matches!(result, Err(Error::HardwareIdParseFailed)));
+        let result = parse("bad_key_id", &["-k", "256"]);
+        assert!(matches!(result, Err(Error::KeyIdParseFailed)));
+        let result = parse("bad_bank_size", &["-b", "4k"]);
+        assert!(matches!(result, Err(Error::BankSizeParseFailed)));
+        let result = parse("bad_dependency", &["-d", "1.0.0"]);
+        assert!(matches!(result, Err(Error::DependencyParseFailed)));
+    }
+
+    #[test]
+    fn unknown_schemes_are_rejected() {
+        let command_line = ["signing_tool", "image.bin", "-s", "p521", "key.pem"];
+        assert!(app().get_matches_from_safe(command_line).is_err());
+    }
+
+    #[test]
+    fn keys_for_a_different_scheme_are_rejected() {
+        let key = signing::tests::key_pem(Scheme::Ed25519);
+        let key = temp_file("mismatched_key.pem", key.as_bytes());
+        let key = key.to_str().unwrap();
+        assert!(parse("mismatched_key", &["-s", "ed25519", key]).is_ok());
+        let result = parse("mismatched_key", &["-s", "p384", key]);
+        assert!(matches!(result, Err(Error::KeyParseFailed)));
+        let result = parse("mismatched_key", &[key]);
+        assert!(matches!(result, Err(Error::KeyParseFailed)));
+    }
+
+    #[test]
+    fn missing_files_are_rejected() {
+        let command_line = ["signing_tool", "missing_image.bin"];
+        let result = Options::parse(&app().get_matches_from_safe(command_line).unwrap());
+        assert!(matches!(result, Err(Error::FileOpenFailed(e::File::Image))));
+        let result = parse("missing_key", &["missing_key.pem"]);
+        assert!(matches!(result, Err(Error::FileOpenFailed(e::File::Key))));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::{self, Error};
+use loadstone_image::{
+    magic_string_inverted,
+    patch::{is_copy_in_place, Operation, PatchHeader},
+    Identifier, MAGIC_STRING, MAX_IDENTIFIER_SIZE,
+};
+use std::collections::HashMap;
+
+/// Size of the base image windows indexed when looking for matches.
+const KEY_SIZE: usize = 16;
+/// Copies shorter than this cost more than inserting the bytes they produce.
+const MIN_COPY_SIZE: usize = 32;
+/// Maximum number of base image positions tried per match.
+const MAX_CANDIDATES: usize = 32;
+
+/// Signed image (as stored in the bootable bank: neither encrypted nor compressed) that
+/// patches are produced against.
+pub struct Base {
+    image: Vec<u8>,
+    identifier: Identifier,
+}
+
+impl Base {
+    /// Reads a base image, whose identifier is `identifier_size` bytes long.
+    pub fn read(base_filename: &str, identifier_size: usize) -> Result<Self, Error> {
+        let image =
+            std::fs::read(base_filename).map_err(|_| Error::FileReadFailed(error::File::Base))?;
+        let identifier = identifier(&image, identifier_size).ok_or(Error::BaseImageInvalid)?;
+        Ok(Self { image, identifier })
+    }
+
+    pub fn identifier(&self) -> Identifier { self.identifier }
+}
+
+/// Replaces a signed image with a patch body, reconstructing it from a base image.
+pub fn patch_file(image_filename: &str, base: &Base) -> Result<(), Error> {
+    let image =
+        std::fs::read(image_filename).map_err(|_| Error::FileReadFailed(error::File::Image))?;
+
+    let patch = diff(&base.image, &image);
+    std::fs::write(image_filename, &patch)
+        .map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+    println!(
+        "Successfully replaced image with a patch ({} bytes to {} bytes).",
+        image.len(),
+        patch.len()
+    );
+    Ok(())
+}
+
+/// Identifier of a signed image: the CRC/signature following its magic string.
+fn identifier(image: &[u8], identifier_size: usize) -> Option<Identifier> {
+    let magic = magic_string_inverted();
+    let magic_offset = image.windows(MAGIC_STRING.len()).position(|w| w == magic)?;
+    let start = magic_offset + MAGIC_STRING.len();
+    let bytes = image.get(start..start + identifier_size)?;
+    (identifier_size <= MAX_IDENTIFIER_SIZE).then(|| Identifier::new(bytes))
+}
+
+/// Encodes the operations reconstructing `image` from `base`, applied in place.
+fn diff(base: &[u8], image: &[u8]) -> Vec<u8> {
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (offset, window) in base.windows(KEY_SIZE).enumerate() {
+        index.entry(window).or_default().push(offset);
+    }
+
+    let mut patch = PatchHeader { size: image.len() }.to_bytes().to_vec();
+    let magic = magic_string_inverted();
+    let mut pending_start = 0;
+    let mut position = 0;
+    while position < image.len() {
+        let (operation, length) = if image[position..].starts_with(&magic) {
+            (Some(Operation::Magic), MAGIC_STRING.len())
+        } else {
+            match longest_copy(&index, base, image, position) {
+                Some((offset, length)) => (Some(Operation::Copy { length, offset }), length),
+                None => (None, 1),
+            }
+        };
+        if let Some(operation) = operation {
+            insert(&mut patch, &image[pending_start..position]);
+            patch.extend_from_slice(&operation.to_bytes());
+            pending_start = position + length;
+        }
+        position += length;
+    }
+    insert(&mut patch, &image[pending_start..]);
+    patch
+}
+
+/// Finds the longest copy from the base image producing the bytes at `position`
+/// that doesn't read base image blocks already overwritten.
+fn longest_copy(
+    index: &HashMap<&[u8], Vec<usize>>,
+    base: &[u8],
+    image: &[u8],
+    position: usize,
+) -> Option<(usize, usize)> {
+    let key = image.get(position..position + KEY_SIZE)?;
+    let offsets = index.get(key)?;
+    // Offsets are sorted, so skip straight to the ones allowed by the in-place constraint.
+    let first = offsets.partition_point(|&offset| !is_copy_in_place(position, offset));
+    offsets[first..]
+        .iter()
+        .take(MAX_CANDIDATES)
+        .map(|&offset| {
+            let length = (0..)
+                .take_while(|&i| {
+                    position + i < image.len()
+                        && offset + i < base.len()
+                        && image[position + i] == base[offset + i]
+                        && is_copy_in_place(position + i, offset + i)
+                        && !image[position + i..].starts_with(&magic_string_inverted())
+                })
+                .count();
+            (offset, length)
+        })
+        .max_by_key(|&(_, length)| length)
+        .filter(|&(_, length)| length >= MIN_COPY_SIZE)
+}
+
+fn insert(patch: &mut Vec<u8>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        patch.extend_from_slice(&Operation::Insert { length: bytes.len() }.to_bytes());
+        patch.extend_from_slice(bytes);
+    }
+}
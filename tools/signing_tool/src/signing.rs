@@ -4,6 +4,9 @@ use p256::ecdsa::{
 };
 use std::str::FromStr;
 use crc::{crc32, Hasher32};
+use ed25519_dalek::pkcs8::DecodePrivateKey as _;
+use p384::pkcs8::DecodePrivateKey as _;
+use sha2::{Digest, Sha512};
 
 use crate::{
     error::{self, Error},
@@ -22,21 +25,97 @@ fn read_file(file: &mut File) -> Result<Vec<u8>, Error> {
     }
 }
 
-pub fn read_key(mut file: File) -> Result<SigningKey, Error> {
-    let mut string = String::new();
-    file.read_to_string(&mut string).map_err(|_| Error::KeyParseFailed)?;
-    SigningKey::from_str(string.as_str()).map_err(|_| Error::KeyParseFailed)
+/// Signature schemes supported by Loadstone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// ECDSA over NIST P-256, with a SHA-256 digest.
+    P256,
+    /// ECDSA over NIST P-384, with a SHA-384 digest.
+    P384,
+    /// Ed25519ph (Ed25519 over the SHA-512 digest of the image, with no context).
+    Ed25519,
 }
 
-/// Reads the contents of `file` and signs it using P256 ECDSA/SHA256 with the key in `key_file`.
-pub fn sign_file(image_filename: &str, key: SigningKey) -> Result<usize, Error> {
+impl FromStr for Scheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "p256" => Ok(Scheme::P256),
+            "p384" => Ok(Scheme::P384),
+            "ed25519" => Ok(Scheme::Ed25519),
+            _ => Err(Error::SchemeParseFailed),
+        }
+    }
+}
+
+/// Private key to sign images with, for one of the supported signature schemes.
+pub enum Key {
+    P256(SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl Key {
+    /// Reads a PKCS8 private key in PEM format, for the given signature scheme.
+    pub fn read(mut file: File, scheme: Scheme) -> Result<Self, Error> {
+        let mut string = String::new();
+        file.read_to_string(&mut string).map_err(|_| Error::KeyParseFailed)?;
+        let key = string.as_str();
+        Ok(match scheme {
+            Scheme::P256 => {
+                Key::P256(SigningKey::from_str(key).map_err(|_| Error::KeyParseFailed)?)
+            }
+            Scheme::P384 => Key::P384(
+                p384::ecdsa::SigningKey::from_pkcs8_pem(key).map_err(|_| Error::KeyParseFailed)?,
+            ),
+            Scheme::Ed25519 => Key::Ed25519(
+                ed25519_dalek::SigningKey::from_pkcs8_pem(key)
+                    .map_err(|_| Error::KeyParseFailed)?,
+            ),
+        })
+    }
+
+    /// Size in bytes of the signatures made with this key.
+    pub fn signature_size(&self) -> usize {
+        match self {
+            Key::P256(_) | Key::Ed25519(_) => 64,
+            Key::P384(_) => 96,
+        }
+    }
+}
+
+/// Size in bytes of the identifier appended to an image: a signature if a key is
+/// supplied, or a CRC otherwise.
+pub fn identifier_size(key: Option<&Key>) -> usize {
+    key.map_or(core::mem::size_of::<u32>(), Key::signature_size)
+}
+
+fn sign(plaintext: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
+    Ok(match key {
+        Key::P256(key) => key.sign(plaintext).as_bytes().to_vec(),
+        Key::P384(key) => {
+            let signature: p384::ecdsa::Signature = key.sign(plaintext);
+            signature.as_bytes().to_vec()
+        }
+        Key::Ed25519(key) => {
+            let digest = Sha512::new().chain_update(plaintext);
+            let signature =
+                key.sign_prehashed(digest, None).map_err(|_| Error::KeyParseFailed)?;
+            signature.to_bytes().to_vec()
+        }
+    })
+}
+
+/// Reads the contents of `file` and signs it with `key`.
+pub fn sign_file(image_filename: &str, key: &Key) -> Result<usize, Error> {
     let mut file = open_image(image_filename)?;
     let plaintext = read_file(&mut file)?;
-    let signature = key.sign(&plaintext);
+    let signature = sign(&plaintext, key)?;
     let bytes_written =
-        file.write(signature.as_bytes()).map_err(|_| Error::FileWriteFailed(error::File::Image))?;
+        file.write(&signature).map_err(|_| Error::FileWriteFailed(error::File::Image))?;
 
-    if bytes_written == signature.as_bytes().len() {
+    if bytes_written == signature.len() {
         Ok(bytes_written)
     } else {
         Err(Error::FileWriteFailed(error::File::Image))
@@ -59,3 +138,90 @@ pub fn calculate_and_append_crc(image_filename: &str) -> Result<usize, Error> {
         Err(Error::FileWriteFailed(error::File::Image))
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tests::temp_file;
+    use p256::{ecdsa::signature::Verifier, pkcs8::LineEnding};
+
+    const P256_SECRET: [u8; 32] = [0x11; 32];
+    const P384_SECRET: [u8; 48] = [0x22; 48];
+    const ED25519_SECRET: [u8; 32] = [0x33; 32];
+
+    /// PKCS8 PEM encoding of the test private key for a scheme.
+    pub fn key_pem(scheme: Scheme) -> String {
+        use ed25519_dalek::pkcs8::EncodePrivateKey as _;
+        use p256::pkcs8::EncodePrivateKey as _;
+        match scheme {
+            Scheme::P256 => p256::SecretKey::from_be_bytes(&P256_SECRET)
+                .unwrap()
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            Scheme::P384 => p384::SecretKey::from_be_bytes(&P384_SECRET)
+                .unwrap()
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+            Scheme::Ed25519 => ed25519_dalek::SigningKey::from_bytes(&ED25519_SECRET)
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .to_string(),
+        }
+    }
+
+    /// Signs an image file with the test key for a scheme, read back from its PEM file.
+    /// Returns the image contents and the signature appended to them.
+    fn sign_image(scheme: Scheme) -> (Vec<u8>, Vec<u8>) {
+        let name = format!("{:?}", scheme);
+        let key_file = temp_file(&format!("{}_key.pem", name), key_pem(scheme).as_bytes());
+        let key = Key::read(File::open(key_file).unwrap(), scheme).ok().unwrap();
+        let image: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let image_file = temp_file(&format!("{}_image.bin", name), &image);
+
+        let written = sign_file(image_file.to_str().unwrap(), &key).ok().unwrap();
+        assert_eq!(written, key.signature_size());
+        let mut signed = std::fs::read(image_file).unwrap();
+        let signature = signed.split_off(image.len());
+        assert_eq!(signed, image);
+        assert_eq!(signature.len(), written);
+        (image, signature)
+    }
+
+    #[test]
+    fn p256_signatures_verify() {
+        let (image, signature) = sign_image(Scheme::P256);
+        let key = SigningKey::from_bytes(&P256_SECRET).unwrap();
+        let signature = p256::ecdsa::Signature::from_bytes(&signature).unwrap();
+        assert!(key.verifying_key().verify(&image, &signature).is_ok());
+    }
+
+    #[test]
+    fn p384_signatures_verify() {
+        let (image, signature) = sign_image(Scheme::P384);
+        let key = p384::ecdsa::SigningKey::from_bytes(&P384_SECRET).unwrap();
+        let signature = p384::ecdsa::Signature::from_bytes(&signature).unwrap();
+        assert!(key.verifying_key().verify(&image, &signature).is_ok());
+    }
+
+    #[test]
+    fn ed25519_signatures_verify() {
+        let (image, signature) = sign_image(Scheme::Ed25519);
+        let key = ed25519_dalek::SigningKey::from_bytes(&ED25519_SECRET);
+        let signature = ed25519_dalek::Signature::from_slice(&signature).unwrap();
+        let digest = Sha512::new().chain_update(&image);
+        assert!(key.verifying_key().verify_prehashed(digest, None, &signature).is_ok());
+    }
+
+    #[test]
+    fn crcs_match_the_image() {
+        let image = [0x5A; 100];
+        let image_file = temp_file("crc_image.bin", &image);
+
+        assert_eq!(calculate_and_append_crc(image_file.to_str().unwrap()).ok(), Some(4));
+        let signed = std::fs::read(image_file).unwrap();
+        assert_eq!(signed[..100], image[..]);
+        assert_eq!(signed[100..], crc32::checksum_ieee(&image).to_le_bytes()[..]);
+    }
+}